@@ -0,0 +1,102 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use firestore::firestore::synthetic::{
+    synthetic_document, synthetic_fvalue, synthetic_json, synthetic_record, SyntheticRecord,
+};
+use firestore::firestore::{from_document, to_fvalue, FValue};
+use serde_json::Value as JValue;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// counts the allocations to report them per conversion.
+struct CountingAllocator;
+
+static ALLOCATED_NUM: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED_NUM.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const DEPTH: usize = 3;
+const WIDTH: usize = 5;
+const SEED: u64 = 1;
+
+fn doc_name() -> String {
+    "projects/bench/databases/(default)/documents/bench/doc".to_owned()
+}
+
+fn report_allocations<F: FnOnce()>(name: &str, f: F) {
+    let num = ALLOCATED_NUM.load(Ordering::Relaxed);
+    let bytes = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    f();
+    println!(
+        "{}: {} allocations, {} bytes",
+        name,
+        ALLOCATED_NUM.load(Ordering::Relaxed) - num,
+        ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes
+    );
+}
+
+fn value_conversion(c: &mut Criterion) {
+    let record = synthetic_record(DEPTH, WIDTH, SEED);
+    report_allocations("to_fvalue", || {
+        to_fvalue(record.clone()).unwrap();
+    });
+    c.bench_function("to_fvalue", |b| {
+        b.iter_batched(
+            || record.clone(),
+            |record| to_fvalue(black_box(record)).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+
+    report_allocations("from_document", || {
+        from_document::<SyntheticRecord>(synthetic_document(doc_name(), DEPTH, WIDTH, SEED))
+            .unwrap();
+    });
+    c.bench_function("from_document", |b| {
+        b.iter_batched(
+            || synthetic_document(doc_name(), DEPTH, WIDTH, SEED),
+            |doc| from_document::<SyntheticRecord>(black_box(doc)).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+
+    let fvalue = synthetic_fvalue(DEPTH, WIDTH, SEED);
+    report_allocations("fvalue_to_json", || {
+        let _ = JValue::from(fvalue.clone());
+    });
+    c.bench_function("fvalue_to_json", |b| {
+        b.iter_batched(
+            || fvalue.clone(),
+            |fvalue| JValue::from(black_box(fvalue)),
+            BatchSize::LargeInput,
+        )
+    });
+
+    let json = synthetic_json(DEPTH, WIDTH, SEED);
+    report_allocations("json_to_fvalue", || {
+        let _ = FValue::from(json.clone());
+    });
+    c.bench_function("json_to_fvalue", |b| {
+        b.iter_batched(
+            || json.clone(),
+            |json| FValue::from(black_box(json)),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, value_conversion);
+criterion_main!(benches);
@@ -0,0 +1,349 @@
+//! a synchronous facade of `firestore::firestore::FirestoreClient` for the cli tools, like
+//! `reqwest::blocking`. the client owns a tokio runtime and blocks on each call.
+//!
+//! it must not be called, nor dropped, within an async context (it panics as blocking in the
+//! runtime). the background tasks of the client are stopped when the last clone is dropped.
+//!
+//! ```ignore
+//! firestore = { path = "../firestore", features = ["blocking"] }
+//!
+//! use firestore::blocking::FirestoreClient;
+//! let client = FirestoreClient::with_service_account_file(project_id, cred_path)?;
+//! let user: Option<User> = client.get_document_as("/users/user_1".to_owned(), None, None)?;
+//! client.in_transaction(path, |client, tx, path| {
+//!     let user: Option<User> = client.get_document_as(path, None, Some(tx.transaction.clone()))?;
+//!     tx.add_operation(update_ope(user)?)?;
+//!     Ok(())
+//! })?;
+//! ```
+
+use crate::firestore::{
+    self as nonblocking, DocumentWriteOperation, FDocumentPath, FirestoreClientBuilder,
+    FirestoreError, MissingDocPaths, QueryBuilder, ReadConsistency, Result, TransactionOperation,
+};
+use google_cloud_grpc_proto::firestore::v1::{Document, StructuredQuery, Value, WriteResult};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::runtime::Runtime;
+
+/// the time to wait for the background tasks of the client when the last clone is dropped.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Inner {
+    client: nonblocking::FirestoreClient,
+    runtime: Runtime,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let report = self
+            .runtime
+            .block_on(self.client.shutdown(SHUTDOWN_TIMEOUT));
+        if !report.is_clean() {
+            log::warn!("failed to stop {:?}", report.failed);
+        }
+    }
+}
+
+/// the blocking client. the clones share the runtime and the connections.
+#[derive(Clone)]
+pub struct FirestoreClient {
+    inner: Arc<Inner>,
+}
+
+fn new_runtime() -> Result<Runtime> {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| FirestoreError::Internal(format!("failed to start the runtime: {}", e)))
+}
+
+/// block on the future. it also works in the closures of the transactions, which run on the
+/// runtime. (the runtime must be multi thread for it)
+fn block_on<F: Future>(runtime: &Runtime, future: F) -> F::Output {
+    tokio::task::block_in_place(|| runtime.handle().block_on(future))
+}
+
+impl FirestoreClient {
+    /// build the async client on the runtime of the blocking client.
+    pub fn from_builder(builder: FirestoreClientBuilder) -> Result<Self> {
+        let runtime = new_runtime()?;
+        let client = runtime.block_on(builder.build())?;
+        Ok(Self {
+            inner: Arc::new(Inner { client, runtime }),
+        })
+    }
+
+    pub fn with_service_account_file(
+        project_id: String,
+        service_account_cred_path: PathBuf,
+    ) -> Result<Self> {
+        Self::from_builder(
+            FirestoreClientBuilder::new(project_id).service_account_file(service_account_cred_path),
+        )
+    }
+
+    /// connect to the firestore emulator at `host` (e.g. "localhost:8080").
+    pub fn with_emulator(project_id: String, host: String) -> Result<Self> {
+        Self::from_builder(FirestoreClientBuilder::new(project_id).emulator(host))
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        block_on(&self.inner.runtime, future)
+    }
+
+    /// the async client, to call the methods not provided by the facade with `block_on`.
+    pub fn client(&self) -> &nonblocking::FirestoreClient {
+        &self.inner.client
+    }
+
+    pub fn project_id(&self) -> &str {
+        self.inner.client.project_id()
+    }
+
+    pub fn get_document<C>(
+        &self,
+        document_path: String,
+        field_mask: Option<Vec<String>>,
+        consistency: C,
+    ) -> Result<Option<Document>>
+    where
+        C: Into<ReadConsistency>,
+    {
+        self.block_on(
+            self.inner
+                .client
+                .get_document(document_path, field_mask, consistency),
+        )
+    }
+
+    pub fn get_document_as<T, C>(
+        &self,
+        document_path: String,
+        field_mask: Option<Vec<String>>,
+        consistency: C,
+    ) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+        C: Into<ReadConsistency>,
+    {
+        self.block_on(
+            self.inner
+                .client
+                .get_document_as(document_path, field_mask, consistency),
+        )
+    }
+
+    pub fn create_document<D>(
+        &self,
+        parent_path: Option<String>,
+        collection_id: String,
+        document_id: String,
+        document: D,
+    ) -> Result<Document>
+    where
+        D: Into<HashMap<String, Value>>,
+    {
+        self.block_on(self.inner.client.create_document(
+            parent_path,
+            collection_id,
+            document_id,
+            document,
+        ))
+    }
+
+    /// create the document with the id assigned by the server.
+    pub fn create_document_auto_id<D>(
+        &self,
+        parent_path: Option<String>,
+        collection_id: String,
+        document: D,
+    ) -> Result<(FDocumentPath, Document)>
+    where
+        D: Into<HashMap<String, Value>>,
+    {
+        self.block_on(self.inner.client.create_document_auto_id(
+            parent_path,
+            collection_id,
+            document,
+        ))
+    }
+
+    pub fn update_document<D>(
+        &self,
+        document_path: String,
+        document: D,
+        update_field_mask: Option<Vec<String>>,
+        response_field_mask: Option<Vec<String>>,
+    ) -> Result<Document>
+    where
+        D: Into<HashMap<String, Value>>,
+    {
+        self.block_on(self.inner.client.update_document(
+            document_path,
+            document,
+            update_field_mask,
+            response_field_mask,
+        ))
+    }
+
+    pub fn delete_document(&self, document_path: String) -> Result<()> {
+        self.block_on(self.inner.client.delete_document(document_path))
+    }
+
+    pub fn commit(
+        &self,
+        operations: Vec<DocumentWriteOperation>,
+        transaction: Option<Vec<u8>>,
+    ) -> Result<Vec<WriteResult>> {
+        self.block_on(self.inner.client.commit(operations, transaction))
+    }
+
+    pub fn batch_write(&self, operations: Vec<DocumentWriteOperation>) -> Result<Vec<WriteResult>> {
+        self.block_on(self.inner.client.batch_write(operations))
+    }
+
+    pub fn batch_get_documents<F, C>(
+        &self,
+        document_paths: Vec<String>,
+        field_mask: Option<Vec<String>>,
+        consistency: C,
+        with_each_doc: F,
+    ) -> Result<MissingDocPaths>
+    where
+        F: FnMut(Document) -> anyhow::Result<()>,
+        C: Into<ReadConsistency>,
+    {
+        self.block_on(self.inner.client.batch_get_documents(
+            document_paths,
+            field_mask,
+            consistency,
+            with_each_doc,
+        ))
+    }
+
+    pub fn run_query<F, C>(
+        &self,
+        parent_path: Option<String>,
+        query: StructuredQuery,
+        consistency: C,
+        with_each_doc: F,
+    ) -> Result<i64>
+    where
+        F: FnMut(Document) -> anyhow::Result<()>,
+        C: Into<ReadConsistency>,
+    {
+        self.block_on(
+            self.inner
+                .client
+                .run_query(parent_path, query, consistency, with_each_doc),
+        )
+    }
+
+    /// the documents of the query collected.
+    pub fn run_query_all<C>(
+        &self,
+        parent_path: Option<String>,
+        query: StructuredQuery,
+        consistency: C,
+    ) -> Result<Vec<Document>>
+    where
+        C: Into<ReadConsistency>,
+    {
+        let mut documents = Vec::new();
+        self.run_query(parent_path, query, consistency, |document| {
+            documents.push(document);
+            Ok(())
+        })?;
+        Ok(documents)
+    }
+
+    pub fn count(&self, parent_path: Option<String>, query: QueryBuilder) -> Result<i64> {
+        self.block_on(self.inner.client.count(parent_path, query))
+    }
+
+    /// `nonblocking::FirestoreClient::in_transaction` with the synchronous `with_tx`.
+    /// pass `tx.transaction` to the reads to read in the transaction. `with_tx` can't borrow
+    /// the caller's data. pass it in `ctx`.
+    pub fn in_transaction<F, R, Ctx>(&self, ctx: Ctx, with_tx: F) -> Result<R>
+    where
+        F: Fn(&FirestoreClient, &mut TransactionOperation, Ctx) -> anyhow::Result<R> + 'static,
+        R: 'static,
+        Ctx: 'static,
+    {
+        let with_tx = Arc::new(with_tx);
+        self.block_on(
+            self.inner
+                .client
+                .in_transaction((self.clone(), with_tx, ctx), call_in_transaction),
+        )
+    }
+
+    /// `nonblocking::FirestoreClient::in_transaction_with_retry` with the synchronous `with_tx`.
+    /// `with_tx` is called again with the cloned `ctx` on the retries.
+    pub fn in_transaction_with_retry<F, R, Ctx>(&self, ctx: Ctx, with_tx: F) -> Result<R>
+    where
+        F: Fn(&FirestoreClient, &mut TransactionOperation, Ctx) -> anyhow::Result<R> + 'static,
+        R: 'static,
+        Ctx: Clone + 'static,
+    {
+        let with_tx = Arc::new(with_tx);
+        self.block_on(
+            self.inner
+                .client
+                .in_transaction_with_retry((self.clone(), with_tx, ctx), call_in_transaction),
+        )
+    }
+
+    /// `nonblocking::FirestoreClient::read_only_transaction` with the synchronous `with_tx`.
+    pub fn read_only_transaction<F, R, Ctx>(
+        &self,
+        read_time: Option<SystemTime>,
+        ctx: Ctx,
+        with_tx: F,
+    ) -> Result<R>
+    where
+        F: FnOnce(&FirestoreClient, Vec<u8>, Ctx) -> anyhow::Result<R>,
+    {
+        let client = self.clone();
+        let tx = self.block_on(self.inner.client.begin_read_only_transaction(read_time))?;
+        with_tx(&client, tx, ctx).map_err(FirestoreError::Callback)
+    }
+}
+
+async fn call_in_transaction<F, R, Ctx>(
+    _: &mut nonblocking::FirestoreClient,
+    tx: &mut TransactionOperation,
+    (client, with_tx, ctx): (FirestoreClient, Arc<F>, Ctx),
+) -> anyhow::Result<R>
+where
+    F: Fn(&FirestoreClient, &mut TransactionOperation, Ctx) -> anyhow::Result<R>,
+{
+    // the reads in `with_tx` block on the runtime polling this future
+    tokio::task::block_in_place(|| with_tx(&client, tx, ctx))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn nested_block_on_test() {
+        let runtime = new_runtime().unwrap();
+        let value = block_on(&runtime, async {
+            // as the reads in the closure of `in_transaction`
+            tokio::task::block_in_place(|| block_on(&runtime, async { 1 }))
+        });
+        assert_eq!(1, value);
+    }
+
+    #[test]
+    fn blocking_client_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync + Clone>() {}
+        assert_send_sync::<FirestoreClient>();
+    }
+}
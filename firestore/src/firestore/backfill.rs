@@ -0,0 +1,223 @@
+use super::client::FirestoreClient;
+use super::query::QueryBuilder;
+use super::request::DocumentWriteOperation;
+use super::trigger::relative_document_path;
+use super::value::FFields;
+
+use super::error::{FirestoreError, Result};
+use futures::TryStreamExt;
+use google_cloud_grpc_proto::firestore::v1::Document;
+use serde::{de::DeserializeOwned, Serialize};
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+/// the documents read at once. the changed documents of a page are written together.
+pub const BACKFILL_PAGE_SIZE: i32 = 300;
+
+/// the progress of `Backfill::run` after each page.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BackfillProgress {
+    pub read_num: usize,
+    /// the documents the transform returned Some.
+    pub changed_num: usize,
+    pub written_num: usize,
+    /// the path of the last document read. pass it to `with_resume_after` to continue.
+    pub last_document_path: Option<String>,
+}
+
+/// read the documents of the query page by page in the order of the document names,
+/// transform them, and write the changed ones. for one-off data fixes.
+///
+/// the changed documents are updated with the fields of the transformed value, the other
+/// fields are kept.
+///
+/// ```ignore
+/// let progress = client
+///     .backfill(None, QueryBuilder::collection("users".to_owned(), false), |user: User| {
+///         if user.email.is_empty() { None } else { Some(EmailLower { email: user.email.to_lowercase() }) }
+///     })
+///     .with_max_writes_per_sec(500)
+///     .with_resume_after(saved_path)
+///     .run(|progress| save_checkpoint(progress))
+///     .await?;
+/// ```
+pub struct Backfill<T, U, F> {
+    client: FirestoreClient,
+    parent_path: Option<String>,
+    query: QueryBuilder,
+    transform: F,
+    page_size: i32,
+    max_writes_per_sec: Option<u32>,
+    resume_after: Option<String>,
+    dry_run: bool,
+    _types: PhantomData<(T, U)>,
+}
+
+impl<T, U, F> Backfill<T, U, F>
+where
+    T: DeserializeOwned,
+    U: Serialize,
+    F: FnMut(T) -> Option<U>,
+{
+    pub(crate) fn new(
+        client: FirestoreClient,
+        parent_path: Option<String>,
+        query: QueryBuilder,
+        transform: F,
+    ) -> Self {
+        Self {
+            // the documents are decoded as a whole before the transform
+            client: client.without_default_field_masks(),
+            parent_path,
+            query,
+            transform,
+            page_size: BACKFILL_PAGE_SIZE,
+            max_writes_per_sec: None,
+            resume_after: None,
+            dry_run: false,
+            _types: PhantomData,
+        }
+    }
+
+    pub fn with_page_size(self, page_size: i32) -> Self {
+        Self {
+            page_size: page_size.max(1),
+            ..self
+        }
+    }
+
+    /// wait between the pages so that the writes don't exceed the rate.
+    pub fn with_max_writes_per_sec(self, max_writes_per_sec: u32) -> Self {
+        Self {
+            max_writes_per_sec: Some(max_writes_per_sec.max(1)),
+            ..self
+        }
+    }
+
+    /// start after the document (e.g. "/users/u1"), the `last_document_path` of a progress.
+    pub fn with_resume_after(self, document_path: Option<String>) -> Self {
+        Self {
+            resume_after: document_path,
+            ..self
+        }
+    }
+
+    /// transform without writing. `written_num` stays 0.
+    pub fn with_dry_run(self, dry_run: bool) -> Self {
+        Self { dry_run, ..self }
+    }
+
+    /// `with_each_progress` is called after each page is written.
+    /// if an error occurred, the last progress tells where to resume.
+    pub async fn run<P>(mut self, mut with_each_progress: P) -> Result<BackfillProgress>
+    where
+        P: FnMut(&BackfillProgress) -> anyhow::Result<()>,
+    {
+        // ordered by the name only, to resume from the document name.
+        let mut query = self.query.clone().without_orders();
+        if let Some(document_path) = &self.resume_after {
+            query = query.start_after(Document {
+                name: self.client.database_ref().resource_name(document_path),
+                ..Default::default()
+            });
+        }
+
+        let mut progress = BackfillProgress::default();
+        let started_at = Instant::now();
+        let mut pages = Box::pin(self.client.paginate_query(
+            self.parent_path.clone(),
+            query,
+            self.page_size,
+        ));
+        while let Some(page) = pages.try_next().await? {
+            progress.read_num += page.len();
+            progress.last_document_path = page
+                .last()
+                .map(|document| relative_document_path(&document.name));
+
+            let mut operations = Vec::new();
+            for document in page {
+                let document_path = relative_document_path(&document.name);
+                if let Some(changed) = (self.transform)(self.client.decode(document)?) {
+                    operations.push(update_operation(
+                        document_path,
+                        self.client.encode(&changed)?,
+                    ));
+                }
+            }
+            progress.changed_num += operations.len();
+
+            if !self.dry_run && !operations.is_empty() {
+                progress.written_num += self.client.large_batch_write(operations).await?.len();
+                if let Some(rate) = self.max_writes_per_sec {
+                    tokio::time::sleep(rate_limit_delay(
+                        progress.written_num,
+                        started_at.elapsed(),
+                        rate,
+                    ))
+                    .await;
+                }
+            }
+            with_each_progress(&progress).map_err(FirestoreError::Callback)?;
+        }
+        Ok(progress)
+    }
+}
+
+fn update_operation(document_path: String, changed: FFields) -> DocumentWriteOperation {
+    let (fields, transforms) = changed.split_transforms();
+    let update_field_mask = fields.keys().cloned().collect();
+    DocumentWriteOperation::new_update(document_path, fields, Some(update_field_mask))
+        .with_update_transforms(transforms)
+}
+
+/// the wait so that `written` writes take at least `written / rate` seconds since the start.
+fn rate_limit_delay(written: usize, elapsed: Duration, rate: u32) -> Duration {
+    let expected = Duration::from_secs_f64(written as f64 / rate as f64);
+    expected.checked_sub(elapsed).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rate_limit_delay_test() {
+        assert_eq!(
+            Duration::from_millis(1500),
+            rate_limit_delay(1000, Duration::from_millis(500), 500)
+        );
+        assert_eq!(
+            Duration::from_secs(0),
+            rate_limit_delay(100, Duration::from_secs(1), 500)
+        );
+    }
+
+    #[tokio::test]
+    async fn update_operation_with_casing_test() {
+        use crate::firestore::value::fvalue::FieldCasing;
+        use serde::Deserialize;
+
+        #[derive(Serialize, Deserialize)]
+        struct User {
+            display_name: Option<String>,
+        }
+        let client = FirestoreClient::offline("p").with_field_casing(FieldCasing::camel());
+        let mut fields = FFields::empty();
+        fields.add("displayName", "jeff");
+        let document = Document {
+            name: "projects/p/databases/(default)/documents/users/u1".to_owned(),
+            fields: fields.to_grpc_fields(),
+            ..Default::default()
+        };
+
+        let mut user: User = client.decode(document).unwrap();
+        assert_eq!(Some("jeff"), user.display_name.as_deref());
+        user.display_name = Some("jeff2".to_owned());
+        let ope = update_operation("/users/u1".to_owned(), client.encode(&user).unwrap());
+        assert_eq!(
+            Some(&["displayName".to_owned()][..]),
+            ope.update_field_mask()
+        );
+    }
+}
@@ -0,0 +1,619 @@
+use super::cancel::CancellationToken;
+use super::client::{FirestoreClient, MAX_BATCH_WRTIE_SIZE};
+use super::components::ComponentTier;
+use super::request::DocumentWriteOperation;
+
+use super::error::{FirestoreError, Result};
+use crate::grpc::events::ClientEvent;
+use futures::FutureExt;
+use google_cloud_grpc_proto::{
+    firestore::v1::WriteResult,
+    tonic::{Code, Status},
+};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+/// the writes per second at the start. the "500" of the 500/50/5 rule.
+pub const BULK_WRITER_INITIAL_OPS_PER_SEC: u32 = 500;
+/// the upper limit of the ramp-up.
+pub const BULK_WRITER_MAX_OPS_PER_SEC: u32 = 10_000;
+/// the attempts of each write including the first one.
+pub const DEFAULT_BULK_WRITER_MAX_ATTEMPTS: usize = 10;
+
+/// the writes per second increases by 50% every 5 minutes.
+const RAMP_UP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const RAMP_UP_FACTOR: f64 = 1.5;
+
+const RETRY_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+const RETRY_BACKOFF_FACTOR: f64 = 1.5;
+
+type SuccessCallback = Box<dyn FnMut(&str, &WriteResult) + Send>;
+type ErrorCallback = Box<dyn FnMut(&str, &FirestoreError, usize) -> bool + Send>;
+
+/// the options of `BulkWriter`.
+///
+/// ```ignore
+/// let writer = client.bulk_writer_with(
+///     BulkWriterOptions::default()
+///         .on_success(|path, _| log::info!("written {}", path))
+///         .on_error(|path, e, attempts| e.is_retryable() && attempts < 5),
+/// );
+/// ```
+pub struct BulkWriterOptions {
+    throttling: bool,
+    initial_ops_per_sec: u32,
+    max_ops_per_sec: u32,
+    max_attempts: usize,
+    on_success: Option<SuccessCallback>,
+    on_error: Option<ErrorCallback>,
+}
+
+impl Default for BulkWriterOptions {
+    fn default() -> Self {
+        Self {
+            throttling: true,
+            initial_ops_per_sec: BULK_WRITER_INITIAL_OPS_PER_SEC,
+            max_ops_per_sec: BULK_WRITER_MAX_OPS_PER_SEC,
+            max_attempts: DEFAULT_BULK_WRITER_MAX_ATTEMPTS,
+            on_success: None,
+            on_error: None,
+        }
+    }
+}
+
+impl BulkWriterOptions {
+    /// write without the ramp-up. (e.g. for the emulator)
+    pub fn with_throttling(self, throttling: bool) -> Self {
+        Self { throttling, ..self }
+    }
+
+    pub fn with_initial_ops_per_sec(self, initial_ops_per_sec: u32) -> Self {
+        Self {
+            initial_ops_per_sec: initial_ops_per_sec.max(1),
+            ..self
+        }
+    }
+
+    pub fn with_max_ops_per_sec(self, max_ops_per_sec: u32) -> Self {
+        Self {
+            max_ops_per_sec: max_ops_per_sec.max(1),
+            ..self
+        }
+    }
+
+    pub fn with_max_attempts(self, max_attempts: usize) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            ..self
+        }
+    }
+
+    /// called with the document path after each write is applied.
+    pub fn on_success<F>(self, on_success: F) -> Self
+    where
+        F: FnMut(&str, &WriteResult) + Send + 'static,
+    {
+        Self {
+            on_success: Some(Box::new(on_success)),
+            ..self
+        }
+    }
+
+    /// called with the document path, the error and the attempts so far after each failed write.
+    /// returns whether to retry the write, up to `max_attempts`.
+    /// without this, the writes failed with the retryable errors are retried.
+    pub fn on_error<F>(self, on_error: F) -> Self
+    where
+        F: FnMut(&str, &FirestoreError, usize) -> bool + Send + 'static,
+    {
+        Self {
+            on_error: Some(Box::new(on_error)),
+            ..self
+        }
+    }
+}
+
+/// the 500/50/5 rule : start at 500 writes per second and increase by 50% every 5 minutes.
+/// https://firebase.google.com/docs/firestore/best-practices#ramping_up_traffic
+struct RampUpThrottle {
+    started_at: Instant,
+    initial_ops_per_sec: f64,
+    max_ops_per_sec: f64,
+    available: f64,
+    refilled_at: Instant,
+}
+
+impl RampUpThrottle {
+    fn new(initial_ops_per_sec: u32, max_ops_per_sec: u32, now: Instant) -> Self {
+        Self {
+            started_at: now,
+            initial_ops_per_sec: initial_ops_per_sec as f64,
+            max_ops_per_sec: max_ops_per_sec as f64,
+            available: initial_ops_per_sec.min(max_ops_per_sec) as f64,
+            refilled_at: now,
+        }
+    }
+
+    fn ops_per_sec(&self, now: Instant) -> f64 {
+        let ramp_ups = now.duration_since(self.started_at).as_secs() / RAMP_UP_INTERVAL.as_secs();
+        (self.initial_ops_per_sec * RAMP_UP_FACTOR.powi(ramp_ups as i32)).min(self.max_ops_per_sec)
+    }
+
+    /// take the budget of the writes and returns how long to wait before writing them.
+    fn reserve(&mut self, ops: usize, now: Instant) -> Duration {
+        let ops_per_sec = self.ops_per_sec(now);
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.available = (self.available + elapsed * ops_per_sec).min(ops_per_sec);
+        self.refilled_at = now;
+        self.available -= ops as f64;
+        if self.available >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-self.available / ops_per_sec)
+        }
+    }
+}
+
+/// the server's retry delay if any, otherwise exponential backoff.
+fn retry_delay(error: &FirestoreError, attempts: usize) -> Duration {
+    if let Some(delay) = error.error_details().retry_delay() {
+        return delay;
+    }
+    if error.code() == Some(Code::ResourceExhausted) {
+        return RETRY_MAX_DELAY;
+    }
+    RETRY_INITIAL_DELAY
+        .mul_f64(RETRY_BACKOFF_FACTOR.powi(attempts.saturating_sub(1) as i32))
+        .min(RETRY_MAX_DELAY)
+}
+
+/// the error is not Clone. the same status is passed to every write of the batch.
+fn clone_error(e: &FirestoreError) -> FirestoreError {
+    match e {
+        // the details keep the retry delay of the server
+        FirestoreError::Status(status) => FirestoreError::from(Status::with_details(
+            status.code(),
+            status.message(),
+            status.details().to_vec().into(),
+        )),
+        other => FirestoreError::Internal(format!("batch write failed: {}", other)),
+    }
+}
+
+/// the write whose result is missing in the response may have been applied.
+fn missing_outcome() -> WriteOutcome {
+    (
+        Err(FirestoreError::Internal(
+            "the result of the write is missing".to_owned(),
+        )),
+        true,
+    )
+}
+
+enum Message {
+    Write(PendingWrite),
+    Flush(oneshot::Sender<()>),
+}
+
+/// completion handle of an enqueued write.
+/// dropping the handle doesn't cancel the write.
+pub struct WriteHandle {
+    receiver: oneshot::Receiver<Result<WriteResult>>,
+}
+
+impl WriteHandle {
+    /// wait until the write is applied, or failed after the retries.
+    pub async fn wait(self) -> Result<WriteResult> {
+        self.receiver.await.map_err(|_| {
+            FirestoreError::Internal("bulk writer stopped before the write was applied".to_owned())
+        })?
+    }
+}
+
+/// writes the enqueued operations with BatchWrite in a background task.
+///
+/// for the large ingestion jobs and the low-priority writes (e.g. telemetry) that the caller
+/// doesn't want to wait for. the operations queued at once are written together up to
+/// `MAX_BATCH_WRTIE_SIZE`, ramping up the traffic by the 500/50/5 rule.
+/// each failed write is retried individually with backoff.
+/// BatchWrite doesn't guarantee the order of the writes.
+///
+/// the write whose request failed may have been applied on the server. such writes are retried
+/// only if they are idempotent (see `DocumentWriteOperation::is_idempotent`). to retry the
+/// `Increment` without double counting, put the precondition on the `update_time` read before,
+/// or enqueue it with a dedup token.
+///
+/// ```ignore
+/// let writer = client.bulk_writer();
+/// writer.enqueue(ope)?; // fire and forget
+/// let result = writer.enqueue(another_ope)?.wait().await?;
+/// writer.close().await?;
+/// ```
+pub struct BulkWriter {
+    sender: mpsc::UnboundedSender<Message>,
+    worker: JoinHandle<()>,
+}
+
+impl BulkWriter {
+    pub(crate) fn new(client: FirestoreClient, options: BulkWriterOptions) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let (stop, guard) = client
+            .components()
+            .register_task("bulk_writer", ComponentTier::Writer);
+        let worker = tokio::spawn(async move {
+            let _guard = guard;
+            Worker::new(client, options).run(receiver, stop).await
+        });
+        Self { sender, worker }
+    }
+
+    /// enqueue the operation and returns immediately.
+    pub fn enqueue(&self, operation: DocumentWriteOperation) -> Result<WriteHandle> {
+        self.send_write(operation, None)
+    }
+
+    /// enqueue the operation committed atomically with the creation of the token document
+    /// (e.g. "/counter_tokens/{event_id}"). the operation is applied at most once per token,
+    /// even if it is retried or enqueued again.
+    /// the write already applied with the token results in the default `WriteResult`.
+    ///
+    /// ```ignore
+    /// let ope = new_write_ope_update(None, "counters".to_owned(), "page_views".to_owned(), Some(vec![]), views)?;
+    /// writer.enqueue_with_dedup_token(ope, format!("/counter_tokens/{}", event_id))?;
+    /// ```
+    pub fn enqueue_with_dedup_token(
+        &self,
+        operation: DocumentWriteOperation,
+        token_document_path: String,
+    ) -> Result<WriteHandle> {
+        self.send_write(operation, Some(token_document_path))
+    }
+
+    fn send_write(
+        &self,
+        operation: DocumentWriteOperation,
+        dedup_token: Option<String>,
+    ) -> Result<WriteHandle> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Message::Write(PendingWrite {
+                operation,
+                dedup_token,
+                attempts: 0,
+                sender,
+            }))
+            .map_err(|_| FirestoreError::Internal("bulk writer has been stopped".to_owned()))?;
+        Ok(WriteHandle { receiver })
+    }
+
+    /// wait until all the operations enqueued before are written.
+    pub async fn flush(&self) -> Result<()> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Message::Flush(sender))
+            .map_err(|_| FirestoreError::Internal("bulk writer has been stopped".to_owned()))?;
+        receiver
+            .await
+            .map_err(|_| FirestoreError::Internal("bulk writer stopped before flushing".to_owned()))
+    }
+
+    /// write all the enqueued operations and stop the background task.
+    pub async fn close(self) -> Result<()> {
+        let BulkWriter { sender, worker } = self;
+        drop(sender);
+        worker
+            .await
+            .map_err(|e| FirestoreError::Internal(format!("bulk writer task failed: {}", e)))
+    }
+}
+
+struct PendingWrite {
+    operation: DocumentWriteOperation,
+    dedup_token: Option<String>,
+    attempts: usize,
+    sender: oneshot::Sender<Result<WriteResult>>,
+}
+
+impl PendingWrite {
+    fn is_idempotent(&self) -> bool {
+        self.dedup_token.is_some() || self.operation.is_idempotent()
+    }
+
+    /// the token document is written with the operation.
+    fn ops_num(&self) -> usize {
+        if self.dedup_token.is_some() {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+/// the result of a write and whether the write may have been applied even though it failed.
+type WriteOutcome = (Result<WriteResult>, bool);
+
+struct Worker {
+    client: FirestoreClient,
+    options: BulkWriterOptions,
+    throttle: Option<RampUpThrottle>,
+    pending: Vec<PendingWrite>,
+}
+
+impl Worker {
+    fn new(client: FirestoreClient, options: BulkWriterOptions) -> Self {
+        let throttle = if options.throttling {
+            Some(RampUpThrottle::new(
+                options.initial_ops_per_sec,
+                options.max_ops_per_sec,
+                Instant::now(),
+            ))
+        } else {
+            None
+        };
+        Self {
+            client,
+            options,
+            throttle,
+            pending: Vec::new(),
+        }
+    }
+
+    /// until the senders are dropped, or `stop` is cancelled. the operations already enqueued
+    /// are written before it returns.
+    async fn run(
+        mut self,
+        mut receiver: mpsc::UnboundedReceiver<Message>,
+        stop: CancellationToken,
+    ) {
+        loop {
+            let message = tokio::select! {
+                message = receiver.recv() => message,
+                _ = stop.cancelled() => {
+                    receiver.close();
+                    receiver.recv().await
+                }
+            };
+            let message = match message {
+                Some(message) => message,
+                None => break,
+            };
+            let mut next = Some(message);
+            while let Some(message) = next.take() {
+                match message {
+                    Message::Write(write) => {
+                        self.pending.push(write);
+                        if self.pending.len() >= MAX_BATCH_WRTIE_SIZE {
+                            self.write_pending().await;
+                        }
+                    }
+                    Message::Flush(sender) => {
+                        self.write_pending().await;
+                        let _ = sender.send(());
+                    }
+                }
+                // take the messages already queued without waiting, to write them together
+                next = receiver.recv().now_or_never().flatten();
+            }
+            self.write_pending().await;
+        }
+    }
+
+    /// write until all the pending writes are applied or given up.
+    async fn write_pending(&mut self) {
+        while !self.pending.is_empty() {
+            let batch_size = self.pending.len().min(MAX_BATCH_WRTIE_SIZE);
+            let batch: Vec<PendingWrite> = self.pending.drain(..batch_size).collect();
+
+            if let Some(throttle) = self.throttle.as_mut() {
+                let ops_num = batch.iter().map(PendingWrite::ops_num).sum();
+                let delay = throttle.reserve(ops_num, Instant::now());
+                if delay > Duration::from_secs(0) {
+                    self.client.events().emit(ClientEvent::ThrottleApplied {
+                        delay,
+                        ops: ops_num,
+                    });
+                    tokio::time::sleep(delay).await;
+                }
+            }
+
+            let mut results = self.write_batch(&batch).await.into_iter();
+            let mut retries = Vec::<PendingWrite>::new();
+            let mut retry_after = Duration::from_secs(0);
+            for mut write in batch {
+                write.attempts += 1;
+                let document_path = write.operation.document_path();
+                let (result, maybe_applied) = results.next().unwrap_or_else(missing_outcome);
+                match result {
+                    Ok(write_result) => {
+                        if let Some(on_success) = self.options.on_success.as_mut() {
+                            on_success(document_path, &write_result);
+                        }
+                        let _ = write.sender.send(Ok(write_result));
+                    }
+                    Err(e) => {
+                        let retry = match self.options.on_error.as_mut() {
+                            Some(on_error) => on_error(document_path, &e, write.attempts),
+                            None => e.is_retryable(),
+                        };
+                        // retrying the write that may have been applied must not apply it twice
+                        let retry = retry && (!maybe_applied || write.is_idempotent());
+                        if retry && write.attempts < self.options.max_attempts {
+                            retry_after = retry_after.max(retry_delay(&e, write.attempts));
+                            retries.push(write);
+                        } else {
+                            if retry {
+                                self.client.events().emit(ClientEvent::RetryExhausted {
+                                    operation: document_path.to_owned(),
+                                    attempts: write.attempts,
+                                    error: e.to_string(),
+                                });
+                            }
+                            let _ = write.sender.send(Err(e));
+                        }
+                    }
+                }
+            }
+
+            if !retries.is_empty() {
+                log::warn!("bulk writer retries {} writes", retries.len());
+                #[cfg(feature = "tracing")]
+                tracing::warn!(writes = retries.len(), ?retry_after, "bulk writer retries");
+                tokio::time::sleep(retry_after).await;
+                // retried before the writes enqueued after them
+                self.pending.splice(0..0, retries);
+            }
+        }
+    }
+
+    /// the writes without the dedup token are written together with BatchWrite,
+    /// the others are committed with their token documents one by one.
+    async fn write_batch(&mut self, batch: &[PendingWrite]) -> Vec<WriteOutcome> {
+        let mut outcomes: Vec<Option<WriteOutcome>> = batch.iter().map(|_| None).collect();
+
+        let (indexes, operations): (Vec<usize>, Vec<DocumentWriteOperation>) = batch
+            .iter()
+            .enumerate()
+            .filter(|(_, write)| write.dedup_token.is_none())
+            .map(|(index, write)| (index, write.operation.clone()))
+            .unzip();
+        if !operations.is_empty() {
+            let result = self.client.batch_write_with_status(operations).await;
+            match result {
+                Ok(results) => {
+                    for (index, result) in indexes.iter().zip(results) {
+                        outcomes[*index] = Some((result, false));
+                    }
+                }
+                Err(e) => {
+                    log::error!("bulk writer failed to write: {}", e);
+                    for index in indexes {
+                        outcomes[index] = Some((Err(clone_error(&e)), true));
+                    }
+                }
+            }
+        }
+
+        for (index, write) in batch.iter().enumerate() {
+            if let Some(token_document_path) = write.dedup_token.as_ref() {
+                let operations = vec![
+                    DocumentWriteOperation::new_dedup_token(token_document_path.clone()),
+                    write.operation.clone(),
+                ];
+                outcomes[index] = Some(match self.client.commit(operations, None).await {
+                    Ok(mut write_results) => (Ok(write_results.pop().unwrap_or_default()), false),
+                    Err(e) if e.is_already_exists() => {
+                        // applied by the previous attempt only if the token document exists,
+                        // otherwise the write itself failed. e.g. create an existing document
+                        match self
+                            .client
+                            .get_document(token_document_path.clone(), None, None)
+                            .await
+                        {
+                            Ok(Some(_)) => (Ok(WriteResult::default()), false),
+                            Ok(None) => (Err(e), false),
+                            Err(lookup_error) => (Err(lookup_error), false),
+                        }
+                    }
+                    Err(e) => (Err(e), true),
+                });
+            }
+        }
+
+        outcomes
+            .into_iter()
+            .map(|outcome| outcome.unwrap_or_else(missing_outcome))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{clone_error, retry_delay, RampUpThrottle, WriteHandle, RETRY_MAX_DELAY};
+    use crate::firestore::error::FirestoreError;
+    use google_cloud_grpc_proto::{
+        firestore::v1::WriteResult,
+        prost::Message,
+        prost_types,
+        rpc::{self, RetryInfo},
+        tonic::{Code, Status},
+    };
+    use std::time::{Duration, Instant};
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn write_handle_test() {
+        let (sender, receiver) = oneshot::channel();
+        let handle = WriteHandle { receiver };
+        sender.send(Ok(WriteResult::default())).unwrap();
+        assert_eq!(WriteResult::default(), handle.wait().await.unwrap());
+
+        let (sender, receiver) = oneshot::channel();
+        let handle = WriteHandle { receiver };
+        drop(sender);
+        assert!(handle.wait().await.is_err());
+    }
+
+    #[test]
+    fn ramp_up_throttle_test() {
+        let now = Instant::now();
+        let mut throttle = RampUpThrottle::new(500, 1000, now);
+        assert_eq!(500.0, throttle.ops_per_sec(now));
+        assert_eq!(500.0, throttle.ops_per_sec(now + Duration::from_secs(299)));
+        assert_eq!(750.0, throttle.ops_per_sec(now + Duration::from_secs(300)));
+        assert_eq!(1000.0, throttle.ops_per_sec(now + Duration::from_secs(600)));
+
+        assert_eq!(Duration::from_secs(0), throttle.reserve(450, now));
+        // 50 left, 400 more writes take 0.8 sec at 500/sec
+        assert_eq!(Duration::from_millis(800), throttle.reserve(450, now));
+        assert_eq!(
+            Duration::from_secs(0),
+            throttle.reserve(100, now + Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn clone_error_test() {
+        let mut retry_info = Vec::new();
+        RetryInfo {
+            retry_delay: Some(prost_types::Duration {
+                seconds: 3,
+                nanos: 0,
+            }),
+        }
+        .encode(&mut retry_info)
+        .unwrap();
+        let mut details = Vec::new();
+        rpc::Status {
+            code: Code::Unavailable as i32,
+            message: "unavailable".to_owned(),
+            details: vec![prost_types::Any {
+                type_url: "type.googleapis.com/google.rpc.RetryInfo".to_owned(),
+                value: retry_info,
+            }],
+        }
+        .encode(&mut details)
+        .unwrap();
+        let e = FirestoreError::from(Status::with_details(
+            Code::Unavailable,
+            "unavailable",
+            details.into(),
+        ));
+        assert_eq!(
+            Some(Duration::from_secs(3)),
+            clone_error(&e).error_details().retry_delay()
+        );
+    }
+
+    #[test]
+    fn retry_delay_test() {
+        let unavailable = FirestoreError::from(Status::unavailable("unavailable"));
+        assert_eq!(Duration::from_secs(1), retry_delay(&unavailable, 1));
+        assert_eq!(Duration::from_millis(1500), retry_delay(&unavailable, 2));
+        assert_eq!(RETRY_MAX_DELAY, retry_delay(&unavailable, 100));
+
+        let exhausted = FirestoreError::from(Status::resource_exhausted("quota"));
+        assert_eq!(RETRY_MAX_DELAY, retry_delay(&exhausted, 1));
+    }
+}
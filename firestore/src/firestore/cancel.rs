@@ -0,0 +1,77 @@
+use super::error::{FirestoreError, Result};
+use futures::{future, stream, Future, Stream, StreamExt};
+pub use tokio_util::sync::CancellationToken;
+
+/// `future` failing with `FirestoreError::Cancelled` as soon as the token is cancelled.
+/// the future is dropped, so is the grpc call in flight.
+pub(crate) async fn cancellable<F, T>(token: Option<&CancellationToken>, future: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    match token {
+        Some(token) => tokio::select! {
+            _ = token.cancelled() => Err(FirestoreError::Cancelled),
+            result = future => result,
+        },
+        None => future.await,
+    }
+}
+
+/// `stream` ending with `FirestoreError::Cancelled` as soon as the token is cancelled.
+/// the stream is dropped, so is the grpc stream.
+pub(crate) fn until_cancelled<S, T>(
+    token: Option<CancellationToken>,
+    stream: S,
+) -> impl Stream<Item = Result<T>>
+where
+    S: Stream<Item = Result<T>>,
+{
+    let (wait_token, check_token) = (token.clone(), token);
+    let cancelled = async move {
+        match wait_token {
+            Some(token) => token.cancelled().await,
+            None => future::pending().await,
+        }
+    };
+    let cancelled_error = stream::once(async move {
+        match check_token {
+            Some(token) if token.is_cancelled() => Some(Err(FirestoreError::Cancelled)),
+            _ => None,
+        }
+    })
+    .filter_map(future::ready);
+
+    stream.take_until(cancelled).chain(cancelled_error)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::TryStreamExt;
+
+    #[tokio::test]
+    async fn until_cancelled_test() {
+        let token = CancellationToken::new();
+        let values: Vec<u32> =
+            until_cancelled(Some(token.clone()), stream::iter(vec![Ok(1), Ok(2)]))
+                .try_collect()
+                .await
+                .unwrap();
+        assert_eq!(vec![1, 2], values);
+
+        let mut pending = Box::pin(until_cancelled(
+            Some(token.clone()),
+            stream::iter(vec![Ok(1)]).chain(stream::pending()),
+        ));
+        assert_eq!(Some(1), pending.try_next().await.unwrap());
+        token.cancel();
+        assert!(matches!(
+            pending.next().await,
+            Some(Err(FirestoreError::Cancelled))
+        ));
+        assert!(pending.next().await.is_none());
+
+        let result = cancellable(Some(&token), future::pending::<Result<()>>()).await;
+        assert!(matches!(result, Err(FirestoreError::Cancelled)));
+    }
+}
@@ -0,0 +1,271 @@
+//! the changes of the documents counted per collection path over a sliding window, to find the
+//! hot collections to cache or to shard. only the counts of the recent buckets are kept.
+//!
+//! ```ignore
+//! let counter = client
+//!     .change_stream(None, QueryBuilder::collection_group("posts".to_owned()).build())
+//!     .count_changes(Duration::from_secs(600))
+//!     .await?;
+//! // later
+//! for hot in counter.hottest(10) {
+//!     println!("{} {}", hot.collection_path, hot.changes);
+//! }
+//! ```
+
+use super::cancel::{until_cancelled, CancellationToken};
+use super::change_stream::ChangeStream;
+use super::error::{FirestoreError, Result};
+use futures::TryStreamExt;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// the number of the buckets the window of `ChangeCounter` is divided into.
+pub const CHANGE_COUNTER_BUCKETS: usize = 60;
+
+/// the changes of the documents in a collection within the window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathChangeCount {
+    /// e.g. "/users/user_1/posts"
+    pub collection_path: String,
+    pub changes: u64,
+}
+
+/// counts the changes of a `ChangeStream` per collection path until stopped or the stream
+/// ends. the clones share the counts.
+#[derive(Debug, Clone)]
+pub struct ChangeCounter {
+    counts: Arc<Mutex<Counts>>,
+    stop: CancellationToken,
+}
+
+impl ChangeCounter {
+    pub(crate) async fn start(change_stream: ChangeStream, window: Duration) -> Result<Self> {
+        let counts = Arc::new(Mutex::new(Counts::new(window, Instant::now())?));
+        let events = change_stream.start().await?;
+        let stop = CancellationToken::new();
+
+        let task_counts = counts.clone();
+        let mut events = Box::pin(until_cancelled(Some(stop.clone()), events));
+        tokio::spawn(async move {
+            loop {
+                match events.try_next().await {
+                    Ok(Some(event)) => task_counts
+                        .lock()
+                        .unwrap()
+                        .record(collection_path(&event.path), Instant::now()),
+                    Ok(None) | Err(FirestoreError::Cancelled) => break,
+                    Err(e) => {
+                        log::warn!("change counter stopped: {}", e);
+                        break;
+                    }
+                }
+            }
+            task_counts.lock().unwrap().running = false;
+        });
+        Ok(Self { counts, stop })
+    }
+
+    /// the `n` collections changed most within the window, the most changed first.
+    pub fn hottest(&self, n: usize) -> Vec<PathChangeCount> {
+        self.counts.lock().unwrap().hottest(n, Instant::now())
+    }
+
+    /// the changes of the documents in the collection (e.g. "/users") within the window.
+    pub fn changes(&self, collection_path: &str) -> u64 {
+        self.counts
+            .lock()
+            .unwrap()
+            .changes(collection_path, Instant::now())
+    }
+
+    /// false after `stop` or the end of the change stream (e.g. by an error, or the shutdown
+    /// of the client). the counts are kept.
+    pub fn is_running(&self) -> bool {
+        self.counts.lock().unwrap().running
+    }
+
+    pub fn stop(&self) {
+        self.stop.cancel();
+    }
+}
+
+impl ChangeStream {
+    /// count the changes per collection path over the sliding `window`, in the background.
+    pub async fn count_changes(self, window: Duration) -> Result<ChangeCounter> {
+        ChangeCounter::start(self, window).await
+    }
+}
+
+/// "/users/user_1/posts/post_1" => "/users/user_1/posts"
+fn collection_path(document_path: &str) -> &str {
+    document_path
+        .rfind('/')
+        .map_or(document_path, |i| &document_path[..i])
+}
+
+#[derive(Debug)]
+struct Counts {
+    started_at: Instant,
+    bucket_len: Duration,
+    /// the bucket of the last change recorded
+    current_bucket: u64,
+    paths: HashMap<String, PathBuckets>,
+    running: bool,
+}
+
+/// the ring of the counts of the last `CHANGE_COUNTER_BUCKETS` buckets up to `last_bucket`.
+#[derive(Debug)]
+struct PathBuckets {
+    last_bucket: u64,
+    counts: [u32; CHANGE_COUNTER_BUCKETS],
+}
+
+impl PathBuckets {
+    fn sum_at(&self, bucket: u64) -> u64 {
+        let oldest = (bucket + 1).saturating_sub(CHANGE_COUNTER_BUCKETS as u64);
+        (oldest..=bucket.min(self.last_bucket))
+            .filter(|b| b + (CHANGE_COUNTER_BUCKETS as u64) > self.last_bucket)
+            .map(|b| self.counts[ring_index(b)] as u64)
+            .sum()
+    }
+}
+
+fn ring_index(bucket: u64) -> usize {
+    (bucket % CHANGE_COUNTER_BUCKETS as u64) as usize
+}
+
+impl Counts {
+    fn new(window: Duration, started_at: Instant) -> Result<Self> {
+        let bucket_len = window / CHANGE_COUNTER_BUCKETS as u32;
+        if bucket_len.is_zero() {
+            return Err(FirestoreError::invalid_argument(format!(
+                "the window must be at least {} ns",
+                CHANGE_COUNTER_BUCKETS
+            )));
+        }
+        Ok(Self {
+            started_at,
+            bucket_len,
+            current_bucket: 0,
+            paths: HashMap::new(),
+            running: true,
+        })
+    }
+
+    fn bucket_at(&self, at: Instant) -> u64 {
+        (at.saturating_duration_since(self.started_at).as_nanos() / self.bucket_len.as_nanos())
+            as u64
+    }
+
+    fn record(&mut self, collection_path: &str, at: Instant) {
+        let bucket = self.bucket_at(at);
+        if bucket > self.current_bucket {
+            self.current_bucket = bucket;
+            // forget the collections not changed within the window
+            self.paths
+                .retain(|_, buckets| buckets.last_bucket + CHANGE_COUNTER_BUCKETS as u64 > bucket);
+        }
+        let buckets = self
+            .paths
+            .entry(collection_path.to_owned())
+            .or_insert_with(|| PathBuckets {
+                last_bucket: bucket,
+                counts: [0; CHANGE_COUNTER_BUCKETS],
+            });
+        // clear the buckets passed since the last change
+        let cleared =
+            (bucket.saturating_sub(buckets.last_bucket)).min(CHANGE_COUNTER_BUCKETS as u64);
+        for b in (bucket + 1 - cleared)..=bucket {
+            buckets.counts[ring_index(b)] = 0;
+        }
+        buckets.last_bucket = buckets.last_bucket.max(bucket);
+        let count = &mut buckets.counts[ring_index(bucket)];
+        *count = count.saturating_add(1);
+    }
+
+    fn changes(&self, collection_path: &str, at: Instant) -> u64 {
+        let bucket = self.bucket_at(at);
+        self.paths
+            .get(collection_path)
+            .map_or(0, |buckets| buckets.sum_at(bucket))
+    }
+
+    fn hottest(&self, n: usize, at: Instant) -> Vec<PathChangeCount> {
+        let bucket = self.bucket_at(at);
+        let mut counts: Vec<PathChangeCount> = self
+            .paths
+            .iter()
+            .map(|(collection_path, buckets)| PathChangeCount {
+                collection_path: collection_path.clone(),
+                changes: buckets.sum_at(bucket),
+            })
+            .filter(|count| count.changes > 0)
+            .collect();
+        counts.sort_by(|a, b| {
+            b.changes
+                .cmp(&a.changes)
+                .then_with(|| a.collection_path.cmp(&b.collection_path))
+        });
+        counts.truncate(n);
+        counts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn collection_path_test() {
+        assert_eq!("/users", collection_path("/users/user_1"));
+        assert_eq!(
+            "/users/user_1/posts",
+            collection_path("/users/user_1/posts/post_1")
+        );
+    }
+
+    #[test]
+    fn counts_test() {
+        let start = Instant::now();
+        // 1 second buckets
+        let mut counts = Counts::new(Duration::from_secs(60), start).unwrap();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        for _ in 0..3 {
+            counts.record("/users", at(0));
+        }
+        counts.record("/posts", at(10));
+        counts.record("/posts", at(30));
+        counts.record("/users/u1/likes", at(30));
+        assert_eq!(
+            vec![
+                PathChangeCount {
+                    collection_path: "/users".to_owned(),
+                    changes: 3
+                },
+                PathChangeCount {
+                    collection_path: "/posts".to_owned(),
+                    changes: 2
+                },
+            ],
+            counts.hottest(2, at(30))
+        );
+
+        // the changes at 0 are out of the window
+        assert_eq!(0, counts.changes("/users", at(60)));
+        assert_eq!(2, counts.changes("/posts", at(60)));
+        assert_eq!(1, counts.changes("/posts", at(70)));
+
+        // the ring is reused, and "/users" is forgotten
+        counts.record("/posts", at(65));
+        assert_eq!(3, counts.changes("/posts", at(65)));
+        assert_eq!(2, counts.changes("/posts", at(71)));
+        assert_eq!(2, counts.paths.len());
+        counts.record("/posts", at(200));
+        assert_eq!(1, counts.paths.len());
+        assert!(counts.hottest(10, at(300)).is_empty());
+
+        assert!(Counts::new(Duration::from_nanos(1), start).is_err());
+    }
+}
@@ -0,0 +1,399 @@
+use super::client::FirestoreClient;
+use super::request::fmt_document_path;
+use super::trigger::relative_document_path;
+use super::value::{FFields, FValue};
+
+use super::error::{FirestoreError, Result};
+use futures::{stream, Stream, TryStreamExt};
+use google_cloud_grpc_proto::firestore::v1::{
+    listen_response::ResponseType,
+    target::{self, query_target, ResumeType},
+    target_change::TargetChangeType,
+    Document, ListenResponse, StructuredQuery, Target,
+};
+use serde_json::Value as JValue;
+use std::time::SystemTime;
+
+const CHANGE_STREAM_TARGET_ID: i32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DocumentEventKind {
+    Created,
+    Updated,
+    Deleted,
+    /// the document no longer matches the query.
+    Removed,
+}
+
+impl DocumentEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DocumentEventKind::Created => "created",
+            DocumentEventKind::Updated => "updated",
+            DocumentEventKind::Deleted => "deleted",
+            DocumentEventKind::Removed => "removed",
+        }
+    }
+}
+
+/// a change of a document in `ChangeStream`, to relay into Pub/Sub or Kafka.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocumentEvent {
+    /// e.g. "/users/user_1"
+    pub path: String,
+    pub kind: DocumentEventKind,
+    /// the fields after the change. None on delete or remove.
+    pub fields: Option<FFields>,
+    /// the update time of the document, or the time of the delete if known.
+    pub update_time: Option<SystemTime>,
+    /// `ChangeStream::resume_from` this token replays the events after this one.
+    /// the token is of a whole snapshot, so the events but the last of a snapshot carry the
+    /// token of the previous snapshot, and resuming from them may replay some events twice.
+    pub resume_token: Vec<u8>,
+}
+
+impl DocumentEvent {
+    /// json object of the event, for the message payload. the resume token is in base64.
+    ///
+    /// ```ignore
+    /// {"path": "/users/u1", "kind": "updated", "fields": {..}, "update_time": "2021-..+00:00", "resume_token": "CgkI.."}
+    /// ```
+    pub fn to_json(&self) -> JValue {
+        let mut event = FFields::empty();
+        event.add("path", FValue::Str(self.path.clone()));
+        event.add("kind", FValue::Str(self.kind.as_str().to_owned()));
+        event.add(
+            "fields",
+            self.fields
+                .clone()
+                .map_or(FValue::NullValue, |fields| fields.into()),
+        );
+        event.add(
+            "update_time",
+            self.update_time
+                .map_or(FValue::NullValue, FValue::Timestamp),
+        );
+        event.add(
+            "resume_token",
+            FValue::Str(base64::encode(&self.resume_token)),
+        );
+        JValue::from(event)
+    }
+}
+
+/// the resume token of `DocumentEvent::to_json`.
+pub fn decode_resume_token(resume_token: &str) -> Result<Vec<u8>> {
+    base64::decode(resume_token)
+        .map_err(|e| FirestoreError::invalid_argument(format!("invalid resume token: {}", e)))
+}
+
+/// the changes of the documents of a query as `DocumentEvent`s, with Listen.
+///
+/// the documents existing at the start are not reported. to continue from where the previous
+/// stream stopped, pass the resume token of the last event relayed to `resume_from`.
+/// the server may not be able to replay from a token too old (e.g. older than 30 minutes).
+///
+/// ```ignore
+/// let events = client
+///     .change_stream(None, QueryBuilder::collection("users".to_owned(), false).build())
+///     .resume_from(last_token)
+///     .start()
+///     .await?;
+/// while let Some(event) = events.try_next().await? {
+///     publisher.publish(event.to_json().to_string()).await?;
+///     last_token = event.resume_token;
+/// }
+/// ```
+pub struct ChangeStream {
+    client: FirestoreClient,
+    parent_path: Option<String>,
+    query: StructuredQuery,
+    resume_token: Option<Vec<u8>>,
+}
+
+impl ChangeStream {
+    pub(crate) fn new(
+        client: FirestoreClient,
+        parent_path: Option<String>,
+        query: StructuredQuery,
+    ) -> Self {
+        Self {
+            client,
+            parent_path,
+            query,
+            resume_token: None,
+        }
+    }
+
+    /// replay the changes after the event of the token. some of the changes before the token
+    /// may be replayed too, see `DocumentEvent::resume_token`.
+    pub fn resume_from(self, resume_token: Vec<u8>) -> Self {
+        Self {
+            resume_token: Some(resume_token),
+            ..self
+        }
+    }
+
+    /// listen to the query. the stream continues until the server closes it.
+    pub async fn start(self) -> Result<impl Stream<Item = Result<DocumentEvent>>> {
+        let target = Target {
+            target_id: CHANGE_STREAM_TARGET_ID,
+            once: false,
+            target_type: Some(target::TargetType::Query(target::QueryTarget {
+                parent: fmt_document_path(
+                    self.client.project_id(),
+                    self.parent_path.unwrap_or_default(),
+                ),
+                query_type: Some(query_target::QueryType::StructuredQuery(self.query)),
+            })),
+            resume_type: self.resume_token.clone().map(ResumeType::ResumeToken),
+        };
+        let mut state = ChangeState::new(self.resume_token);
+        let responses = self.client.listen(vec![target]).await?;
+        Ok(responses
+            .map_ok(move |response| stream::iter(state.apply(response).into_iter().map(Ok)))
+            .try_flatten())
+    }
+}
+
+/// the events are held until the server sends the resume token of the snapshot including them.
+struct ChangeState {
+    /// false while the server sends the documents existing at the start (or at a reset).
+    current: bool,
+    pending: Vec<DocumentEvent>,
+    /// the token of the last snapshot.
+    resume_token: Vec<u8>,
+}
+
+impl ChangeState {
+    fn new(resume_token: Option<Vec<u8>>) -> Self {
+        Self {
+            // the server sends only the changes after the token
+            current: resume_token.is_some(),
+            pending: Vec::new(),
+            resume_token: resume_token.unwrap_or_default(),
+        }
+    }
+
+    fn apply(&mut self, response: ListenResponse) -> Vec<DocumentEvent> {
+        match response.response_type {
+            Some(ResponseType::TargetChange(change)) => {
+                match TargetChangeType::from_i32(change.target_change_type) {
+                    Some(TargetChangeType::Current) => self.current = true,
+                    Some(TargetChangeType::Reset) => {
+                        self.current = false;
+                        self.pending.clear();
+                    }
+                    _ => {}
+                }
+                // the token is consistent for all the targets only if target_ids is empty
+                if !change.resume_token.is_empty() && change.target_ids.is_empty() {
+                    let previous_token =
+                        std::mem::replace(&mut self.resume_token, change.resume_token);
+                    let mut events = std::mem::take(&mut self.pending);
+                    let last = events.len().saturating_sub(1);
+                    for (i, event) in events.iter_mut().enumerate() {
+                        event.resume_token = if i == last {
+                            self.resume_token.clone()
+                        } else {
+                            previous_token.clone()
+                        };
+                    }
+                    return events;
+                }
+            }
+            Some(ResponseType::DocumentChange(change)) => {
+                if let Some(document) = change.document {
+                    if self.current {
+                        let event = if change.target_ids.contains(&CHANGE_STREAM_TARGET_ID) {
+                            changed_event(document)
+                        } else if change.removed_target_ids.contains(&CHANGE_STREAM_TARGET_ID) {
+                            // the document no longer matches the query
+                            removed_event(document)
+                        } else {
+                            return vec![];
+                        };
+                        self.pending.push(event);
+                    }
+                }
+            }
+            Some(ResponseType::DocumentDelete(delete)) => {
+                self.push_deleted(&delete.document, delete.read_time.map(SystemTime::from));
+            }
+            Some(ResponseType::DocumentRemove(remove)) => {
+                self.push_deleted(&remove.document, remove.read_time.map(SystemTime::from));
+            }
+            Some(ResponseType::Filter(_)) | None => {}
+        }
+        vec![]
+    }
+
+    fn push_deleted(&mut self, name: &str, read_time: Option<SystemTime>) {
+        if self.current {
+            self.pending.push(DocumentEvent {
+                path: relative_document_path(name),
+                kind: DocumentEventKind::Deleted,
+                fields: None,
+                update_time: read_time,
+                resume_token: vec![],
+            });
+        }
+    }
+}
+
+/// created if the document has not been updated since the creation.
+fn changed_event(document: Document) -> DocumentEvent {
+    let kind = if document.create_time.is_some() && document.create_time == document.update_time {
+        DocumentEventKind::Created
+    } else {
+        DocumentEventKind::Updated
+    };
+    let path = relative_document_path(&document.name);
+    let update_time = document.update_time.clone().map(SystemTime::from);
+    DocumentEvent {
+        path,
+        kind,
+        fields: Some(FFields::from_grpc_doc(document)),
+        update_time,
+        resume_token: vec![],
+    }
+}
+
+fn removed_event(document: Document) -> DocumentEvent {
+    DocumentEvent {
+        path: relative_document_path(&document.name),
+        kind: DocumentEventKind::Removed,
+        fields: None,
+        update_time: document.update_time.map(SystemTime::from),
+        resume_token: vec![],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use google_cloud_grpc_proto::firestore::v1::{DocumentChange, DocumentDelete, TargetChange};
+    use google_cloud_grpc_proto::prost_types::Timestamp;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn doc(name: &str, create_seconds: i64, update_seconds: i64) -> Document {
+        let mut fields = HashMap::new();
+        fields.insert("name".to_owned(), FValue::from("taco").to_grpc_value());
+        Document {
+            name: format!("projects/p/databases/(default)/documents{}", name),
+            fields,
+            create_time: Some(Timestamp {
+                seconds: create_seconds,
+                nanos: 0,
+            }),
+            update_time: Some(Timestamp {
+                seconds: update_seconds,
+                nanos: 0,
+            }),
+        }
+    }
+
+    fn change(document: Document) -> ListenResponse {
+        ListenResponse {
+            response_type: Some(ResponseType::DocumentChange(DocumentChange {
+                document: Some(document),
+                target_ids: vec![CHANGE_STREAM_TARGET_ID],
+                removed_target_ids: vec![],
+            })),
+        }
+    }
+
+    fn target_change(change_type: TargetChangeType, resume_token: &[u8]) -> ListenResponse {
+        ListenResponse {
+            response_type: Some(ResponseType::TargetChange(TargetChange {
+                target_change_type: change_type as i32,
+                target_ids: vec![],
+                cause: None,
+                resume_token: resume_token.to_vec(),
+                read_time: None,
+            })),
+        }
+    }
+
+    #[test]
+    fn change_state_test() {
+        let mut state = ChangeState::new(None);
+
+        // initial snapshot
+        assert!(state.apply(change(doc("/users/u1", 10, 20))).is_empty());
+        assert!(state
+            .apply(target_change(TargetChangeType::Current, b"t1"))
+            .is_empty());
+
+        assert!(state.apply(change(doc("/users/u2", 30, 30))).is_empty());
+        assert!(state.apply(change(doc("/users/u1", 10, 30))).is_empty());
+        let events = state.apply(target_change(TargetChangeType::NoChange, b"t2"));
+        assert_eq!(2, events.len());
+        assert_eq!("/users/u2", events[0].path);
+        assert_eq!(DocumentEventKind::Created, events[0].kind);
+        assert_eq!(DocumentEventKind::Updated, events[1].kind);
+        // resuming from the first event replays the second
+        assert_eq!(b"t1".to_vec(), events[0].resume_token);
+        assert_eq!(b"t2".to_vec(), events[1].resume_token);
+
+        state.apply(ListenResponse {
+            response_type: Some(ResponseType::DocumentChange(DocumentChange {
+                document: Some(doc("/users/u2", 30, 40)),
+                target_ids: vec![],
+                removed_target_ids: vec![CHANGE_STREAM_TARGET_ID],
+            })),
+        });
+        let events = state.apply(target_change(TargetChangeType::NoChange, b"t2_1"));
+        assert_eq!(DocumentEventKind::Removed, events[0].kind);
+        assert_eq!(None, events[0].fields);
+
+        state.apply(ListenResponse {
+            response_type: Some(ResponseType::DocumentDelete(DocumentDelete {
+                document: doc("/users/u1", 0, 0).name,
+                removed_target_ids: vec![CHANGE_STREAM_TARGET_ID],
+                read_time: None,
+            })),
+        });
+        let events = state.apply(target_change(TargetChangeType::NoChange, b"t3"));
+        assert_eq!(DocumentEventKind::Deleted, events[0].kind);
+        assert_eq!(None, events[0].fields);
+
+        // the documents are sent again after a reset
+        state.apply(target_change(TargetChangeType::Reset, b""));
+        state.apply(change(doc("/users/u2", 30, 30)));
+        assert!(state
+            .apply(target_change(TargetChangeType::Current, b"t4"))
+            .is_empty());
+    }
+
+    #[test]
+    fn resumed_change_state_test() {
+        let mut state = ChangeState::new(Some(b"t4".to_vec()));
+        state.apply(change(doc("/users/u3", 40, 40)));
+        state.apply(change(doc("/users/u4", 40, 40)));
+        let events = state.apply(target_change(TargetChangeType::Current, b"t5"));
+        assert_eq!(2, events.len());
+        assert_eq!("/users/u3", events[0].path);
+        assert_eq!(b"t4".to_vec(), events[0].resume_token);
+        assert_eq!(b"t5".to_vec(), events[1].resume_token);
+    }
+
+    #[test]
+    fn event_to_json_test() {
+        let event = changed_event(doc("/users/u1", 0, 1));
+        let event = DocumentEvent {
+            resume_token: b"token".to_vec(),
+            ..event
+        };
+        let json = event.to_json();
+        assert_eq!(json!("/users/u1"), json["path"]);
+        assert_eq!(json!("updated"), json["kind"]);
+        assert_eq!(json!({"name": "taco"}), json["fields"]);
+        assert_eq!(json!("1970-01-01T00:00:01+00:00"), json["update_time"]);
+        assert_eq!(
+            b"token".to_vec(),
+            decode_resume_token(json["resume_token"].as_str().unwrap()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,371 @@
+use super::client::FirestoreClient;
+use super::query::QueryBuilder;
+use super::trigger::relative_document_path;
+use super::value::{timestamp::to_seconds_nanos, FFields, FMap, FValue};
+
+use super::error::Result;
+use futures::{stream, Stream, TryStreamExt};
+use google_cloud_grpc_proto::firestore::v1::Document;
+use ring::digest::{Context, SHA256};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// the documents read at once to compute the checksums.
+pub const CHECKSUM_PAGE_SIZE: i32 = 300;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DocumentChecksum {
+    /// e.g. "/users/u1"
+    pub document_path: String,
+    /// sha-256 of the canonicalized fields in hex.
+    pub checksum: String,
+}
+
+impl DocumentChecksum {
+    /// the checksum of the fields of the document at the path, e.g. of a document of an export.
+    pub fn new<P: Into<String>>(document_path: P, fields: &FFields) -> Self {
+        Self {
+            document_path: document_path.into(),
+            checksum: fields_checksum(fields),
+        }
+    }
+
+    fn from_document(document: Document) -> Self {
+        let document_path = relative_document_path(&document.name);
+        Self::new(document_path, &FFields::from_grpc_doc(document))
+    }
+}
+
+/// the checksums of the exported documents, to validate a backup without restoring it.
+/// it can be stored along with the backup with serde.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChecksumManifest {
+    /// in the order of the document paths.
+    pub documents: Vec<DocumentChecksum>,
+    /// sha-256 of the document paths and the checksums in hex.
+    pub aggregate: String,
+}
+
+impl ChecksumManifest {
+    pub fn new(mut documents: Vec<DocumentChecksum>) -> Self {
+        documents.sort_by(|a, b| a.document_path.cmp(&b.document_path));
+        let mut context = Context::new(&SHA256);
+        for document in documents.iter() {
+            write_str(&mut context, &document.document_path);
+            write_str(&mut context, &document.checksum);
+        }
+        Self {
+            documents,
+            aggregate: hex(context.finish().as_ref()),
+        }
+    }
+
+    /// the manifest of the documents supplied, e.g. decoded from the export files, to validate
+    /// the backup without reading the database.
+    ///
+    /// ```ignore
+    /// let exported = ChecksumManifest::from_documents(read_export(path)?);
+    /// assert!(manifest.verify(&exported).is_valid());
+    /// ```
+    pub fn from_documents<I: IntoIterator<Item = Document>>(documents: I) -> Self {
+        Self::new(
+            documents
+                .into_iter()
+                .map(DocumentChecksum::from_document)
+                .collect(),
+        )
+    }
+
+    /// `from_documents` of a stream of the documents.
+    pub async fn from_stream<S>(documents: S) -> Result<Self>
+    where
+        S: Stream<Item = Result<Document>>,
+    {
+        let documents = documents
+            .map_ok(DocumentChecksum::from_document)
+            .try_collect()
+            .await?;
+        Ok(Self::new(documents))
+    }
+
+    /// compare the documents in `actual` with this manifest.
+    pub fn verify(&self, actual: &ChecksumManifest) -> ChecksumVerifyReport {
+        let mut report = ChecksumVerifyReport {
+            checked_num: actual.documents.len(),
+            ..Default::default()
+        };
+        if self.aggregate == actual.aggregate {
+            return report;
+        }
+
+        let mut expected: BTreeMap<&str, &str> = self
+            .documents
+            .iter()
+            .map(|d| (d.document_path.as_str(), d.checksum.as_str()))
+            .collect();
+        for document in actual.documents.iter() {
+            match expected.remove(document.document_path.as_str()) {
+                Some(checksum) if checksum == document.checksum => {}
+                Some(_) => report.mismatched.push(document.document_path.clone()),
+                None => report.unexpected.push(document.document_path.clone()),
+            }
+        }
+        report.missing = expected.keys().map(|path| path.to_string()).collect();
+        report
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ChecksumVerifyReport {
+    pub checked_num: usize,
+    /// the documents whose fields differ from the manifest.
+    pub mismatched: Vec<String>,
+    /// the documents in the manifest but not read.
+    pub missing: Vec<String>,
+    /// the documents read but not in the manifest.
+    pub unexpected: Vec<String>,
+}
+
+impl ChecksumVerifyReport {
+    pub fn is_valid(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty() && self.unexpected.is_empty()
+    }
+}
+
+/// compute the checksums of the documents of `source` page by page,
+/// or re-read them and compare with a manifest.
+///
+/// ```ignore
+/// let manifest = client
+///     .data_checksum(None, QueryBuilder::collection("users".to_owned(), false))
+///     .manifest()
+///     .await?;
+/// // after restoring the backup
+/// let report = restored_client
+///     .data_checksum(None, QueryBuilder::collection("users".to_owned(), false))
+///     .verify(&manifest)
+///     .await?;
+/// assert!(report.is_valid());
+/// ```
+pub struct DataChecksum {
+    client: FirestoreClient,
+    parent_path: Option<String>,
+    source: QueryBuilder,
+    page_size: i32,
+}
+
+impl DataChecksum {
+    pub(crate) fn new(
+        client: FirestoreClient,
+        parent_path: Option<String>,
+        source: QueryBuilder,
+    ) -> Self {
+        Self {
+            // the checksums are of the whole documents
+            client: client.without_default_field_masks(),
+            parent_path,
+            source,
+            page_size: CHECKSUM_PAGE_SIZE,
+        }
+    }
+
+    pub fn with_page_size(self, page_size: i32) -> Self {
+        Self {
+            page_size: page_size.max(1),
+            ..self
+        }
+    }
+
+    pub async fn manifest(self) -> Result<ChecksumManifest> {
+        let pages = self
+            .client
+            .paginate_query(self.parent_path, self.source, self.page_size);
+        ChecksumManifest::from_stream(
+            pages
+                .map_ok(|page| stream::iter(page.into_iter().map(Ok)))
+                .try_flatten(),
+        )
+        .await
+    }
+
+    /// re-read the documents and compare them with `expected`.
+    pub async fn verify(self, expected: &ChecksumManifest) -> Result<ChecksumVerifyReport> {
+        let actual = self.manifest().await?;
+        Ok(expected.verify(&actual))
+    }
+}
+
+/// sha-256 of the canonicalized fields in hex. independent of the order of the map entries.
+pub fn fields_checksum(fields: &FFields) -> String {
+    let mut context = Context::new(&SHA256);
+    let mut keys: Vec<&String> = fields.keys().collect();
+    keys.sort();
+    write_len(&mut context, keys.len());
+    for key in keys {
+        write_str(&mut context, key);
+        if let Some(value) = fields.get(key) {
+            write_value(&mut context, value);
+        }
+    }
+    hex(context.finish().as_ref())
+}
+
+fn write_value(context: &mut Context, value: &FValue) {
+    match value {
+        FValue::NullValue => context.update(&[0]),
+        FValue::Bool(v) => context.update(&[1, *v as u8]),
+        FValue::Int(v) => {
+            context.update(&[2]);
+            context.update(&v.to_be_bytes());
+        }
+        FValue::Double(v) => {
+            context.update(&[3]);
+            let bits = if v.is_nan() {
+                f64::NAN.to_bits()
+            } else {
+                v.to_bits()
+            };
+            context.update(&bits.to_be_bytes());
+        }
+        FValue::Str(v) => {
+            context.update(&[4]);
+            write_str(context, v);
+        }
+        FValue::Bytes(v) => {
+            context.update(&[5]);
+            write_len(context, v.len());
+            context.update(v);
+        }
+        FValue::Timestamp(v) => {
+            let (seconds, nanos) = to_seconds_nanos(*v);
+            context.update(&[6]);
+            context.update(&seconds.to_be_bytes());
+            context.update(&nanos.to_be_bytes());
+        }
+        FValue::Array(vs) => {
+            context.update(&[7]);
+            write_len(context, vs.len());
+            for v in vs {
+                write_value(context, v);
+            }
+        }
+        FValue::Map(m) => {
+            context.update(&[8]);
+            write_map(context, m);
+        }
+        FValue::Vector(vs) => {
+            context.update(&[9]);
+            write_len(context, vs.len());
+            for v in vs {
+                write_value(context, &FValue::Double(*v));
+            }
+        }
+    }
+}
+
+fn write_map(context: &mut Context, m: &FMap<FValue>) {
+    let mut entries: Vec<(&String, &FValue)> = m.iter().collect();
+    entries.sort_by_key(|(k, _)| *k);
+    write_len(context, entries.len());
+    for (k, v) in entries {
+        write_str(context, k);
+        write_value(context, v);
+    }
+}
+
+fn write_str(context: &mut Context, s: &str) {
+    write_len(context, s.len());
+    context.update(s.as_bytes());
+}
+
+fn write_len(context: &mut Context, len: usize) {
+    context.update(&(len as u64).to_be_bytes());
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fields(values: Vec<(&str, FValue)>) -> FFields {
+        FFields::new(values.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+    }
+
+    fn document(path: &str, fields: &FFields) -> DocumentChecksum {
+        DocumentChecksum::new(path, fields)
+    }
+
+    #[test]
+    fn fields_checksum_test() {
+        let a = fields(vec![("name", "taco".into()), ("age", 20i64.into())]);
+        let b = fields(vec![("age", 20i64.into()), ("name", "taco".into())]);
+        assert_eq!(fields_checksum(&a), fields_checksum(&b));
+
+        // the types are distinguished
+        let c = fields(vec![("name", "taco".into()), ("age", 20f64.into())]);
+        assert_ne!(fields_checksum(&a), fields_checksum(&c));
+
+        // the boundaries of the strings are distinguished
+        let d = fields(vec![("ab", "c".into())]);
+        let e = fields(vec![("a", "bc".into())]);
+        assert_ne!(fields_checksum(&d), fields_checksum(&e));
+    }
+
+    #[test]
+    fn verify_test() {
+        let a = fields(vec![("name", "taco".into())]);
+        let b = fields(vec![("name", "burrito".into())]);
+        let expected =
+            ChecksumManifest::new(vec![document("/users/u2", &b), document("/users/u1", &a)]);
+        assert_eq!("/users/u1", expected.documents[0].document_path);
+
+        let same =
+            ChecksumManifest::new(vec![document("/users/u1", &a), document("/users/u2", &b)]);
+        assert_eq!(expected.aggregate, same.aggregate);
+        assert!(expected.verify(&same).is_valid());
+
+        let actual =
+            ChecksumManifest::new(vec![document("/users/u1", &b), document("/users/u3", &a)]);
+        let report = expected.verify(&actual);
+        assert!(!report.is_valid());
+        assert_eq!(2, report.checked_num);
+        assert_eq!(vec!["/users/u1".to_owned()], report.mismatched);
+        assert_eq!(vec!["/users/u2".to_owned()], report.missing);
+        assert_eq!(vec!["/users/u3".to_owned()], report.unexpected);
+    }
+
+    #[tokio::test]
+    async fn supplied_documents_test() {
+        let a = fields(vec![("name", "taco".into())]);
+        let exported = |path: &str, fields: &FFields| Document {
+            name: format!("projects/p/databases/(default)/documents{}", path),
+            fields: fields.clone().to_grpc_fields(),
+            ..Default::default()
+        };
+        let documents = vec![exported("/users/u2", &a), exported("/users/u1", &a)];
+        let expected =
+            ChecksumManifest::new(vec![document("/users/u1", &a), document("/users/u2", &a)]);
+
+        let manifest = ChecksumManifest::from_documents(documents.clone());
+        assert_eq!(expected, manifest);
+        let streamed = ChecksumManifest::from_stream(stream::iter(documents.into_iter().map(Ok)))
+            .await
+            .unwrap();
+        assert!(expected.verify(&streamed).is_valid());
+    }
+
+    #[tokio::test]
+    async fn without_default_field_masks_test() {
+        use crate::firestore::DefaultFieldMasks;
+
+        let client = FirestoreClient::offline("p")
+            .with_default_field_masks(DefaultFieldMasks::new().with_mask("users", vec!["name"]));
+        let checksum =
+            client.data_checksum(None, QueryBuilder::collection("users".to_owned(), false));
+        assert!(checksum.client.default_field_masks().is_empty());
+        assert!(!client.default_field_masks().is_empty());
+    }
+}
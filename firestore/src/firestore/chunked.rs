@@ -0,0 +1,406 @@
+use super::checksum::hex;
+use super::client::FirestoreClient;
+use super::request::{DocumentWriteOperation, ReadConsistency};
+use super::value::{FFields, FMap, FValue};
+
+use super::error::{FirestoreError, Result};
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::HashMap;
+
+/// the max bytes of a chunk by default. leaves room in the 1 MiB document for the hash.
+pub const DEFAULT_CHUNK_BYTES: usize = 900 * 1024;
+
+/// the collection of the chunks under the document. e.g. "/files/f1/chunks/{generation}-0"
+pub const CHUNKS_COLLECTION_ID: &str = "chunks";
+
+/// the chunks written in a batch, to keep the request under the 10 MiB limit.
+const CHUNK_WRITE_BATCH_NUM: usize = 8;
+
+const MANIFEST_MARKER: &str = "__chunked__";
+const MANIFEST_KIND: &str = "kind";
+const MANIFEST_LENGTH: &str = "length";
+const MANIFEST_CHUNK_NUM: &str = "chunk_num";
+const MANIFEST_SHA256: &str = "sha256";
+const MANIFEST_GENERATION: &str = "generation";
+const CHUNK_DATA: &str = "data";
+const CHUNK_SHA256: &str = "sha256";
+
+/// a `Str` or `Bytes` field which may exceed the size limit of a document.
+///
+/// the value larger than the chunk size is written into the companion documents
+/// `{document_path}/chunks/{generation}-{n}`, and the field keeps a manifest with the
+/// generation and the sha256 hashes.
+/// `get` reassembles the value and verifies the hashes. the documents not written by
+/// `write` are read as they are.
+///
+/// ```ignore
+/// let mut attachment = client.chunked_field("body");
+/// attachment.write("/mails/m1".to_owned(), fields).await?;
+/// let fields = attachment.get("/mails/m1".to_owned()).await?;
+/// ```
+pub struct ChunkedField {
+    client: FirestoreClient,
+    field: String,
+    chunk_bytes: usize,
+}
+
+impl ChunkedField {
+    pub(crate) fn new(client: FirestoreClient, field: String) -> Self {
+        Self {
+            client,
+            field,
+            chunk_bytes: DEFAULT_CHUNK_BYTES,
+        }
+    }
+
+    pub fn with_chunk_bytes(self, chunk_bytes: usize) -> Self {
+        Self {
+            chunk_bytes: chunk_bytes.max(1),
+            ..self
+        }
+    }
+
+    /// overwrite the document with the fields, splitting the field if it's larger than
+    /// the chunk size. returns the number of the chunks.
+    ///
+    /// the chunks are written under a new generation before the document, so the manifest
+    /// is switched last and the chunks of the previous value are never overwritten. the
+    /// chunks of the previous generation are deleted after. if interrupted, the document
+    /// keeps the previous value, and the chunks of the new generation may be left unreferenced.
+    /// fails without switching the manifest if any chunk failed to write.
+    pub async fn write(&mut self, document_path: String, mut fields: FFields) -> Result<usize> {
+        let (kind, bytes) = match fields.get(&self.field) {
+            Some(FValue::Str(s)) => (ChunkKind::Str, s.as_bytes().to_vec()),
+            Some(FValue::Bytes(b)) => (ChunkKind::Bytes, b.clone()),
+            other => {
+                return Err(FirestoreError::invalid_argument(format!(
+                    "chunked field {} must be a string or bytes: {:?}",
+                    self.field, other
+                )))
+            }
+        };
+        let previous_manifest = self.previous_manifest(&document_path).await?;
+
+        let chunks = split_into_chunks(&bytes, self.chunk_bytes);
+        let chunk_num = if chunks.len() > 1 { chunks.len() } else { 0 };
+        if chunk_num > 0 {
+            let generation = new_generation();
+            let chunk_operations: Vec<DocumentWriteOperation> = chunks
+                .iter()
+                .enumerate()
+                .map(|(index, chunk)| {
+                    let mut chunk_fields = FFields::empty();
+                    chunk_fields.add(CHUNK_DATA, FValue::Bytes(chunk.to_vec()));
+                    chunk_fields.add(CHUNK_SHA256, sha256_hex(chunk));
+                    DocumentWriteOperation::new_upsert(
+                        chunk_path(&document_path, &generation, index),
+                        chunk_fields,
+                    )
+                })
+                .collect();
+            // the manifest must not refer to the chunks failed to write
+            for each_operations in chunk_operations.chunks(CHUNK_WRITE_BATCH_NUM) {
+                for result in self
+                    .client
+                    .batch_write_with_status(each_operations.to_vec())
+                    .await?
+                {
+                    result?;
+                }
+            }
+            fields.add(
+                self.field.clone(),
+                manifest(kind, &bytes, chunk_num, &generation),
+            );
+        }
+
+        self.client
+            .commit(
+                vec![DocumentWriteOperation::new_upsert(
+                    document_path.clone(),
+                    fields,
+                )],
+                None,
+            )
+            .await?;
+
+        if let Some(previous) = previous_manifest {
+            let stale = (0..previous.chunk_num)
+                .map(|index| {
+                    DocumentWriteOperation::new_delete(previous.chunk_path(&document_path, index))
+                })
+                .collect();
+            self.client.large_batch_write(stale).await?;
+        }
+        Ok(chunk_num)
+    }
+
+    /// the fields of the document with the chunked field reassembled. None if not found.
+    ///
+    /// the document and the chunks are read in a read-only transaction, so the chunks
+    /// of the generation deleted by a concurrent `write` are still read.
+    pub async fn get(&mut self, document_path: String) -> Result<Option<FFields>> {
+        let tx = self
+            .client
+            .begin_read_only_transaction(self.client.read_time())
+            .await?;
+        let mut fields = match self
+            .client
+            .get_document(
+                document_path.clone(),
+                None,
+                ReadConsistency::Transaction(tx.clone()),
+            )
+            .await?
+        {
+            Some(document) => FFields::from_grpc_doc(document),
+            None => return Ok(None),
+        };
+        let manifest = match fields.get(&self.field).and_then(Manifest::parse) {
+            Some(manifest) => manifest,
+            None => return Ok(Some(fields)),
+        };
+
+        let chunk_paths = (0..manifest.chunk_num)
+            .map(|index| manifest.chunk_path(&document_path, index))
+            .collect();
+        let mut chunks = HashMap::<usize, FFields>::new();
+        let missing = self
+            .client
+            .batch_get_documents(
+                chunk_paths,
+                None,
+                ReadConsistency::Transaction(tx),
+                |document| {
+                    let index = chunk_index(&document.name)?;
+                    chunks.insert(index, FFields::from_grpc_doc(document));
+                    Ok(())
+                },
+            )
+            .await?;
+        if !missing.is_empty() {
+            return Err(FirestoreError::Integrity(format!(
+                "missing chunks of {}: {:?}",
+                document_path, missing
+            )));
+        }
+
+        fields.add(self.field.clone(), manifest.reassemble(chunks)?);
+        Ok(Some(fields))
+    }
+
+    async fn previous_manifest(&mut self, document_path: &str) -> Result<Option<Manifest>> {
+        Ok(self
+            .client
+            .get_document(
+                document_path.to_owned(),
+                Some(vec![self.field.clone()]),
+                None,
+            )
+            .await?
+            .map(FFields::from_grpc_doc)
+            .and_then(|fields| fields.get(&self.field).and_then(Manifest::parse)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChunkKind {
+    Str,
+    Bytes,
+}
+
+impl ChunkKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ChunkKind::Str => "str",
+            ChunkKind::Bytes => "bytes",
+        }
+    }
+}
+
+/// the value of the chunked field in the document.
+#[derive(Debug, PartialEq)]
+struct Manifest {
+    kind: ChunkKind,
+    length: usize,
+    chunk_num: usize,
+    sha256: String,
+    /// the chunks of each write have the ids of a new generation
+    generation: String,
+}
+
+fn manifest(kind: ChunkKind, bytes: &[u8], chunk_num: usize, generation: &str) -> FValue {
+    let mut m = FMap::new();
+    m.insert(MANIFEST_MARKER.to_owned(), FValue::Bool(true));
+    m.insert(MANIFEST_KIND.to_owned(), FValue::from(kind.as_str()));
+    m.insert(MANIFEST_LENGTH.to_owned(), FValue::Int(bytes.len() as i64));
+    m.insert(MANIFEST_CHUNK_NUM.to_owned(), FValue::Int(chunk_num as i64));
+    m.insert(MANIFEST_SHA256.to_owned(), FValue::Str(sha256_hex(bytes)));
+    m.insert(
+        MANIFEST_GENERATION.to_owned(),
+        FValue::Str(generation.to_owned()),
+    );
+    FValue::Map(m)
+}
+
+impl Manifest {
+    /// None if the value is not a manifest. (i.e. not chunked)
+    fn parse(value: &FValue) -> Option<Self> {
+        let m = match value {
+            FValue::Map(m) if m.get(MANIFEST_MARKER) == Some(&FValue::Bool(true)) => m,
+            _ => return None,
+        };
+        let int = |key: &str| match m.get(key) {
+            Some(FValue::Int(v)) if *v >= 0 => Some(*v as usize),
+            _ => None,
+        };
+        let kind = match m.get(MANIFEST_KIND) {
+            Some(FValue::Str(kind)) if kind == "str" => ChunkKind::Str,
+            Some(FValue::Str(kind)) if kind == "bytes" => ChunkKind::Bytes,
+            _ => return None,
+        };
+        let string = |key: &str| match m.get(key) {
+            Some(FValue::Str(v)) => Some(v.clone()),
+            _ => None,
+        };
+        Some(Manifest {
+            kind,
+            length: int(MANIFEST_LENGTH)?,
+            chunk_num: int(MANIFEST_CHUNK_NUM)?,
+            sha256: string(MANIFEST_SHA256)?,
+            generation: string(MANIFEST_GENERATION)?,
+        })
+    }
+
+    fn chunk_path(&self, document_path: &str, index: usize) -> String {
+        chunk_path(document_path, &self.generation, index)
+    }
+
+    /// concat the chunks in the order of the indices and verify the hashes.
+    fn reassemble(&self, mut chunks: HashMap<usize, FFields>) -> Result<FValue> {
+        let mut bytes = Vec::with_capacity(self.length);
+        for index in 0..self.chunk_num {
+            let chunk = chunks
+                .remove(&index)
+                .ok_or_else(|| FirestoreError::Integrity(format!("missing chunk {}", index)))?;
+            let (data, sha256) = match (chunk.get(CHUNK_DATA), chunk.get(CHUNK_SHA256)) {
+                (Some(FValue::Bytes(data)), Some(FValue::Str(sha256))) => (data, sha256),
+                _ => {
+                    return Err(FirestoreError::Integrity(format!(
+                        "malformed chunk {}",
+                        index
+                    )))
+                }
+            };
+            if &sha256_hex(data) != sha256 {
+                return Err(FirestoreError::Integrity(format!(
+                    "hash mismatch of chunk {}",
+                    index
+                )));
+            }
+            bytes.extend_from_slice(data);
+        }
+        if bytes.len() != self.length || sha256_hex(&bytes) != self.sha256 {
+            return Err(FirestoreError::Integrity(
+                "hash mismatch of the reassembled value".to_owned(),
+            ));
+        }
+
+        match self.kind {
+            ChunkKind::Bytes => Ok(FValue::Bytes(bytes)),
+            ChunkKind::Str => String::from_utf8(bytes)
+                .map(FValue::Str)
+                .map_err(|e| FirestoreError::Integrity(format!("invalid utf8: {}", e))),
+        }
+    }
+}
+
+/// at least one chunk, even if empty.
+fn split_into_chunks(bytes: &[u8], chunk_bytes: usize) -> Vec<&[u8]> {
+    if bytes.is_empty() {
+        return vec![bytes];
+    }
+    bytes.chunks(chunk_bytes).collect()
+}
+
+/// 128 random bits in hex
+fn new_generation() -> String {
+    let mut bytes = [0u8; 16];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("failed to generate the random bytes");
+    hex(&bytes)
+}
+
+fn chunk_path(document_path: &str, generation: &str, index: usize) -> String {
+    format!(
+        "{}/{}/{}-{}",
+        document_path, CHUNKS_COLLECTION_ID, generation, index
+    )
+}
+
+/// "projects/p/databases/(default)/documents/files/f1/chunks/{generation}-3" => 3
+fn chunk_index(name: &str) -> anyhow::Result<usize> {
+    name.rsplit(['/', '-'])
+        .next()
+        .and_then(|index| index.parse().ok())
+        .ok_or_else(|| anyhow::anyhow!("invalid chunk name {}", name))
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    hex(digest(&SHA256, bytes).as_ref())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn chunk_fields(data: &[u8]) -> FFields {
+        let mut fields = FFields::empty();
+        fields.add(CHUNK_DATA, FValue::Bytes(data.to_vec()));
+        fields.add(CHUNK_SHA256, sha256_hex(data));
+        fields
+    }
+
+    #[test]
+    fn reassemble_test() {
+        let value = "chunked value".as_bytes();
+        let chunks = split_into_chunks(value, 5);
+        assert_eq!(3, chunks.len());
+
+        let manifest =
+            Manifest::parse(&manifest(ChunkKind::Str, value, chunks.len(), "g1")).unwrap();
+        assert_eq!(3, manifest.chunk_num);
+        assert_eq!(value.len(), manifest.length);
+        assert_eq!("/files/f1/chunks/g1-2", manifest.chunk_path("/files/f1", 2));
+
+        let stored: HashMap<usize, FFields> = chunks
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| (index, chunk_fields(chunk)))
+            .collect();
+        assert_eq!(
+            FValue::Str("chunked value".to_owned()),
+            manifest.reassemble(stored).unwrap()
+        );
+
+        let mut tampered: HashMap<usize, FFields> = chunks
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| (index, chunk_fields(chunk)))
+            .collect();
+        tampered
+            .get_mut(&1)
+            .unwrap()
+            .add(CHUNK_DATA, FValue::Bytes(b"xxxxx".to_vec()));
+        assert!(manifest.reassemble(tampered).is_err());
+
+        assert_eq!(None, Manifest::parse(&FValue::Str("plain".to_owned())));
+        assert_eq!(
+            3,
+            chunk_index("projects/p/databases/(default)/documents/files/f1/chunks/g1-3").unwrap()
+        );
+        assert_ne!(new_generation(), new_generation());
+    }
+}
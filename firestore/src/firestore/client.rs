@@ -1,35 +1,83 @@
-use super::query::QueryBuilder;
-use super::request;
+use super::backfill::Backfill;
+use super::builder::FirestoreClientBuilder;
+use super::bulk_writer::{BulkWriter, BulkWriterOptions};
+use super::cancel::{cancellable, until_cancelled, CancellationToken};
+use super::change_stream::ChangeStream;
+use super::checksum::DataChecksum;
+use super::chunked::ChunkedField;
+use super::collection::CollectionRef;
+use super::collection_id_cache::CollectionIdCache;
+use super::components::{until_stopped, ClientComponents, ComponentTier, ShutdownReport};
+use super::dry_run::{synthetic_document, DryRunRecorder, DryRunWrite};
+use super::fan_out::DatabaseRef;
+use super::field_masks::DefaultFieldMasks;
+use super::health::{HealthReport, HEALTH_CHECK_DOCUMENT_PATH};
+use super::helper::{new_write_ope_transform, new_write_ope_update, new_write_ope_upsert};
+use super::page_size::AdaptivePageSize;
+use super::permission_probe::{
+    PermissionReport, ProbeOperation, ProbeResult, PERMISSION_PROBE_COLLECTION_ID,
+};
+use super::priority::{BatchChannelOptions, Priority};
+use super::query::{
+    partition_queries, resume_query, validate_field_paths, validate_resumable_query, Aggregation,
+    OrderDirection, QueryBuilder,
+};
+use super::raw::RawFirestoreClient;
+use super::read_repair::{ReadRepair, RepairTarget};
+use super::request::{
+    self, ListDocumentsOptions, PrefixMatch, ReadConsistency, RequestFactory, V1RequestFactory,
+};
+use super::schema::DocumentSchema;
+use super::transaction::{call_with_context, TransactionContext};
+use super::write_stream::{WriteStream, WriteStreamToken};
+use super::write_validation::MAX_REQUEST_SIZE;
 use crate::grpc::{
-    auth::{auth_interceptor, scopes, TokenManager, TokenManagerBuilder},
+    auth::{auth_headers, emulator_auth_headers, TokenManager, TokenRefresher},
+    client_info::api_client_header,
     connection_point,
-    error::GrpcErrorStatus,
+    events::{ClientEvent, ClientEvents},
+    hooks::{HookedChannel, RequestHeaders, RpcHook},
     GrpcChannel,
 };
 
 use crate::firestore::{
-    value::{array_value_from_vec, doc_path, map_value_from_vec, FFields, FValue},
-    FDocument,
+    value::fdoc::validate_document_path,
+    value::field_path::{escape_field_name, join_field_path, parse_field_path},
+    value::{
+        decode_document_with_casing, doc_path,
+        fvalue::{required_fields, FieldCasing},
+        FFields, FMap, FValue, TryIntoFFields,
+    },
+    DocumentSnapshot, FCollectionPath, FDocument, FDocumentPath, FTransform,
 };
 
 use backoff::future::retry;
-use backoff::{Error as BackoffError, ExponentialBackoff};
+use backoff::{backoff::Backoff, ExponentialBackoff};
 
-use anyhow::{anyhow, Error, Result};
-use futures::{Future, FutureExt, Stream};
+use super::error::{FirestoreError, Result};
+use anyhow::anyhow;
+use futures::stream::BoxStream;
+use futures::{future, stream, Future, FutureExt, Stream, StreamExt, TryStreamExt};
+use regex::Regex;
 
 use batch_get_documents_response::Result as DocResult;
 use google_cloud_grpc_proto::{
+    firestore::admin::v1::firestore_admin_client,
     firestore::v1::{
-        batch_get_documents_response, firestore_client, Cursor, Document, StructuredQuery, Value,
-        WriteResult,
+        batch_get_documents_response, firestore_client, structured_aggregation_query,
+        value::ValueType, BatchWriteRequest, Cursor, Document, ListenRequest, ListenResponse,
+        RunQueryResponse, StructuredAggregationQuery, StructuredQuery, Target, Value, WriteResult,
     },
-    tonic::{transport::Channel, Code},
+    prost::Message,
+    tonic::{transport::Channel, Code, Status},
 };
-use std::collections::HashMap;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::panic::AssertUnwindSafe;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{mpsc, Semaphore};
 use yup_oauth2::authenticator::{DefaultHyperClient, HyperClientBuilder};
 
 //TODO 413 Entity too large might occure if set to 500
@@ -39,16 +87,214 @@ pub const MAX_BATCH_WRTIE_SIZE: usize = 450;
 pub const MAX_IN_CLAUS_NUM: usize = 10;
 pub const MAX_BATCH_GET_DOC_NUM: usize = 1000; //TODO(tacogips) confirm
 
+/// the documents buffered by `run_partitioned_query_stream` before the consumer reads them.
+pub const PARTITIONED_QUERY_BUFFER_SIZE: usize = 1000;
+
+/// the queries of `run_query_union` run at once.
+pub const QUERY_UNION_CONCURRENCY: usize = 8;
+
+/// documents read in a query of `list_document_names`
+pub const LIST_DOCUMENT_NAMES_PAGE_SIZE: i32 = 1000;
+
+/// the max random probes of `sample_documents` per document requested, including the probes
+/// hitting the documents sampled already.
+pub const SAMPLE_PROBES_PER_DOCUMENT: usize = 3;
+
 // failed :Status { code: InvalidArgument, message: "datastore transaction or write too big.", metadata: MetadataMap { headers: {"content-type": "application/grpc", "date": "Wed, 12 May 2021 15:59:53 GMT", "alt-svc": "h3-29=\":443\"; ma=2592000,h3-T051=\":443\"; ma=2592000,h3-Q050=\":443\"; ma=2592000,h3-Q046=\":443\"; ma=2592000,h3-Q043=\":443\"; ma=2592000,quic=\":443\"; ma=2592000; v=\"46,43\""} } }
 //pub const MAX_WRITE_OPE_IN_TX: usize = 500;
 //pub const MAX_WRITE_OPE_IN_TX: usize = 200;
 pub const MAX_WRITE_OPE_IN_TX: usize = 500;
 
+/// attempts of `in_transaction_with_retry` including the first one when the commit is aborted
+/// by contention.
+pub const DEFAULT_TRANSACTION_MAX_ATTEMPTS: usize = 5;
+
 pub type MissingDocPaths = Vec<String>;
 
+pub const FIRESTORE_EMULATOR_HOST_ENV: &str = "FIRESTORE_EMULATOR_HOST";
+
+/// filter of `list_collection_ids_stream`
+#[derive(Debug, Clone)]
+pub enum CollectionIdFilter {
+    All,
+    Prefix(String),
+    Regex(Regex),
+}
+
+impl CollectionIdFilter {
+    fn matches(&self, collection_id: &str) -> bool {
+        match self {
+            CollectionIdFilter::All => true,
+            CollectionIdFilter::Prefix(prefix) => collection_id.starts_with(prefix.as_str()),
+            CollectionIdFilter::Regex(regex) => regex.is_match(collection_id),
+        }
+    }
+
+    /// the ids are sorted, so no more ids would match after this id.
+    fn is_past(&self, collection_id: &str) -> bool {
+        match self {
+            CollectionIdFilter::Prefix(prefix) => {
+                !collection_id.starts_with(prefix.as_str()) && collection_id > prefix.as_str()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// the progress of `large_batch_write_with_checkpoint`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchWriteCheckpoint {
+    pub chunk_index: usize,
+    /// number of operations written including the chunk.
+    pub written_operation_num: usize,
+}
+
+/// the result of a chunk written by `large_batch_write_concurrent` or
+/// `large_batch_write_with_report`
+#[derive(Debug)]
+pub struct ChunkWriteResult {
+    pub chunk_index: usize,
+    /// the offsets of the operations of the chunk in the operations passed first.
+    pub operation_offsets: Vec<usize>,
+    /// the result of each write in the order of the operations, or the error of the request.
+    pub result: Result<Vec<Result<WriteResult>>>,
+}
+
+impl ChunkWriteResult {
+    /// true if the request and all the writes succeeded.
+    pub fn is_all_written(&self) -> bool {
+        match &self.result {
+            Ok(results) => results.iter().all(|result| result.is_ok()),
+            Err(_) => false,
+        }
+    }
+
+    /// the operations of the chunk not applied by the result.
+    fn unapplied(
+        &self,
+        operations: &[(usize, request::DocumentWriteOperation)],
+    ) -> Vec<(usize, request::DocumentWriteOperation)> {
+        match &self.result {
+            Ok(results) => operations
+                .iter()
+                .zip(results)
+                .filter(|(_, result)| result.is_err())
+                .map(|(operation, _)| operation.clone())
+                .collect(),
+            Err(_) => operations.to_vec(),
+        }
+    }
+}
+
+/// the results of the chunks of `large_batch_write_concurrent` and `large_batch_write_with_report`
+/// in the order of the chunks. some chunks may have been written even if the others failed.
+#[derive(Debug)]
+pub struct ConcurrentBatchWriteReport {
+    /// the chunks attempted. `large_batch_write_with_report` doesn't attempt the chunks after
+    /// the first chunk not fully written.
+    pub chunks: Vec<ChunkWriteResult>,
+    /// the operations not applied, including the ones of the chunks not attempted.
+    resume: BatchWriteResume,
+}
+
+impl ConcurrentBatchWriteReport {
+    pub fn is_all_written(&self) -> bool {
+        self.resume.operations.is_empty()
+            && self.chunks.iter().all(ChunkWriteResult::is_all_written)
+    }
+
+    /// the chunks whose request or some of whose writes failed.
+    pub fn failed_chunks(&self) -> impl Iterator<Item = &ChunkWriteResult> {
+        self.chunks.iter().filter(|chunk| !chunk.is_all_written())
+    }
+
+    /// the offsets of the operations failed individually, with the errors. the operations of
+    /// the failed requests are not included, see `failed_chunks`.
+    pub fn failed_writes(&self) -> impl Iterator<Item = (usize, &FirestoreError)> {
+        self.chunks.iter().flat_map(|chunk| {
+            chunk.result.iter().flat_map(move |results| {
+                chunk
+                    .operation_offsets
+                    .iter()
+                    .zip(results)
+                    .filter_map(|(offset, result)| result.as_ref().err().map(|e| (*offset, e)))
+            })
+        })
+    }
+
+    /// the write results of the applied operations with their offsets.
+    pub fn write_results(&self) -> impl Iterator<Item = (usize, &WriteResult)> {
+        self.chunks.iter().flat_map(|chunk| {
+            chunk.result.iter().flat_map(move |results| {
+                chunk
+                    .operation_offsets
+                    .iter()
+                    .zip(results)
+                    .filter_map(|(offset, result)| result.as_ref().ok().map(|r| (*offset, r)))
+            })
+        })
+    }
+
+    /// the offsets of the operations not applied.
+    pub fn unapplied_offsets(&self) -> Vec<usize> {
+        self.resume
+            .operations
+            .iter()
+            .map(|(offset, _)| *offset)
+            .collect()
+    }
+
+    /// the first error of the chunks.
+    pub fn error(&self) -> Option<&FirestoreError> {
+        self.chunks.iter().find_map(|chunk| match &chunk.result {
+            Ok(results) => results.iter().find_map(|result| result.as_ref().err()),
+            Err(e) => Some(e),
+        })
+    }
+
+    /// the write results of all the operations, or the first error.
+    pub fn into_write_results(self) -> Result<Vec<WriteResult>> {
+        let mut write_results = Vec::new();
+        for chunk in self.chunks {
+            for result in chunk.result? {
+                write_results.push(result?);
+            }
+        }
+        Ok(write_results)
+    }
+
+    /// the operations not applied to pass to `resume_large_batch_write`.
+    /// None if all the operations are written.
+    pub fn into_resume(self) -> Option<BatchWriteResume> {
+        if self.resume.operations.is_empty() {
+            None
+        } else {
+            Some(self.resume)
+        }
+    }
+}
+
+/// the operations not applied by `large_batch_write_with_report` or
+/// `large_batch_write_concurrent`. pass it to `resume_large_batch_write` to retry only the remainder.
+#[derive(Debug, Clone)]
+pub struct BatchWriteResume {
+    pub ordered: bool,
+    /// the operations with their offsets in the operations passed first.
+    pub operations: Vec<(usize, request::DocumentWriteOperation)>,
+}
+
+/// the state of the transaction of `in_transaction`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionState {
+    Open,
+    Committed,
+    RolledBack,
+}
+
 pub struct TransactionOperation {
     pub transaction: Vec<u8>,
     operations: Vec<request::DocumentWriteOperation>,
+    state: TransactionState,
 }
 
 impl TransactionOperation {
@@ -56,10 +302,81 @@ impl TransactionOperation {
         TransactionOperation {
             transaction,
             operations: Vec::<request::DocumentWriteOperation>::new(),
+            state: TransactionState::Open,
         }
     }
-    pub fn add_operation(&mut self, write_operation: request::DocumentWriteOperation) {
-        self.operations.push(write_operation)
+
+    /// the operation is written on commit. fails if the transaction is already committed
+    /// or rolled back, the operation would be lost silently otherwise.
+    pub fn add_operation(
+        &mut self,
+        write_operation: request::DocumentWriteOperation,
+    ) -> Result<()> {
+        if self.state != TransactionState::Open {
+            return Err(FirestoreError::invalid_argument(format!(
+                "the transaction is already {:?}. the operation of {} is not written",
+                self.state,
+                write_operation.document_path()
+            )));
+        }
+        self.operations.push(write_operation);
+        Ok(())
+    }
+
+    pub fn state(&self) -> TransactionState {
+        self.state
+    }
+
+    /// the number of the operations added.
+    pub fn operation_num(&self) -> usize {
+        self.operations.len()
+    }
+
+    /// fails if the operations added are more than a commit of `in_transaction` accepts,
+    /// to stop before the side effects (e.g. the calls to the other services) in the closure.
+    /// see `FirestoreClient::in_transaction_chunked` for more operations.
+    pub fn validate_commit_size(&self) -> Result<()> {
+        if self.operations.len() > MAX_BATCH_WRTIE_SIZE {
+            return Err(FirestoreError::invalid_argument(format!(
+                "max batch write in transaction size = {} but passed {}",
+                MAX_BATCH_WRTIE_SIZE,
+                self.operations.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// the state of `run_query_stream_resumable`.
+struct ResumableQuery {
+    client: FirestoreClient,
+    parent_path: Option<String>,
+    query: StructuredQuery,
+    consistency: ReadConsistency,
+    last_document: Option<Document>,
+    delivered: i32,
+    resumes_left: usize,
+    backoff: ExponentialBackoff,
+}
+
+impl ResumableQuery {
+    /// the stream of the query after the last document delivered.
+    async fn open(&mut self) -> Result<Option<BoxStream<'static, Result<RunQueryResponse>>>> {
+        let query = match self.last_document.as_ref() {
+            None => self.query.clone(),
+            Some(last_document) => {
+                match resume_query(&self.query, last_document, self.delivered)? {
+                    Some(query) => query,
+                    // the limit is reached by the documents delivered
+                    None => return Ok(None),
+                }
+            }
+        };
+        let responses = self
+            .client
+            .run_query_responses(self.parent_path.clone(), query, self.consistency.clone())
+            .await?;
+        Ok(Some(responses.boxed()))
     }
 }
 
@@ -69,7 +386,7 @@ impl TransactionOperation {
 /// https://github.com/rustasync/team/issues/19
 /// https://gendignoux.com/blog/2020/12/17/rust-async-type-system-limits.html
 pub trait WithTransaction<'a, Res, Ctx> {
-    type Output: 'a + Future<Output = Result<Res>>;
+    type Output: 'a + Future<Output = anyhow::Result<Res>>;
     fn call(
         &self,
         arg: &'a mut FirestoreClient,
@@ -82,7 +399,7 @@ impl<'a, R, F, Res, Ctx> WithTransaction<'a, Res, Ctx> for F
 where
     R: 'a,
     F: Fn(&'a mut FirestoreClient, &'a mut TransactionOperation, Ctx) -> R,
-    R: Future<Output = Result<Res>> + 'a,
+    R: Future<Output = anyhow::Result<Res>> + 'a,
 {
     type Output = R;
     fn call(
@@ -95,10 +412,74 @@ where
     }
 }
 
+/// same as `WithTransaction` for `read_only_transaction`. the closure takes the transaction id.
+pub trait WithReadOnlyTransaction<'a, Res, Ctx> {
+    type Output: 'a + Future<Output = anyhow::Result<Res>>;
+    fn call(&self, arg: &'a mut FirestoreClient, tx: Vec<u8>, context: Ctx) -> Self::Output;
+}
+
+impl<'a, R, F, Res, Ctx> WithReadOnlyTransaction<'a, Res, Ctx> for F
+where
+    R: 'a,
+    F: Fn(&'a mut FirestoreClient, Vec<u8>, Ctx) -> R,
+    R: Future<Output = anyhow::Result<Res>> + 'a,
+{
+    type Output = R;
+    fn call(&self, arg: &'a mut FirestoreClient, tx: Vec<u8>, context: Ctx) -> R {
+        self(arg, tx, context)
+    }
+}
+
+/// the methods take `&self`, each rpc runs on a clone of the grpc client (cloning the channel is
+/// cheap). so the client can be shared in an `Arc` and used concurrently without a Mutex.
 pub struct FirestoreClient {
     project_id: String,
-    firestore_client: firestore_client::FirestoreClient<Channel>,
-    token_manager: Arc<TokenManager<<DefaultHyperClient as HyperClientBuilder>::Connector>>,
+    /// `interactive_client` or `batch_client` by `priority`
+    firestore_client: firestore_client::FirestoreClient<HookedChannel>,
+    interactive_client: firestore_client::FirestoreClient<HookedChannel>,
+    /// on the dedicated channel for `Priority::Batch` if configured
+    batch_client: Option<firestore_client::FirestoreClient<HookedChannel>>,
+    priority: Priority,
+    /// on the same channel as `firestore_client`
+    admin_client: firestore_admin_client::FirestoreAdminClient<HookedChannel>,
+    /// None if connected to the emulator
+    token_manager: Option<Arc<TokenManager<<DefaultHyperClient as HyperClientBuilder>::Connector>>>,
+    transaction_max_attempts: usize,
+    cancellation: Option<CancellationToken>,
+    read_only: bool,
+    /// the reads of `ReadConsistency::Default` are at the time if set by `read_at`
+    read_time: Option<SystemTime>,
+    request_factory: Arc<dyn RequestFactory>,
+    events: ClientEvents,
+    /// the grpc clients are made again on these with the rpc hooks added
+    channel: HookedChannel,
+    batch_channel: Option<HookedChannel>,
+    /// the background tasks of the client and its clones
+    components: ClientComponents,
+    /// applied to the reads without the field mask
+    default_field_masks: Arc<DefaultFieldMasks>,
+    /// the writes are recorded instead of being sent if set
+    dry_run: Option<DryRunRecorder>,
+    /// the field names of the typed documents
+    field_casing: Option<FieldCasing>,
+    /// added to the requests accepting the labels
+    labels: Arc<HashMap<String, String>>,
+}
+
+type GrpcClients = (
+    firestore_client::FirestoreClient<HookedChannel>,
+    Option<firestore_client::FirestoreClient<HookedChannel>>,
+    firestore_admin_client::FirestoreAdminClient<HookedChannel>,
+);
+
+/// the interactive, the batch and the admin clients. the admin client is on the interactive channel.
+fn grpc_clients(channel: &HookedChannel, batch_channel: Option<&HookedChannel>) -> GrpcClients {
+    (
+        firestore_client::FirestoreClient::new(channel.clone()),
+        batch_channel
+            .map(|batch_channel| firestore_client::FirestoreClient::new(batch_channel.clone())),
+        firestore_admin_client::FirestoreAdminClient::new(channel.clone()),
+    )
 }
 
 pub(crate) fn id_filter<T>() -> impl FnMut(&T) -> bool + Copy {
@@ -110,216 +491,1683 @@ impl FirestoreClient {
         project_id: String,
         service_acocunt_cred_path: PathBuf,
     ) -> Result<FirestoreClient> {
-        let channel = GrpcChannel::new_connected_channnel(&connection_point::FIRESTORE).await?;
+        FirestoreClientBuilder::new(project_id)
+            .service_account_file(service_acocunt_cred_path)
+            .build()
+            .await
+    }
+
+    /// authenticate with workload identity federation credential configuration file.
+    pub async fn with_external_account_file(
+        project_id: String,
+        external_account_cred_path: PathBuf,
+    ) -> Result<FirestoreClient> {
+        FirestoreClientBuilder::new(project_id)
+            .external_account_file(external_account_cred_path)
+            .build()
+            .await
+    }
 
-        let token_manager =
-            TokenManagerBuilder::new(vec![&scopes::CLOUD_PLATFORM, &scopes::DATASTORE])
-                .service_account_file(service_acocunt_cred_path)
-                .build()
-                .await?;
+    pub(crate) async fn with_token_manager(
+        project_id: String,
+        token_manager: TokenManager<<DefaultHyperClient as HyperClientBuilder>::Connector>,
+        channel_pool_size: usize,
+        batch_channel: Option<BatchChannelOptions>,
+        events: ClientEvents,
+    ) -> Result<FirestoreClient> {
+        let channel =
+            GrpcChannel::new_pooled_channel(&connection_point::FIRESTORE, channel_pool_size)
+                .await
+                .map_err(FirestoreError::Connection)?;
 
         let token_manager = Arc::new(token_manager);
         let shared_token = token_manager.shared_token();
+        let components = ClientComponents::new();
+        let (stop, exited) = token_manager.stop_handle();
+        components.register("token_manager", ComponentTier::Auth, stop, exited);
 
-        let firestore_client = firestore_client::FirestoreClient::with_interceptor(
+        let token_refresher: Arc<dyn TokenRefresher> = token_manager.clone();
+        let request_headers: RequestHeaders = Arc::new(auth_headers(shared_token));
+        let channel = HookedChannel::new(
             channel.opened_channel.unwrap(),
-            auth_interceptor(shared_token),
-        );
+            request_headers.clone(),
+            events.clone(),
+        )
+        .with_token_refresher(token_refresher.clone());
+        let batch_channel = match batch_channel {
+            Some(options) => {
+                let batch_channel = GrpcChannel::new_limited_pooled_channel(
+                    &connection_point::FIRESTORE,
+                    options.pool_size,
+                    Some(options.concurrency_limit),
+                )
+                .await
+                .map_err(FirestoreError::Connection)?;
+                Some(
+                    HookedChannel::new(
+                        batch_channel.opened_channel.unwrap(),
+                        request_headers,
+                        events.clone(),
+                    )
+                    .with_token_refresher(token_refresher),
+                )
+            }
+            None => None,
+        };
+        let (firestore_client, batch_client, admin_client) =
+            grpc_clients(&channel, batch_channel.as_ref());
         Ok(Self {
             project_id,
-            firestore_client,
-            token_manager,
+            firestore_client: firestore_client.clone(),
+            interactive_client: firestore_client,
+            batch_client,
+            priority: Priority::Interactive,
+            admin_client,
+            token_manager: Some(token_manager),
+            transaction_max_attempts: DEFAULT_TRANSACTION_MAX_ATTEMPTS,
+            cancellation: None,
+            read_only: false,
+            read_time: None,
+            request_factory: Arc::new(V1RequestFactory),
+            events,
+            channel,
+            batch_channel,
+            components,
+            default_field_masks: Arc::new(DefaultFieldMasks::new()),
+            dry_run: None,
+            field_casing: None,
+            labels: Arc::new(HashMap::new()),
         })
     }
-    pub fn refresh_auth_token(&self) -> Result<()> {
-        self.token_manager.force_refresh_token()
+
+    /// connect to the firestore emulator at `host` (e.g. "localhost:8080").
+    pub async fn with_emulator(project_id: String, host: String) -> Result<FirestoreClient> {
+        let channel = GrpcChannel::new_insecure_channel(format!("http://{}", host))
+            .await
+            .map_err(FirestoreError::Connection)?;
+        Ok(Self::with_emulator_channel(
+            project_id,
+            channel.opened_channel.unwrap(),
+        ))
     }
 
-    /// attention : with_tx:F sould  be a function pointer, but closuere.
-    pub async fn in_transaction<F, R, Ctx>(&mut self, ctx: Ctx, with_tx: F) -> Result<R>
-    where
-        F: for<'a> WithTransaction<'a, R, Ctx>,
-    {
-        let tx = self
-            .firestore_client
-            .begin_transaction(request::new_begin_transaction_request(
-                self.project_id.clone(),
-                None,
-            ))
-            .await?
-            .into_inner()
-            .transaction;
+    fn with_emulator_channel(project_id: String, channel: Channel) -> FirestoreClient {
+        let events = ClientEvents::new();
+        let request_headers: RequestHeaders = Arc::new(emulator_auth_headers());
+        let channel = HookedChannel::new(channel, request_headers, events.clone());
+        let (firestore_client, _, admin_client) = grpc_clients(&channel, None);
+        Self {
+            project_id,
+            firestore_client: firestore_client.clone(),
+            interactive_client: firestore_client,
+            batch_client: None,
+            priority: Priority::Interactive,
+            admin_client,
+            token_manager: None,
+            transaction_max_attempts: DEFAULT_TRANSACTION_MAX_ATTEMPTS,
+            cancellation: None,
+            read_only: false,
+            read_time: None,
+            request_factory: Arc::new(V1RequestFactory),
+            events,
+            channel,
+            batch_channel: None,
+            components: ClientComponents::new(),
+            default_field_masks: Arc::new(DefaultFieldMasks::new()),
+            dry_run: None,
+            field_casing: None,
+            labels: Arc::new(HashMap::new()),
+        }
+    }
 
-        let mut tx_ope = TransactionOperation::new(tx);
-        let maybe_panic_in_tx = AssertUnwindSafe(with_tx.call(self, &mut tx_ope, ctx))
-            .catch_unwind()
-            .await;
+    /// the client of the emulator which is never connected. the rpcs fail as unavailable.
+    #[cfg(test)]
+    pub(crate) fn offline(project_id: &str) -> FirestoreClient {
+        let channel = Channel::from_static("http://127.0.0.1:1")
+            .connect_lazy()
+            .unwrap();
+        Self::with_emulator_channel(project_id.to_owned(), channel)
+    }
 
-        let err: Error;
-        match maybe_panic_in_tx {
-            Ok(result) => match result {
-                Ok(success_value) => {
-                    if tx_ope.operations.len() > MAX_BATCH_WRTIE_SIZE {
-                        return Err(anyhow!(
-                            "max batch write in transaction size = {} but passed {}",
-                            MAX_BATCH_WRTIE_SIZE,
-                            tx_ope.operations.len()
-                        ));
-                    }
+    /// connect to the emulator at `FIRESTORE_EMULATOR_HOST`.
+    pub async fn from_emulator_env(project_id: String) -> Result<FirestoreClient> {
+        let host = std::env::var(FIRESTORE_EMULATOR_HOST_ENV).map_err(|e| {
+            FirestoreError::invalid_argument(format!("{} : {}", FIRESTORE_EMULATOR_HOST_ENV, e))
+        })?;
+        Self::with_emulator(project_id, host).await
+    }
 
-                    self.commit(tx_ope.operations, Some(tx_ope.transaction))
-                        .await?;
-                    return Ok(success_value);
-                }
-                Err(e) => err = e,
-            },
-            Err(e) => err = anyhow!("panic occured in tx. rollback : {:?}", e),
-        }
+    /// max attempts of `in_transaction_with_retry` when the commit is aborted. must be greater than 0.
+    pub fn with_transaction_max_attempts(mut self, max_attempts: usize) -> Self {
+        self.transaction_max_attempts = max_attempts.max(1);
+        self
+    }
+    /// identify the app in the `x-goog-api-client` header of the requests
+    /// (e.g. "my-app/1.2.3"). the clones made before are not affected.
+    pub fn with_user_agent_suffix<S: AsRef<str>>(self, suffix: S) -> Result<Self> {
+        let header = api_client_header(Some(suffix.as_ref()))
+            .map_err(|e| FirestoreError::invalid_argument(e.to_string()))?;
+        Ok(self.with_channels(|channel| channel.with_api_client_header(header.clone())))
+    }
 
-        // TODO(tacogips) need backoff?
-        self.rollback(tx_ope.transaction).await?;
-        Err(err)
+    /// the queries, the batch gets and the listens of the client abort with
+    /// `FirestoreError::Cancelled` as soon as the token is cancelled.
+    /// the clones made from the client (e.g. by `paginate_query`) share the token.
+    ///
+    /// ```ignore
+    /// let token = CancellationToken::new();
+    /// let docs = client.clone().with_cancellation(token.child_token()).run_query_stream(..).await?;
+    /// ```
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
     }
 
-    pub async fn begin_transaction(&mut self) -> Result<Vec<u8>> {
-        self.firestore_client
-            .begin_transaction(request::new_begin_transaction_request(
-                self.project_id.clone(),
-                None,
-            ))
-            .await
-            .map(|resp| resp.into_inner().transaction)
-            .map_err(|e| Error::from(GrpcErrorStatus::from(e)))
+    /// the writes of the client (and of its clones) fail with `FirestoreError::ReadOnlyViolation`
+    /// before the request, even if the credential is allowed to write.
+    /// read-only transactions are still available.
+    ///
+    /// ```ignore
+    /// let client = FirestoreClient::with_service_account_file(project_id, path).await?.read_only();
+    /// ```
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
     }
 
-    pub async fn commit(
-        &mut self,
-        operations: Vec<request::DocumentWriteOperation>,
-        transaction: Option<Vec<u8>>,
-    ) -> Result<Vec<WriteResult>> {
-        self.firestore_client
-            .commit(request::new_commit_request(
-                self.project_id.clone(),
-                operations,
-                transaction,
-            ))
-            .await
-            .map(|resp| resp.into_inner().write_results)
-            .map_err(|e| Error::from(GrpcErrorStatus::from(e)))
+    /// the writes of the client (and of its clones) are recorded into `recorder` with the
+    /// paths, the masks and the sizes, and return the synthetic results instead of being sent.
+    /// to preview the maintenance scripts with the production config. the reads are sent.
+    ///
+    /// the commits of the transactions roll them back, and the Write streams can't be opened.
+    /// the writes of a read-only client still fail with `FirestoreError::ReadOnlyViolation`.
+    ///
+    /// ```ignore
+    /// let recorder = DryRunRecorder::new();
+    /// cleanup_expired_sessions(&client.clone().with_dry_run(recorder.clone())).await?;
+    /// println!("{} deletes", recorder.len());
+    /// ```
+    pub fn with_dry_run(mut self, recorder: DryRunRecorder) -> Self {
+        self.dry_run = Some(recorder);
+        self
     }
 
-    pub async fn rollback(&mut self, transaction: Vec<u8>) -> Result<()> {
-        self.firestore_client
-            .rollback(request::new_rollback_request(
-                self.project_id.clone(),
-                transaction,
-            ))
-            .await
-            .map(|resp| resp.into_inner())
-            .map_err(|e| Error::from(GrpcErrorStatus::from(e)))
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run.is_some()
     }
 
-    pub async fn search_prefix_like<F>(
-        &mut self,
-        parent_path: Option<String>,
-        collection: String,
-        field: &str,
-        prefix: &str,
-        contain_exact_match: bool,
-        transaction: Option<Vec<u8>>,
-        mut with_each_doc: F,
-    ) -> Result<i64>
-    where
-        F: FnMut(Document) -> Result<()>,
-    {
-        let query = QueryBuilder::collection(collection, false)
-            .filter_bin(field, ">=", prefix.clone())
-            .build();
+    /// add the labels (e.g. for the cost attribution) to the requests of the client (and of its
+    /// clones) accepting them: `batch_write`, the Write streams and `listen`. the v1 api
+    /// doesn't accept the labels on the commits and the queries.
+    /// the labels set by the request factory are not overwritten.
+    ///
+    /// ```ignore
+    /// let mut labels = HashMap::new();
+    /// labels.insert("job".to_owned(), "nightly-export".to_owned());
+    /// let client = client.clone().with_labels(labels)?;
+    /// ```
+    pub fn with_labels(mut self, labels: HashMap<String, String>) -> Result<Self> {
+        request::validate_labels(&labels)?;
+        self.labels = Arc::new(labels);
+        Ok(self)
+    }
 
-        let mut result_num = 0;
-        let mut result_stream = self
-            .firestore_client
-            .run_query(request::new_query_request(
-                self.project_id.clone(),
-                parent_path.unwrap_or("".to_owned()),
-                query,
-                transaction,
-            ))
-            .await?
-            .into_inner();
+    pub fn labels(&self) -> &HashMap<String, String> {
+        &self.labels
+    }
 
-        while let Some(each_response) = result_stream.message().await? {
-            match each_response.document {
-                Some(doc) => {
-                    // check prefix
-                    match doc.fields.get(field) {
-                        None => break,
-                        Some(field_value) => match FValue::from(field_value.clone()).as_string() {
-                            None => break,
-                            Some(str_value) => {
-                                if !str_value.starts_with(prefix) {
-                                    break;
-                                }
-                                if !contain_exact_match && str_value == prefix {
-                                    continue;
-                                }
-                            }
-                        },
-                    }
+    /// store the field names of the structs by `casing` on the typed writes of the client (and
+    /// of its clones), and read them back on the typed reads (e.g. `get_document_as`,
+    /// `CollectionRef` and `TypedTransaction`). the `FFields` and the field paths of the
+    /// queries and the masks are not converted. see `FieldCasing`.
+    ///
+    /// ```ignore
+    /// let client = client.clone().with_field_casing(FieldCasing::camel());
+    /// users.set("u1", &User { display_name: "jeff".to_owned() }).await?; // {"displayName": "jeff"}
+    /// ```
+    pub fn with_field_casing(mut self, casing: FieldCasing) -> Self {
+        self.field_casing = Some(casing);
+        self
+    }
 
-                    result_num += 1;
-                    with_each_doc(doc)?;
-                }
-                None => continue, //TODO(need to be interept?)
-            }
+    pub fn field_casing(&self) -> Option<&FieldCasing> {
+        self.field_casing.as_ref()
+    }
+
+    /// the document or the struct as the fields by `field_casing`.
+    pub(crate) fn encode<P: TryIntoFFields>(&self, doc: P) -> Result<FFields> {
+        match &self.field_casing {
+            Some(casing) => doc.try_into_ffields_with_casing(casing),
+            None => doc.try_into_ffields(),
         }
-        Ok(result_num)
     }
 
-    pub async fn run_query<F>(
-        &mut self,
-        parent_path: Option<String>,
-        query: StructuredQuery,
-        transaction: Option<Vec<u8>>,
-        mut with_each_doc: F,
-    ) -> Result<i64>
-    where
-        F: FnMut(Document) -> Result<()>,
-    {
-        let mut result_num = 0;
-        let mut result_stream = self
-            .firestore_client
-            .run_query(request::new_query_request(
-                self.project_id.clone(),
-                parent_path.unwrap_or("".to_owned()),
-                query,
-                transaction,
-            ))
-            .await?
-            .into_inner();
+    /// `from_document` by `field_casing`, failing with `FirestoreError::Decode`.
+    pub(crate) fn decode<T: DeserializeOwned>(&self, document: Document) -> Result<T> {
+        decode_document_with_casing(document, self.field_casing.as_ref())
+    }
 
-        while let Some(each_response) = result_stream.message().await? {
-            match each_response.document {
-                Some(doc) => {
-                    result_num += 1;
-                    with_each_doc(doc)?
-                }
-                None => continue, //TODO(need to be interept?)
-            }
+    pub(crate) fn decode_snapshot<T: DeserializeOwned>(
+        &self,
+        document: Document,
+    ) -> Result<DocumentSnapshot<T>> {
+        DocumentSnapshot::from_document_with_casing(document, self.field_casing.as_ref())
+    }
+
+    /// read the data at `read_time` with the reads of the client (and of its clones) unless
+    /// the consistency is specified. for the time travel debugging with point-in-time recovery
+    /// (e.g. "what did this doc look like yesterday"). the client is read-only.
+    ///
+    /// returns Err if `read_time` is outside the point-in-time recovery window.
+    /// the reads older than `VERSION_RETENTION_PERIOD` fail with FAILED_PRECONDITION
+    /// if point-in-time recovery is not enabled on the database.
+    ///
+    /// ```ignore
+    /// let yesterday = SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+    /// let mut client = client.clone().read_at(timestamp::truncate_to_minute(yesterday))?;
+    /// let doc = client.get_document(path, None, None).await?;
+    /// ```
+    pub fn read_at(mut self, read_time: SystemTime) -> Result<Self> {
+        request::validate_read_time(read_time, SystemTime::now())?;
+        self.read_time = Some(read_time);
+        self.read_only = true;
+        Ok(self)
+    }
+
+    /// the time set by `read_at`.
+    pub fn read_time(&self) -> Option<SystemTime> {
+        self.read_time
+    }
+
+    /// the consistency of a read. the time set by `read_at` if not specified.
+    fn consistency<C: Into<ReadConsistency>>(&self, consistency: C) -> ReadConsistency {
+        match (consistency.into(), self.read_time) {
+            (ReadConsistency::Default, Some(read_time)) => ReadConsistency::ReadTime(read_time),
+            (consistency, _) => consistency,
         }
-        Ok(result_num)
     }
 
-    pub async fn partition_query_all(
-        &mut self,
-        document_path: String,
-        query: StructuredQuery,
-        max_partition_count: i64,
-        chunk_size: i32,
-    ) -> Result<Vec<Cursor>> {
-        //TODO(tacogips) to be procedual macro
-        let ref mut next_token = "".to_owned();
-        let mut result = Vec::<Cursor>::new();
+    /// build the requests with `request_factory` instead of `V1RequestFactory`.
+    /// the clones made from the client share the factory.
+    pub fn with_request_factory(mut self, request_factory: Arc<dyn RequestFactory>) -> Self {
+        self.request_factory = request_factory;
+        self
+    }
+
+    /// send the requests of the client (and of its clones) on the channel of `priority`.
+    /// `Priority::Batch` falls back to the shared channel if the client has no batch channel
+    /// (see `FirestoreClientBuilder::batch_channel`).
+    ///
+    /// ```ignore
+    /// let mut export_client = client.clone().with_priority(Priority::Batch);
+    /// ```
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.firestore_client = match (priority, &self.batch_client) {
+            (Priority::Batch, Some(batch_client)) => batch_client.clone(),
+            _ => self.interactive_client.clone(),
+        };
+        self.priority = priority;
+        self
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// read the fields of `masks` by the reads of the client (and of its clones) without the
+    /// field mask: `get_document`, `batch_get_documents`, `list_documents_with`,
+    /// `list_documents_chunk`, `run_query` and `run_query_stream` (and the reads using them).
+    /// the queries with `select` are not changed. `data_checksum`, `read_repair` and `backfill`
+    /// read the whole documents regardless.
+    pub fn with_default_field_masks(mut self, masks: DefaultFieldMasks) -> Self {
+        self.default_field_masks = Arc::new(masks);
+        self
+    }
+
+    /// read all the fields unless the field mask is passed.
+    pub fn without_default_field_masks(self) -> Self {
+        self.with_default_field_masks(DefaultFieldMasks::new())
+    }
+
+    pub fn default_field_masks(&self) -> &DefaultFieldMasks {
+        &self.default_field_masks
+    }
+
+    /// the events of the token refreshes, the reconnects, the throttling and the retries
+    /// of the client and its clones.
+    ///
+    /// ```ignore
+    /// let mut events = client.subscribe_events();
+    /// tokio::spawn(async move {
+    ///     while let Ok(event) = events.recv().await {
+    ///         if let ClientEvent::TokenRefreshFailed { error } = event {
+    ///             alert(error);
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn subscribe_events(&self) -> tokio::sync::broadcast::Receiver<ClientEvent> {
+        self.events.subscribe()
+    }
+
+    /// call `hook` on every rpc of the client and of its clones made after,
+    /// after the hooks added before. the clones made before are not affected.
+    ///
+    /// ```ignore
+    /// let client = client.with_rpc_hook(Arc::new(LatencyRecorder::new()));
+    /// ```
+    pub fn with_rpc_hook(self, hook: Arc<dyn RpcHook>) -> Self {
+        self.with_channels(|channel| channel.with_rpc_hook(hook.clone()))
+    }
+
+    /// rebuild the grpc clients on the channels made from the ones of the client.
+    fn with_channels<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&HookedChannel) -> HookedChannel,
+    {
+        self.channel = f(&self.channel);
+        self.batch_channel = self.batch_channel.as_ref().map(&f);
+        let (interactive_client, batch_client, admin_client) =
+            grpc_clients(&self.channel, self.batch_channel.as_ref());
+        self.interactive_client = interactive_client;
+        self.batch_client = batch_client;
+        self.admin_client = admin_client;
+        let priority = self.priority;
+        self.with_priority(priority)
+    }
+
+    pub(crate) fn events(&self) -> &ClientEvents {
+        &self.events
+    }
+
+    /// the background tasks started by the client and its clones.
+    pub fn components(&self) -> &ClientComponents {
+        &self.components
+    }
+
+    /// stop the background tasks of the client and its clones (see `ClientComponents::shutdown`).
+    /// the client can't refresh the token afterwards.
+    pub async fn shutdown(&self, timeout: Duration) -> ShutdownReport {
+        self.components.shutdown(timeout).await
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn ensure_writable(&self, rpc: &str) -> Result<()> {
+        if self.read_only {
+            Err(FirestoreError::ReadOnlyViolation(rpc.to_owned()))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn project_id(&self) -> &str {
+        &self.project_id
+    }
+
+    /// the generated grpc client on the channel of the client (by `priority`), with the auth
+    /// token, the `x-goog-api-client` header and the rpc hooks of the client. see `raw`.
+    /// the requests sent by it bypass the retries of the client.
+    ///
+    /// returns Err on a read-only client (or a client of `read_at`) and on a dry-run client,
+    /// as the raw requests can't be checked or recorded.
+    pub fn raw_client(&self) -> Result<RawFirestoreClient> {
+        self.ensure_writable("raw_client")?;
+        if self.dry_run.is_some() {
+            return Err(FirestoreError::invalid_argument(
+                "raw_client is not available on the dry-run client",
+            ));
+        }
+        Ok(self.firestore_client.clone())
+    }
+
+    /// the factory of the requests of the client. see `with_request_factory`.
+    pub fn request_factory(&self) -> &dyn RequestFactory {
+        self.request_factory.as_ref()
+    }
+
+    /// the client always connects to the default database of the project.
+    pub fn database_ref(&self) -> DatabaseRef {
+        DatabaseRef::default_database(self.project_id.clone())
+    }
+
+    pub fn refresh_auth_token(&self) -> Result<()> {
+        match &self.token_manager {
+            Some(token_manager) => token_manager
+                .force_refresh_token()
+                .map_err(FirestoreError::Auth),
+            None => Ok(()),
+        }
+    }
+
+    /// typed handle of the collection. the client is cloned into the handle.
+    pub fn collection<T>(&self, collection_id: impl Into<String>) -> CollectionRef<T>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        CollectionRef::new(self.clone(), None, collection_id.into())
+    }
+
+    /// typed handle of the sub collection under `parent_path` (e.g. "/users/user_1").
+    pub fn sub_collection<T>(
+        &self,
+        parent_path: impl Into<String>,
+        collection_id: impl Into<String>,
+    ) -> CollectionRef<T>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        CollectionRef::new(self.clone(), Some(parent_path.into()), collection_id.into())
+    }
+
+    /// the document `doc_id` of the `collection` (e.g. "users" or "users/user_1/orders").
+    ///
+    /// ```ignore
+    /// let user: Option<User> = client.get_as("users", "user_1").await?;
+    /// ```
+    pub async fn get_as<T: DeserializeOwned>(
+        &self,
+        collection: &str,
+        doc_id: &str,
+    ) -> Result<Option<T>> {
+        let collection = FCollectionPath::parse(collection)?;
+        let document_path = doc_path(
+            collection.parent_path,
+            collection.collection_id,
+            doc_id.to_owned(),
+        );
+        self.get_document_as(document_path, None, ReadConsistency::Default)
+            .await
+    }
+
+    /// create or overwrite the document `doc_id` of the `collection` with `value`.
+    ///
+    /// ```ignore
+    /// client.set("users/user_1/orders", "order_1", &order).await?;
+    /// ```
+    pub async fn set<T: Serialize>(
+        &self,
+        collection: &str,
+        doc_id: &str,
+        value: &T,
+    ) -> Result<WriteResult> {
+        let collection = FCollectionPath::parse(collection)?;
+        let ope = new_write_ope_upsert(
+            collection.parent_path,
+            collection.collection_id,
+            doc_id.to_owned(),
+            self.encode(value)?,
+        )?;
+        Ok(self
+            .commit(vec![ope], None)
+            .await?
+            .pop()
+            .unwrap_or_default())
+    }
+
+    /// update the fields of the existing document `doc_id` of the `collection` in `mask`
+    /// (e.g. "address.city") with `partial`. the fields in `mask` but not in `partial` are
+    /// deleted. without `mask`, the top level fields of `partial` are updated.
+    ///
+    /// ```ignore
+    /// client
+    ///     .update_fields("users", "user_1", json!({"name": "jeff"}), None)
+    ///     .await?;
+    /// ```
+    pub async fn update_fields<P: TryIntoFFields>(
+        &self,
+        collection: &str,
+        doc_id: &str,
+        partial: P,
+        mask: Option<Vec<String>>,
+    ) -> Result<WriteResult> {
+        let collection = FCollectionPath::parse(collection)?;
+        let fields = self.encode(partial)?;
+        let mask = match mask {
+            Some(mask) => mask,
+            // the transforms are not in the mask
+            None => {
+                let (updated, _) = fields.clone().split_transforms();
+                updated.keys().map(|key| escape_field_name(key)).collect()
+            }
+        };
+        let ope = new_write_ope_update(
+            collection.parent_path,
+            collection.collection_id,
+            doc_id.to_owned(),
+            Some(mask),
+            fields,
+        )?;
+        Ok(self
+            .commit(vec![ope], None)
+            .await?
+            .pop()
+            .unwrap_or_default())
+    }
+
+    /// cache of `list_collection_ids` expiring after `ttl`. the client is cloned into the cache.
+    pub fn collection_id_cache(&self, ttl: Duration) -> CollectionIdCache {
+        CollectionIdCache::new(self.clone(), ttl)
+    }
+
+    /// the changes of the documents of the query as events. the client is cloned into the stream.
+    pub fn change_stream(
+        &self,
+        parent_path: Option<String>,
+        query: StructuredQuery,
+    ) -> ChangeStream {
+        ChangeStream::new(self.clone(), parent_path, query)
+    }
+
+    /// compare the documents of the `source` query with their denormalized copies mapped by
+    /// `mapping`, and repair the diverged copies. the client is cloned into the repair.
+    pub fn read_repair<M>(
+        &self,
+        parent_path: Option<String>,
+        source: QueryBuilder,
+        mapping: M,
+    ) -> ReadRepair<M>
+    where
+        M: FnMut(&FDocument) -> Vec<RepairTarget>,
+    {
+        ReadRepair::new(self.clone(), parent_path, source, mapping)
+    }
+
+    /// the checksums of the documents of `source`, to validate exported data.
+    /// the client is cloned into the checksum with `Priority::Batch`. for the exported documents
+    /// themselves, see `ChecksumManifest::from_documents`.
+    pub fn data_checksum(&self, parent_path: Option<String>, source: QueryBuilder) -> DataChecksum {
+        DataChecksum::new(
+            self.clone().with_priority(Priority::Batch),
+            parent_path,
+            source,
+        )
+    }
+
+    /// read the documents of `query`, transform them into `U` and write the changed ones.
+    /// the client is cloned into the backfill with `Priority::Batch`.
+    pub fn backfill<T, U, F>(
+        &self,
+        parent_path: Option<String>,
+        query: QueryBuilder,
+        transform: F,
+    ) -> Backfill<T, U, F>
+    where
+        T: DeserializeOwned,
+        U: Serialize,
+        F: FnMut(T) -> Option<U>,
+    {
+        Backfill::new(
+            self.clone().with_priority(Priority::Batch),
+            parent_path,
+            query,
+            transform,
+        )
+    }
+
+    /// the `Str` or `Bytes` field split into the companion documents when it exceeds
+    /// the document size limit. the client is cloned into the field.
+    pub fn chunked_field<F: Into<String>>(&self, field: F) -> ChunkedField {
+        ChunkedField::new(self.clone(), field.into())
+    }
+
+    /// background writer for large ingestion jobs and low-priority writes.
+    /// the client is cloned into the writer with `Priority::Batch`.
+    /// must be called within a tokio runtime.
+    pub fn bulk_writer(&self) -> BulkWriter {
+        self.bulk_writer_with(BulkWriterOptions::default())
+    }
+
+    /// `bulk_writer` with the throttling, retry and callback options.
+    pub fn bulk_writer_with(&self, options: BulkWriterOptions) -> BulkWriter {
+        BulkWriter::new(self.clone().with_priority(Priority::Batch), options)
+    }
+
+    /// attention : with_tx:F sould  be a function pointer, but closuere.
+    ///
+    /// `ctx` is moved into `with_tx`, so the transaction is not retried if the commit is
+    /// aborted by contention. see `in_transaction_with_retry`.
+    pub async fn in_transaction<F, R, Ctx>(&self, ctx: Ctx, with_tx: F) -> Result<R>
+    where
+        F: for<'a> WithTransaction<'a, R, Ctx>,
+    {
+        let (result, _, _) = self
+            .run_in_transaction(ctx, with_tx, false, |_| None)
+            .await?;
+        Ok(result)
+    }
+
+    /// `in_transaction` retrying the transaction aborted by contention.
+    /// the transaction is begun again with `retry_transaction` and `with_tx` is called again
+    /// with the cloned `ctx`, up to `transaction_max_attempts` times.
+    pub async fn in_transaction_with_retry<F, R, Ctx>(&self, ctx: Ctx, with_tx: F) -> Result<R>
+    where
+        F: for<'a> WithTransaction<'a, R, Ctx>,
+        Ctx: Clone,
+    {
+        let (result, _, _) = self
+            .run_in_transaction(ctx, with_tx, false, |ctx| Some(ctx.clone()))
+            .await?;
+        Ok(result)
+    }
+
+    /// `in_transaction` accepting more than `MAX_BATCH_WRTIE_SIZE` operations.
+    ///
+    /// NOT ATOMIC over the chunks: the first `MAX_BATCH_WRTIE_SIZE` operations are committed
+    /// with the transaction, and the rest are committed after it in the chunks of
+    /// `MAX_BATCH_WRTIE_SIZE` without the transaction, as `large_batch_write_with_report` with
+    /// `ordered`. the reads of the transaction don't guard the later chunks, and the chunks
+    /// written are not rolled back if a later one fails. the report tells the operations
+    /// not applied with their offsets in the operations added.
+    ///
+    /// ```ignore
+    /// let (_, report) = client.in_transaction_chunked(ctx, rebuild_index).await?;
+    /// if let Some(resume) = report.into_resume() {
+    ///     client.resume_large_batch_write(resume).await;
+    /// }
+    /// ```
+    pub async fn in_transaction_chunked<F, R, Ctx>(
+        &self,
+        ctx: Ctx,
+        with_tx: F,
+    ) -> Result<(R, ConcurrentBatchWriteReport)>
+    where
+        F: for<'a> WithTransaction<'a, R, Ctx>,
+    {
+        let (result, committed, overflow) = self
+            .run_in_transaction(ctx, with_tx, true, |_| None)
+            .await?;
+        let committed_num = committed.len();
+        let mut report = self
+            .resume_large_batch_write(BatchWriteResume {
+                ordered: true,
+                operations: overflow
+                    .into_iter()
+                    .enumerate()
+                    .map(|(offset, operation)| (committed_num + offset, operation))
+                    .collect(),
+            })
+            .await;
+        for chunk in report.chunks.iter_mut() {
+            chunk.chunk_index += 1;
+        }
+        report.chunks.insert(
+            0,
+            ChunkWriteResult {
+                chunk_index: 0,
+                operation_offsets: (0..committed_num).collect(),
+                result: Ok(committed.into_iter().map(Ok).collect()),
+            },
+        );
+        Ok((result, report))
+    }
+
+    /// the result of `with_tx`, the write results of the commit and the operations over
+    /// `MAX_BATCH_WRTIE_SIZE` not committed if `split_overflow`.
+    /// the aborted transaction is retried only if `retry_ctx` copies the context for the attempt.
+    async fn run_in_transaction<F, R, Ctx>(
+        &self,
+        ctx: Ctx,
+        with_tx: F,
+        split_overflow: bool,
+        retry_ctx: fn(&Ctx) -> Option<Ctx>,
+    ) -> Result<(R, Vec<WriteResult>, Vec<request::DocumentWriteOperation>)>
+    where
+        F: for<'a> WithTransaction<'a, R, Ctx>,
+    {
+        let mut ctx = Some(ctx);
+        let mut backoff = ExponentialBackoff::default();
+        let mut retry_transaction = Vec::<u8>::new();
+        let mut attempt = 1;
+        self.ensure_writable("BeginTransaction")?;
+        loop {
+            let tx = self
+                .firestore_client
+                .clone()
+                .begin_transaction(
+                    self.request_factory
+                        .new_begin_read_write_transaction_request(
+                            self.project_id.clone(),
+                            retry_transaction,
+                        ),
+                )
+                .await?
+                .into_inner()
+                .transaction;
+
+            let mut tx_ope = TransactionOperation::new(tx);
+            // the closure takes a clone, the client is shared by `&self`
+            let mut client = self.clone();
+            // the context is kept for the retries if copied
+            let attempt_ctx = match ctx.as_ref().and_then(retry_ctx) {
+                Some(attempt_ctx) => attempt_ctx,
+                None => ctx.take().expect("the context is kept for the retry"),
+            };
+            let maybe_panic_in_tx =
+                AssertUnwindSafe(with_tx.call(&mut client, &mut tx_ope, attempt_ctx))
+                    .catch_unwind()
+                    .await;
+
+            let err: FirestoreError;
+            match maybe_panic_in_tx {
+                Ok(result) => match result {
+                    Ok(success_value) => {
+                        let overflow =
+                            if split_overflow && tx_ope.operations.len() > MAX_BATCH_WRTIE_SIZE {
+                                tx_ope.operations.split_off(MAX_BATCH_WRTIE_SIZE)
+                            } else {
+                                Vec::new()
+                            };
+                        tx_ope.validate_commit_size()?;
+
+                        let operations = std::mem::take(&mut tx_ope.operations);
+                        match self
+                            .commit(operations, Some(tx_ope.transaction.clone()))
+                            .await
+                        {
+                            Ok(write_results) => {
+                                tx_ope.state = TransactionState::Committed;
+                                return Ok((success_value, write_results, overflow));
+                            }
+                            Err(e)
+                                if ctx.is_some()
+                                    && attempt < self.transaction_max_attempts
+                                    && e.is_aborted() =>
+                            {
+                                #[cfg(feature = "tracing")]
+                                tracing::warn!(attempt, error = %e, "retrying aborted transaction");
+                                if let Some(wait) = backoff.next_backoff() {
+                                    tokio::time::sleep(wait).await;
+                                }
+                                retry_transaction = tx_ope.transaction;
+                                attempt += 1;
+                                continue;
+                            }
+                            Err(e) if ctx.is_some() && e.is_aborted() => {
+                                self.events.emit(ClientEvent::RetryExhausted {
+                                    operation: "Commit".to_owned(),
+                                    attempts: attempt,
+                                    error: e.to_string(),
+                                });
+                                return Err(e);
+                            }
+                            Err(e) => return Err(e),
+                        }
+                    }
+                    Err(e) => err = FirestoreError::Callback(e),
+                },
+                Err(e) => {
+                    err =
+                        FirestoreError::Callback(anyhow!("panic occured in tx. rollback : {:?}", e))
+                }
+            }
+
+            // TODO(tacogips) need backoff?
+            tx_ope.state = TransactionState::RolledBack;
+            self.rollback(tx_ope.transaction).await?;
+            return Err(err);
+        }
+    }
+
+    /// `in_transaction` with the reads and the writes on `TransactionContext`.
+    /// the reads are in the transaction without passing the transaction id.
+    /// `with_tx` is called again on the retries, so it owns the values it captures and clones
+    /// them into the future.
+    ///
+    /// ```ignore
+    /// client
+    ///     .run_transaction(move |tx| {
+    ///         let path = path.clone();
+    ///         async move {
+    ///             let user: Option<User> = tx.get(&path).await?;
+    ///             tx.update(&path, UserVisits { visits: user.map_or(0, |u| u.visits) + 1 })?;
+    ///             Ok(())
+    ///         }
+    ///     })
+    ///     .await?;
+    /// ```
+    pub async fn run_transaction<F, Fut, R>(&self, with_tx: F) -> Result<R>
+    where
+        F: Fn(TransactionContext) -> Fut + 'static,
+        Fut: Future<Output = anyhow::Result<R>> + 'static,
+        R: 'static,
+    {
+        self.in_transaction_with_retry(Arc::new(with_tx), call_with_context)
+            .await
+    }
+
+    /// append the values missing in the array field without reading the document.
+    pub async fn array_union<F, V>(
+        &self,
+        document_path: String,
+        field_path: F,
+        values: Vec<V>,
+    ) -> Result<WriteResult>
+    where
+        F: Into<String>,
+        V: Into<FValue>,
+    {
+        let values = values.into_iter().map(|v| v.into()).collect();
+        let ope = new_write_ope_transform(
+            document_path,
+            field_path.into(),
+            FTransform::ArrayUnion(values),
+        );
+        Ok(self
+            .commit(vec![ope], None)
+            .await?
+            .pop()
+            .unwrap_or_default())
+    }
+
+    /// remove all the elements equal to the values from the array field without reading the document.
+    pub async fn array_remove<F, V>(
+        &self,
+        document_path: String,
+        field_path: F,
+        values: Vec<V>,
+    ) -> Result<WriteResult>
+    where
+        F: Into<String>,
+        V: Into<FValue>,
+    {
+        let values = values.into_iter().map(|v| v.into()).collect();
+        let ope = new_write_ope_transform(
+            document_path,
+            field_path.into(),
+            FTransform::ArrayRemove(values),
+        );
+        Ok(self
+            .commit(vec![ope], None)
+            .await?
+            .pop()
+            .unwrap_or_default())
+    }
+
+    /// replace the element at `index` of the array field.
+    /// firestore has no positional update, so the array is read and rewritten in a transaction.
+    /// the field names with dots are quoted in `field_path`. (e.g. "`a.b`.c")
+    pub async fn update_array_element<V>(
+        &self,
+        document_path: String,
+        field_path: String,
+        index: usize,
+        value: V,
+    ) -> Result<()>
+    where
+        V: Into<FValue>,
+    {
+        self.in_transaction_with_retry(
+            (document_path, field_path, index, value.into()),
+            update_array_element_in_tx,
+        )
+        .await
+    }
+
+    pub async fn begin_transaction(&self) -> Result<Vec<u8>> {
+        self.ensure_writable("BeginTransaction")?;
+        self.firestore_client
+            .clone()
+            .begin_transaction(
+                self.request_factory
+                    .new_begin_transaction_request(self.project_id.clone(), None),
+            )
+            .await
+            .map(|resp| resp.into_inner().transaction)
+            .map_err(FirestoreError::from)
+    }
+
+    /// begin a read-only transaction which reads a consistent snapshot at `read_time`
+    /// (or the latest if None) without taking locks.
+    pub async fn begin_read_only_transaction(
+        &self,
+        read_time: Option<SystemTime>,
+    ) -> Result<Vec<u8>> {
+        self.firestore_client
+            .clone()
+            .begin_transaction(
+                self.request_factory
+                    .new_begin_read_only_transaction_request(self.project_id.clone(), read_time),
+            )
+            .await
+            .map(|resp| resp.into_inner().transaction)
+            .map_err(FirestoreError::from)
+    }
+
+    /// run `with_tx` in a read-only transaction. pass the transaction id to the read methods
+    /// (e.g. `get_document`) to read from the same snapshot.
+    /// read-only transactions need neither commit nor rollback.
+    pub async fn read_only_transaction<F, R, Ctx>(
+        &self,
+        read_time: Option<SystemTime>,
+        ctx: Ctx,
+        with_tx: F,
+    ) -> Result<R>
+    where
+        F: for<'a> WithReadOnlyTransaction<'a, R, Ctx>,
+    {
+        let tx = self.begin_read_only_transaction(read_time).await?;
+        let mut client = self.clone();
+        match AssertUnwindSafe(with_tx.call(&mut client, tx, ctx))
+            .catch_unwind()
+            .await
+        {
+            Ok(result) => result.map_err(FirestoreError::Callback),
+            Err(e) => Err(FirestoreError::Callback(anyhow!(
+                "panic occured in read only tx : {:?}",
+                e
+            ))),
+        }
+    }
+
+    pub async fn commit(
+        &self,
+        operations: Vec<request::DocumentWriteOperation>,
+        transaction: Option<Vec<u8>>,
+    ) -> Result<Vec<WriteResult>> {
+        self.ensure_writable("Commit")?;
+        if let Some(recorder) = self.dry_run.as_ref() {
+            if let Some(transaction) = transaction {
+                self.rollback(transaction).await?;
+            }
+            return Ok(recorder.record_operations("Commit", &operations));
+        }
+        request::check_request_size(&operations)?;
+        self.firestore_client
+            .clone()
+            .commit(self.request_factory.new_commit_request(
+                self.project_id.clone(),
+                operations,
+                transaction,
+            ))
+            .await
+            .map(|resp| resp.into_inner().write_results)
+            .map_err(FirestoreError::from)
+    }
+
+    /// commit the masked update over `MAX_REQUEST_SIZE` split by
+    /// `DocumentWriteOperation::split_update_mask`, one commit for each part. the result is of
+    /// the last commit (which has the transform results).
+    ///
+    /// the update is not atomic. if a commit fails, the parts before it remain written.
+    pub async fn commit_split_update(
+        &self,
+        operation: request::DocumentWriteOperation,
+    ) -> Result<WriteResult> {
+        let mut write_result = None;
+        for part in operation.split_update_mask(MAX_REQUEST_SIZE)? {
+            write_result = self.commit(vec![part], None).await?.pop();
+        }
+        write_result.ok_or_else(|| FirestoreError::Internal("no write result returned".to_owned()))
+    }
+
+    pub async fn rollback(&self, transaction: Vec<u8>) -> Result<()> {
+        self.firestore_client
+            .clone()
+            .rollback(
+                self.request_factory
+                    .new_rollback_request(self.project_id.clone(), transaction),
+            )
+            .await
+            .map(|resp| resp.into_inner())
+            .map_err(FirestoreError::from)
+    }
+
+    pub async fn search_prefix_like<F, C>(
+        &self,
+        parent_path: Option<String>,
+        collection: String,
+        prefix_match: PrefixMatch,
+        consistency: C,
+        mut with_each_doc: F,
+    ) -> Result<i64>
+    where
+        F: FnMut(Document) -> anyhow::Result<()>,
+        C: Into<ReadConsistency>,
+    {
+        let PrefixMatch {
+            field,
+            prefix,
+            contain_exact_match,
+        } = prefix_match;
+        let query = QueryBuilder::collection(collection, false)
+            .filter_bin(&field, ">=", prefix.as_str())
+            .build();
+
+        let mut result_num = 0;
+        let mut result_stream = self
+            .firestore_client
+            .clone()
+            .run_query(self.request_factory.new_query_request(
+                self.project_id.clone(),
+                parent_path.unwrap_or("".to_owned()),
+                query,
+                self.consistency(consistency),
+            ))
+            .await?
+            .into_inner();
+
+        while let Some(each_response) = result_stream.message().await? {
+            match each_response.document {
+                Some(doc) => {
+                    // check prefix
+                    match doc.fields.get(&field) {
+                        None => break,
+                        Some(field_value) => match FValue::from(field_value.clone()).as_string() {
+                            None => break,
+                            Some(str_value) => {
+                                if !str_value.starts_with(&prefix) {
+                                    break;
+                                }
+                                if !contain_exact_match && *str_value == prefix {
+                                    continue;
+                                }
+                            }
+                        },
+                    }
+
+                    result_num += 1;
+                    with_each_doc(doc).map_err(FirestoreError::from_callback)?;
+                }
+                None => continue, //TODO(need to be interept?)
+            }
+        }
+        Ok(result_num)
+    }
+
+    pub async fn run_query<F, C>(
+        &self,
+        parent_path: Option<String>,
+        query: StructuredQuery,
+        consistency: C,
+        mut with_each_doc: F,
+    ) -> Result<i64>
+    where
+        F: FnMut(Document) -> anyhow::Result<()>,
+        C: Into<ReadConsistency>,
+    {
+        validate_field_paths(&query)?;
+        let query = self.default_field_masks.apply_to_query(query);
+        let cancellation = self.cancellation.clone();
+        cancellable(cancellation.as_ref(), async {
+            let mut result_num = 0;
+            let mut result_stream = self
+                .firestore_client
+                .clone()
+                .run_query(self.request_factory.new_query_request(
+                    self.project_id.clone(),
+                    parent_path.unwrap_or("".to_owned()),
+                    query,
+                    self.consistency(consistency),
+                ))
+                .await?
+                .into_inner();
+
+            while let Some(each_response) = result_stream.message().await? {
+                match each_response.document {
+                    Some(doc) => {
+                        result_num += 1;
+                        with_each_doc(doc).map_err(FirestoreError::from_callback)?
+                    }
+                    None => continue, //TODO(need to be interept?)
+                }
+            }
+            Ok(result_num)
+        })
+        .await
+    }
+
+    /// same as `run_query` but returns the documents as a stream.
+    pub async fn run_query_stream<C>(
+        &self,
+        parent_path: Option<String>,
+        query: StructuredQuery,
+        consistency: C,
+    ) -> Result<impl Stream<Item = Result<Document>>>
+    where
+        C: Into<ReadConsistency>,
+    {
+        validate_field_paths(&query)?;
+        let query = self.default_field_masks.apply_to_query(query);
+        Ok(self
+            .run_query_responses(parent_path, query, self.consistency(consistency))
+            .await?
+            .try_filter_map(|each_response| future::ready(Ok(each_response.document))))
+    }
+
+    async fn run_query_responses(
+        &self,
+        parent_path: Option<String>,
+        query: StructuredQuery,
+        consistency: ReadConsistency,
+    ) -> Result<impl Stream<Item = Result<RunQueryResponse>>> {
+        let request = self.request_factory.new_query_request(
+            self.project_id.clone(),
+            parent_path.unwrap_or("".to_owned()),
+            query,
+            consistency,
+        );
+        let cancellation = self.cancellation.clone();
+        let result_stream = cancellable(cancellation.as_ref(), async {
+            self.firestore_client
+                .clone()
+                .run_query(request)
+                .await
+                .map_err(FirestoreError::from)
+        })
+        .await?
+        .into_inner();
+
+        Ok(until_cancelled(
+            cancellation,
+            result_stream.map_err(FirestoreError::from),
+        ))
+    }
+
+    /// `run_query_stream` restarted after the last document delivered when the stream fails
+    /// with a retryable error (e.g. unavailable), up to `max_resumes` times. the documents
+    /// are delivered once and in the order of the query.
+    ///
+    /// the query needs `order_by` to restart from a cursor, and the order fields must be
+    /// selected if the query has a projection. the restarted queries read at the read time of
+    /// the first stream unless the consistency is given, so the documents are of a snapshot.
+    ///
+    /// ```ignore
+    /// let query = QueryBuilder::collection("orders".to_owned(), false)
+    ///     .order_by("created_at", OrderDirection::Asc)
+    ///     .build();
+    /// let orders = client.run_query_stream_resumable(None, query, None, 5)?;
+    /// ```
+    pub fn run_query_stream_resumable<C>(
+        &self,
+        parent_path: Option<String>,
+        query: StructuredQuery,
+        consistency: C,
+        max_resumes: usize,
+    ) -> Result<impl Stream<Item = Result<Document>>>
+    where
+        C: Into<ReadConsistency>,
+    {
+        validate_field_paths(&query)?;
+        let query = self.default_field_masks.apply_to_query(query);
+        validate_resumable_query(&query)?;
+
+        let state = ResumableQuery {
+            client: self.clone(),
+            parent_path,
+            query,
+            consistency: self.consistency(consistency),
+            last_document: None,
+            delivered: 0,
+            resumes_left: max_resumes,
+            backoff: ExponentialBackoff::default(),
+        };
+        let responses: Option<BoxStream<'static, Result<RunQueryResponse>>> = None;
+        Ok(stream::unfold(
+            Some((state, responses)),
+            |current| async move {
+                let (mut state, mut responses) = current?;
+                loop {
+                    let next = match responses.as_mut() {
+                        Some(responses) => responses.next().await,
+                        None => match state.open().await {
+                            Ok(Some(opened)) => {
+                                responses = Some(opened);
+                                continue;
+                            }
+                            Ok(None) => return None,
+                            Err(e) => Some(Err(e)),
+                        },
+                    };
+                    match next {
+                        Some(Ok(response)) => {
+                            if let (ReadConsistency::Default, Some(read_time)) =
+                                (&state.consistency, response.read_time.clone())
+                            {
+                                state.consistency =
+                                    ReadConsistency::ReadTime(SystemTime::from(read_time));
+                            }
+                            if let Some(document) = response.document {
+                                state.delivered += 1;
+                                state.last_document = Some(document.clone());
+                                return Some((Ok(document), Some((state, responses))));
+                            }
+                        }
+                        Some(Err(e)) if e.is_retryable() && state.resumes_left > 0 => {
+                            #[cfg(feature = "tracing")]
+                            tracing::warn!(
+                                delivered = state.delivered,
+                                "resuming the query stream after error: {}",
+                                e
+                            );
+                            state.resumes_left -= 1;
+                            responses = None;
+                            if let Some(wait) = state.backoff.next_backoff() {
+                                tokio::time::sleep(wait).await;
+                            }
+                        }
+                        Some(Err(e)) => return Some((Err(e), None)),
+                        None => return None,
+                    }
+                }
+            },
+        ))
+    }
+
+    /// run the query over all the collections of the id at any depth, e.g. "comments" of
+    /// "/posts/p1/comments" and "/users/u1/posts/p2/comments". the collections of the builder
+    /// are replaced, and the query is rooted at the database.
+    pub async fn run_collection_group_query(
+        &self,
+        collection_id: String,
+        query: QueryBuilder,
+    ) -> Result<impl Stream<Item = Result<Document>>> {
+        let query = query.with_collection_group(collection_id).try_build()?;
+        self.run_query_stream(None, query, None).await
+    }
+
+    /// run the queries of `QueryBuilder::build_queries` concurrently and union the results.
+    /// for `filter_in` etc with more than `MAX_IN_CLAUS_NUM` values.
+    /// at most `QUERY_UNION_CONCURRENCY` queries run at once.
+    ///
+    /// the documents matched by multiple queries are returned once. the order of the
+    /// documents is by the queries, not by the order clauses over all the results.
+    /// the limit of the builder caps the total.
+    pub async fn run_query_union(
+        &self,
+        parent_path: Option<String>,
+        query: QueryBuilder,
+    ) -> Result<Vec<Document>> {
+        let limit = query.limit_num();
+        let queries = query.build_queries()?;
+        let results: Vec<Vec<Document>> = stream::iter(queries)
+            .map(|query| {
+                let client = self.clone();
+                let parent_path = parent_path.clone();
+                async move {
+                    client
+                        .run_query_stream(parent_path, query, None)
+                        .await?
+                        .try_collect::<Vec<Document>>()
+                        .await
+                }
+            })
+            .buffered(QUERY_UNION_CONCURRENCY)
+            .try_collect()
+            .await?;
+
+        let mut names = HashSet::new();
+        let mut documents: Vec<Document> = results
+            .into_iter()
+            .flatten()
+            .filter(|doc| names.insert(doc.name.clone()))
+            .collect();
+        if let Some(limit) = limit {
+            documents.truncate(limit.max(0) as usize);
+        }
+        Ok(documents)
+    }
+
+    /// `run_query_stream` deserializing each document into `T`
+    pub async fn run_query_stream_as<T, C>(
+        &self,
+        parent_path: Option<String>,
+        query: StructuredQuery,
+        consistency: C,
+    ) -> Result<impl Stream<Item = Result<T>>>
+    where
+        T: DeserializeOwned,
+        C: Into<ReadConsistency>,
+    {
+        let casing = self.field_casing.clone();
+        Ok(self
+            .run_query_stream(parent_path, query, consistency)
+            .await?
+            .and_then(move |doc| future::ready(decode_document_with_casing(doc, casing.as_ref()))))
+    }
+
+    /// the paths of the documents in the collection ordered by the name, without reading the fields.
+    /// for reconciliations with the keys of other systems.
+    ///
+    /// the documents are read page by page with keys only queries which start after the last name.
+    pub fn list_document_names(
+        &self,
+        parent_path: Option<String>,
+        collection_id: String,
+    ) -> impl Stream<Item = Result<FDocumentPath>> {
+        let client = self.clone();
+        // (last document name, finished)
+        stream::try_unfold(
+            (client, None::<String>, false),
+            move |(client, last_name, finished)| {
+                let parent_path = parent_path.clone();
+                let collection_id = collection_id.clone();
+                async move {
+                    if finished {
+                        return Ok::<_, FirestoreError>(None);
+                    }
+                    let start_after = last_name.map(|name| Cursor {
+                        values: vec![Value {
+                            value_type: Some(ValueType::ReferenceValue(name)),
+                        }],
+                        before: false,
+                    });
+                    let query = QueryBuilder::collection(collection_id, false)
+                        .select(vec!["__name__"])
+                        .order_by("__name__", OrderDirection::Asc)
+                        .limit(LIST_DOCUMENT_NAMES_PAGE_SIZE)
+                        .build_with_cursor(start_after, None);
+
+                    let names: Vec<String> = client
+                        .run_query_stream(parent_path, query, None)
+                        .await?
+                        .map_ok(|doc| doc.name)
+                        .try_collect()
+                        .await?;
+
+                    let finished = names.len() < LIST_DOCUMENT_NAMES_PAGE_SIZE as usize;
+                    let last_name = names.last().cloned();
+                    Ok(Some((names, (client, last_name, finished))))
+                }
+            },
+        )
+        .map_ok(|names| stream::iter(names.into_iter().map(|name| FDocumentPath::parse(&name))))
+        .try_flatten()
+    }
+
+    /// up to `n` documents of the collection chosen approximately at random, without a full scan.
+    /// for spot checks and dataset sampling.
+    ///
+    /// each probe reads the first document at or after a random auto id (wrapping around to the
+    /// first document), so the documents after the wider gaps of the ids are chosen more often
+    /// (e.g. the ids are not auto ids). fewer than `n` if the collection doesn't have enough.
+    pub async fn sample_documents(
+        &self,
+        parent_path: Option<String>,
+        collection_id: String,
+        n: usize,
+    ) -> Result<Vec<Document>> {
+        sample_by_probes(n, || async {
+            let random_name = request::fmt_document_path(
+                &self.project_id,
+                doc_path(
+                    parent_path.clone(),
+                    collection_id.clone(),
+                    request::new_auto_id(),
+                ),
+            );
+            let at_random_name = Cursor {
+                values: vec![Value {
+                    value_type: Some(ValueType::ReferenceValue(random_name)),
+                }],
+                before: true,
+            };
+            let probed = self
+                .first_document_by_name(parent_path.clone(), &collection_id, Some(at_random_name))
+                .await?;
+            match probed {
+                Some(doc) => Ok(Some(doc)),
+                None => {
+                    self.first_document_by_name(parent_path.clone(), &collection_id, None)
+                        .await
+                }
+            }
+        })
+        .await
+    }
+
+    async fn first_document_by_name(
+        &self,
+        parent_path: Option<String>,
+        collection_id: &str,
+        start_at: Option<Cursor>,
+    ) -> Result<Option<Document>> {
+        let query = QueryBuilder::collection(collection_id.to_owned(), false)
+            .order_by("__name__", OrderDirection::Asc)
+            .limit(1)
+            .build_with_cursor(start_at, None);
+        let docs: Vec<Document> = self
+            .run_query_stream(parent_path, query, None)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(docs.into_iter().next())
+    }
+
+    /// the pages of the query. each page is queried again starting after the last document
+    /// of the previous page, ordered by `__name__` after the order clauses of the query.
+    pub fn paginate_query(
+        &self,
+        parent_path: Option<String>,
+        query: QueryBuilder,
+        page_size: i32,
+    ) -> impl Stream<Item = Result<Vec<Document>>> {
+        // (last document, fetched count, finished)
+        let initial_state = (self.clone(), None::<Document>, 0i32, false);
+        stream::try_unfold(
+            initial_state,
+            move |(client, last_document, fetched, finished)| {
+                let parent_path = parent_path.clone();
+                let page_query = if finished {
+                    Ok(None)
+                } else {
+                    query.build_page(last_document.as_ref(), fetched, page_size)
+                };
+                async move {
+                    let page_query = match page_query? {
+                        Some(page_query) => page_query,
+                        None => return Ok::<_, FirestoreError>(None),
+                    };
+                    let requested = page_query.limit.unwrap_or(page_size);
+                    let documents: Vec<Document> = client
+                        .run_query_stream(parent_path, page_query, None)
+                        .await?
+                        .try_collect()
+                        .await?;
+                    if documents.is_empty() {
+                        return Ok(None);
+                    }
+
+                    let finished = (documents.len() as i32) < requested;
+                    let fetched = fetched + documents.len() as i32;
+                    let last_document = documents.last().cloned();
+                    Ok(Some((
+                        documents,
+                        (client, last_document, fetched, finished),
+                    )))
+                }
+            },
+        )
+    }
+
+    /// `paginate_query` flattened into the documents.
+    pub fn paginate_query_documents(
+        &self,
+        parent_path: Option<String>,
+        query: QueryBuilder,
+        page_size: i32,
+    ) -> impl Stream<Item = Result<Document>> {
+        self.paginate_query(parent_path, query, page_size)
+            .map_ok(|documents| stream::iter(documents.into_iter().map(Ok)))
+            .try_flatten()
+    }
+
+    /// infer the schema of the collection from the first `sample_num` documents.
+    /// e.g. to generate the struct definitions with `DocumentSchema::to_rust_struct`.
+    pub async fn infer_schema(
+        &self,
+        parent_path: Option<String>,
+        collection_id: String,
+        sample_num: i32,
+    ) -> Result<DocumentSchema> {
+        let query = QueryBuilder::collection(collection_id, false)
+            .limit(sample_num.max(1))
+            .build();
+        let mut schema = DocumentSchema::new();
+        self.run_query(parent_path, query, None, |document| {
+            schema.observe(&FFields::from_grpc_doc(document));
+            Ok(())
+        })
+        .await?;
+        Ok(schema)
+    }
+
+    /// run the aggregations on the server and returns the results keyed by the alias.
+    pub async fn run_aggregation_query<C>(
+        &self,
+        parent_path: Option<String>,
+        query: StructuredAggregationQuery,
+        consistency: C,
+    ) -> Result<HashMap<String, FValue>>
+    where
+        C: Into<ReadConsistency>,
+    {
+        if let Some(structured_aggregation_query::QueryType::StructuredQuery(query)) =
+            &query.query_type
+        {
+            validate_field_paths(query)?;
+        }
+        let mut result_stream = self
+            .firestore_client
+            .clone()
+            .run_aggregation_query(self.request_factory.new_aggregation_query_request(
+                self.project_id.clone(),
+                parent_path.unwrap_or("".to_owned()),
+                query,
+                self.consistency(consistency),
+            ))
+            .await?
+            .into_inner();
+
+        // an aggregation query returns a single result.
+        while let Some(each_response) = result_stream.message().await? {
+            if let Some(result) = each_response.result {
+                return Ok(result
+                    .aggregate_fields
+                    .into_iter()
+                    .map(|(alias, value)| (alias, FValue::from_grpc_value(value)))
+                    .collect());
+            }
+        }
+        Err(FirestoreError::Internal(
+            "no aggregation result returned".to_owned(),
+        ))
+    }
+
+    /// count the documents matching the query without fetching them.
+    pub async fn count(&self, parent_path: Option<String>, query: QueryBuilder) -> Result<i64> {
+        let value = self
+            .run_single_aggregation(parent_path, query, Aggregation::count("count"))
+            .await?;
+        value.into_int().ok_or_else(|| {
+            FirestoreError::Internal("count aggregation returned non integer".to_owned())
+        })
+    }
+
+    /// sum of the field. the result is `FValue::Int` if all the values are integers
+    /// and it doesn't overflow, otherwise `FValue::Double`.
+    pub async fn sum<F: Into<String>>(
+        &self,
+        parent_path: Option<String>,
+        query: QueryBuilder,
+        field: F,
+    ) -> Result<FValue> {
+        self.run_single_aggregation(parent_path, query, Aggregation::sum("sum", field))
+            .await
+    }
+
+    /// average of the field. `None` if no numeric value is found.
+    pub async fn avg<F: Into<String>>(
+        &self,
+        parent_path: Option<String>,
+        query: QueryBuilder,
+        field: F,
+    ) -> Result<Option<f64>> {
+        let value = self
+            .run_single_aggregation(parent_path, query, Aggregation::avg("avg", field))
+            .await?;
+        match value {
+            FValue::NullValue => Ok(None),
+            FValue::Double(v) => Ok(Some(v)),
+            FValue::Int(v) => Ok(Some(v as f64)),
+            other => Err(FirestoreError::Internal(format!(
+                "avg aggregation returned unexpected value {:?}",
+                other
+            ))),
+        }
+    }
+
+    async fn run_single_aggregation(
+        &self,
+        parent_path: Option<String>,
+        query: QueryBuilder,
+        aggregation: Aggregation,
+    ) -> Result<FValue> {
+        let alias = aggregation.alias().to_owned();
+        let mut results = self
+            .run_aggregation_query(
+                parent_path,
+                query.build_aggregation(vec![aggregation]),
+                None,
+            )
+            .await?;
+        results.remove(&alias).ok_or_else(|| {
+            FirestoreError::Internal(format!("aggregation result not found: {}", alias))
+        })
+    }
+
+    /// listen to the changes of the targets. the stream continues until the server closes it.
+    pub async fn listen(
+        &self,
+        targets: Vec<Target>,
+    ) -> Result<impl Stream<Item = Result<ListenResponse>>> {
+        let requests: Vec<ListenRequest> = targets
+            .into_iter()
+            .map(|target| {
+                let mut request = self
+                    .request_factory
+                    .new_listen_request(self.project_id.clone(), target);
+                request::add_labels(&mut request.labels, &self.labels);
+                request
+            })
+            .collect();
+        // keep the request stream open. the server may close the response stream when it ends.
+        let requests = stream::iter(requests).chain(stream::pending());
+
+        let cancellation = self.cancellation.clone();
+        let response = cancellable(cancellation.as_ref(), async {
+            self.firestore_client
+                .clone()
+                .listen(requests)
+                .await
+                .map_err(FirestoreError::from)
+        })
+        .await?;
+        let (stop, guard) = self
+            .components
+            .register_task("listen", ComponentTier::Stream);
+        Ok(until_stopped(
+            until_cancelled(
+                cancellation,
+                response.into_inner().map_err(FirestoreError::from),
+            ),
+            stop,
+            guard,
+        ))
+    }
+
+    pub async fn partition_query_all(
+        &self,
+        document_path: String,
+        query: StructuredQuery,
+        max_partition_count: i64,
+        chunk_size: i32,
+    ) -> Result<Vec<Cursor>> {
+        //TODO(tacogips) to be procedual macro
+        let ref mut next_token = "".to_owned();
+        let mut result = Vec::<Cursor>::new();
         loop {
             let response = self
                 .partition_query_chunk(
@@ -339,12 +2187,100 @@ impl FirestoreClient {
             if next_token.is_empty() {
                 break;
             }
-        }
-        return Ok(result);
+        }
+        return Ok(result);
+    }
+
+    /// run the partitions of `query` split by `partitions` (from `partition_query_all`)
+    /// concurrently with the clones of the client, and pass the documents to `sink`
+    /// in no particular order. returns the number of the documents.
+    ///
+    /// ```ignore
+    /// let query = QueryBuilder::collection_group("orders".to_owned()).build();
+    /// let partitions = client.partition_query_all("".to_owned(), query.clone(), 16, 100).await?;
+    /// client
+    ///     .run_partitioned_query(None, query, partitions, 8, |doc| export(doc))
+    ///     .await?;
+    /// ```
+    pub async fn run_partitioned_query<F>(
+        &self,
+        parent_path: Option<String>,
+        query: StructuredQuery,
+        partitions: Vec<Cursor>,
+        concurrency: usize,
+        mut sink: F,
+    ) -> Result<i64>
+    where
+        F: FnMut(Document) -> anyhow::Result<()>,
+    {
+        let mut documents = Box::pin(self.run_partitioned_query_stream(
+            parent_path,
+            query,
+            partitions,
+            concurrency,
+        ));
+        let mut result_num = 0;
+        while let Some(document) = documents.try_next().await? {
+            result_num += 1;
+            sink(document).map_err(FirestoreError::from_callback)?;
+        }
+        Ok(result_num)
+    }
+
+    /// `run_partitioned_query` as a stream. at most `concurrency` partitions are read at once
+    /// on a spawned task, so it must be called within a tokio runtime.
+    /// the reads stop when the stream is dropped.
+    pub fn run_partitioned_query_stream(
+        &self,
+        parent_path: Option<String>,
+        query: StructuredQuery,
+        partitions: Vec<Cursor>,
+        concurrency: usize,
+    ) -> impl Stream<Item = Result<Document>> {
+        let (sender, receiver) = mpsc::channel(PARTITIONED_QUERY_BUFFER_SIZE);
+        let queries = partition_queries(query, &partitions);
+        let client = self.clone();
+        let (stop, guard) = self
+            .components
+            .register_task("partitioned_query", ComponentTier::Stream);
+        tokio::spawn(async move {
+            let _guard = guard;
+            let reads = stream::iter(queries)
+                .map(|query| {
+                    let client = client.clone();
+                    let parent_path = parent_path.clone();
+                    let sender = sender.clone();
+                    async move {
+                        let documents = client.run_query_stream(parent_path, query, None).await;
+                        let mut documents = match documents {
+                            Ok(documents) => Box::pin(documents),
+                            Err(e) => {
+                                let _ = sender.send(Err(e)).await;
+                                return;
+                            }
+                        };
+                        while let Some(document) = documents.next().await {
+                            let failed = document.is_err();
+                            if sender.send(document).await.is_err() || failed {
+                                return;
+                            }
+                        }
+                    }
+                })
+                .buffer_unordered(concurrency.max(1))
+                .for_each(|_| future::ready(()));
+            tokio::select! {
+                _ = reads => {}
+                _ = stop.cancelled() => {}
+            }
+        });
+        stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|document| (document, receiver))
+        })
     }
 
     pub async fn partition_query_chunk(
-        &mut self,
+        &self,
         document_path: String,
         query: StructuredQuery,
         max_partition_count: i64,
@@ -353,7 +2289,8 @@ impl FirestoreClient {
     ) -> Result<(Vec<Cursor>, String)> {
         return self
             .firestore_client
-            .partition_query(request::new_partition_query_request(
+            .clone()
+            .partition_query(self.request_factory.new_partition_query_request(
                 self.project_id.clone(),
                 document_path,
                 query,
@@ -366,11 +2303,11 @@ impl FirestoreClient {
                 let result = resp.into_inner();
                 (result.partitions, result.next_page_token)
             })
-            .map_err(|e| GrpcErrorStatus::from(e).into());
+            .map_err(FirestoreError::from);
     }
 
     pub async fn update_document<D>(
-        &mut self,
+        &self,
         document_path: String,
         document: D,
         update_field_mask: Option<Vec<String>>,
@@ -379,34 +2316,60 @@ impl FirestoreClient {
     where
         D: Into<HashMap<String, Value>>,
     {
+        self.ensure_writable("UpdateDocument")?;
+        let document = document.into();
+        request::check_update_size(&document_path, &document, update_field_mask.as_deref())?;
+        if let Some(recorder) = self.dry_run.as_ref() {
+            recorder.record(DryRunWrite::from_document(
+                "UpdateDocument",
+                "update",
+                document_path.clone(),
+                &document,
+                update_field_mask,
+            ));
+            return Ok(synthetic_document(
+                &self.project_id,
+                &document_path,
+                document,
+            ));
+        }
         return self
             .firestore_client
-            .update_document(request::new_update_document_request(
+            .clone()
+            .update_document(self.request_factory.new_update_document_request(
                 self.project_id.clone(),
                 document_path,
-                document.into(),
+                document,
                 update_field_mask,
                 response_field_mask,
             ))
             .await
             .map(|resp| resp.into_inner())
-            .map_err(|e| GrpcErrorStatus::from(e).into());
+            .map_err(FirestoreError::from);
     }
 
-    pub async fn delete_document(&mut self, document_path: String) -> Result<()> {
+    pub async fn delete_document(&self, document_path: String) -> Result<()> {
+        self.ensure_writable("DeleteDocument")?;
+        if let Some(recorder) = self.dry_run.as_ref() {
+            recorder.record(DryRunWrite::delete("DeleteDocument", document_path));
+            return Ok(());
+        }
         return self
             .firestore_client
-            .delete_document(request::new_delete_document_request(
-                self.project_id.clone(),
-                document_path,
-            ))
+            .clone()
+            .delete_document(
+                self.request_factory
+                    .new_delete_document_request(self.project_id.clone(), document_path),
+            )
             .await
             .map(|resp| resp.into_inner())
-            .map_err(|e| GrpcErrorStatus::from(e).into());
+            .map_err(FirestoreError::from);
     }
 
+    /// the id is assigned by the server if `document_id` is empty. the path of the created
+    /// document is `FDocumentPath::from_document` of the response (see `create_document_auto_id`).
     pub async fn create_document<D>(
-        &mut self,
+        &self,
         parent_path: Option<String>,
         collection_id: String,
         document_id: String,
@@ -415,198 +2378,485 @@ impl FirestoreClient {
     where
         D: Into<HashMap<String, Value>>,
     {
+        self.ensure_writable("CreateDocument")?;
+        let document = document.into();
+        if let Some(recorder) = self.dry_run.as_ref() {
+            let document_id = if document_id.is_empty() {
+                request::new_auto_id()
+            } else {
+                document_id
+            };
+            let document_path = doc_path(parent_path, collection_id, document_id);
+            recorder.record(DryRunWrite::from_document(
+                "CreateDocument",
+                "create",
+                document_path.clone(),
+                &document,
+                None,
+            ));
+            return Ok(synthetic_document(
+                &self.project_id,
+                &document_path,
+                document,
+            ));
+        }
         return self
             .firestore_client
-            .create_document(request::new_create_document_request(
+            .clone()
+            .create_document(self.request_factory.new_create_document_request(
                 self.project_id.clone(),
                 parent_path.unwrap_or("".to_owned()),
                 collection_id,
                 document_id,
-                document.into(),
+                document,
                 None,
             ))
             .await
             .map(|resp| resp.into_inner())
-            .map_err(|e| GrpcErrorStatus::from(e).into());
+            .map_err(FirestoreError::from);
+    }
+
+    /// create the document with the id assigned by the server.
+    /// returns the path of the document with the assigned id, and the created document.
+    pub async fn create_document_auto_id<D>(
+        &self,
+        parent_path: Option<String>,
+        collection_id: String,
+        document: D,
+    ) -> Result<(FDocumentPath, Document)>
+    where
+        D: Into<HashMap<String, Value>>,
+    {
+        let created = self
+            .create_document(parent_path, collection_id, "".to_owned(), document)
+            .await?;
+        let path = FDocumentPath::from_document(&created)?;
+        Ok((path, created))
+    }
+
+    /// open the Write stream, or resume it from the token of the previous stream.
+    pub async fn open_write_stream(
+        &self,
+        resume_from: Option<WriteStreamToken>,
+    ) -> Result<WriteStream> {
+        self.ensure_writable("Write")?;
+        if self.dry_run.is_some() {
+            return Err(FirestoreError::invalid_argument(
+                "the Write stream is not available in dry-run",
+            ));
+        }
+        WriteStream::open(
+            &mut self.firestore_client.clone(),
+            Arc::clone(&self.request_factory),
+            Arc::clone(&self.labels),
+            self.project_id.clone(),
+            resume_from,
+            self.cancellation.clone(),
+        )
+        .await
     }
 
-    //TODO(tacogips)
+    /// write each chunk of the operations in order on a Write stream.
+    /// returns the number of the write results.
     pub async fn stream_write<F>(
-        &mut self,
-        _operations: impl Stream<Item = Vec<request::DocumentWriteOperation>> + Unpin,
-        _with_each_response: F,
-        _stream_id: Option<String>,
-        _stream_token: Option<Vec<u8>>,
+        &self,
+        mut operations: impl Stream<Item = Vec<request::DocumentWriteOperation>> + Unpin,
+        mut with_each_response: F,
+        stream_id: Option<String>,
+        stream_token: Option<Vec<u8>>,
     ) -> Result<usize>
     where
-        F: FnMut(Vec<WriteResult>) -> Result<()>,
+        F: FnMut(Vec<WriteResult>) -> anyhow::Result<()>,
     {
-        unimplemented!(
-            "could not write without error. The Firestore stream write API might be broken? "
-        )
-        //if stream_id.is_none() {
-        //    let mut reqs = Vec::new();
-        //    reqs.push(request::new_start_stream_write_request(
-        //        self.project_id.clone(),
-        //        stream_id.clone(),
-        //    ));
-
-        //    let mut begin_stream_reasult = self
-        //        .firestore_client
-        //        .write(stream::iter(reqs))
-        //        .await?
-        //        .into_inner();
-        //    while let Some(resp) = begin_stream_reasult.message().await? {
-        //        stream_id = Some(resp.stream_id);
-        //        stream_token = Some(resp.stream_token);
-        //    }
-        //}
-
-        //let mut result_num: usize = 0;
-
-        //while let Some(each_opes) = operations.next().await {
-        //    let mut reqs = Vec::new();
-        //    let req = request::new_stream_write_request(
-        //        self.project_id.clone(),
-        //        each_opes,
-        //        stream_id.clone().unwrap(),
-        //        stream_token.clone().unwrap(),
-        //    );
-        //    reqs.push(req);
-
-        //    let mut stream_result = self
-        //        .firestore_client
-        //        .write(stream::iter(reqs))
-        //        .await?
-        //        .into_inner();
-
-        //    while let Some(each_response) = stream_result.message().await? {
-        //        stream_id = Some(each_response.stream_id);
-        //        stream_token = Some(each_response.stream_token);
-
-        //        result_num += each_response.write_results.len();
-        //        with_each_response(each_response.write_results)?;
-        //    }
-        //}
-
-        //let mut reqs = Vec::new();
-        //reqs.push(request::new_finish_stream_write_request(
-        //    self.project_id.clone(),
-        //    stream_token.unwrap(),
-        //));
-
-        //let mut finish_stream_reasult = self
-        //    .firestore_client
-        //    .write(stream::iter(reqs))
-        //    .await?
-        //    .into_inner();
-        //if let Ok(Some(resp)) = finish_stream_reasult.message().await {
-        //    Ok(result_num)
-        //} else {
-        //    Err(anyhow!("failed to finish stream"))
-        //}
+        if let Some(recorder) = self.dry_run.as_ref() {
+            self.ensure_writable("Write")?;
+            let mut result_num: usize = 0;
+            while let Some(each_opes) = operations.next().await {
+                let write_results = recorder.record_operations("Write", &each_opes);
+                result_num += write_results.len();
+                with_each_response(write_results).map_err(FirestoreError::Callback)?;
+            }
+            return Ok(result_num);
+        }
+        let resume_from = stream_id
+            .zip(stream_token)
+            .map(|(stream_id, stream_token)| WriteStreamToken {
+                stream_id,
+                stream_token,
+            });
+        let mut write_stream = self.open_write_stream(resume_from).await?;
+
+        let mut result_num: usize = 0;
+        while let Some(each_opes) = operations.next().await {
+            let write_results = write_stream.write(each_opes).await?;
+            result_num += write_results.len();
+            with_each_response(write_results).map_err(FirestoreError::Callback)?;
+        }
+        write_stream.close().await?;
+        Ok(result_num)
     }
 
     pub async fn large_batch_write(
-        &mut self,
+        &self,
         operations: Vec<request::DocumentWriteOperation>,
     ) -> Result<Vec<WriteResult>> {
+        self.large_batch_write_with_checkpoint(operations, false, |_, _| Ok(()))
+            .await
+    }
+
+    /// write the operations chunk by chunk, calling `with_each_checkpoint` after each chunk is written.
+    ///
+    /// BatchWrite may apply the writes in a chunk out of order. if `ordered` is true,
+    /// each chunk is written by a (non transactional) commit instead, so the operations are
+    /// applied strictly in the order passed.
+    /// if an error occurred, the last checkpoint tells the operations that have been written already.
+    pub async fn large_batch_write_with_checkpoint<F>(
+        &self,
+        operations: Vec<request::DocumentWriteOperation>,
+        ordered: bool,
+        mut with_each_checkpoint: F,
+    ) -> Result<Vec<WriteResult>>
+    where
+        F: FnMut(BatchWriteCheckpoint, &[WriteResult]) -> anyhow::Result<()>,
+    {
         let mut result = Vec::new();
-        for chunk in operations.chunks(MAX_BATCH_WRTIE_SIZE).into_iter() {
-            let mut each_result = self.batch_write(chunk.to_vec()).await?;
+        let mut written_operation_num = 0;
+        for (chunk_index, chunk) in operations.chunks(MAX_BATCH_WRTIE_SIZE).enumerate() {
+            let mut each_result = if ordered {
+                self.commit(chunk.to_vec(), None).await?
+            } else {
+                self.batch_write(chunk.to_vec()).await?
+            };
+
+            written_operation_num += chunk.len();
+            with_each_checkpoint(
+                BatchWriteCheckpoint {
+                    chunk_index,
+                    written_operation_num,
+                },
+                &each_result,
+            )
+            .map_err(FirestoreError::Callback)?;
             result.append(&mut each_result)
         }
         Ok(result)
     }
 
+    /// `large_batch_write_with_checkpoint` reporting which operations were applied instead of failing.
+    /// the chunks after the first chunk not fully written are not attempted.
+    ///
+    /// ```ignore
+    /// let mut report = client.large_batch_write_with_report(operations, false).await;
+    /// while let Some(resume) = report.into_resume() {
+    ///     report = client.resume_large_batch_write(resume).await;
+    /// }
+    /// ```
+    pub async fn large_batch_write_with_report(
+        &self,
+        operations: Vec<request::DocumentWriteOperation>,
+        ordered: bool,
+    ) -> ConcurrentBatchWriteReport {
+        self.resume_large_batch_write(BatchWriteResume {
+            ordered,
+            operations: operations.into_iter().enumerate().collect(),
+        })
+        .await
+    }
+
+    /// write the operations not applied by `large_batch_write_with_report`.
+    /// the offsets in the report are the ones of the operations passed first.
+    pub async fn resume_large_batch_write(
+        &self,
+        resume: BatchWriteResume,
+    ) -> ConcurrentBatchWriteReport {
+        let BatchWriteResume {
+            ordered,
+            operations,
+        } = resume;
+        let mut chunks = Vec::new();
+        let mut unapplied = Vec::new();
+        let mut operation_chunks = operations.chunks(MAX_BATCH_WRTIE_SIZE).enumerate();
+        for (chunk_index, chunk) in operation_chunks.by_ref() {
+            let chunk_operations = chunk
+                .iter()
+                .map(|(_, operation)| operation.clone())
+                .collect();
+            let result = if ordered {
+                self.commit(chunk_operations, None)
+                    .await
+                    .map(|results| results.into_iter().map(Ok).collect())
+            } else {
+                self.batch_write_with_status(chunk_operations).await
+            };
+            let chunk_result = ChunkWriteResult {
+                chunk_index,
+                operation_offsets: chunk.iter().map(|(offset, _)| *offset).collect(),
+                result,
+            };
+            unapplied.extend(chunk_result.unapplied(chunk));
+            let all_written = chunk_result.is_all_written();
+            chunks.push(chunk_result);
+            if !all_written {
+                break;
+            }
+        }
+        // not attempted
+        for (_, chunk) in operation_chunks {
+            unapplied.extend(chunk.iter().cloned());
+        }
+        ConcurrentBatchWriteReport {
+            chunks,
+            resume: BatchWriteResume {
+                ordered,
+                operations: unapplied,
+            },
+        }
+    }
+
+    /// `large_batch_write` writing at most `max_in_flight` chunks concurrently with the clones of the client
+    /// with `Priority::Batch`. the writes of the chunks are not ordered. a failed chunk doesn't stop the others,
+    /// the errors are reported per chunk and per write.
+    pub async fn large_batch_write_concurrent(
+        &self,
+        operations: Vec<request::DocumentWriteOperation>,
+        max_in_flight: usize,
+    ) -> ConcurrentBatchWriteReport {
+        let semaphore = Arc::new(Semaphore::new(max_in_flight.max(1)));
+        let operations: Vec<(usize, request::DocumentWriteOperation)> =
+            operations.into_iter().enumerate().collect();
+        let writes =
+            operations
+                .chunks(MAX_BATCH_WRTIE_SIZE)
+                .enumerate()
+                .map(|(chunk_index, chunk)| {
+                    let client = self.clone().with_priority(Priority::Batch);
+                    let semaphore = Arc::clone(&semaphore);
+                    async move {
+                        let _permit = semaphore.acquire().await;
+                        let chunk_operations = chunk
+                            .iter()
+                            .map(|(_, operation)| operation.clone())
+                            .collect();
+                        let chunk_result = ChunkWriteResult {
+                            chunk_index,
+                            operation_offsets: chunk.iter().map(|(offset, _)| *offset).collect(),
+                            result: client.batch_write_with_status(chunk_operations).await,
+                        };
+                        let unapplied = chunk_result.unapplied(chunk);
+                        (chunk_result, unapplied)
+                    }
+                });
+        let (chunks, unapplied): (Vec<_>, Vec<_>) =
+            future::join_all(writes).await.into_iter().unzip();
+        ConcurrentBatchWriteReport {
+            chunks,
+            resume: BatchWriteResume {
+                ordered: false,
+                operations: unapplied.into_iter().flatten().collect(),
+            },
+        }
+    }
+
     pub async fn batch_write(
-        &mut self,
+        &self,
         operations: Vec<request::DocumentWriteOperation>,
     ) -> Result<Vec<WriteResult>> {
+        self.ensure_writable("BatchWrite")?;
         if operations.len() > MAX_BATCH_WRTIE_SIZE {
-            return Err(anyhow!(
+            return Err(FirestoreError::invalid_argument(format!(
                 "max batch write size = {} but passed {}",
                 MAX_BATCH_WRTIE_SIZE,
                 operations.len()
-            ));
+            )));
+        }
+        request::check_request_size(&operations)?;
+        if let Some(recorder) = self.dry_run.as_ref() {
+            return Ok(recorder.record_operations("BatchWrite", &operations));
         }
 
         return self
             .firestore_client
-            .batch_write(request::new_batch_write_request(
-                self.project_id.clone(),
-                operations,
-            ))
+            .clone()
+            .batch_write(self.new_batch_write_request(operations))
             .await
             .map(|resp| resp.into_inner().write_results)
-            .map_err(|e| GrpcErrorStatus::from(e).into());
+            .map_err(FirestoreError::from);
     }
 
-    pub async fn batch_get_documents<F>(
-        &mut self,
+    fn new_batch_write_request(
+        &self,
+        operations: Vec<request::DocumentWriteOperation>,
+    ) -> BatchWriteRequest {
+        let mut request = self
+            .request_factory
+            .new_batch_write_request(self.project_id.clone(), operations);
+        request::add_labels(&mut request.labels, &self.labels);
+        request
+    }
+
+    /// `batch_write` returning the result of each write in the order of the operations.
+    /// BatchWrite applies the writes independently, some of them can fail.
+    pub async fn batch_write_with_status(
+        &self,
+        operations: Vec<request::DocumentWriteOperation>,
+    ) -> Result<Vec<Result<WriteResult>>> {
+        self.ensure_writable("BatchWrite")?;
+        if operations.len() > MAX_BATCH_WRTIE_SIZE {
+            return Err(FirestoreError::invalid_argument(format!(
+                "max batch write size = {} but passed {}",
+                MAX_BATCH_WRTIE_SIZE,
+                operations.len()
+            )));
+        }
+        request::check_request_size(&operations)?;
+        if let Some(recorder) = self.dry_run.as_ref() {
+            return Ok(recorder
+                .record_operations("BatchWrite", &operations)
+                .into_iter()
+                .map(Ok)
+                .collect());
+        }
+
+        let response = self
+            .firestore_client
+            .clone()
+            .batch_write(self.new_batch_write_request(operations))
+            .await?
+            .into_inner();
+        let mut statuses = response.status.into_iter();
+        Ok(response
+            .write_results
+            .into_iter()
+            .map(|write_result| match statuses.next() {
+                Some(status) if status.code != Code::Ok as i32 => Err(FirestoreError::from(
+                    Status::new(Code::from(status.code), status.message),
+                )),
+                _ => Ok(write_result),
+            })
+            .collect())
+    }
+
+    pub async fn batch_get_documents<F, C>(
+        &self,
         document_paths: Vec<String>,
         field_mask: Option<Vec<String>>,
-        transaction: Option<Vec<u8>>,
+        consistency: C,
         mut with_each_doc: F,
     ) -> Result<MissingDocPaths>
     where
-        F: FnMut(Document) -> Result<()>,
+        F: FnMut(Document) -> anyhow::Result<()>,
+        C: Into<ReadConsistency>,
     {
-        let mut missing_doc_paths = Vec::<String>::new();
-        for each_document_paths in document_paths
-            .chunks(MAX_BATCH_GET_DOC_NUM)
-            .into_iter()
-            .map(|doc_ids| doc_ids.to_vec())
-        {
-            let mut result_stream = self
-                .firestore_client
-                .batch_get_documents(request::new_batch_get_documents_request(
-                    self.project_id.clone(),
-                    each_document_paths,
-                    field_mask.clone(),
-                    transaction.clone(),
-                ))
-                .await?
-                .into_inner();
+        let consistency = self.consistency(consistency);
+        let field_mask = self
+            .default_field_masks
+            .or_documents(&document_paths, field_mask);
+        let cancellation = self.cancellation.clone();
+        cancellable(cancellation.as_ref(), async {
+            let mut missing_doc_paths = Vec::<String>::new();
+            for each_document_paths in document_paths
+                .chunks(MAX_BATCH_GET_DOC_NUM)
+                .into_iter()
+                .map(|doc_ids| doc_ids.to_vec())
+            {
+                let mut result_stream = self
+                    .firestore_client
+                    .clone()
+                    .batch_get_documents(self.request_factory.new_batch_get_documents_request(
+                        self.project_id.clone(),
+                        each_document_paths,
+                        field_mask.clone(),
+                        consistency.clone(),
+                    ))
+                    .await?
+                    .into_inner();
+
+                while let Some(each_response) = result_stream.message().await? {
+                    match each_response.result {
+                        Some(doc_result) => match doc_result {
+                            DocResult::Found(doc) => {
+                                with_each_doc(doc).map_err(FirestoreError::from_callback)?
+                            }
+                            DocResult::Missing(doc_id) => {
+                                missing_doc_paths.push(doc_id);
+                                continue;
+                            }
+                        },
 
-            while let Some(each_response) = result_stream.message().await? {
-                match each_response.result {
-                    Some(doc_result) => match doc_result {
-                        DocResult::Found(doc) => with_each_doc(doc)?,
-                        DocResult::Missing(doc_id) => {
-                            missing_doc_paths.push(doc_id);
-                            continue;
+                        None => {
+                            log::warn!("batch get document return none result");
+                            break;
                         }
-                    },
-
-                    None => {
-                        log::warn!("batch get document return none result");
-                        break;
                     }
                 }
             }
-        }
 
-        if missing_doc_paths.is_empty() {
-            Ok([].to_vec())
-        } else {
-            Ok(missing_doc_paths)
+            if missing_doc_paths.is_empty() {
+                Ok([].to_vec())
+            } else {
+                Ok(missing_doc_paths)
+            }
+        })
+        .await
+    }
+
+    /// `batch_get_documents` getting at most `max_in_flight` chunks concurrently with the clones of the client
+    /// with `Priority::Batch`.
+    /// the documents are in the order of the chunks. fails if any chunk failed.
+    pub async fn batch_get_documents_concurrent(
+        &self,
+        document_paths: Vec<String>,
+        field_mask: Option<Vec<String>>,
+        max_in_flight: usize,
+    ) -> Result<(Vec<Document>, MissingDocPaths)> {
+        let semaphore = Arc::new(Semaphore::new(max_in_flight.max(1)));
+        let gets = document_paths.chunks(MAX_BATCH_GET_DOC_NUM).map(|chunk| {
+            let client = self.clone().with_priority(Priority::Batch);
+            let semaphore = Arc::clone(&semaphore);
+            let field_mask = field_mask.clone();
+            let chunk = chunk.to_vec();
+            async move {
+                let _permit = semaphore.acquire().await;
+                let mut documents = Vec::new();
+                let missing = client
+                    .batch_get_documents(chunk, field_mask, None, |doc| {
+                        documents.push(doc);
+                        Ok(())
+                    })
+                    .await?;
+                Ok::<_, FirestoreError>((documents, missing))
+            }
+        });
+
+        let mut documents = Vec::new();
+        let mut missing_doc_paths = Vec::new();
+        for (mut each_documents, mut each_missing) in future::try_join_all(gets).await? {
+            documents.append(&mut each_documents);
+            missing_doc_paths.append(&mut each_missing);
         }
+        Ok((documents, missing_doc_paths))
     }
 
-    pub async fn get_document(
-        &mut self,
+    pub async fn get_document<C>(
+        &self,
         document_path: String,
         field_mask: Option<Vec<String>>,
-        transaction: Option<Vec<u8>>,
-    ) -> Result<Option<Document>> {
+        consistency: C,
+    ) -> Result<Option<Document>>
+    where
+        C: Into<ReadConsistency>,
+    {
+        let field_mask = self
+            .default_field_masks
+            .or_document(&document_path, field_mask);
         match self
             .firestore_client
-            .get_document(request::new_get_document_request(
+            .clone()
+            .get_document(self.request_factory.new_get_document_request(
                 self.project_id.clone(),
                 document_path,
                 field_mask,
-                transaction,
+                self.consistency(consistency),
             ))
             .await
             .map(|resp| resp.into_inner())
@@ -616,21 +2866,120 @@ impl FirestoreClient {
                 if status.code() == Code::NotFound {
                     Ok(None)
                 } else {
-                    Err(GrpcErrorStatus::from(status).into())
+                    Err(status.into())
                 }
             }
         }
     }
 
-    pub async fn list_documents_all(
-        &mut self,
+    /// read a document which is not expected to exist, to check the channel and the credentials.
+    /// for readiness probes. never fails, the diagnostics are in the report.
+    pub async fn health_check(&self, timeout: Duration) -> HealthReport {
+        let client = self.clone();
+        let started_at = Instant::now();
+        match tokio::time::timeout(
+            timeout,
+            client.get_document(HEALTH_CHECK_DOCUMENT_PATH.to_owned(), None, None),
+        )
+        .await
+        {
+            Ok(result) => HealthReport::from_result(result, started_at.elapsed()),
+            Err(_) => HealthReport::timed_out(timeout),
+        }
+    }
+
+    /// try a read, a write to `PERMISSION_PROBE_COLLECTION_ID` and listing the indexes,
+    /// to tell which of them the credentials are permitted.
+    /// the write is not checked on a read-only or dry-run client, as it's not sent.
+    pub async fn probe_permissions(&self) -> PermissionReport {
+        let mut client = self.clone();
+        let read = client
+            .get_document(HEALTH_CHECK_DOCUMENT_PATH.to_owned(), None, None)
+            .await
+            .err();
+
+        let write = if self.read_only {
+            ProbeResult::not_checked(ProbeOperation::Write, "the client is read-only")
+        } else if self.dry_run.is_some() {
+            ProbeResult::not_checked(ProbeOperation::Write, "the client is dry-run")
+        } else {
+            let error = match client
+                .create_document_auto_id(
+                    None,
+                    PERMISSION_PROBE_COLLECTION_ID.to_owned(),
+                    FFields::empty(),
+                )
+                .await
+            {
+                Ok((path, _)) => client.delete_document(path.into_string()).await.err(),
+                Err(e) => Some(e),
+            };
+            ProbeResult::new(ProbeOperation::Write, error)
+        };
+
+        let list_indexes = client
+            .admin_client
+            .list_indexes(self.request_factory.new_list_indexes_request(
+                self.project_id.clone(),
+                PERMISSION_PROBE_COLLECTION_ID.to_owned(),
+            ))
+            .await
+            .err()
+            .map(FirestoreError::from);
+
+        PermissionReport {
+            results: vec![
+                ProbeResult::new(ProbeOperation::Read, read),
+                write,
+                ProbeResult::new(ProbeOperation::ListIndexes, list_indexes),
+            ],
+        }
+    }
+
+    /// `get_document` deserializing the document into `T`.
+    /// if the field mask is specified, fails before the request unless the mask covers
+    /// all the required (non `Option`) fields of `T`.
+    pub async fn get_document_as<T, C>(
+        &self,
+        document_path: String,
+        field_mask: Option<Vec<String>>,
+        consistency: C,
+    ) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+        C: Into<ReadConsistency>,
+    {
+        if let Some(field_mask) = field_mask.as_ref() {
+            validate_field_mask::<T>(field_mask, self.field_casing.as_ref())?;
+        }
+        match self
+            .get_document(document_path, field_mask, consistency)
+            .await?
+        {
+            Some(doc) => Ok(Some(self.decode(doc)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn list_documents_all<C>(
+        &self,
         parent_path: Option<String>,
         collection_id: String,
         order_by: Option<String>,
         chunk_size: Option<i32>,
         field_mask: Option<Vec<String>>,
-        transaction: Option<Vec<u8>>,
-    ) -> Result<Vec<Document>> {
+        consistency: C,
+    ) -> Result<Vec<Document>>
+    where
+        C: Into<ReadConsistency>,
+    {
+        let options = ListDocumentsOptions {
+            order_by,
+            page_size: chunk_size,
+            field_mask,
+            consistency: self.consistency(consistency),
+            ..Default::default()
+        };
         let ref mut next_token = "".to_owned();
         let mut result = Vec::<Document>::new();
         loop {
@@ -638,10 +2987,7 @@ impl FirestoreClient {
                 .list_documents_chunk(
                     parent_path.clone(),
                     collection_id.clone(),
-                    order_by.clone(),
-                    chunk_size.clone(),
-                    field_mask.clone(),
-                    transaction.clone(),
+                    options.clone(),
                     next_token.clone(),
                 )
                 .await?;
@@ -658,73 +3004,282 @@ impl FirestoreClient {
         return Ok(result);
     }
 
-    pub async fn list_documents_chunk(
-        &mut self,
+    /// all the documents of the collection, page by page.
+    /// with `adaptive_page_size`, the size of each page is tuned by the previous pages.
+    pub async fn list_documents_with(
+        &self,
         parent_path: Option<String>,
         collection_id: String,
-        order_by: Option<String>,
-        chunk_size: Option<i32>,
+        mut options: ListDocumentsOptions,
+    ) -> Result<Vec<Document>> {
+        options.consistency = self.consistency(options.consistency);
+        options.field_mask = self
+            .default_field_masks
+            .or_collection(&collection_id, options.field_mask);
+        let mut page_token = "".to_owned();
+        let mut result = Vec::<Document>::new();
+        loop {
+            if let Some(adaptive_page_size) = options.adaptive_page_size.as_ref() {
+                options.page_size = Some(adaptive_page_size.page_size());
+            }
+            let started_at = Instant::now();
+            let response = self
+                .firestore_client
+                .clone()
+                .list_documents(self.request_factory.new_list_document_request(
+                    self.project_id.clone(),
+                    parent_path.clone().unwrap_or("".to_owned()),
+                    collection_id.clone(),
+                    page_token,
+                    options.clone(),
+                ))
+                .await?
+                .into_inner();
+            if let Some(adaptive_page_size) = options.adaptive_page_size.as_mut() {
+                let page_bytes = response.documents.iter().map(Message::encoded_len).sum();
+                adaptive_page_size.observe_page(
+                    started_at.elapsed(),
+                    response.documents.len(),
+                    page_bytes,
+                );
+            }
+            result.extend(response.documents);
+            page_token = response.next_page_token;
+            if page_token.is_empty() {
+                return Ok(result);
+            }
+        }
+    }
+
+    /// `get_document_as` with the path and the timestamps of the document.
+    pub async fn get_document_snapshot<T, C>(
+        &self,
+        document_path: String,
         field_mask: Option<Vec<String>>,
-        transaction: Option<Vec<u8>>,
+        consistency: C,
+    ) -> Result<Option<DocumentSnapshot<T>>>
+    where
+        T: DeserializeOwned,
+        C: Into<ReadConsistency>,
+    {
+        if let Some(field_mask) = field_mask.as_ref() {
+            validate_field_mask::<T>(field_mask, self.field_casing.as_ref())?;
+        }
+        self.get_document(document_path, field_mask, consistency)
+            .await?
+            .map(|doc| self.decode_snapshot(doc))
+            .transpose()
+    }
+
+    /// `list_documents_with` deserialized into `T`.
+    /// the missing documents of `show_missing` are deserialized from no fields,
+    /// so `T` must accept them (e.g. with `Option` or `#[serde(default)]` fields).
+    pub async fn list_documents_as<T>(
+        &self,
+        parent_path: Option<String>,
+        collection_id: String,
+        options: ListDocumentsOptions,
+    ) -> Result<Vec<(FDocumentPath, T)>>
+    where
+        T: DeserializeOwned,
+    {
+        self.list_documents_with(parent_path, collection_id, options)
+            .await?
+            .into_iter()
+            .map(|document| {
+                let path = FDocumentPath::parse(&document.name)?;
+                Ok((path, self.decode(document)?))
+            })
+            .collect()
+    }
+
+    /// a page of the documents from `page_token` ("" for the first page) and the token of
+    /// the next page ("" for the last page). `options.adaptive_page_size` is ignored.
+    pub async fn list_documents_chunk(
+        &self,
+        parent_path: Option<String>,
+        collection_id: String,
+        options: ListDocumentsOptions,
         page_token: String,
     ) -> Result<(Vec<Document>, String)> {
+        let field_mask = self
+            .default_field_masks
+            .or_collection(&collection_id, options.field_mask);
         return self
             .firestore_client
-            .list_documents(request::new_list_document_request(
+            .clone()
+            .list_documents(self.request_factory.new_list_document_request(
                 self.project_id.clone(),
                 parent_path.unwrap_or("".to_owned()),
                 collection_id,
                 page_token,
-                order_by,
-                chunk_size,
-                field_mask,
-                transaction,
+                ListDocumentsOptions {
+                    adaptive_page_size: None,
+                    field_mask,
+                    consistency: self.consistency(options.consistency),
+                    ..options
+                },
             ))
             .await
             .map(|resp| {
                 let resp = resp.into_inner();
                 (resp.documents, resp.next_page_token)
             })
-            .map_err(|e| GrpcErrorStatus::from(e).into());
+            .map_err(FirestoreError::from);
+    }
+
+    /// all the collection ids under `document_path` ("" for the root) matching `filter_fn`,
+    /// collected from `list_collection_ids_adaptive_stream`.
+    /// the page size is fixed to `chunk_size` if given, otherwise tuned by the latency.
+    pub async fn list_collection_ids_all<F>(
+        &self,
+        document_path: String,
+        chunk_size: Option<i32>,
+        mut filter_fn: F,
+    ) -> Result<Vec<String>>
+    where
+        F: FnMut(&String) -> bool,
+    {
+        let page_size = chunk_size.map(AdaptivePageSize::fixed).unwrap_or_default();
+        self.list_collection_ids_adaptive_stream(document_path, page_size, CollectionIdFilter::All)
+            .try_filter(|id| future::ready(filter_fn(id)))
+            .try_collect()
+            .await
+    }
+
+    /// the sub collections of the document (e.g. "/users/user_1").
+    /// fails with `InvalidArgument` before the request if `document_path` is not a document path.
+    pub async fn list_sub_collections(
+        &self,
+        document_path: String,
+    ) -> Result<Vec<FCollectionPath>> {
+        self.list_sub_collections_stream(document_path, AdaptivePageSize::default())?
+            .try_collect()
+            .await
+    }
+
+    /// stream the sub collections of the document in the ascending order of the ids.
+    ///
+    /// ```ignore
+    /// let mut orders = client.list_sub_collections_stream("/users/user_1".to_owned(), AdaptivePageSize::new())?;
+    /// while let Some(collection) = orders.try_next().await? {
+    ///     println!("{}", collection.into_string());
+    /// }
+    /// ```
+    pub fn list_sub_collections_stream(
+        &self,
+        document_path: String,
+        page_size: AdaptivePageSize,
+    ) -> Result<impl Stream<Item = Result<FCollectionPath>>> {
+        validate_document_path(&document_path)?;
+        Ok(self
+            .list_collection_ids_adaptive_stream(
+                document_path.clone(),
+                page_size,
+                CollectionIdFilter::All,
+            )
+            .map_ok(move |collection_id| {
+                FCollectionPath::new(Some(document_path.clone()), collection_id)
+            }))
     }
 
-    pub async fn list_collection_ids_all<F>(
-        &mut self,
-        project_id: String,
+    /// stream the collection ids in ascending order, fetching the pages lazily.
+    /// the filter is applied on the client side, but with `CollectionIdFilter::Prefix`
+    /// it stops fetching the pages once the ids pass over the prefix.
+    pub fn list_collection_ids_stream(
+        &self,
         document_path: String,
         chunk_size: Option<i32>,
-        filter_fn: F,
-    ) -> Result<Vec<String>>
-    where
-        F: for<'a> FnMut(&'a String) -> bool + Copy,
-    {
-        let ref mut next_token = "".to_owned();
-        let mut result = Vec::<String>::new();
-        loop {
-            let response = self
-                .list_collection_ids_chunks(
-                    project_id.clone(),
-                    document_path.clone(),
-                    chunk_size,
-                    filter_fn,
-                    next_token.clone(),
-                )
-                .await?;
+        filter: CollectionIdFilter,
+    ) -> impl Stream<Item = Result<String>> {
+        self.collection_ids_stream(
+            document_path,
+            chunk_size.map(AdaptivePageSize::fixed).unwrap_or_default(),
+            chunk_size.is_none(),
+            filter,
+        )
+    }
 
-            let mut items = response.0;
-            *next_token = response.1;
+    /// `list_collection_ids_stream` tuning the page size by the latency of the pages.
+    /// the pages are fetched only while the stream is polled, so dropping the stream
+    /// stops fetching the remaining ids.
+    pub fn list_collection_ids_adaptive_stream(
+        &self,
+        document_path: String,
+        page_size: AdaptivePageSize,
+        filter: CollectionIdFilter,
+    ) -> impl Stream<Item = Result<String>> {
+        self.collection_ids_stream(document_path, page_size, false, filter)
+    }
 
-            result.append(&mut items);
+    /// `server_page_size`: leave the page size to the server instead of `page_size`
+    fn collection_ids_stream(
+        &self,
+        document_path: String,
+        page_size: AdaptivePageSize,
+        server_page_size: bool,
+        filter: CollectionIdFilter,
+    ) -> impl Stream<Item = Result<String>> {
+        let initial_state = (self.clone(), Some("".to_owned()), None::<String>, page_size);
+        stream::try_unfold(
+            initial_state,
+            move |(client, token, last_id, mut page_size)| {
+                let document_path = document_path.clone();
+                let filter = filter.clone();
+                async move {
+                    let token = match token {
+                        Some(token) => token,
+                        None => return Ok(None),
+                    };
+
+                    let project_id = client.project_id.clone();
+                    let chunk_size = if server_page_size {
+                        None
+                    } else {
+                        Some(page_size.page_size())
+                    };
+                    let started_at = Instant::now();
+                    let (mut ids, next_token) = client
+                        .list_collection_ids_chunks(
+                            project_id,
+                            document_path,
+                            chunk_size,
+                            id_filter(),
+                            token,
+                        )
+                        .await?;
+                    page_size.observe(started_at.elapsed(), ids.len());
+                    ids.sort();
+
+                    if let (Some(last_id), Some(first_id)) = (&last_id, ids.first()) {
+                        if first_id < last_id {
+                            return Err(FirestoreError::Internal(format!(
+                                "collection ids are not sorted across pages: {} after {}",
+                                first_id, last_id
+                            )));
+                        }
+                    }
 
-            if next_token.is_empty() {
-                break;
-            }
-        }
-        return Ok(result);
+                    let passed = ids.last().map(|id| filter.is_past(id)).unwrap_or(false);
+                    let last_id = ids.last().cloned().or(last_id);
+                    let next_token = if next_token.is_empty() || passed {
+                        None
+                    } else {
+                        Some(next_token)
+                    };
+
+                    let ids: Vec<String> =
+                        ids.into_iter().filter(|id| filter.matches(id)).collect();
+                    Ok(Some((ids, (client, next_token, last_id, page_size))))
+                }
+            },
+        )
+        .map_ok(|ids| stream::iter(ids.into_iter().map(Ok)))
+        .try_flatten()
     }
 
     pub async fn list_collection_ids_chunks<F>(
-        &mut self,
+        &self,
         project_id: String,
         document_path: String,
         chunk_size: Option<i32>,
@@ -734,9 +3289,18 @@ impl FirestoreClient {
     where
         F: for<'a> FnMut(&'a String) -> bool + Copy,
     {
-        let req = request::new_collection_ids_request(project_id, document_path, chunk_size, token);
+        let req = self.request_factory.new_collection_ids_request(
+            project_id,
+            document_path,
+            chunk_size,
+            token,
+        );
 
-        let response = self.firestore_client.list_collection_ids(req).await?;
+        let response = self
+            .firestore_client
+            .clone()
+            .list_collection_ids(req)
+            .await?;
         let response = response.into_inner();
         let next_token = response.next_page_token;
         let items = response
@@ -759,14 +3323,140 @@ impl Clone for FirestoreClient {
         Self {
             project_id: self.project_id.clone(),
             firestore_client: self.firestore_client.clone(),
-            token_manager: Arc::clone(&self.token_manager),
+            interactive_client: self.interactive_client.clone(),
+            batch_client: self.batch_client.clone(),
+            priority: self.priority,
+            admin_client: self.admin_client.clone(),
+            token_manager: self.token_manager.as_ref().map(Arc::clone),
+            transaction_max_attempts: self.transaction_max_attempts,
+            cancellation: self.cancellation.clone(),
+            read_only: self.read_only,
+            read_time: self.read_time,
+            request_factory: Arc::clone(&self.request_factory),
+            events: self.events.clone(),
+            channel: self.channel.clone(),
+            batch_channel: self.batch_channel.clone(),
+            components: self.components.clone(),
+            default_field_masks: Arc::clone(&self.default_field_masks),
+            dry_run: self.dry_run.clone(),
+            field_casing: self.field_casing.clone(),
+            labels: self.labels.clone(),
+        }
+    }
+}
+
+async fn update_array_element_in_tx(
+    client: &mut FirestoreClient,
+    tx: &mut TransactionOperation,
+    (document_path, field_path, index, value): (String, String, usize, FValue),
+) -> anyhow::Result<()> {
+    // e.g. "`a.b`.c" is the field "c" in the map "a.b"
+    let names = parse_field_path(&field_path)?;
+    let field_path = join_field_path(&names);
+    // the default field masks of the client may not include the array
+    let document = client
+        .get_document(
+            document_path.clone(),
+            Some(vec![field_path.clone()]),
+            Some(tx.transaction.clone()),
+        )
+        .await?
+        .ok_or_else(|| anyhow!("document not found: {}", document_path))?;
+
+    let fields: FValue = FDocument::from(document).into();
+    let mut array = names
+        .iter()
+        .try_fold(fields, |value, key| value.into_map()?.remove(key))
+        .and_then(|value| value.into_array())
+        .ok_or_else(|| anyhow!("{} is not an array in {}", field_path, document_path))?;
+    match array.get_mut(index) {
+        Some(element) => *element = value,
+        None => {
+            return Err(anyhow!(
+                "index {} out of bounds of {} (len {})",
+                index,
+                field_path,
+                array.len()
+            ))
+        }
+    }
+
+    let updated = names.iter().rev().fold(FValue::Array(array), |value, key| {
+        let mut m = FMap::new();
+        m.insert(key.to_owned(), value);
+        FValue::Map(m)
+    });
+    let fields = FFields::new(updated.into_map().unwrap_or_default());
+    tx.add_operation(request::DocumentWriteOperation::new_update(
+        document_path,
+        fields,
+        Some(vec![field_path]),
+    ))?;
+    Ok(())
+}
+
+/// a masked field covers the field of `T` if it's the field itself or its descendant. (e.g. "a.b" for "a")
+pub(crate) fn validate_field_mask<T>(
+    field_mask: &[String],
+    casing: Option<&FieldCasing>,
+) -> Result<()>
+where
+    T: DeserializeOwned,
+{
+    let missing: Vec<String> = required_fields::<T>()?
+        .into_iter()
+        .map(|field| casing.map_or_else(|| field.to_owned(), |casing| casing.to_stored(field)))
+        .filter(|field| {
+            !field_mask.iter().any(|masked| {
+                masked == field
+                    || (masked.starts_with(field) && masked[field.len()..].starts_with('.'))
+            })
+        })
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(FirestoreError::invalid_argument(format!(
+            "field mask doesn't cover the required fields: {}",
+            missing.join(", ")
+        )))
+    }
+}
+
+/// the documents of up to `n * SAMPLE_PROBES_PER_DOCUMENT` probes, without the duplicates.
+/// stops when `n` documents are sampled or a probe finds none (the collection is empty).
+async fn sample_by_probes<F, Fut>(n: usize, mut probe: F) -> Result<Vec<Document>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Option<Document>>>,
+{
+    let mut sampled = Vec::with_capacity(n);
+    let mut sampled_names = HashSet::new();
+    for _ in 0..n * SAMPLE_PROBES_PER_DOCUMENT {
+        if sampled.len() >= n {
+            break;
+        }
+        match probe().await? {
+            Some(doc) => {
+                if sampled_names.insert(doc.name.clone()) {
+                    sampled.push(doc);
+                }
+            }
+            None => break,
         }
     }
+    Ok(sampled)
 }
 
 #[cfg(test)]
 mod test {
-    use super::{request, FirestoreClient, TransactionOperation};
+    use super::{
+        request, sample_by_probes, validate_field_mask, ChunkWriteResult, CollectionIdFilter,
+        ConcurrentBatchWriteReport, FirestoreClient, ListDocumentsOptions, TransactionOperation,
+        TransactionState, MAX_BATCH_WRTIE_SIZE,
+    };
+    use serde::Deserialize;
+    use std::collections::HashMap;
 
     use std::path::Path;
 
@@ -779,7 +3469,8 @@ mod test {
     use super::super::query::QueryBuilder;
 
     use crate::firestore::{
-        value::{array_value_from_vec, doc_path, map_value_from_vec, FFields, FValue},
+        array_value_from_vec, map_value_from_vec,
+        value::{doc_path, FFields, FValue},
         FDocument,
     };
 
@@ -791,33 +3482,334 @@ mod test {
         env::var("TEST_PROJECT_ID").unwrap()
     }
 
+    #[tokio::test]
+    async fn probe_permissions_read_only_test() {
+        use crate::firestore::ProbeOperation;
+        let report = FirestoreClient::offline("p")
+            .read_only()
+            .probe_permissions()
+            .await;
+        let write = report.result_of(ProbeOperation::Write).unwrap();
+        assert!(write.not_checked.is_some());
+        assert!(!write.is_denied());
+    }
+
+    #[tokio::test]
+    async fn user_agent_suffix_test() {
+        use crate::grpc::hooks::RpcHook;
+        use google_cloud_grpc_proto::tonic::metadata::MetadataMap;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Default)]
+        struct Headers(Mutex<Vec<String>>);
+
+        impl RpcHook for Headers {
+            fn on_request(&self, _method: &str, metadata: &mut MetadataMap) {
+                let header = metadata.get("x-goog-api-client").unwrap();
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(header.to_str().unwrap().to_owned());
+            }
+        }
+
+        let headers = Arc::new(Headers::default());
+        let client = FirestoreClient::offline("p").with_rpc_hook(headers.clone());
+        let suffixed = client.clone().with_user_agent_suffix("app/2").unwrap();
+        let _ = suffixed.get_document("/c/d".to_owned(), None, None).await;
+        let _ = client.get_document("/c/d".to_owned(), None, None).await;
+
+        let headers = headers.0.lock().unwrap();
+        assert!(headers[0].ends_with(" app/2"));
+        assert!(!headers[1].ends_with(" app/2"));
+    }
+
+    #[tokio::test]
+    async fn rpc_hook_test() {
+        use crate::grpc::hooks::RpcHook;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        #[derive(Default)]
+        struct Counter(AtomicUsize);
+
+        impl RpcHook for Counter {
+            fn on_response(&self, _method: &str, _code: super::Code, _latency: Duration) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+            fn on_error(
+                &self,
+                _method: &str,
+                _error: &(dyn std::error::Error + Send + Sync),
+                _latency: Duration,
+            ) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let client = FirestoreClient::offline("p");
+        let counter = Arc::new(Counter::default());
+        let hooked = client.clone().with_rpc_hook(counter.clone());
+        let hooked_clone = hooked.clone();
+
+        // the clones made before are not hooked
+        assert!(client
+            .get_document("/c/d".to_owned(), None, None)
+            .await
+            .is_err());
+        assert_eq!(0, counter.0.load(Ordering::SeqCst));
+
+        assert!(hooked
+            .get_document("/c/d".to_owned(), None, None)
+            .await
+            .is_err());
+        assert!(hooked_clone
+            .get_document("/c/d".to_owned(), None, None)
+            .await
+            .is_err());
+        assert_eq!(2, counter.0.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn sample_by_probes_test() {
+        use super::SAMPLE_PROBES_PER_DOCUMENT;
+        use google_cloud_grpc_proto::firestore::v1::Document;
+
+        let names = ["a", "a", "b", "a", "c", "b"];
+        let mut probes = 0;
+        let sampled = sample_by_probes(2, || {
+            let name = names[probes % names.len()];
+            probes += 1;
+            async move {
+                Ok(Some(Document {
+                    name: name.to_owned(),
+                    ..Default::default()
+                }))
+            }
+        })
+        .await
+        .unwrap();
+        let sampled: Vec<&str> = sampled.iter().map(|doc| doc.name.as_str()).collect();
+        assert_eq!(vec!["a", "b"], sampled);
+        assert_eq!(3, probes);
+
+        // the duplicates count as the probes
+        let mut probes = 0;
+        let sampled = sample_by_probes(2, || {
+            probes += 1;
+            async {
+                Ok(Some(Document {
+                    name: "a".to_owned(),
+                    ..Default::default()
+                }))
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(1, sampled.len());
+        assert_eq!(2 * SAMPLE_PROBES_PER_DOCUMENT, probes);
+
+        // the empty collection
+        let mut probes = 0;
+        let sampled = sample_by_probes(2, || {
+            probes += 1;
+            async { Ok(None) }
+        })
+        .await
+        .unwrap();
+        assert!(sampled.is_empty());
+        assert_eq!(1, probes);
+    }
+
+    #[test]
+    fn validate_field_mask_test() {
+        #[derive(serde::Deserialize)]
+        #[allow(dead_code)]
+        struct User {
+            name: String,
+            address: HashMap<String, String>,
+            nickname: Option<String>,
+        }
+
+        let mask = |fields: &[&str]| -> Vec<String> {
+            fields.iter().map(|each| each.to_string()).collect()
+        };
+        assert!(validate_field_mask::<User>(&mask(&["name", "address"]), None).is_ok());
+        assert!(validate_field_mask::<User>(&mask(&["name", "address.city"]), None).is_ok());
+
+        let err =
+            validate_field_mask::<User>(&mask(&["name", "addr", "nickname"]), None).unwrap_err();
+        assert!(err.to_string().contains("address"));
+        assert!(!err.to_string().contains("nickname"));
+    }
+
+    #[test]
+    fn transaction_operation_state_test() {
+        let delete = || request::DocumentWriteOperation::new_delete("/users/u1".to_owned());
+        let mut tx = TransactionOperation::new(vec![1]);
+        assert_eq!(TransactionState::Open, tx.state());
+        assert!(tx.add_operation(delete()).is_ok());
+
+        tx.state = TransactionState::Committed;
+        let err = tx.add_operation(delete()).unwrap_err();
+        assert!(matches!(
+            err,
+            crate::firestore::FirestoreError::InvalidArgument(_)
+        ));
+        assert!(err.to_string().contains("/users/u1"));
+        assert_eq!(1, tx.operations.len());
+    }
+
+    #[test]
+    fn transaction_commit_size_test() {
+        let mut tx = TransactionOperation::new(vec![1]);
+        for i in 0..MAX_BATCH_WRTIE_SIZE {
+            tx.add_operation(request::DocumentWriteOperation::new_delete(format!(
+                "/users/u{}",
+                i
+            )))
+            .unwrap();
+        }
+        assert_eq!(MAX_BATCH_WRTIE_SIZE, tx.operation_num());
+        assert!(tx.validate_commit_size().is_ok());
+
+        tx.add_operation(request::DocumentWriteOperation::new_delete(
+            "/users/overflow".to_owned(),
+        ))
+        .unwrap();
+        assert!(matches!(
+            tx.validate_commit_size().unwrap_err(),
+            crate::firestore::FirestoreError::InvalidArgument(_)
+        ));
+    }
+
+    #[test]
+    fn collection_id_filter() {
+        let filter = CollectionIdFilter::Prefix("user".to_owned());
+        assert!(filter.matches("users"));
+        assert!(!filter.matches("posts"));
+        assert!(!filter.is_past("posts"));
+        assert!(!filter.is_past("users"));
+        assert!(filter.is_past("videos"));
+
+        let filter = CollectionIdFilter::Regex(regex::Regex::new("^log_[0-9]+$").unwrap());
+        assert!(filter.matches("log_2021"));
+        assert!(!filter.matches("log_"));
+        assert!(!filter.is_past("zzz"));
+    }
+
+    #[test]
+    fn concurrent_batch_write_report_test() {
+        let operation =
+            crate::firestore::new_write_ope_delete(None, "c".to_owned(), "d".to_owned());
+        let report = ConcurrentBatchWriteReport {
+            chunks: vec![
+                ChunkWriteResult {
+                    chunk_index: 0,
+                    operation_offsets: (0..MAX_BATCH_WRTIE_SIZE).collect(),
+                    result: Ok((0..MAX_BATCH_WRTIE_SIZE)
+                        .map(|_| Ok(Default::default()))
+                        .collect()),
+                },
+                ChunkWriteResult {
+                    chunk_index: 1,
+                    operation_offsets: vec![450, 451, 452],
+                    result: Ok(vec![
+                        Ok(Default::default()),
+                        Err(crate::firestore::FirestoreError::invalid_argument("failed")),
+                        Ok(Default::default()),
+                    ]),
+                },
+                ChunkWriteResult {
+                    chunk_index: 2,
+                    operation_offsets: vec![900],
+                    result: Err(crate::firestore::FirestoreError::invalid_argument("failed")),
+                },
+            ],
+            resume: super::BatchWriteResume {
+                ordered: false,
+                operations: vec![(451, operation.clone()), (900, operation)],
+            },
+        };
+        assert!(!report.is_all_written());
+        let failed: Vec<usize> = report
+            .failed_chunks()
+            .map(|chunk| chunk.chunk_index)
+            .collect();
+        assert_eq!(vec![1, 2], failed);
+        let failed_writes: Vec<usize> = report.failed_writes().map(|(offset, _)| offset).collect();
+        assert_eq!(vec![451], failed_writes);
+        assert_eq!(MAX_BATCH_WRTIE_SIZE + 2, report.write_results().count());
+        assert_eq!(vec![451, 900], report.unapplied_offsets());
+        assert!(report.error().is_some());
+        assert_eq!(2, report.into_resume().unwrap().operations.len());
+
+        let written = ConcurrentBatchWriteReport {
+            chunks: vec![],
+            resume: super::BatchWriteResume {
+                ordered: true,
+                operations: vec![],
+            },
+        };
+        assert!(written.is_all_written());
+        assert!(written.into_resume().is_none());
+    }
+
+    #[test]
+    fn unapplied_operations_test() {
+        let operation =
+            crate::firestore::new_write_ope_delete(None, "c".to_owned(), "d".to_owned());
+        let operations = vec![(3, operation.clone()), (5, operation)];
+        let chunk = |result| ChunkWriteResult {
+            chunk_index: 0,
+            operation_offsets: vec![3, 5],
+            result,
+        };
+        let unapplied = chunk(Ok(vec![
+            Ok(Default::default()),
+            Err(crate::firestore::FirestoreError::invalid_argument("failed")),
+        ]))
+        .unapplied(&operations);
+        assert_eq!(
+            vec![5],
+            unapplied.iter().map(|(o, _)| *o).collect::<Vec<_>>()
+        );
+        let unapplied = chunk(Err(crate::firestore::FirestoreError::invalid_argument(
+            "failed",
+        )))
+        .unapplied(&operations);
+        assert_eq!(2, unapplied.len());
+    }
+
     #[tokio::test]
     async fn collection_ids() {
         let cred_path = test_service_account_path();
 
-        let mut cli = super::FirestoreClient::with_service_account_file(
+        let cli = super::FirestoreClient::with_service_account_file(
             test_project_id().to_owned(),
             Path::new(&cred_path).to_path_buf(),
         )
         .await
         .unwrap();
-        let coll_ids = cli
-            .list_collection_ids_all(
-                test_project_id().to_owned(),
-                "".into(),
-                None,
-                super::id_filter(),
-            )
-            .await;
+        let coll_ids = cli.list_collection_ids_all("".into(), None, |_| true).await;
 
         assert!(coll_ids.is_ok());
+
+        let invalid = cli
+            .list_sub_collections("/collection_without_document".to_owned())
+            .await;
+        assert!(matches!(
+            invalid,
+            Err(crate::firestore::FirestoreError::InvalidArgument(_))
+        ));
     }
 
     #[tokio::test]
     async fn list_documents() {
         let cred_path = test_service_account_path();
 
-        let mut cli = super::FirestoreClient::with_service_account_file(
+        let cli = super::FirestoreClient::with_service_account_file(
             test_project_id().to_owned(),
             Path::new(&cred_path).to_path_buf(),
         )
@@ -876,6 +3868,26 @@ mod test {
 
         assert_ne!(0, result.len());
 
+        #[derive(Deserialize)]
+        struct Listed {
+            bbb: Option<String>,
+        }
+        let listed = cli
+            .list_documents_as::<Listed>(
+                None,
+                collection_id.clone(),
+                ListDocumentsOptions::default()
+                    .with_field_mask(vec!["bbb"])
+                    .with_show_missing(true),
+            )
+            .await
+            .unwrap();
+        let (_, first) = listed
+            .iter()
+            .find(|(path, _)| path.document_id == doc_id_1)
+            .unwrap();
+        assert_eq!(Some("ssss".to_owned()), first.bbb);
+
         {
             //delete
             let result = cli
@@ -898,11 +3910,59 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn read_only_client() {
+        let cred_path = test_service_account_path();
+
+        let cli = super::FirestoreClient::with_service_account_file(
+            test_project_id().to_owned(),
+            Path::new(&cred_path).to_path_buf(),
+        )
+        .await
+        .unwrap()
+        .read_only();
+        assert!(cli.clone().is_read_only());
+
+        let doc_id = format!("doc_{}", Uuid::new_v4().to_urn());
+        let result = cli
+            .create_document(
+                None,
+                TEST_COLLECTION_ID.to_owned(),
+                doc_id.clone(),
+                FFields::empty(),
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(crate::firestore::FirestoreError::ReadOnlyViolation(_))
+        ));
+
+        let delete = request::DocumentWriteOperation::new_delete(doc_path(
+            None,
+            TEST_COLLECTION_ID.to_owned(),
+            doc_id.clone(),
+        ));
+        assert!(matches!(
+            cli.batch_write(vec![delete]).await,
+            Err(crate::firestore::FirestoreError::ReadOnlyViolation(_))
+        ));
+
+        let found = cli
+            .get_document(
+                doc_path(None, TEST_COLLECTION_ID.to_owned(), doc_id),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(found.is_none());
+    }
+
     #[tokio::test]
     async fn crud_object() {
         let cred_path = test_service_account_path();
 
-        let mut cli = super::FirestoreClient::with_service_account_file(
+        let cli = super::FirestoreClient::with_service_account_file(
             test_project_id().to_owned(),
             Path::new(&cred_path).to_path_buf(),
         )
@@ -1015,9 +4075,9 @@ mod test {
 
     #[tokio::test]
     async fn batch_update_object() {
-        use crate::firestore::value::{
-            array_value_from_vec, doc_path, map_value_from_vec, FDocument, FDocumentPath, FFields,
-            FValue,
+        use crate::firestore::{
+            array_value_from_vec, map_value_from_vec,
+            value::{doc_path, FDocument, FDocumentPath, FFields, FValue},
         };
 
         let cred_path = test_service_account_path();
@@ -1028,7 +4088,7 @@ mod test {
         )
         .await
         .unwrap();
-        let mut cli = cli.clone();
+        let cli = cli.clone();
 
         let collection_id = TEST_COLLECTION_ID.to_owned();
         let doc_id = format!("doc_{}", Uuid::new_v4().to_urn());
@@ -1150,7 +4210,7 @@ mod test {
     async fn write_in_transaction() {
         let cred_path = test_service_account_path();
 
-        let mut cli = super::FirestoreClient::with_service_account_file(
+        let cli = super::FirestoreClient::with_service_account_file(
             test_project_id().to_owned(),
             Path::new(&cred_path).to_path_buf(),
         )
@@ -1183,7 +4243,7 @@ mod test {
                     None,
                     collection_id.clone(),
                     ctx.doc_id.clone(),
-                )));
+                )))?;
 
                 Ok(100i32)
             }
@@ -1193,11 +4253,48 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn read_in_read_only_transaction() {
+        let cred_path = test_service_account_path();
+
+        let cli = super::FirestoreClient::with_service_account_file(
+            test_project_id().to_owned(),
+            Path::new(&cred_path).to_path_buf(),
+        )
+        .await
+        .unwrap();
+
+        let doc_id = format!("doc_{}", Uuid::new_v4().to_urn());
+        let mut fields = FFields::empty();
+        fields.add("ssss".to_owned(), "asdf".to_owned());
+        cli.create_document(None, TEST_COLLECTION_ID.to_owned(), doc_id.clone(), fields)
+            .await
+            .unwrap();
+
+        async fn read_ope(
+            cli_in_tx: &mut FirestoreClient,
+            tx: Vec<u8>,
+            doc_path: String,
+        ) -> Result<bool> {
+            let doc = cli_in_tx.get_document(doc_path, None, Some(tx)).await?;
+            Ok(doc.is_some())
+        }
+
+        let path = doc_path(None, TEST_COLLECTION_ID.to_owned(), doc_id);
+        let found = cli
+            .read_only_transaction(None, path.clone(), read_ope)
+            .await
+            .unwrap();
+        assert!(found);
+
+        cli.delete_document(path).await.unwrap();
+    }
+
     #[tokio::test]
     async fn error_in_transaction() {
         let cred_path = test_service_account_path();
 
-        let mut cli = super::FirestoreClient::with_service_account_file(
+        let cli = super::FirestoreClient::with_service_account_file(
             test_project_id().to_owned(),
             Path::new(&cred_path).to_path_buf(),
         )
@@ -1227,7 +4324,7 @@ mod test {
                     collection_id.clone(),
                     ctx.doc_id.clone(),
                     fields,
-                ));
+                ))?;
 
                 Err(anyhow!("something went wrong"))
             }
@@ -1241,7 +4338,7 @@ mod test {
     async fn panic_in_transaction() {
         let cred_path = test_service_account_path();
 
-        let mut cli = super::FirestoreClient::with_service_account_file(
+        let cli = super::FirestoreClient::with_service_account_file(
             test_project_id().to_owned(),
             Path::new(&cred_path).to_path_buf(),
         )
@@ -1271,7 +4368,7 @@ mod test {
                     collection_id.clone(),
                     ctx.doc_id.clone(),
                     fields,
-                ));
+                ))?;
                 panic!("something went south");
 
                 Ok(111i32)
@@ -1286,7 +4383,7 @@ mod test {
     async fn query_test() {
         let cred_path = test_service_account_path();
 
-        let mut cli = super::FirestoreClient::with_service_account_file(
+        let cli = super::FirestoreClient::with_service_account_file(
             test_project_id().to_owned(),
             Path::new(&cred_path).to_path_buf(),
         )
@@ -1361,6 +4458,20 @@ mod test {
             assert_eq!(1, result.unwrap())
         }
 
+        {
+            let q = || {
+                QueryBuilder::collection(collection_id.clone(), false)
+                    .filter_bin("bbb", "==", "ssss".to_owned())
+                    .filter_bin("cccc", "array-contains", "oh".to_owned())
+            };
+            assert_eq!(1, cli.count(None, q()).await.unwrap());
+            assert_eq!(
+                FValue::Double(123f64),
+                cli.sum(None, q(), "aaa").await.unwrap()
+            );
+            assert_eq!(Some(123f64), cli.avg(None, q(), "aaa").await.unwrap());
+        }
+
         {
             {
                 //batch delete
@@ -1382,13 +4493,83 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn collection_group_query_test() {
+        use futures::TryStreamExt;
+        let cred_path = test_service_account_path();
+
+        let cli = super::FirestoreClient::with_service_account_file(
+            test_project_id().to_owned(),
+            Path::new(&cred_path).to_path_buf(),
+        )
+        .await
+        .unwrap();
+
+        let group_id = format!("test_group_{}", Uuid::new_v4().to_simple());
+        let parent_doc = format!(
+            "/{}/parent_{}",
+            TEST_COLLECTION_ID,
+            Uuid::new_v4().to_simple()
+        );
+        let nested_doc = format!("{}/nested/nested_1", parent_doc);
+        // the group at the root, under a document and under a nested sub collection
+        let paths = vec![
+            doc_path(None, group_id.clone(), "c1".to_owned()),
+            doc_path(Some(parent_doc.clone()), group_id.clone(), "c2".to_owned()),
+            doc_path(Some(nested_doc.clone()), group_id.clone(), "c3".to_owned()),
+        ];
+        let creates = vec![
+            request::DocumentWriteOperation::new_create(
+                None,
+                group_id.clone(),
+                "c1".to_owned(),
+                FFields::empty(),
+            ),
+            request::DocumentWriteOperation::new_create(
+                Some(parent_doc.clone()),
+                group_id.clone(),
+                "c2".to_owned(),
+                FFields::empty(),
+            ),
+            request::DocumentWriteOperation::new_create(
+                Some(nested_doc.clone()),
+                group_id.clone(),
+                "c3".to_owned(),
+                FFields::empty(),
+            ),
+        ];
+        cli.batch_write(creates).await.unwrap();
+
+        let mut found: Vec<String> = cli
+            .run_collection_group_query(
+                group_id.clone(),
+                QueryBuilder::collection("ignored".to_owned(), false),
+            )
+            .await
+            .unwrap()
+            .map_ok(|doc| FDocument::from(doc).doc_path.into_string())
+            .try_collect()
+            .await
+            .unwrap();
+        found.sort();
+        let mut expected = paths.clone();
+        expected.sort();
+        assert_eq!(expected, found);
+
+        let deletes = paths
+            .into_iter()
+            .map(request::DocumentWriteOperation::new_delete)
+            .collect();
+        cli.batch_write(deletes).await.unwrap();
+    }
+
     #[tokio::test]
     #[ignore]
     async fn query_stream() {
         use futures_util::stream;
         let cred_path = test_service_account_path();
 
-        let mut cli = super::FirestoreClient::with_service_account_file(
+        let cli = super::FirestoreClient::with_service_account_file(
             test_project_id().to_owned(),
             Path::new(&cred_path).to_path_buf(),
         )
@@ -1455,4 +4636,90 @@ mod test {
             assert_eq!(3, response);
         }
     }
+
+    #[tokio::test]
+    async fn increment_with_dedup_token() {
+        use crate::firestore::{FTransform, WritePrecondition};
+        let cred_path = test_service_account_path();
+
+        let cli = super::FirestoreClient::with_service_account_file(
+            test_project_id().to_owned(),
+            Path::new(&cred_path).to_path_buf(),
+        )
+        .await
+        .unwrap();
+
+        let counter_path = doc_path(
+            None,
+            TEST_COLLECTION_ID.to_owned(),
+            format!("counter_{}", Uuid::new_v4().to_urn()),
+        );
+        let token_path = doc_path(
+            None,
+            TEST_COLLECTION_ID.to_owned(),
+            format!("counter_token_{}", Uuid::new_v4().to_urn()),
+        );
+        let increment =
+            request::DocumentWriteOperation::new_upsert(counter_path.clone(), FFields::empty())
+                .with_update_transforms(vec![(
+                    "count".to_owned(),
+                    FTransform::Increment(1.into()),
+                )]);
+        assert!(!increment.is_idempotent());
+
+        let writer = cli.bulk_writer();
+        // the same token twice, as if the write was retried
+        writer
+            .enqueue_with_dedup_token(increment.clone(), token_path.clone())
+            .unwrap()
+            .wait()
+            .await
+            .unwrap();
+        writer
+            .enqueue_with_dedup_token(increment.clone(), token_path.clone())
+            .unwrap()
+            .wait()
+            .await
+            .unwrap();
+
+        // the update time has changed since the first read
+        let doc = cli
+            .get_document(counter_path.clone(), None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        let stale_update_time = FDocument::from(doc).update_time.unwrap();
+        writer
+            .enqueue(increment.clone())
+            .unwrap()
+            .wait()
+            .await
+            .unwrap();
+        let result = writer
+            .enqueue(increment.with_precondition(WritePrecondition::UpdateTime(stale_update_time)))
+            .unwrap()
+            .wait()
+            .await;
+        assert!(result.is_err());
+        // the write failed by itself with a new token is not reported as applied
+        let result = writer
+            .enqueue_with_dedup_token(
+                request::DocumentWriteOperation::new_upsert(counter_path.clone(), FFields::empty())
+                    .with_precondition(WritePrecondition::Exists(false)),
+                format!("{}_create", token_path),
+            )
+            .unwrap()
+            .wait()
+            .await;
+        assert!(result.is_err());
+        writer.close().await.unwrap();
+
+        let doc = cli
+            .get_document(counter_path, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        let fields = FFields::from_grpc_doc(doc);
+        assert_eq!(Some(&2), fields.get("count").unwrap().as_int());
+    }
 }
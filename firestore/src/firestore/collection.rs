@@ -0,0 +1,527 @@
+use super::client::{FirestoreClient, TransactionOperation};
+use super::helper::{
+    new_write_ope_create, new_write_ope_delete, new_write_ope_update, new_write_ope_upsert,
+};
+use super::query::QueryBuilder;
+use super::request::DocumentWriteOperation;
+use super::value::{doc_path, field_path::escape_field_name, DocumentSnapshot, TryIntoFFields};
+
+use super::error::Result;
+use google_cloud_grpc_proto::firestore::v1::{StructuredQuery, WriteResult};
+use serde::{de::DeserializeOwned, Serialize};
+use std::marker::PhantomData;
+
+/// typed handle of a collection.
+/// the documents are (de)serialized into `T` with the fvalue serde.
+///
+/// ```ignore
+/// let mut users = client.collection::<User>("users");
+/// users.set("user_1", &user).await?;
+/// let user: Option<User> = users.get("user_1").await?;
+/// ```
+pub struct CollectionRef<T> {
+    client: FirestoreClient,
+    parent_path: Option<String>,
+    collection_id: String,
+    _document_type: PhantomData<T>,
+}
+
+impl<T> CollectionRef<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub(crate) fn new(
+        client: FirestoreClient,
+        parent_path: Option<String>,
+        collection_id: String,
+    ) -> Self {
+        Self {
+            client,
+            parent_path,
+            collection_id,
+            _document_type: PhantomData,
+        }
+    }
+
+    pub fn collection_id(&self) -> &str {
+        &self.collection_id
+    }
+
+    pub fn parent_path(&self) -> Option<&String> {
+        self.parent_path.as_ref()
+    }
+
+    pub fn document_path<D: Into<String>>(&self, doc_id: D) -> String {
+        doc_path(
+            self.parent_path.clone(),
+            self.collection_id.clone(),
+            doc_id.into(),
+        )
+    }
+
+    pub async fn get<D: Into<String>>(&mut self, doc_id: D) -> Result<Option<T>> {
+        let document_path = self.document_path(doc_id);
+        match self.client.get_document(document_path, None, None).await? {
+            Some(doc) => Ok(Some(self.client.decode(doc)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// `get` with the path and the timestamps of the document.
+    pub async fn get_snapshot<D: Into<String>>(
+        &mut self,
+        doc_id: D,
+    ) -> Result<Option<DocumentSnapshot<T>>> {
+        let document_path = self.document_path(doc_id);
+        self.client
+            .get_document_snapshot(document_path, None, None)
+            .await
+    }
+
+    /// get the document with only the masked fields.
+    /// fails if the mask doesn't cover the required fields of `T`.
+    pub async fn get_masked<D, F>(&mut self, doc_id: D, field_mask: Vec<F>) -> Result<Option<T>>
+    where
+        D: Into<String>,
+        F: Into<String>,
+    {
+        let document_path = self.document_path(doc_id);
+        let field_mask = field_mask.into_iter().map(|each| each.into()).collect();
+        self.client
+            .get_document_as(document_path, Some(field_mask), None)
+            .await
+    }
+
+    /// create the document with the id generated by the server. returns the document id.
+    pub async fn add(&mut self, doc: &T) -> Result<String> {
+        let fields = self.client.encode(doc)?;
+        let (path, _) = self
+            .client
+            .create_document_auto_id(self.parent_path.clone(), self.collection_id.clone(), fields)
+            .await?;
+        Ok(path.document_id)
+    }
+
+    /// create the document. fails if the document already exists.
+    pub async fn create<D: Into<String>>(&mut self, doc_id: D, doc: &T) -> Result<WriteResult> {
+        let ope = new_write_ope_create(
+            self.parent_path.clone(),
+            self.collection_id.clone(),
+            doc_id.into(),
+            self.client.encode(doc)?,
+        )?;
+        self.commit_one(ope).await
+    }
+
+    /// create or overwrite the document.
+    pub async fn set<D: Into<String>>(&mut self, doc_id: D, doc: &T) -> Result<WriteResult> {
+        let ope = new_write_ope_upsert(
+            self.parent_path.clone(),
+            self.collection_id.clone(),
+            doc_id.into(),
+            self.client.encode(doc)?,
+        )?;
+        self.commit_one(ope).await
+    }
+
+    /// update the fields in `update_field_mask`, or overwrite the whole document if it's None.
+    pub async fn update<D: Into<String>>(
+        &mut self,
+        doc_id: D,
+        doc: &T,
+        update_field_mask: Option<Vec<String>>,
+    ) -> Result<WriteResult> {
+        let ope = new_write_ope_update(
+            self.parent_path.clone(),
+            self.collection_id.clone(),
+            doc_id.into(),
+            update_field_mask,
+            self.client.encode(doc)?,
+        )?;
+        self.commit_one(ope).await
+    }
+
+    pub async fn delete<D: Into<String>>(&mut self, doc_id: D) -> Result<()> {
+        let document_path = self.document_path(doc_id);
+        self.client.delete_document(document_path).await
+    }
+
+    /// query builder from this collection
+    pub fn query_builder(&self) -> QueryBuilder {
+        QueryBuilder::collection(self.collection_id.clone(), false)
+    }
+
+    pub async fn query(&mut self, query: StructuredQuery) -> Result<Vec<T>> {
+        let mut result = Vec::<T>::new();
+        let client = &self.client;
+        client
+            .run_query(self.parent_path.clone(), query, None, |doc| {
+                result.push(client.decode(doc)?);
+                Ok(())
+            })
+            .await?;
+        Ok(result)
+    }
+
+    /// `query` with the paths and the timestamps of the documents.
+    pub async fn query_snapshots(
+        &mut self,
+        query: StructuredQuery,
+    ) -> Result<Vec<DocumentSnapshot<T>>> {
+        let mut result = Vec::new();
+        let client = &self.client;
+        client
+            .run_query(self.parent_path.clone(), query, None, |doc| {
+                result.push(client.decode_snapshot(doc)?);
+                Ok(())
+            })
+            .await?;
+        Ok(result)
+    }
+
+    /// query this collection with the builder from `query_builder`.
+    /// unlike `query`, the query can't be of another collection.
+    ///
+    /// ```ignore
+    /// let adults = users.query_with(|q| q.filter_field("age", FieldOp::Ge, 20i64)).await?;
+    /// ```
+    pub async fn query_with<F>(&mut self, build: F) -> Result<Vec<T>>
+    where
+        F: FnOnce(QueryBuilder) -> QueryBuilder,
+    {
+        let query = build(self.query_builder()).try_build()?;
+        self.query(query).await
+    }
+
+    async fn commit_one(&mut self, operation: DocumentWriteOperation) -> Result<WriteResult> {
+        let mut write_results = self.client.commit(vec![operation], None).await?;
+        Ok(write_results.pop().unwrap_or_default())
+    }
+}
+
+impl<T> Clone for CollectionRef<T> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            parent_path: self.parent_path.clone(),
+            collection_id: self.collection_id.clone(),
+            _document_type: PhantomData,
+        }
+    }
+}
+
+/// the client and the transaction passed to the closure of `in_transaction`,
+/// to access the collections bound to the transaction.
+///
+/// ```ignore
+/// transaction_collections! {
+///     pub trait AppCollections {
+///         users: User => "users",
+///     }
+/// }
+///
+/// async fn rename(
+///     client: &mut FirestoreClient,
+///     tx: &mut TransactionOperation,
+///     (id, name): (String, String),
+/// ) -> anyhow::Result<()> {
+///     let mut tx = TypedTransaction::new(client, tx);
+///     if tx.users().get(id.clone()).await?.is_some() {
+///         tx.users().update(id, FFields::new(vec![("name".to_owned(), name.into())].into_iter().collect()))?;
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct TypedTransaction<'a> {
+    client: &'a mut FirestoreClient,
+    transaction: &'a mut TransactionOperation,
+}
+
+impl<'a> TypedTransaction<'a> {
+    pub fn new(client: &'a mut FirestoreClient, transaction: &'a mut TransactionOperation) -> Self {
+        Self {
+            client,
+            transaction,
+        }
+    }
+
+    pub fn collection<T>(&mut self, collection_id: impl Into<String>) -> TxCollection<'_, T>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        TxCollection::new(self, None, collection_id.into())
+    }
+
+    /// the sub collection under `parent_path` (e.g. "/users/user_1").
+    pub fn sub_collection<T>(
+        &mut self,
+        parent_path: impl Into<String>,
+        collection_id: impl Into<String>,
+    ) -> TxCollection<'_, T>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        TxCollection::new(self, Some(parent_path.into()), collection_id.into())
+    }
+}
+
+/// typed handle of a collection bound to a transaction.
+/// the reads are in the transaction, and the writes are added to the transaction
+/// to be committed with it.
+pub struct TxCollection<'a, T> {
+    client: &'a mut FirestoreClient,
+    transaction: &'a mut TransactionOperation,
+    parent_path: Option<String>,
+    collection_id: String,
+    _document_type: PhantomData<T>,
+}
+
+impl<'a, T> TxCollection<'a, T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn new(
+        tx: &'a mut TypedTransaction<'_>,
+        parent_path: Option<String>,
+        collection_id: String,
+    ) -> Self {
+        Self {
+            client: tx.client,
+            transaction: tx.transaction,
+            parent_path,
+            collection_id,
+            _document_type: PhantomData,
+        }
+    }
+
+    pub fn document_path<D: Into<String>>(&self, doc_id: D) -> String {
+        doc_path(
+            self.parent_path.clone(),
+            self.collection_id.clone(),
+            doc_id.into(),
+        )
+    }
+
+    pub async fn get<D: Into<String>>(&mut self, doc_id: D) -> Result<Option<T>> {
+        let document_path = self.document_path(doc_id);
+        let transaction = Some(self.transaction.transaction.clone());
+        self.client
+            .get_document_as(document_path, None, transaction)
+            .await
+    }
+
+    /// create the document on commit. the transaction fails if the document already exists.
+    pub fn create<D: Into<String>>(&mut self, doc_id: D, doc: &T) -> Result<()> {
+        let ope = new_write_ope_create(
+            self.parent_path.clone(),
+            self.collection_id.clone(),
+            doc_id.into(),
+            self.client.encode(doc)?,
+        )?;
+        self.transaction.add_operation(ope)
+    }
+
+    /// create or overwrite the document on commit.
+    pub fn set<D: Into<String>>(&mut self, doc_id: D, doc: &T) -> Result<()> {
+        let ope = new_write_ope_upsert(
+            self.parent_path.clone(),
+            self.collection_id.clone(),
+            doc_id.into(),
+            self.client.encode(doc)?,
+        )?;
+        self.transaction.add_operation(ope)
+    }
+
+    /// update only the top level fields in `patch` on commit. (e.g. FFields or a struct of some fields)
+    pub fn update<D, P>(&mut self, doc_id: D, patch: P) -> Result<()>
+    where
+        D: Into<String>,
+        P: TryIntoFFields,
+    {
+        let (fields, transforms) = self.client.encode(patch)?.split_transforms();
+        // the top level keys like "a.b" are not the nested fields
+        let update_field_mask = fields.keys().map(|key| escape_field_name(key)).collect();
+        let ope = DocumentWriteOperation::new_update(
+            self.document_path(doc_id),
+            fields,
+            Some(update_field_mask),
+        )
+        .with_update_transforms(transforms);
+        self.transaction.add_operation(ope)
+    }
+
+    /// delete the document on commit.
+    pub fn delete<D: Into<String>>(&mut self, doc_id: D) -> Result<()> {
+        self.transaction.add_operation(new_write_ope_delete(
+            self.parent_path.clone(),
+            self.collection_id.clone(),
+            doc_id.into(),
+        ))
+    }
+}
+
+/// define a trait of the typed accessors of the collections for `FirestoreClient`,
+/// so the collection id and the document type are bound in one place.
+///
+/// ```ignore
+/// collections! {
+///     pub trait AppCollections {
+///         users: User => "users",
+///         orders: Order => "orders",
+///     }
+/// }
+/// // Vec<User>, never Vec<Order>
+/// let users = client.users().query_with(|q| q.limit(10)).await?;
+/// ```
+#[macro_export]
+macro_rules! collections {
+    ($vis:vis trait $trait_name:ident { $($method:ident : $ty:ty => $collection_id:expr),* $(,)? }) => {
+        $vis trait $trait_name {
+            $(fn $method(&self) -> $crate::firestore::CollectionRef<$ty>;)*
+        }
+
+        impl $trait_name for $crate::firestore::FirestoreClient {
+            $(
+                fn $method(&self) -> $crate::firestore::CollectionRef<$ty> {
+                    self.collection::<$ty>($collection_id)
+                }
+            )*
+        }
+    };
+}
+
+/// define a trait of the accessors of the collections for `TypedTransaction`.
+///
+/// ```ignore
+/// transaction_collections! {
+///     pub trait AppCollections {
+///         users: User => "users",
+///         orders: Order => "orders",
+///     }
+/// }
+/// // tx.users().get(id).await?
+/// ```
+#[macro_export]
+macro_rules! transaction_collections {
+    ($vis:vis trait $trait_name:ident { $($method:ident : $ty:ty => $collection_id:expr),* $(,)? }) => {
+        $vis trait $trait_name {
+            $(fn $method(&mut self) -> $crate::firestore::TxCollection<'_, $ty>;)*
+        }
+
+        impl<'a> $trait_name for $crate::firestore::TypedTransaction<'a> {
+            $(
+                fn $method(&mut self) -> $crate::firestore::TxCollection<'_, $ty> {
+                    self.collection::<$ty>($collection_id)
+                }
+            )*
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{FFields, FieldOp, FirestoreClient, TransactionOperation, TypedTransaction};
+    use serde::{Deserialize, Serialize};
+    use std::env;
+    use std::path::Path;
+    use uuid::Uuid;
+
+    const TEST_COLLECTION_ID: &str = "test_coll";
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct User {
+        name: String,
+        age: i64,
+    }
+
+    crate::collections! {
+        trait TestClientCollections {
+            users: User => TEST_COLLECTION_ID,
+        }
+    }
+
+    #[tokio::test]
+    async fn typed_collection_crud() {
+        let cred_path = env::var("TEST_SERVICE_ACCOUT").unwrap();
+        let cli = FirestoreClient::with_service_account_file(
+            env::var("TEST_PROJECT_ID").unwrap(),
+            Path::new(&cred_path).to_path_buf(),
+        )
+        .await
+        .unwrap();
+
+        let mut users = cli.users();
+        let doc_id = format!("doc_{}", Uuid::new_v4().to_urn());
+        let user = User {
+            name: "taco".to_owned(),
+            age: 20,
+        };
+
+        users.set(doc_id.clone(), &user).await.unwrap();
+        assert_eq!(
+            Some(&user),
+            users.get(doc_id.clone()).await.unwrap().as_ref()
+        );
+        let found = users
+            .query_with(|q| q.filter_field("age", FieldOp::Eq, 20i64))
+            .await
+            .unwrap();
+        assert!(found.contains(&user));
+
+        users.delete(doc_id.clone()).await.unwrap();
+        assert_eq!(None, users.get(doc_id).await.unwrap());
+    }
+
+    crate::transaction_collections! {
+        trait TestCollections {
+            users: User => TEST_COLLECTION_ID,
+        }
+    }
+
+    async fn birthday(
+        client: &mut FirestoreClient,
+        tx: &mut TransactionOperation,
+        doc_id: String,
+    ) -> anyhow::Result<Option<i64>> {
+        let mut tx = TypedTransaction::new(client, tx);
+        let user = match tx.users().get(doc_id.clone()).await? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+        let mut patch = FFields::empty();
+        patch.add("age", user.age + 1);
+        tx.users().update(doc_id, patch)?;
+        Ok(Some(user.age + 1))
+    }
+
+    #[tokio::test]
+    async fn typed_collection_in_transaction() {
+        let cred_path = env::var("TEST_SERVICE_ACCOUT").unwrap();
+        let cli = FirestoreClient::with_service_account_file(
+            env::var("TEST_PROJECT_ID").unwrap(),
+            Path::new(&cred_path).to_path_buf(),
+        )
+        .await
+        .unwrap();
+
+        let mut users = cli.collection::<User>(TEST_COLLECTION_ID);
+        let doc_id = format!("doc_{}", Uuid::new_v4().to_urn());
+        users
+            .set(
+                doc_id.clone(),
+                &User {
+                    name: "taco".to_owned(),
+                    age: 20,
+                },
+            )
+            .await
+            .unwrap();
+
+        let age = cli.in_transaction(doc_id.clone(), birthday).await.unwrap();
+        assert_eq!(Some(21), age);
+        let user = users.get(doc_id.clone()).await.unwrap().unwrap();
+        assert_eq!(("taco", 21), (user.name.as_str(), user.age));
+
+        users.delete(doc_id).await.unwrap();
+    }
+}
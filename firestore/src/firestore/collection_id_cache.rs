@@ -0,0 +1,281 @@
+use super::client::FirestoreClient;
+use super::components::{until_stopped, ComponentTier};
+use super::query::QueryBuilder;
+use super::request::documents_root_path;
+use super::trigger::relative_document_path;
+
+use super::error::Result;
+use futures::TryStreamExt;
+use google_cloud_grpc_proto::firestore::v1::{
+    listen_response::ResponseType,
+    target::{self, query_target},
+    ListenResponse, Target,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const WATCH_TARGET_ID: i32 = 1;
+
+/// `list_collection_ids` results cached per parent document path for `ttl`.
+/// for tooling which enumerates the collections repeatedly.
+///
+/// ```ignore
+/// let cache = client.collection_id_cache(Duration::from_secs(60));
+/// tokio::spawn({
+///     let cache = cache.clone();
+///     async move { cache.watch_changes().await }
+/// });
+/// let ids = cache.collection_ids(None).await?;
+/// ```
+#[derive(Clone)]
+pub struct CollectionIdCache {
+    client: FirestoreClient,
+    entries: Arc<Mutex<CachedCollectionIds>>,
+}
+
+impl CollectionIdCache {
+    pub(crate) fn new(client: FirestoreClient, ttl: Duration) -> Self {
+        Self {
+            client,
+            entries: Arc::new(Mutex::new(CachedCollectionIds::new(ttl))),
+        }
+    }
+
+    /// the collection ids under the document (e.g. "/users/user_1"), or the root if None.
+    pub async fn collection_ids(&self, parent_path: Option<String>) -> Result<Vec<String>> {
+        let parent_path = parent_path.unwrap_or_default();
+        if let Some(ids) = self
+            .entries
+            .lock()
+            .unwrap()
+            .get(&parent_path, Instant::now())
+        {
+            return Ok(ids);
+        }
+
+        let client = self.client.clone();
+        let ids = client
+            .list_collection_ids_all(parent_path.clone(), None, |_| true)
+            .await?;
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(parent_path, ids.clone(), Instant::now());
+        Ok(ids)
+    }
+
+    /// whether the collection exists according to the cache. None if not cached or expired.
+    pub fn is_cached_collection(
+        &self,
+        parent_path: Option<&str>,
+        collection_id: &str,
+    ) -> Option<bool> {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(parent_path.unwrap_or_default(), Instant::now())
+            .map(|ids| ids.iter().any(|id| id == collection_id))
+    }
+
+    pub fn invalidate(&self, parent_path: Option<&str>) {
+        self.entries
+            .lock()
+            .unwrap()
+            .invalidate(parent_path.unwrap_or_default());
+    }
+
+    pub fn invalidate_all(&self) {
+        self.entries.lock().unwrap().invalidate_all();
+    }
+
+    /// listen to all the documents of the database and invalidate the cached parents
+    /// when a document appears in a collection not cached, or a document is deleted
+    /// (the collection may have become empty). runs until the stream is closed, or the
+    /// client is shut down.
+    ///
+    /// every document change of the database is sent to the client. not for large databases.
+    pub async fn watch_changes(&self) -> Result<()> {
+        let client = self.client.clone();
+        let target = Target {
+            target_id: WATCH_TARGET_ID,
+            once: false,
+            target_type: Some(target::TargetType::Query(target::QueryTarget {
+                parent: documents_root_path(client.project_id().to_owned()),
+                // empty collection id with all descendants matches every document.
+                query_type: Some(query_target::QueryType::StructuredQuery(
+                    QueryBuilder::collection("".to_owned(), true)
+                        .select(vec!["__name__"])
+                        .build(),
+                )),
+            })),
+            resume_type: None,
+        };
+
+        let (stop, guard) = client
+            .components()
+            .register_task("collection_id_cache", ComponentTier::Cache);
+        let mut responses = Box::pin(until_stopped(
+            client.listen(vec![target]).await?,
+            stop,
+            guard,
+        ));
+        while let Some(response) = responses.try_next().await? {
+            self.entries.lock().unwrap().apply(response, Instant::now());
+        }
+        Ok(())
+    }
+}
+
+struct CachedIds {
+    fetched_at: Instant,
+    ids: Vec<String>,
+}
+
+struct CachedCollectionIds {
+    ttl: Duration,
+    entries: HashMap<String, CachedIds>,
+}
+
+impl CachedCollectionIds {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: HashMap::new(),
+        }
+    }
+
+    fn get(&self, parent_path: &str, now: Instant) -> Option<Vec<String>> {
+        self.entries
+            .get(parent_path)
+            .filter(|cached| now.duration_since(cached.fetched_at) < self.ttl)
+            .map(|cached| cached.ids.clone())
+    }
+
+    fn insert(&mut self, parent_path: String, ids: Vec<String>, now: Instant) {
+        self.entries.insert(
+            parent_path,
+            CachedIds {
+                fetched_at: now,
+                ids,
+            },
+        );
+    }
+
+    fn invalidate(&mut self, parent_path: &str) {
+        self.entries.remove(parent_path);
+    }
+
+    fn invalidate_all(&mut self) {
+        self.entries.clear();
+    }
+
+    fn apply(&mut self, response: ListenResponse, now: Instant) {
+        match response.response_type {
+            Some(ResponseType::DocumentChange(change)) => {
+                if let Some(document) = change.document {
+                    for (parent_path, collection_id) in
+                        parent_collections(&relative_document_path(&document.name))
+                    {
+                        let known = self
+                            .get(&parent_path, now)
+                            .map(|ids| ids.iter().any(|id| id == collection_id));
+                        if known == Some(false) {
+                            self.invalidate(&parent_path);
+                        }
+                    }
+                }
+            }
+            Some(ResponseType::DocumentDelete(delete)) => {
+                self.invalidate_parent_of(&delete.document)
+            }
+            Some(ResponseType::DocumentRemove(remove)) => {
+                self.invalidate_parent_of(&remove.document)
+            }
+            _ => {}
+        }
+    }
+
+    fn invalidate_parent_of(&mut self, name: &str) {
+        if let Some((parent_path, _)) = parent_collections(&relative_document_path(name)).pop() {
+            self.invalidate(&parent_path);
+        }
+    }
+}
+
+/// "/users/user_1/posts/post_1" => [("", "users"), ("/users/user_1", "posts")]
+fn parent_collections(document_path: &str) -> Vec<(String, &str)> {
+    let segments: Vec<&str> = document_path.trim_start_matches('/').split('/').collect();
+    segments
+        .chunks_exact(2)
+        .enumerate()
+        .map(|(i, pair)| {
+            let parent_path: String = segments[..i * 2]
+                .iter()
+                .map(|segment| format!("/{}", segment))
+                .collect();
+            (parent_path, pair[0])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use google_cloud_grpc_proto::firestore::v1::{Document, DocumentChange, DocumentDelete};
+
+    fn change(name: &str) -> ListenResponse {
+        ListenResponse {
+            response_type: Some(ResponseType::DocumentChange(DocumentChange {
+                document: Some(Document {
+                    name: format!("projects/p/databases/(default)/documents{}", name),
+                    ..Default::default()
+                }),
+                target_ids: vec![WATCH_TARGET_ID],
+                removed_target_ids: vec![],
+            })),
+        }
+    }
+
+    #[test]
+    fn parent_collections_test() {
+        assert_eq!(
+            vec![("".to_owned(), "users"), ("/users/u1".to_owned(), "posts")],
+            parent_collections("/users/u1/posts/p1")
+        );
+    }
+
+    #[test]
+    fn cached_collection_ids_test() {
+        let now = Instant::now();
+        let mut cache = CachedCollectionIds::new(Duration::from_secs(60));
+        cache.insert("".to_owned(), vec!["users".to_owned()], now);
+        cache.insert("/users/u1".to_owned(), vec!["posts".to_owned()], now);
+
+        assert!(cache.get("", now + Duration::from_secs(61)).is_none());
+
+        // known collections keep the cache
+        cache.apply(change("/users/u2"), now);
+        cache.apply(change("/users/u1/posts/p1"), now);
+        assert_eq!(Some(vec!["users".to_owned()]), cache.get("", now));
+        assert!(cache.get("/users/u1", now).is_some());
+
+        // unknown collection
+        cache.apply(change("/users/u1/likes/l1"), now);
+        assert!(cache.get("/users/u1", now).is_none());
+        assert!(cache.get("", now).is_some());
+
+        // the collection may have become empty
+        cache.apply(
+            ListenResponse {
+                response_type: Some(ResponseType::DocumentDelete(DocumentDelete {
+                    document: "projects/p/databases/(default)/documents/users/u2".to_owned(),
+                    removed_target_ids: vec![WATCH_TARGET_ID],
+                    read_time: None,
+                })),
+            },
+            now,
+        );
+        assert!(cache.get("", now).is_none());
+    }
+}
@@ -0,0 +1,270 @@
+use super::cancel::CancellationToken;
+use futures::{future, stream, Future, Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// the order in which the background components are stopped by `ClientComponents::shutdown`.
+/// the components using the others are stopped first, the token refresh which every rpc
+/// depends on is stopped last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ComponentTier {
+    /// `BulkWriter`s, which write the pending operations before they stop.
+    Writer,
+    /// the refreshers of the caches (e.g. `CollectionIdCache::watch_changes`).
+    Cache,
+    /// the Listen streams and the partitioned reads.
+    Stream,
+    /// the token refresh task.
+    Auth,
+}
+
+/// the components stopped by `ClientComponents::shutdown`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ShutdownReport {
+    pub stopped: Vec<String>,
+    /// the components which didn't stop within the timeout.
+    pub failed: Vec<String>,
+}
+
+impl ShutdownReport {
+    pub fn is_clean(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+type StoppedFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+type StopFn = Box<dyn FnOnce() + Send>;
+
+struct Component {
+    name: String,
+    tier: ComponentTier,
+    stop: StopFn,
+    /// resolves when the component has stopped.
+    stopped: StoppedFuture,
+    /// cancelled when the task has exited, if registered by `register_task`.
+    exited: Option<CancellationToken>,
+}
+
+#[derive(Default)]
+struct Registry {
+    components: Vec<Component>,
+    shut_down: bool,
+}
+
+/// the background tasks started by the client and its clones (the token refresh, the bulk
+/// writers, the Listen streams and the cache refreshers), to stop them together before the
+/// runtime is dropped. the tasks left running are aborted at the drop of the runtime, which
+/// loses the writes queued in the bulk writers.
+///
+/// ```ignore
+/// let report = client.components().shutdown(Duration::from_secs(10)).await;
+/// if !report.is_clean() {
+///     log::warn!("failed to stop {:?}", report.failed);
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct ClientComponents {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl ClientComponents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// register the component stopped by calling `stop`. `stopped` resolves when it has
+    /// stopped. the component registered after `shutdown` is stopped immediately.
+    pub(crate) fn register<S, F>(
+        &self,
+        name: impl Into<String>,
+        tier: ComponentTier,
+        stop: S,
+        stopped: F,
+    ) where
+        S: FnOnce() + Send + 'static,
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.push(Component {
+            name: name.into(),
+            tier,
+            stop: Box::new(stop),
+            stopped: Box::pin(stopped),
+            exited: None,
+        });
+    }
+
+    /// register the task which stops when `stop` is cancelled and drops the guard on exit.
+    pub(crate) fn register_task(
+        &self,
+        name: impl Into<String>,
+        tier: ComponentTier,
+    ) -> (CancellationToken, StoppedGuard) {
+        let stop = CancellationToken::new();
+        let stopped = CancellationToken::new();
+        let guard = StoppedGuard(stopped.clone());
+        self.push(Component {
+            name: name.into(),
+            tier,
+            stop: Box::new({
+                let stop = stop.clone();
+                move || stop.cancel()
+            }),
+            stopped: Box::pin({
+                let stopped = stopped.clone();
+                async move { stopped.cancelled().await }
+            }),
+            exited: Some(stopped),
+        });
+        (stop, guard)
+    }
+
+    fn push(&self, component: Component) {
+        let mut registry = self.registry.lock().unwrap();
+        if registry.shut_down {
+            (component.stop)();
+            return;
+        }
+        // forget the tasks which have already exited by themselves
+        registry.components.retain(|component| {
+            !component
+                .exited
+                .as_ref()
+                .is_some_and(CancellationToken::is_cancelled)
+        });
+        registry.components.push(component);
+    }
+
+    /// the number of the components registered and not known to have stopped.
+    pub fn len(&self) -> usize {
+        self.registry.lock().unwrap().components.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// stop the components tier by tier in the order of `ComponentTier`, waiting for each tier
+    /// before stopping the next one. the components not stopped until `timeout` are reported
+    /// as failed. the components registered afterwards are stopped immediately.
+    pub async fn shutdown(&self, timeout: Duration) -> ShutdownReport {
+        let mut components = {
+            let mut registry = self.registry.lock().unwrap();
+            registry.shut_down = true;
+            std::mem::take(&mut registry.components)
+        };
+        components.sort_by_key(|component| component.tier);
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut report = ShutdownReport::default();
+        let mut remaining = components.into_iter().peekable();
+        while let Some(tier) = remaining.peek().map(|component| component.tier) {
+            let mut in_tier = Vec::new();
+            while let Some(component) = remaining.next_if(|component| component.tier == tier) {
+                in_tier.push(component);
+            }
+            let results = future::join_all(in_tier.into_iter().map(|component| async move {
+                (component.stop)();
+                let stopped = tokio::time::timeout_at(deadline, component.stopped)
+                    .await
+                    .is_ok();
+                (component.name, stopped)
+            }))
+            .await;
+            for (name, stopped) in results {
+                if stopped {
+                    report.stopped.push(name);
+                } else {
+                    log::warn!("{} didn't stop within the shutdown timeout", name);
+                    report.failed.push(name);
+                }
+            }
+        }
+        report
+    }
+}
+
+/// held by a registered task. the task is regarded as stopped when it is dropped.
+pub(crate) struct StoppedGuard(CancellationToken);
+
+impl Drop for StoppedGuard {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+/// `stream` ending when `stop` is cancelled. the guard is dropped when the stream ends.
+pub(crate) fn until_stopped<S>(
+    stream: S,
+    stop: CancellationToken,
+    guard: StoppedGuard,
+) -> impl Stream<Item = S::Item>
+where
+    S: Stream,
+{
+    let stream = Box::pin(stream.take_until(async move { stop.cancelled().await }));
+    stream::unfold(Some((stream, guard)), |state| async move {
+        let (mut stream, guard) = state?;
+        let item = stream.next().await?;
+        Some((item, Some((stream, guard))))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn shutdown_test() {
+        let components = ClientComponents::new();
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        for (name, tier) in [
+            ("token_manager", ComponentTier::Auth),
+            ("listen", ComponentTier::Stream),
+            ("bulk_writer", ComponentTier::Writer),
+        ] {
+            let (stop, guard) = components.register_task(name, tier);
+            let order = order.clone();
+            tokio::spawn(async move {
+                stop.cancelled().await;
+                order.lock().unwrap().push(name);
+                drop(guard);
+            });
+        }
+        // never stops
+        let (_stop, stuck) = components.register_task("stuck", ComponentTier::Auth);
+        assert_eq!(4, components.len());
+
+        let report = components.shutdown(Duration::from_millis(100)).await;
+        assert_eq!(
+            vec!["bulk_writer", "listen", "token_manager"],
+            report.stopped
+        );
+        assert_eq!(vec!["stuck"], report.failed);
+        assert!(!report.is_clean());
+        assert_eq!(
+            vec!["bulk_writer", "listen", "token_manager"],
+            *order.lock().unwrap()
+        );
+        assert!(components.is_empty());
+        drop(stuck);
+
+        let (late, _guard) = components.register_task("late", ComponentTier::Stream);
+        assert!(late.is_cancelled());
+        assert!(components.is_empty());
+    }
+
+    #[tokio::test]
+    async fn register_forgets_stopped_components_test() {
+        let components = ClientComponents::new();
+        for _ in 0..3 {
+            let (_stop, guard) = components.register_task("bulk_writer", ComponentTier::Writer);
+            drop(guard);
+        }
+        // the last one is not checked until the next registration
+        assert_eq!(1, components.len());
+        let report = components.shutdown(Duration::from_millis(10)).await;
+        assert_eq!(vec!["bulk_writer"], report.stopped);
+    }
+}
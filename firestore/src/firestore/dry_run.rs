@@ -0,0 +1,212 @@
+use super::request::{fmt_document_path, DocumentWriteOperation};
+use super::size_calculator::{document_path_size, fields_size, DOCUMENT_ADDITIONAL_BYTES};
+use super::value::FFields;
+use google_cloud_grpc_proto::{
+    firestore::v1::{Document, Value, WriteResult},
+    prost_types::Timestamp,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// a write not sent by the client of `FirestoreClient::with_dry_run`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DryRunWrite {
+    /// e.g. "Commit", "BatchWrite"
+    pub rpc: String,
+    /// "create", "update" or "delete"
+    pub kind: String,
+    /// e.g. "/users/user_1"
+    pub document_path: String,
+    pub update_field_mask: Option<Vec<String>>,
+    /// the field paths of the transforms (e.g. `Increment`)
+    pub transform_field_paths: Vec<String>,
+    /// the size of the document of the fields written, by `size_calculator`. 0 for the deletes.
+    pub size: usize,
+}
+
+impl DryRunWrite {
+    pub(crate) fn from_operation(rpc: &str, operation: &DocumentWriteOperation) -> Self {
+        Self {
+            rpc: rpc.to_owned(),
+            kind: operation.kind().to_owned(),
+            document_path: operation.document_path().to_owned(),
+            update_field_mask: operation.update_field_mask().map(<[String]>::to_vec),
+            transform_field_paths: operation
+                .update_transforms()
+                .iter()
+                .map(|(field_path, _)| field_path.clone())
+                .collect(),
+            size: operation
+                .fields()
+                .map(|fields| write_size(operation.document_path(), fields))
+                .unwrap_or(0),
+        }
+    }
+
+    pub(crate) fn from_document(
+        rpc: &str,
+        kind: &str,
+        document_path: String,
+        fields: &HashMap<String, Value>,
+        update_field_mask: Option<Vec<String>>,
+    ) -> Self {
+        Self {
+            rpc: rpc.to_owned(),
+            kind: kind.to_owned(),
+            size: write_size(&document_path, fields),
+            document_path,
+            update_field_mask,
+            transform_field_paths: Vec::new(),
+        }
+    }
+
+    pub(crate) fn delete(rpc: &str, document_path: String) -> Self {
+        Self {
+            rpc: rpc.to_owned(),
+            kind: "delete".to_owned(),
+            document_path,
+            update_field_mask: None,
+            transform_field_paths: Vec::new(),
+            size: 0,
+        }
+    }
+}
+
+fn write_size(document_path: &str, fields: &HashMap<String, Value>) -> usize {
+    document_path_size(document_path)
+        + fields_size(&FFields::from(fields.clone()))
+        + DOCUMENT_ADDITIONAL_BYTES
+}
+
+/// the writes recorded by the clients of `FirestoreClient::with_dry_run` instead of being sent.
+/// the clones of the recorder share the writes.
+///
+/// ```ignore
+/// let recorder = DryRunRecorder::new();
+/// let client = client.clone().with_dry_run(recorder.clone());
+/// migrate(&client).await?;
+/// for write in recorder.writes() {
+///     println!("{} {} {} bytes", write.kind, write.document_path, write.size);
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DryRunRecorder {
+    writes: Arc<Mutex<Vec<DryRunWrite>>>,
+}
+
+impl DryRunRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the writes recorded so far.
+    pub fn writes(&self) -> Vec<DryRunWrite> {
+        self.writes.lock().unwrap().clone()
+    }
+
+    /// the writes recorded so far, removed from the recorder.
+    pub fn take(&self) -> Vec<DryRunWrite> {
+        std::mem::take(&mut *self.writes.lock().unwrap())
+    }
+
+    pub fn len(&self) -> usize {
+        self.writes.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn record(&self, write: DryRunWrite) {
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            rpc = %write.rpc,
+            kind = %write.kind,
+            document_path = %write.document_path,
+            update_field_mask = ?write.update_field_mask,
+            size = write.size,
+            "dry-run write"
+        );
+        self.writes.lock().unwrap().push(write);
+    }
+
+    /// record the operations, and the synthetic results of them written now.
+    pub(crate) fn record_operations(
+        &self,
+        rpc: &str,
+        operations: &[DocumentWriteOperation],
+    ) -> Vec<WriteResult> {
+        for operation in operations {
+            self.record(DryRunWrite::from_operation(rpc, operation));
+        }
+        operations
+            .iter()
+            .map(|_| synthetic_write_result())
+            .collect()
+    }
+}
+
+pub(crate) fn synthetic_write_result() -> WriteResult {
+    WriteResult {
+        update_time: Some(Timestamp::from(SystemTime::now())),
+        transform_results: Vec::new(),
+    }
+}
+
+/// the document as if it was written now.
+pub(crate) fn synthetic_document(
+    project_id: &str,
+    document_path: &str,
+    fields: HashMap<String, Value>,
+) -> Document {
+    let now = Timestamp::from(SystemTime::now());
+    Document {
+        name: fmt_document_path(project_id, document_path),
+        fields,
+        create_time: Some(now.clone()),
+        update_time: Some(now),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::firestore::FValue;
+
+    #[test]
+    fn dry_run_recorder_test() {
+        let recorder = DryRunRecorder::new();
+        let mut fields = HashMap::new();
+        fields.insert("name".to_owned(), FValue::from("jeff").to_grpc_value());
+        let operations = vec![
+            DocumentWriteOperation::new_update(
+                "/users/jeff".to_owned(),
+                fields,
+                Some(vec!["name".to_owned()]),
+            ),
+            DocumentWriteOperation::new_delete("/users/bob".to_owned()),
+        ];
+
+        let results = recorder.clone().record_operations("Commit", &operations);
+        assert_eq!(2, results.len());
+        assert!(results[0].update_time.is_some());
+
+        let writes = recorder.take();
+        assert_eq!(
+            DryRunWrite {
+                rpc: "Commit".to_owned(),
+                kind: "update".to_owned(),
+                document_path: "/users/jeff".to_owned(),
+                update_field_mask: Some(vec!["name".to_owned()]),
+                transform_field_paths: Vec::new(),
+                // "users" 6 + "jeff" 5 + 16, "name" 5 + "jeff" 5, 32
+                size: 69,
+            },
+            writes[0]
+        );
+        assert_eq!("delete", writes[1].kind);
+        assert_eq!(0, writes[1].size);
+        assert!(recorder.is_empty());
+    }
+}
@@ -0,0 +1,369 @@
+use super::value::SerdeError;
+
+#[cfg(feature = "grpc")]
+use google_cloud_grpc_proto::prost::Message;
+#[cfg(feature = "grpc")]
+use google_cloud_grpc_proto::rpc::{self, BadRequest, QuotaFailure, RetryInfo};
+#[cfg(feature = "grpc")]
+use google_cloud_grpc_proto::tonic::{Code, Status};
+#[cfg(feature = "grpc")]
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+#[cfg(feature = "grpc")]
+use std::time::Duration;
+
+pub type Result<T, E = FirestoreError> = std::result::Result<T, E>;
+
+/// the error of the firestore api. non-exhaustive, as `Status` exists only with the `grpc`
+/// feature.
+///
+/// ```ignore
+/// match client.commit(operations, None).await {
+///     Err(e) if e.code() == Some(Code::PermissionDenied) => ...,
+///     ...
+/// }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum FirestoreError {
+    /// the status returned from the server.
+    #[cfg(feature = "grpc")]
+    Status(Box<Status>),
+    /// failed to connect to the server.
+    Connection(anyhow::Error),
+    /// failed to get the access token.
+    Auth(anyhow::Error),
+    /// failed to convert the values from/into the documents.
+    Serde(SerdeError),
+    /// failed to deserialize the document read (e.g. in `run_query_stream_as`).
+    Decode {
+        /// e.g. "/users/user_1"
+        document_path: String,
+        error: SerdeError,
+    },
+    /// rejected before sending the request.
+    InvalidArgument(String),
+    /// the error returned from the callbacks or the closures passed by the caller.
+    Callback(anyhow::Error),
+    /// the background tasks of the client stopped unexpectedly.
+    Internal(String),
+    /// the request was aborted by the cancellation token of the client.
+    Cancelled,
+    /// the data read doesn't match the hash written with it. (e.g. a chunked field)
+    Integrity(String),
+    /// a write was attempted with a read-only client. the name of the rpc.
+    ReadOnlyViolation(String),
+}
+
+/// the coarse classification of `FirestoreError`, e.g. to decide whether a stream of typed
+/// documents should skip the document or stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// the request didn't reach the server.
+    Transport,
+    /// the server returned the status.
+    Status,
+    /// the value read or written couldn't be converted.
+    Decode,
+    Other,
+}
+
+impl FirestoreError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            #[cfg(feature = "grpc")]
+            FirestoreError::Status(_) => ErrorKind::Status,
+            FirestoreError::Connection(_) => ErrorKind::Transport,
+            FirestoreError::Serde(_) | FirestoreError::Decode { .. } => ErrorKind::Decode,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// the path of the document which failed to be deserialized.
+    pub fn document_path(&self) -> Option<&str> {
+        match self {
+            FirestoreError::Decode { document_path, .. } => Some(document_path.as_str()),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "grpc")]
+    /// the grpc status code if the server returned the error.
+    pub fn code(&self) -> Option<Code> {
+        match self {
+            FirestoreError::Status(status) => Some(status.code()),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "grpc")]
+    pub fn is_not_found(&self) -> bool {
+        self.code() == Some(Code::NotFound)
+    }
+
+    #[cfg(feature = "grpc")]
+    pub fn is_already_exists(&self) -> bool {
+        self.code() == Some(Code::AlreadyExists)
+    }
+
+    #[cfg(feature = "grpc")]
+    /// the transaction was aborted by contention with another transaction.
+    pub fn is_aborted(&self) -> bool {
+        self.code() == Some(Code::Aborted)
+    }
+
+    /// the message of the status returned from the server, or the description of the error.
+    pub fn message(&self) -> String {
+        match self {
+            #[cfg(feature = "grpc")]
+            FirestoreError::Status(status) => status.message().to_owned(),
+            _ => self.to_string(),
+        }
+    }
+
+    /// the request may succeed if retried later. (e.g. unavailable, quota exceeded)
+    /// the transient connection errors are retryable too.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            #[cfg(feature = "grpc")]
+            FirestoreError::Status(status) => matches!(
+                status.code(),
+                Code::Unavailable
+                    | Code::DeadlineExceeded
+                    | Code::ResourceExhausted
+                    | Code::Aborted
+                    | Code::Internal
+            ),
+            FirestoreError::Connection(_) => true,
+            _ => false,
+        }
+    }
+
+    #[cfg(feature = "grpc")]
+    /// the google.rpc error details attached to the status returned from the server.
+    pub fn error_details(&self) -> ErrorDetails {
+        match self {
+            FirestoreError::Status(status) => ErrorDetails::from_status_details(status.details()),
+            _ => ErrorDetails::default(),
+        }
+    }
+
+    pub(crate) fn invalid_argument<S: Into<String>>(message: S) -> Self {
+        FirestoreError::InvalidArgument(message.into())
+    }
+
+    /// the error returned by the callback of the caller. the `FirestoreError` returned through
+    /// `anyhow` (e.g. `Decode` from a typed helper) is kept as it is.
+    #[cfg(feature = "grpc")]
+    pub(crate) fn from_callback(e: anyhow::Error) -> Self {
+        match e.downcast::<FirestoreError>() {
+            Ok(e) => e,
+            Err(e) => FirestoreError::Callback(e),
+        }
+    }
+}
+
+/// the error details of the server (in "grpc-status-details-bin"). the unknown details are ignored.
+#[cfg(feature = "grpc")]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ErrorDetails {
+    pub bad_request: Option<BadRequest>,
+    pub quota_failure: Option<QuotaFailure>,
+    pub retry_info: Option<RetryInfo>,
+}
+
+#[cfg(feature = "grpc")]
+impl ErrorDetails {
+    fn from_status_details(details: &[u8]) -> Self {
+        let mut result = ErrorDetails::default();
+        let status = match rpc::Status::decode(details) {
+            Ok(status) => status,
+            Err(_) => return result,
+        };
+        for detail in status.details {
+            let value = detail.value.as_slice();
+            match detail.type_url.rsplit('/').next() {
+                Some("google.rpc.BadRequest") => {
+                    result.bad_request = BadRequest::decode(value).ok()
+                }
+                Some("google.rpc.QuotaFailure") => {
+                    result.quota_failure = QuotaFailure::decode(value).ok()
+                }
+                Some("google.rpc.RetryInfo") => result.retry_info = RetryInfo::decode(value).ok(),
+                _ => {}
+            }
+        }
+        result
+    }
+
+    /// the delay the server asks to wait before retrying.
+    pub fn retry_delay(&self) -> Option<Duration> {
+        let delay = self.retry_info.as_ref()?.retry_delay.clone()?;
+        Duration::try_from(delay).ok()
+    }
+}
+
+impl Display for FirestoreError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "grpc")]
+            FirestoreError::Status(status) => write!(f, "grpc status error {:?}", status),
+            FirestoreError::Connection(e) => write!(f, "connection error: {}", e),
+            FirestoreError::Auth(e) => write!(f, "auth error: {}", e),
+            FirestoreError::Serde(e) => write!(f, "{}", e),
+            FirestoreError::Decode {
+                document_path,
+                error,
+            } => write!(f, "failed to deserialize {}: {}", document_path, error),
+            FirestoreError::InvalidArgument(message) => write!(f, "invalid argument: {}", message),
+            FirestoreError::Callback(e) => write!(f, "{}", e),
+            FirestoreError::Internal(message) => write!(f, "internal error: {}", message),
+            FirestoreError::Cancelled => write!(f, "cancelled"),
+            FirestoreError::Integrity(message) => write!(f, "integrity error: {}", message),
+            FirestoreError::ReadOnlyViolation(rpc) => {
+                write!(f, "{} is not allowed on the read-only client", rpc)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FirestoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "grpc")]
+            FirestoreError::Status(status) => Some(status.as_ref()),
+            FirestoreError::Connection(e)
+            | FirestoreError::Auth(e)
+            | FirestoreError::Callback(e) => Some(e.as_ref()),
+            FirestoreError::Serde(e) | FirestoreError::Decode { error: e, .. } => Some(e),
+            FirestoreError::InvalidArgument(_)
+            | FirestoreError::Internal(_)
+            | FirestoreError::Cancelled
+            | FirestoreError::Integrity(_)
+            | FirestoreError::ReadOnlyViolation(_) => None,
+        }
+    }
+}
+
+#[cfg(feature = "grpc")]
+impl From<Status> for FirestoreError {
+    fn from(status: Status) -> Self {
+        FirestoreError::Status(Box::new(status))
+    }
+}
+
+impl From<SerdeError> for FirestoreError {
+    fn from(e: SerdeError) -> Self {
+        FirestoreError::Serde(e)
+    }
+}
+
+#[cfg(all(test, feature = "grpc"))]
+mod test {
+    use super::{ErrorKind, FirestoreError};
+    use crate::firestore::value::SerdeError;
+    use google_cloud_grpc_proto::prost::Message;
+    use google_cloud_grpc_proto::prost_types::{self, Any};
+    use google_cloud_grpc_proto::rpc::{self, QuotaFailure, RetryInfo};
+    use google_cloud_grpc_proto::tonic::{Code, Status};
+    use std::time::Duration;
+
+    fn any<M: Message>(type_name: &str, message: M) -> Any {
+        let mut value = Vec::new();
+        message.encode(&mut value).unwrap();
+        Any {
+            type_url: format!("type.googleapis.com/{}", type_name),
+            value,
+        }
+    }
+
+    #[test]
+    fn error_code_test() {
+        let e = FirestoreError::from(Status::not_found("doc"));
+        assert_eq!(Some(Code::NotFound), e.code());
+        assert!(e.is_not_found());
+        assert!(!e.is_aborted());
+        assert!(FirestoreError::from(Status::aborted("contention")).is_aborted());
+
+        let e = FirestoreError::invalid_argument("too many operations");
+        assert_eq!(None, e.code());
+        assert!(!e.is_not_found());
+        assert!(!e.is_retryable());
+        assert_eq!("invalid argument: too many operations", e.message());
+    }
+
+    #[test]
+    fn error_kind_test() {
+        assert_eq!(
+            ErrorKind::Status,
+            FirestoreError::from(Status::unavailable("")).kind()
+        );
+        assert_eq!(
+            ErrorKind::Transport,
+            FirestoreError::Connection(anyhow::anyhow!("refused")).kind()
+        );
+        let decode = FirestoreError::Decode {
+            document_path: "/users/u1".to_owned(),
+            error: SerdeError::CustomError("invalid type".to_owned()),
+        };
+        assert_eq!(ErrorKind::Decode, decode.kind());
+        assert_eq!(Some("/users/u1"), decode.document_path());
+        assert_eq!(ErrorKind::Other, FirestoreError::Cancelled.kind());
+    }
+
+    #[test]
+    fn from_callback_test() {
+        let decode = FirestoreError::Decode {
+            document_path: "/users/u1".to_owned(),
+            error: SerdeError::CustomError("invalid type".to_owned()),
+        };
+        let e = FirestoreError::from_callback(anyhow::Error::from(decode));
+        assert_eq!(Some("/users/u1"), e.document_path());
+
+        let e = FirestoreError::from_callback(anyhow::anyhow!("failed in the callback"));
+        assert!(matches!(e, FirestoreError::Callback(_)));
+    }
+
+    #[test]
+    fn error_details_test() {
+        let mut details = Vec::new();
+        rpc::Status {
+            code: Code::ResourceExhausted as i32,
+            message: "quota".to_owned(),
+            details: vec![
+                any(
+                    "google.rpc.RetryInfo",
+                    RetryInfo {
+                        retry_delay: Some(prost_types::Duration {
+                            seconds: 3,
+                            nanos: 0,
+                        }),
+                    },
+                ),
+                any(
+                    "google.rpc.QuotaFailure",
+                    QuotaFailure { violations: vec![] },
+                ),
+                any("google.rpc.Unknown", RetryInfo { retry_delay: None }),
+            ],
+        }
+        .encode(&mut details)
+        .unwrap();
+
+        let e = FirestoreError::from(Status::with_details(
+            Code::ResourceExhausted,
+            "quota",
+            details.into(),
+        ));
+        assert!(e.is_retryable());
+        assert_eq!("quota", e.message());
+        let details = e.error_details();
+        assert_eq!(Some(Duration::from_secs(3)), details.retry_delay());
+        assert!(details.quota_failure.is_some());
+        assert!(details.bad_request.is_none());
+
+        let e = FirestoreError::from(Status::not_found("doc"));
+        assert!(!e.is_retryable());
+        assert_eq!(None, e.error_details().retry_delay());
+    }
+}
@@ -0,0 +1,201 @@
+use super::client::FirestoreClient;
+
+use super::error::{FirestoreError, Result};
+use super::request::documents_root_path;
+use futures::future;
+use google_cloud_grpc_proto::firestore::v1::Document;
+use std::collections::HashMap;
+
+/// a database of a project.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DatabaseRef {
+    pub project_id: String,
+    pub database_id: String,
+}
+
+impl DatabaseRef {
+    pub fn new<P: Into<String>, D: Into<String>>(project_id: P, database_id: D) -> Self {
+        Self {
+            project_id: project_id.into(),
+            database_id: database_id.into(),
+        }
+    }
+
+    /// the "(default)" database of the project.
+    pub fn default_database<P: Into<String>>(project_id: P) -> Self {
+        Self::new(project_id, "(default)")
+    }
+
+    /// "/coll/doc" => "projects/{project_id}/databases/{database_id}/documents/coll/doc"
+    pub fn resource_name(&self, document_path: &str) -> String {
+        format!(
+            "projects/{}/databases/{}/documents{}",
+            self.project_id, self.database_id, document_path
+        )
+    }
+}
+
+/// the clients of the databases an app is sharded across.
+///
+/// ```ignore
+/// let mut pool = FirestoreClientPool::new();
+/// pool.insert(db_a.clone(), client_a);
+/// pool.insert(db_b.clone(), client_b);
+/// let docs = pool
+///     .batch_get_documents(vec![(db_a, "/users/u1".to_owned()), (db_b, "/users/u2".to_owned())], None)
+///     .await?;
+/// ```
+#[derive(Clone, Default)]
+pub struct FirestoreClientPool {
+    clients: HashMap<DatabaseRef, FirestoreClient>,
+}
+
+impl FirestoreClientPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// register the client reading from `database`. returns the client replaced if any.
+    pub fn insert(
+        &mut self,
+        database: DatabaseRef,
+        client: FirestoreClient,
+    ) -> Option<FirestoreClient> {
+        self.clients.insert(database, client)
+    }
+
+    pub fn get(&self, database: &DatabaseRef) -> Option<&FirestoreClient> {
+        self.clients.get(database)
+    }
+
+    /// get the documents from the databases concurrently, one batch get per database.
+    /// the results are keyed by the resource name in the database passed
+    /// (`DatabaseRef::resource_name`), and None is the missing document.
+    /// fails without sending any request if a database has no client.
+    pub async fn batch_get_documents(
+        &self,
+        document_paths: Vec<(DatabaseRef, String)>,
+        field_mask: Option<Vec<String>>,
+    ) -> Result<HashMap<String, Option<Document>>> {
+        let grouped = group_by_database(document_paths);
+        let mut requests = Vec::with_capacity(grouped.len());
+        for (database, paths) in grouped {
+            let client = self.clients.get(&database).ok_or_else(|| {
+                FirestoreError::invalid_argument(format!(
+                    "no client for the database {}/{}",
+                    database.project_id, database.database_id
+                ))
+            })?;
+            requests.push(batch_get_from(
+                database,
+                client.clone(),
+                paths,
+                field_mask.clone(),
+            ));
+        }
+
+        let mut result = HashMap::new();
+        for each in future::try_join_all(requests).await? {
+            result.extend(each);
+        }
+        Ok(result)
+    }
+}
+
+async fn batch_get_from(
+    database: DatabaseRef,
+    client: FirestoreClient,
+    document_paths: Vec<String>,
+    field_mask: Option<Vec<String>>,
+) -> Result<Vec<(String, Option<Document>)>> {
+    let root_path = documents_root_path(client.project_id().to_owned());
+    let resource_name = |name: &str| {
+        let document_path = name.strip_prefix(root_path.as_str()).unwrap_or(name);
+        database.resource_name(document_path)
+    };
+    let mut documents = Vec::with_capacity(document_paths.len());
+    let missing = client
+        .batch_get_documents(document_paths, field_mask, None, |doc| {
+            documents.push((resource_name(&doc.name), Some(doc)));
+            Ok(())
+        })
+        .await?;
+    documents.extend(missing.iter().map(|name| (resource_name(name), None)));
+    Ok(documents)
+}
+
+fn group_by_database(
+    document_paths: Vec<(DatabaseRef, String)>,
+) -> HashMap<DatabaseRef, Vec<String>> {
+    let mut grouped = HashMap::<DatabaseRef, Vec<String>>::new();
+    for (database, path) in document_paths {
+        grouped.entry(database).or_default().push(path);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn group_by_database_test() {
+        let db_a = DatabaseRef::default_database("project_a");
+        let db_b = DatabaseRef::new("project_b", "shard_1");
+        assert_eq!(
+            "projects/project_b/databases/shard_1/documents/users/u1",
+            db_b.resource_name("/users/u1")
+        );
+
+        let grouped = group_by_database(vec![
+            (db_a.clone(), "/users/u1".to_owned()),
+            (db_b.clone(), "/users/u2".to_owned()),
+            (db_a.clone(), "/users/u3".to_owned()),
+        ]);
+        assert_eq!(
+            Some(&vec!["/users/u1".to_owned(), "/users/u3".to_owned()]),
+            grouped.get(&db_a)
+        );
+        assert_eq!(Some(&vec!["/users/u2".to_owned()]), grouped.get(&db_b));
+    }
+
+    #[tokio::test]
+    async fn unknown_database_test() {
+        let pool = FirestoreClientPool::new();
+        let result = pool
+            .batch_get_documents(
+                vec![(DatabaseRef::default_database("p"), "/users/u1".to_owned())],
+                None,
+            )
+            .await;
+        assert!(matches!(result, Err(FirestoreError::InvalidArgument(_))));
+    }
+
+    #[tokio::test]
+    async fn two_databases_test() {
+        let default_db = DatabaseRef::default_database("p");
+        let shard = DatabaseRef::new("p", "shard_1");
+        let mut pool = FirestoreClientPool::new();
+        assert!(pool
+            .insert(default_db.clone(), FirestoreClient::offline("p"))
+            .is_none());
+        assert!(pool
+            .insert(shard.clone(), FirestoreClient::offline("p"))
+            .is_none());
+        assert!(pool.get(&default_db).is_some());
+        assert!(pool.get(&shard).is_some());
+        assert!(pool.get(&DatabaseRef::new("p", "shard_2")).is_none());
+
+        // both databases are served by their clients, which fail to connect
+        let result = pool
+            .batch_get_documents(
+                vec![
+                    (default_db, "/users/u1".to_owned()),
+                    (shard, "/users/u2".to_owned()),
+                ],
+                None,
+            )
+            .await;
+        assert!(matches!(result, Err(FirestoreError::Status(_))));
+    }
+}
@@ -0,0 +1,195 @@
+use google_cloud_grpc_proto::firestore::v1::{
+    structured_query::{FieldReference, Projection},
+    StructuredQuery,
+};
+use std::collections::HashMap;
+
+/// the field masks of the reads of the collections, applied by the client when no mask is
+/// passed. to keep the huge fields (e.g. `blob`) from being read by accident.
+///
+/// the masks list the fields to read, as the field masks of firestore can't exclude a field.
+/// pass the mask to the read to read the other fields, or read with the client of
+/// `without_default_field_masks`.
+///
+/// ```ignore
+/// let client = client.with_default_field_masks(
+///     DefaultFieldMasks::new().with_mask("attachments", vec!["name", "content_type", "size"]),
+/// );
+/// // without `blob`
+/// let attachment = client.get_document("/attachments/a1".to_owned(), None, None).await?;
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DefaultFieldMasks {
+    masks: HashMap<String, Vec<String>>,
+}
+
+impl DefaultFieldMasks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the mask of the collections of `collection_id` at any depth.
+    pub fn with_mask<C, F>(mut self, collection_id: C, fields: Vec<F>) -> Self
+    where
+        C: Into<String>,
+        F: Into<String>,
+    {
+        self.masks.insert(
+            collection_id.into(),
+            fields.into_iter().map(Into::into).collect(),
+        );
+        self
+    }
+
+    pub fn get(&self, collection_id: &str) -> Option<&[String]> {
+        self.masks.get(collection_id).map(Vec::as_slice)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.masks.is_empty()
+    }
+
+    /// `field_mask` if given, otherwise the mask of the collection.
+    pub(crate) fn or_collection(
+        &self,
+        collection_id: &str,
+        field_mask: Option<Vec<String>>,
+    ) -> Option<Vec<String>> {
+        field_mask.or_else(|| self.get(collection_id).map(<[String]>::to_vec))
+    }
+
+    /// `field_mask` if given, otherwise the mask of the collection of the document.
+    pub(crate) fn or_document(
+        &self,
+        document_path: &str,
+        field_mask: Option<Vec<String>>,
+    ) -> Option<Vec<String>> {
+        match collection_id_of(document_path) {
+            Some(collection_id) => self.or_collection(collection_id, field_mask),
+            None => field_mask,
+        }
+    }
+
+    /// `field_mask` if given, otherwise the mask of the collection if all the documents are
+    /// in the collections of the same id.
+    pub(crate) fn or_documents(
+        &self,
+        document_paths: &[String],
+        field_mask: Option<Vec<String>>,
+    ) -> Option<Vec<String>> {
+        if field_mask.is_some() || self.is_empty() {
+            return field_mask;
+        }
+        let mut collection_ids = document_paths.iter().map(|path| collection_id_of(path));
+        match collection_ids.next() {
+            Some(Some(first)) if collection_ids.all(|id| id == Some(first)) => {
+                self.or_collection(first, None)
+            }
+            _ => None,
+        }
+    }
+
+    /// select the fields of the mask in the query of a collection with no projection.
+    /// the fields of the orders are selected too, for the cursors of the pages.
+    pub(crate) fn apply_to_query(&self, mut query: StructuredQuery) -> StructuredQuery {
+        if query.select.is_some() || query.from.len() != 1 {
+            return query;
+        }
+        let mut fields = match self.get(&query.from[0].collection_id) {
+            Some(fields) => fields.to_vec(),
+            None => return query,
+        };
+        for order in query.order_by.iter() {
+            if let Some(field) = order.field.as_ref() {
+                if !fields.contains(&field.field_path) {
+                    fields.push(field.field_path.clone());
+                }
+            }
+        }
+        query.select = Some(Projection {
+            fields: fields
+                .into_iter()
+                .map(|field_path| FieldReference { field_path })
+                .collect(),
+        });
+        query
+    }
+}
+
+/// e.g. "users" of "/groups/g1/users/u1"
+fn collection_id_of(document_path: &str) -> Option<&str> {
+    let segments: Vec<&str> = document_path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    // the last pair is [collection_id, document_id] of a document path
+    match segments.chunks(2).last() {
+        Some([collection_id, _]) => Some(collection_id),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::firestore::{OrderDirection, QueryBuilder};
+
+    #[test]
+    fn default_field_masks_test() {
+        let masks = DefaultFieldMasks::new().with_mask("attachments", vec!["name", "size"]);
+        let mask = Some(vec!["name".to_owned(), "size".to_owned()]);
+
+        assert_eq!(mask, masks.or_document("/users/u1/attachments/a1", None));
+        assert_eq!(None, masks.or_document("/users/u1", None));
+        assert_eq!(
+            Some(vec!["blob".to_owned()]),
+            masks.or_document("/attachments/a1", Some(vec!["blob".to_owned()]))
+        );
+
+        assert_eq!(
+            mask,
+            masks.or_documents(
+                &["/attachments/a1".to_owned(), "/attachments/a2".to_owned()],
+                None
+            )
+        );
+        assert_eq!(
+            None,
+            masks.or_documents(
+                &["/attachments/a1".to_owned(), "/users/u1".to_owned()],
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn apply_to_query_test() {
+        let masks = DefaultFieldMasks::new().with_mask("attachments", vec!["name", "size"]);
+
+        let query = masks.apply_to_query(
+            QueryBuilder::collection("attachments".to_owned(), false)
+                .order_by("created_at", OrderDirection::Desc)
+                .build(),
+        );
+        let selected: Vec<String> = query
+            .select
+            .unwrap()
+            .fields
+            .into_iter()
+            .map(|field| field.field_path)
+            .collect();
+        assert_eq!(vec!["name", "size", "created_at"], selected);
+
+        // the projection of the query is kept
+        let query = masks.apply_to_query(
+            QueryBuilder::collection("attachments".to_owned(), false)
+                .select(vec!["blob"])
+                .build(),
+        );
+        assert_eq!(1, query.select.unwrap().fields.len());
+
+        let query =
+            masks.apply_to_query(QueryBuilder::collection("users".to_owned(), false).build());
+        assert!(query.select.is_none());
+    }
+}
@@ -0,0 +1,96 @@
+use super::error::{FirestoreError, Result};
+use google_cloud_grpc_proto::tonic::Code;
+use std::time::Duration;
+
+/// the document read by `health_check`. expected not to exist.
+pub(crate) const HEALTH_CHECK_DOCUMENT_PATH: &str = "/firestore-rs-health-check/probe";
+
+/// the result of `FirestoreClient::health_check`, for readiness probes.
+///
+/// ```ignore
+/// let report = client.health_check(Duration::from_secs(3)).await;
+/// if !report.is_healthy() {
+///     log::warn!("firestore is not ready {:?}", report);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct HealthReport {
+    /// the server responded.
+    pub channel_ok: bool,
+    /// the credentials were accepted. None if it can't be told. (e.g. no response)
+    pub auth_ok: Option<bool>,
+    /// the round trip time of the request, or the timeout.
+    pub latency: Duration,
+    pub timed_out: bool,
+    pub error: Option<FirestoreError>,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        !self.timed_out && self.error.is_none()
+    }
+
+    pub(crate) fn from_result<T>(result: Result<T>, latency: Duration) -> Self {
+        let (channel_ok, auth_ok, error) = match result {
+            Ok(_) => (true, Some(true), None),
+            Err(e) => match e.code() {
+                Some(Code::Unauthenticated) | Some(Code::PermissionDenied) => {
+                    (true, Some(false), Some(e))
+                }
+                Some(Code::Unavailable) | Some(Code::DeadlineExceeded) => (false, None, Some(e)),
+                Some(_) => (true, Some(true), Some(e)),
+                None => match e {
+                    FirestoreError::Auth(_) => (true, Some(false), Some(e)),
+                    _ => (false, None, Some(e)),
+                },
+            },
+        };
+        Self {
+            channel_ok,
+            auth_ok,
+            latency,
+            timed_out: false,
+            error,
+        }
+    }
+
+    pub(crate) fn timed_out(timeout: Duration) -> Self {
+        Self {
+            channel_ok: false,
+            auth_ok: None,
+            latency: timeout,
+            timed_out: true,
+            error: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use google_cloud_grpc_proto::tonic::Status;
+
+    #[test]
+    fn health_report_test() {
+        let latency = Duration::from_millis(12);
+        let status = |code| -> Result<()> { Err(Status::new(code, "").into()) };
+
+        let ok = HealthReport::from_result(Ok(()), latency);
+        assert!(ok.is_healthy());
+        assert_eq!(
+            (true, Some(true), latency),
+            (ok.channel_ok, ok.auth_ok, ok.latency)
+        );
+
+        let denied = HealthReport::from_result(status(Code::PermissionDenied), latency);
+        assert!(!denied.is_healthy());
+        assert_eq!((true, Some(false)), (denied.channel_ok, denied.auth_ok));
+
+        let unavailable = HealthReport::from_result(status(Code::Unavailable), latency);
+        assert_eq!((false, None), (unavailable.channel_ok, unavailable.auth_ok));
+
+        let timed_out = HealthReport::timed_out(Duration::from_secs(3));
+        assert!(!timed_out.is_healthy());
+        assert_eq!(Duration::from_secs(3), timed_out.latency);
+    }
+}
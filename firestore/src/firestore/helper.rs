@@ -1,36 +1,53 @@
 use super::request::DocumentWriteOperation;
 
+use super::error::Result;
 use super::value::fdoc::doc_path;
-use super::value::FFields;
-use serde::Serialize;
+use super::value::field_path::{normalize_field_path, FieldMaskBuilder};
+use super::value::{FFields, FTransform, FValue, TryIntoFFields};
 
+/// `doc` is FFields or any value serialized into a map (e.g. struct or HashMap<String, FValue>).
 pub fn new_write_ope_create<T>(
     parent: Option<String>,
     collection_id: String,
     doc_id: String,
     doc: T,
-) -> DocumentWriteOperation
+) -> Result<DocumentWriteOperation>
 where
-    T: Serialize,
+    T: TryIntoFFields,
 {
-    DocumentWriteOperation::new_create(parent, collection_id, doc_id, FFields::from(doc))
+    let (fields, transforms) = doc.try_into_ffields()?.split_transforms();
+    Ok(
+        DocumentWriteOperation::new_create(parent, collection_id, doc_id, fields)
+            .with_update_transforms(transforms),
+    )
 }
 
+/// the paths of `update_field_mask` are validated and escaped if needed
+/// (e.g. "a.b c" into "a.`b c`"). build them with `FieldMaskBuilder` for the names containing dots.
 pub fn new_write_ope_update<T>(
     parent: Option<String>,
     collection_id: String,
     doc_id: String,
     update_field_mask: Option<Vec<String>>,
     doc: T,
-) -> DocumentWriteOperation
+) -> Result<DocumentWriteOperation>
 where
-    T: Into<FFields>,
+    T: TryIntoFFields,
 {
-    DocumentWriteOperation::new_update(
+    let update_field_mask = update_field_mask
+        .map(|mask| {
+            mask.iter()
+                .map(|path| normalize_field_path(path))
+                .collect::<Result<Vec<String>>>()
+        })
+        .transpose()?;
+    let (fields, transforms) = doc.try_into_ffields()?.split_transforms();
+    Ok(DocumentWriteOperation::new_update(
         doc_path(parent, collection_id, doc_id),
-        doc.into(),
+        fields,
         update_field_mask,
     )
+    .with_update_transforms(transforms))
 }
 
 pub fn new_write_ope_upsert<T>(
@@ -38,11 +55,65 @@ pub fn new_write_ope_upsert<T>(
     collection_id: String,
     doc_id: String,
     doc: T,
-) -> DocumentWriteOperation
+) -> Result<DocumentWriteOperation>
+where
+    T: TryIntoFFields,
+{
+    let (fields, transforms) = doc.try_into_ffields()?.split_transforms();
+    Ok(
+        DocumentWriteOperation::new_upsert(doc_path(parent, collection_id, doc_id), fields)
+            .with_update_transforms(transforms),
+    )
+}
+
+/// `set(doc, {merge: true})` of the other SDKs. only the fields of `doc` are written and the
+/// other fields on the server are kept, while the update without the mask overwrites the whole
+/// document. the nested maps are merged too (e.g. {"address": {"city": ..}} keeps
+/// "address.zip"). with `merge_fields` (e.g. "address.city"), only the fields in it are written.
+/// the document is created if it doesn't exist.
+pub fn new_write_ope_set_merge<T>(
+    parent: Option<String>,
+    collection_id: String,
+    doc_id: String,
+    doc: T,
+    merge_fields: Option<Vec<String>>,
+) -> Result<DocumentWriteOperation>
 where
-    T: Into<FFields>,
+    T: TryIntoFFields,
 {
-    DocumentWriteOperation::new_upsert(doc_path(parent, collection_id, doc_id), doc.into())
+    let (fields, transforms) = doc.try_into_ffields()?.split_transforms();
+    let (fields, mask) = match merge_fields {
+        None => {
+            let mask = FieldMaskBuilder::from_fields(&fields).build();
+            (fields, mask)
+        }
+        Some(merge_fields) => {
+            let mut merged = FFields::empty();
+            let mut mask = Vec::with_capacity(merge_fields.len());
+            for path in merge_fields {
+                let path = normalize_field_path(&path)?;
+                // the transforms are applied regardless of the mask
+                if transforms
+                    .iter()
+                    .any(|(transform_path, _)| *transform_path == path)
+                {
+                    continue;
+                }
+                // the fields in the mask but missing in `doc` are deleted
+                if let Some(value) = fields.get_path(&path) {
+                    merged.set_path(&path, value.clone())?;
+                }
+                mask.push(path);
+            }
+            (merged, mask)
+        }
+    };
+    Ok(DocumentWriteOperation::new_update(
+        doc_path(parent, collection_id, doc_id),
+        fields,
+        Some(mask),
+    )
+    .with_update_transforms(transforms))
 }
 
 pub fn new_write_ope_delete(
@@ -52,3 +123,95 @@ pub fn new_write_ope_delete(
 ) -> DocumentWriteOperation {
     DocumentWriteOperation::new_delete(doc_path(parent, collection_id, doc_id))
 }
+
+/// append the values missing in the array field on the server side. the other fields are kept.
+pub fn new_write_ope_array_union<F, V>(
+    parent: Option<String>,
+    collection_id: String,
+    doc_id: String,
+    field_path: F,
+    values: Vec<V>,
+) -> DocumentWriteOperation
+where
+    F: Into<String>,
+    V: Into<FValue>,
+{
+    let values = values.into_iter().map(|v| v.into()).collect();
+    new_write_ope_transform(
+        doc_path(parent, collection_id, doc_id),
+        field_path.into(),
+        FTransform::ArrayUnion(values),
+    )
+}
+
+/// remove all the elements equal to the values from the array field on the server side.
+pub fn new_write_ope_array_remove<F, V>(
+    parent: Option<String>,
+    collection_id: String,
+    doc_id: String,
+    field_path: F,
+    values: Vec<V>,
+) -> DocumentWriteOperation
+where
+    F: Into<String>,
+    V: Into<FValue>,
+{
+    let values = values.into_iter().map(|v| v.into()).collect();
+    new_write_ope_transform(
+        doc_path(parent, collection_id, doc_id),
+        field_path.into(),
+        FTransform::ArrayRemove(values),
+    )
+}
+
+/// the empty update mask leaves the fields as they are and only the transform is applied.
+pub(crate) fn new_write_ope_transform(
+    document_path: String,
+    field_path: String,
+    transform: FTransform,
+) -> DocumentWriteOperation {
+    DocumentWriteOperation::new_update(document_path, FFields::empty(), Some(Vec::new()))
+        .with_update_transforms(vec![(field_path, transform)])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn new_write_ope_set_merge_test() {
+        let doc = json!({"name": "jeff", "address": {"city": "tokyo", "zip code": "100"}});
+
+        let ope =
+            new_write_ope_set_merge(None, "users".to_owned(), "u1".to_owned(), doc.clone(), None)
+                .unwrap();
+        assert_eq!("/users/u1", ope.document_path());
+        let mut mask = ope.update_field_mask().unwrap().to_vec();
+        mask.sort();
+        assert_eq!(vec!["address.`zip code`", "address.city", "name"], mask);
+        assert!(ope.precondition().is_none());
+
+        let ope = new_write_ope_set_merge(
+            None,
+            "users".to_owned(),
+            "u1".to_owned(),
+            doc,
+            Some(vec!["address.city".to_owned(), "age".to_owned()]),
+        )
+        .unwrap();
+        assert_eq!(
+            Some(&["address.city".to_owned(), "age".to_owned()][..]),
+            ope.update_field_mask()
+        );
+
+        assert!(new_write_ope_set_merge(
+            None,
+            "users".to_owned(),
+            "u1".to_owned(),
+            json!({"name": "jeff"}),
+            Some(vec!["a..b".to_owned()]),
+        )
+        .is_err());
+    }
+}
@@ -1,79 +1,195 @@
+#[cfg(feature = "grpc")]
+mod backfill;
+#[cfg(feature = "grpc")]
+mod builder;
+#[cfg(feature = "grpc")]
+mod bulk_writer;
+#[cfg(feature = "grpc")]
+mod cancel;
+#[cfg(feature = "grpc")]
+mod change_counter;
+#[cfg(feature = "grpc")]
+mod change_stream;
+#[cfg(feature = "grpc")]
+mod checksum;
+#[cfg(feature = "grpc")]
+mod chunked;
+#[cfg(feature = "grpc")]
 mod client;
+#[cfg(feature = "grpc")]
+mod collection;
+#[cfg(feature = "grpc")]
+mod collection_id_cache;
+#[cfg(feature = "grpc")]
+mod components;
+#[cfg(feature = "grpc")]
+mod dry_run;
+mod error;
+#[cfg(feature = "grpc")]
+mod fan_out;
+#[cfg(feature = "grpc")]
+mod field_masks;
+#[cfg(feature = "grpc")]
+mod health;
+#[cfg(feature = "grpc")]
+mod page_size;
+#[cfg(feature = "grpc")]
+mod permission_probe;
+#[cfg(feature = "grpc")]
+mod priority;
+#[cfg(feature = "grpc")]
 mod query;
+#[cfg(feature = "grpc")]
+mod read_repair;
+#[cfg(feature = "grpc")]
+pub mod repl;
+#[cfg(feature = "grpc")]
 mod request;
+#[cfg(feature = "grpc")]
+mod schema;
+#[cfg(feature = "grpc")]
+mod shared;
+pub mod size_calculator;
+#[cfg(feature = "grpc")]
+mod transaction;
+#[cfg(feature = "grpc")]
+pub mod trigger;
 mod value;
+#[cfg(feature = "grpc")]
+mod write_stream;
+#[cfg(feature = "grpc")]
+mod write_validation;
 
+#[cfg(feature = "grpc")]
 mod helper;
+#[cfg(feature = "grpc")]
 pub mod raw;
+#[cfg(feature = "grpc")]
+pub mod synthetic;
 
+#[cfg(feature = "grpc")]
 pub use client::{
-    FirestoreClient, MissingDocPaths, TransactionOperation, MAX_BATCH_WRTIE_SIZE, MAX_IN_CLAUS_NUM,
-    MAX_WRITE_OPE_IN_TX,
+    BatchWriteCheckpoint, BatchWriteResume, ChunkWriteResult, CollectionIdFilter,
+    ConcurrentBatchWriteReport, FirestoreClient, MissingDocPaths, TransactionOperation,
+    TransactionState, WithReadOnlyTransaction, WithTransaction, DEFAULT_TRANSACTION_MAX_ATTEMPTS,
+    FIRESTORE_EMULATOR_HOST_ENV, LIST_DOCUMENT_NAMES_PAGE_SIZE, MAX_BATCH_WRTIE_SIZE,
+    MAX_IN_CLAUS_NUM, MAX_WRITE_OPE_IN_TX, PARTITIONED_QUERY_BUFFER_SIZE, QUERY_UNION_CONCURRENCY,
+    SAMPLE_PROBES_PER_DOCUMENT,
 };
 
-pub use query::QueryBuilder;
+#[cfg(feature = "grpc")]
+pub use crate::grpc::auth::scopes::{self, Scope};
+#[cfg(feature = "grpc")]
+pub use crate::grpc::events::{ClientEvent, ClientEvents, CLIENT_EVENT_CAPACITY};
+#[cfg(feature = "grpc")]
+pub use crate::grpc::hooks::{HookedBody, HookedChannel, RpcHook};
+#[cfg(feature = "grpc")]
+pub use backfill::{Backfill, BackfillProgress, BACKFILL_PAGE_SIZE};
+#[cfg(feature = "grpc")]
+pub use builder::FirestoreClientBuilder;
+#[cfg(feature = "grpc")]
+pub use bulk_writer::{
+    BulkWriter, BulkWriterOptions, WriteHandle, BULK_WRITER_INITIAL_OPS_PER_SEC,
+    BULK_WRITER_MAX_OPS_PER_SEC, DEFAULT_BULK_WRITER_MAX_ATTEMPTS,
+};
+#[cfg(feature = "grpc")]
+pub use cancel::CancellationToken;
+#[cfg(feature = "grpc")]
+pub use change_counter::{ChangeCounter, PathChangeCount, CHANGE_COUNTER_BUCKETS};
+#[cfg(feature = "grpc")]
+pub use change_stream::{decode_resume_token, ChangeStream, DocumentEvent, DocumentEventKind};
+#[cfg(feature = "grpc")]
+pub use checksum::{
+    fields_checksum, ChecksumManifest, ChecksumVerifyReport, DataChecksum, DocumentChecksum,
+    CHECKSUM_PAGE_SIZE,
+};
+#[cfg(feature = "grpc")]
+pub use chunked::{ChunkedField, CHUNKS_COLLECTION_ID, DEFAULT_CHUNK_BYTES};
+#[cfg(feature = "grpc")]
+pub use collection::{CollectionRef, TxCollection, TypedTransaction};
+#[cfg(feature = "grpc")]
+pub use collection_id_cache::CollectionIdCache;
+#[cfg(feature = "grpc")]
+pub use components::{ClientComponents, ComponentTier, ShutdownReport};
+#[cfg(feature = "grpc")]
+pub use dry_run::{DryRunRecorder, DryRunWrite};
+#[cfg(feature = "grpc")]
+pub use error::ErrorDetails;
+pub use error::{ErrorKind, FirestoreError, Result};
+#[cfg(feature = "grpc")]
+pub use fan_out::{DatabaseRef, FirestoreClientPool};
+#[cfg(feature = "grpc")]
+pub use field_masks::DefaultFieldMasks;
+#[cfg(feature = "grpc")]
+pub use health::HealthReport;
+#[cfg(feature = "grpc")]
+pub use page_size::{
+    AdaptivePageSize, ADAPTIVE_INITIAL_PAGE_SIZE, ADAPTIVE_MAX_PAGE_SIZE, ADAPTIVE_MIN_PAGE_SIZE,
+    ADAPTIVE_TARGET_LATENCY, ADAPTIVE_TARGET_PAGE_BYTES,
+};
+#[cfg(feature = "grpc")]
+pub use permission_probe::{
+    PermissionReport, ProbeOperation, ProbeResult, PERMISSION_PROBE_COLLECTION_ID,
+};
+#[cfg(feature = "grpc")]
+pub use priority::{BatchChannelOptions, Priority, DEFAULT_BATCH_CONCURRENCY_LIMIT};
+#[cfg(feature = "grpc")]
+pub use query::{
+    param, partition_queries, Aggregation, CursorValues, DistanceMeasure, FieldOp, OrderDirection,
+    QueryBuilder, QueryParam, QueryTemplate, UnaryOp,
+};
+#[cfg(feature = "grpc")]
+pub use read_repair::{ReadRepair, ReadRepairReport, RepairTarget, READ_REPAIR_PAGE_SIZE};
+#[cfg(feature = "grpc")]
+pub use schema::{DocumentSchema, FieldSchema, FieldType, SCHEMA_SAMPLE_NUM};
+#[cfg(feature = "grpc")]
+#[allow(deprecated)]
+pub use shared::SharedFirestoreClient;
+pub use size_calculator::{estimate_document_size, MAX_DOCUMENT_SIZE};
+#[cfg(feature = "grpc")]
+pub use transaction::TransactionContext;
+#[cfg(feature = "grpc")]
+pub use value::serde::{from_document, from_document_with_casing};
+#[cfg(feature = "grpc")]
+pub use value::DeterministicMessage;
 pub use value::{
-    fdoc::{doc_path, FDocument, FDocumentPath},
-    ffields::FFields,
-    fvalue::{array_value_from_vec, map_value_from_vec, FValue},
-    serde::{from_document, from_fvalue, to_fvalue},
+    fdoc::{
+        doc_path, DocumentSnapshot, FCollectionPath, FDocument, FDocumentPath, JsonMetadataKeys,
+        DOCUMENT_ID_FIELD, DOCUMENT_NAME_FIELD,
+    },
+    ffields::{FFields, TryIntoFFields},
+    field_path::{
+        escape_field_name, join_field_path, parse_field_path, FieldMaskBuilder, FieldPath,
+    },
+    fmap::FMap,
+    fvalue::{array_value_from_vec, map_value_from_vec, FValue, SerdeError},
+    sentinel::{ArrayRemove, ArrayUnion, FTransform, Increment, ServerTimestamp},
+    serde::{
+        from_fvalue, from_fvalue_with_casing, from_fvalues, required_fields, to_fvalue,
+        to_fvalue_with, to_fvalue_with_casing, to_fvalues, FieldCasing, FieldNameCase,
+        NonFiniteDouble,
+    },
+    timestamp,
 };
+#[cfg(feature = "grpc")]
+pub use yup_oauth2::ServiceAccountKey;
 
+#[cfg(feature = "grpc")]
 pub use helper::{
-    new_write_ope_create, new_write_ope_delete, new_write_ope_update, new_write_ope_upsert,
+    new_write_ope_array_remove, new_write_ope_array_union, new_write_ope_create,
+    new_write_ope_delete, new_write_ope_set_merge, new_write_ope_update, new_write_ope_upsert,
+};
+#[cfg(feature = "grpc")]
+pub use write_stream::{WriteStream, WriteStreamToken};
+#[cfg(feature = "grpc")]
+pub use write_validation::{
+    validate_write, WriteValidationError, MAX_FIELD_DEPTH, MAX_FIELD_PATH_SIZE, MAX_INDEX_ENTRIES,
+    MAX_REQUEST_SIZE,
 };
-pub use request::DocumentWriteOperation;
-
-pub mod size_calculator {
-
-    pub const HASH_MAP_ADDITIONAL_BYTES: usize = 32 + 15; //15 is for map name
-
-    //https://firebase.google.com/docs/firestore/storage-size#document-size
-    use std::time::SystemTime;
-    //https://firebase.google.com/docs/firestore/storage-size#document-size
-    //
-    pub fn datetime_size(s: &SystemTime) -> usize {
-        8
-    }
-
-    pub fn datetime_size_opt(v: &Option<SystemTime>) -> usize {
-        match v {
-            Some(vs) => datetime_size(vs),
-            None => 0,
-        }
-    }
-
-    pub fn usize_size(s: &usize) -> usize {
-        8
-    }
-
-    pub fn string_size(s: &str) -> usize {
-        s.len() * 5
-    }
-
-    pub fn number_size(v: &i64) -> usize {
-        8
-    }
-
-    pub fn float_size(v: &f32) -> usize {
-        8
-    }
-
-    pub fn string_size_opt(v: &Option<String>) -> usize {
-        match v {
-            Some(v) => string_size(v),
-            None => 0,
-        }
-    }
-
-    pub fn string_size_vec(vs: &Vec<String>) -> usize {
-        vs.iter().map(|s| string_size(s)).sum()
-    }
 
-    pub fn string_size_vec_opt(v: &Option<Vec<String>>) -> usize {
-        match v {
-            Some(vs) => string_size_vec(vs),
-            None => 0,
-        }
-    }
-}
+#[cfg(feature = "grpc")]
+pub use request::{
+    DocumentWriteOperation, ListDocumentsOptions, PrefixMatch, ReadConsistency, RequestFactory,
+    V1RequestFactory, WritePrecondition, MAX_LABEL_LEN, POINT_IN_TIME_RECOVERY_WINDOW,
+    VERSION_RETENTION_PERIOD,
+};
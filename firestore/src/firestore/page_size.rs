@@ -0,0 +1,204 @@
+use std::time::Duration;
+
+pub const ADAPTIVE_INITIAL_PAGE_SIZE: i32 = 300;
+pub const ADAPTIVE_MIN_PAGE_SIZE: i32 = 50;
+pub const ADAPTIVE_MAX_PAGE_SIZE: i32 = 5000;
+pub const ADAPTIVE_TARGET_LATENCY: Duration = Duration::from_millis(500);
+/// the default budget of `AdaptivePageSize::with_target_page_bytes`.
+pub const ADAPTIVE_TARGET_PAGE_BYTES: usize = 1024 * 1024;
+
+/// the page size tuned by the latency of the pages.
+/// doubled while the full pages return within the half of `target_latency`,
+/// halved when a page takes longer than `target_latency`.
+///
+/// ```ignore
+/// let ids = client.list_collection_ids_adaptive_stream(
+///     "".to_owned(),
+///     AdaptivePageSize::new().with_target_latency(Duration::from_millis(200)),
+///     CollectionIdFilter::All,
+/// );
+/// ```
+///
+/// with `with_target_page_bytes`, the page size is the budget divided by the average size of
+/// the items of the pages observed so far instead, e.g. for the documents of various sizes.
+///
+/// ```ignore
+/// let documents = client
+///     .list_documents_with(
+///         None,
+///         "logs".to_owned(),
+///         ListDocumentsOptions::default().with_adaptive_page_size(
+///             AdaptivePageSize::new().with_target_page_bytes(ADAPTIVE_TARGET_PAGE_BYTES),
+///         ),
+///     )
+///     .await?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptivePageSize {
+    page_size: i32,
+    min_page_size: i32,
+    max_page_size: i32,
+    target_latency: Duration,
+    target_page_bytes: Option<usize>,
+    observed_items: usize,
+    observed_bytes: usize,
+}
+
+impl Default for AdaptivePageSize {
+    fn default() -> Self {
+        Self {
+            page_size: ADAPTIVE_INITIAL_PAGE_SIZE,
+            min_page_size: ADAPTIVE_MIN_PAGE_SIZE,
+            max_page_size: ADAPTIVE_MAX_PAGE_SIZE,
+            target_latency: ADAPTIVE_TARGET_LATENCY,
+            target_page_bytes: None,
+            observed_items: 0,
+            observed_bytes: 0,
+        }
+    }
+}
+
+impl AdaptivePageSize {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// never changes the page size.
+    pub fn fixed(page_size: i32) -> Self {
+        let page_size = page_size.max(1);
+        Self {
+            page_size,
+            min_page_size: page_size,
+            max_page_size: page_size,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_initial_page_size(self, page_size: i32) -> Self {
+        Self {
+            page_size: page_size.max(self.min_page_size).min(self.max_page_size),
+            ..self
+        }
+    }
+
+    /// the range of the page size. `min` is at least 1.
+    pub fn with_bounds(self, min: i32, max: i32) -> Self {
+        let min_page_size = min.max(1);
+        let max_page_size = max.max(min_page_size);
+        Self {
+            page_size: self.page_size.max(min_page_size).min(max_page_size),
+            min_page_size,
+            max_page_size,
+            ..self
+        }
+    }
+
+    pub fn with_target_latency(self, target_latency: Duration) -> Self {
+        Self {
+            target_latency,
+            ..self
+        }
+    }
+
+    /// size the pages by the bytes instead of the latency. see `observe_page`.
+    pub fn with_target_page_bytes(self, target_page_bytes: usize) -> Self {
+        Self {
+            target_page_bytes: Some(target_page_bytes.max(1)),
+            ..self
+        }
+    }
+
+    /// the page size of the next request.
+    pub fn page_size(&self) -> i32 {
+        self.page_size
+    }
+
+    /// tune the page size with the latency and the number of the items of the last page.
+    /// the pages not full (e.g. the last page) don't grow the page size.
+    pub fn observe(&mut self, latency: Duration, item_num: usize) {
+        if latency > self.target_latency {
+            self.page_size = (self.page_size / 2).max(self.min_page_size);
+        } else if latency < self.target_latency / 2 && item_num >= self.page_size as usize {
+            self.page_size = self.page_size.saturating_mul(2).min(self.max_page_size);
+        }
+    }
+
+    /// tune the page size with the last page and its serialized size in bytes
+    /// (e.g. the sum of `prost::Message::encoded_len` of the documents).
+    /// without `with_target_page_bytes`, same as `observe`.
+    pub fn observe_page(&mut self, latency: Duration, item_num: usize, page_bytes: usize) {
+        let target_page_bytes = match self.target_page_bytes {
+            Some(target_page_bytes) => target_page_bytes,
+            None => return self.observe(latency, item_num),
+        };
+        self.observed_items += item_num;
+        self.observed_bytes += page_bytes;
+        if self.observed_items == 0 {
+            return;
+        }
+        let average_bytes = (self.observed_bytes / self.observed_items).max(1);
+        let page_size = (target_page_bytes / average_bytes).min(i32::MAX as usize) as i32;
+        self.page_size = page_size.max(self.min_page_size).min(self.max_page_size);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn adaptive_page_size_test() {
+        let mut page_size = AdaptivePageSize::new()
+            .with_bounds(10, 40)
+            .with_initial_page_size(10)
+            .with_target_latency(Duration::from_millis(100));
+
+        page_size.observe(Duration::from_millis(10), 10);
+        assert_eq!(20, page_size.page_size());
+        page_size.observe(Duration::from_millis(10), 20);
+        page_size.observe(Duration::from_millis(10), 40);
+        assert_eq!(40, page_size.page_size());
+
+        // not full
+        page_size.observe(Duration::from_millis(10), 3);
+        assert_eq!(40, page_size.page_size());
+        // neither fast nor slow
+        page_size.observe(Duration::from_millis(80), 40);
+        assert_eq!(40, page_size.page_size());
+
+        page_size.observe(Duration::from_millis(300), 40);
+        assert_eq!(20, page_size.page_size());
+        page_size.observe(Duration::from_millis(300), 20);
+        page_size.observe(Duration::from_millis(300), 10);
+        assert_eq!(10, page_size.page_size());
+
+        let mut fixed = AdaptivePageSize::fixed(100);
+        fixed.observe(Duration::from_millis(1), 100);
+        fixed.observe(Duration::from_secs(10), 100);
+        assert_eq!(100, fixed.page_size());
+    }
+
+    #[test]
+    fn adaptive_page_size_by_bytes_test() {
+        let mut page_size = AdaptivePageSize::new()
+            .with_bounds(10, 1000)
+            .with_initial_page_size(100)
+            .with_target_page_bytes(100_000);
+
+        // 100 bytes each
+        page_size.observe_page(Duration::from_secs(10), 100, 10_000);
+        assert_eq!(1000, page_size.page_size());
+        // the average of the pages so far, 10_010_000 bytes / 1100 items
+        page_size.observe_page(Duration::from_millis(1), 1000, 10_000_000);
+        assert_eq!(10, page_size.page_size());
+
+        // an empty page keeps the page size
+        page_size.observe_page(Duration::from_millis(1), 0, 0);
+        assert_eq!(10, page_size.page_size());
+
+        // by the latency without the budget
+        let mut by_latency = AdaptivePageSize::new().with_initial_page_size(100);
+        by_latency.observe_page(Duration::from_millis(1), 100, 100_000_000);
+        assert_eq!(200, by_latency.page_size());
+    }
+}
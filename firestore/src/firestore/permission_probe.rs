@@ -0,0 +1,205 @@
+use super::error::FirestoreError;
+use google_cloud_grpc_proto::tonic::Code;
+
+/// the collection `probe_permissions` writes to. the documents are deleted right after written.
+pub const PERMISSION_PROBE_COLLECTION_ID: &str = "firestore-rs-permission-probe";
+
+/// the operation tried by `FirestoreClient::probe_permissions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeOperation {
+    /// get a document.
+    Read,
+    /// create and delete a document in `PERMISSION_PROBE_COLLECTION_ID`.
+    Write,
+    /// list the indexes with the admin api.
+    ListIndexes,
+}
+
+impl ProbeOperation {
+    /// the IAM permissions the operation requires.
+    pub fn required_permissions(&self) -> &'static [&'static str] {
+        match self {
+            ProbeOperation::Read => &["datastore.entities.get"],
+            ProbeOperation::Write => &["datastore.entities.create", "datastore.entities.delete"],
+            ProbeOperation::ListIndexes => &["datastore.indexes.list"],
+        }
+    }
+
+    /// the predefined role granting the permissions.
+    pub fn suggested_role(&self) -> &'static str {
+        match self {
+            ProbeOperation::Read => "roles/datastore.viewer",
+            ProbeOperation::Write => "roles/datastore.user",
+            ProbeOperation::ListIndexes => "roles/datastore.indexAdmin",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ProbeResult {
+    pub operation: ProbeOperation,
+    /// None if the operation succeeded or was not tried.
+    pub error: Option<FirestoreError>,
+    /// why the operation was not tried. e.g. the writes of a read-only or dry-run client,
+    /// which would tell the mode of the client instead of the permissions.
+    pub not_checked: Option<&'static str>,
+}
+
+impl ProbeResult {
+    pub(crate) fn new(operation: ProbeOperation, error: Option<FirestoreError>) -> Self {
+        Self {
+            operation,
+            error,
+            not_checked: None,
+        }
+    }
+
+    pub(crate) fn not_checked(operation: ProbeOperation, reason: &'static str) -> Self {
+        Self {
+            operation,
+            error: None,
+            not_checked: Some(reason),
+        }
+    }
+
+    /// false if the operation failed or was not tried.
+    pub fn is_permitted(&self) -> bool {
+        self.not_checked.is_none() && self.error.is_none()
+    }
+
+    pub fn is_denied(&self) -> bool {
+        self.error.is_some()
+    }
+
+    /// why the operation failed, in terms of the credentials and IAM, or why it was not tried.
+    pub fn diagnosis(&self) -> Option<String> {
+        if let Some(reason) = self.not_checked {
+            return Some(format!("not checked: {}", reason));
+        }
+        let error = self.error.as_ref()?;
+        let diagnosis = match error.code() {
+            Some(Code::PermissionDenied) if is_api_disabled(&error.message()) => {
+                "the Cloud Firestore API is not enabled on the project".to_owned()
+            }
+            Some(Code::PermissionDenied) => format!(
+                "the principal lacks {} (e.g. grant {})",
+                self.operation.required_permissions().join(", "),
+                self.operation.suggested_role()
+            ),
+            Some(Code::Unauthenticated) => {
+                "the credentials were rejected. the key may be revoked or expired".to_owned()
+            }
+            Some(Code::NotFound) | Some(Code::FailedPrecondition) => {
+                "the database is not found, or not in Firestore native mode".to_owned()
+            }
+            Some(code) => format!("failed with {:?} : {}", code, error.message()),
+            None => match error {
+                FirestoreError::Auth(_) => {
+                    "failed to get the access token. check the credential file".to_owned()
+                }
+                _ => format!("failed before reaching the server : {}", error),
+            },
+        };
+        Some(diagnosis)
+    }
+}
+
+fn is_api_disabled(message: &str) -> bool {
+    message.contains("has not been used") || message.contains("is disabled")
+}
+
+/// the result of `FirestoreClient::probe_permissions`, to diagnose PERMISSION_DENIED.
+///
+/// ```ignore
+/// let report = client.probe_permissions().await;
+/// for denied in report.denied() {
+///     log::warn!("{:?} : {}", denied.operation, denied.diagnosis().unwrap());
+/// }
+/// ```
+#[derive(Debug)]
+pub struct PermissionReport {
+    pub results: Vec<ProbeResult>,
+}
+
+impl PermissionReport {
+    pub fn is_all_permitted(&self) -> bool {
+        self.results.iter().all(ProbeResult::is_permitted)
+    }
+
+    pub fn denied(&self) -> impl Iterator<Item = &ProbeResult> {
+        self.results.iter().filter(|result| result.is_denied())
+    }
+
+    /// the operations not tried. see `ProbeResult::not_checked`.
+    pub fn not_checked(&self) -> impl Iterator<Item = &ProbeResult> {
+        self.results
+            .iter()
+            .filter(|result| result.not_checked.is_some())
+    }
+
+    pub fn result_of(&self, operation: ProbeOperation) -> Option<&ProbeResult> {
+        self.results
+            .iter()
+            .find(|result| result.operation == operation)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use google_cloud_grpc_proto::tonic::Status;
+
+    #[test]
+    fn permission_report_test() {
+        let report = PermissionReport {
+            results: vec![
+                ProbeResult::new(ProbeOperation::Read, None),
+                ProbeResult::new(
+                    ProbeOperation::Write,
+                    Some(Status::permission_denied("Missing or insufficient permissions.").into()),
+                ),
+                ProbeResult::new(
+                    ProbeOperation::ListIndexes,
+                    Some(
+                        Status::permission_denied(
+                            "Cloud Firestore API has not been used in project p before or it is disabled.",
+                        )
+                        .into(),
+                    ),
+                ),
+            ],
+        };
+        assert!(!report.is_all_permitted());
+        assert_eq!(2, report.denied().count());
+
+        let read = report.result_of(ProbeOperation::Read).unwrap();
+        assert!(read.is_permitted());
+        assert_eq!(None, read.diagnosis());
+
+        let write = report.result_of(ProbeOperation::Write).unwrap();
+        let diagnosis = write.diagnosis().unwrap();
+        assert!(diagnosis.contains("datastore.entities.create"));
+        assert!(diagnosis.contains("roles/datastore.user"));
+
+        let list_indexes = report.result_of(ProbeOperation::ListIndexes).unwrap();
+        assert!(list_indexes.diagnosis().unwrap().contains("not enabled"));
+
+        let unauthenticated = ProbeResult::new(
+            ProbeOperation::Read,
+            Some(Status::unauthenticated("").into()),
+        );
+        assert!(unauthenticated.diagnosis().unwrap().contains("rejected"));
+
+        let report = PermissionReport {
+            results: vec![
+                ProbeResult::new(ProbeOperation::Read, None),
+                ProbeResult::not_checked(ProbeOperation::Write, "the client is read-only"),
+            ],
+        };
+        assert!(!report.is_all_permitted());
+        assert_eq!(0, report.denied().count());
+        let write = report.not_checked().next().unwrap();
+        assert_eq!(ProbeOperation::Write, write.operation);
+        assert!(write.diagnosis().unwrap().starts_with("not checked"));
+    }
+}
@@ -0,0 +1,79 @@
+/// the in-flight requests per connection of the batch channel by default.
+pub const DEFAULT_BATCH_CONCURRENCY_LIMIT: usize = 16;
+
+/// the class of the requests of a client. see `FirestoreClient::with_priority`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    /// latency-sensitive requests (e.g. of the user requests). the default.
+    #[default]
+    Interactive,
+    /// bulk jobs. sent on the batch channel if the client has one,
+    /// so that they don't block the interactive requests on the same connections.
+    Batch,
+}
+
+/// the connections dedicated to `Priority::Batch`.
+///
+/// ```ignore
+/// let client = FirestoreClientBuilder::new(project_id)
+///     .service_account_file(cred_path)
+///     .batch_channel(BatchChannelOptions::new().with_pool_size(2).with_concurrency_limit(8))
+///     .build()
+///     .await?;
+/// // runs on the batch channel
+/// client.bulk_writer().enqueue(ope)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchChannelOptions {
+    pub(crate) pool_size: usize,
+    pub(crate) concurrency_limit: usize,
+}
+
+impl Default for BatchChannelOptions {
+    fn default() -> Self {
+        Self {
+            pool_size: 1,
+            concurrency_limit: DEFAULT_BATCH_CONCURRENCY_LIMIT,
+        }
+    }
+}
+
+impl BatchChannelOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the number of the connections of the batch channel.
+    pub fn with_pool_size(self, pool_size: usize) -> Self {
+        Self {
+            pool_size: pool_size.max(1),
+            ..self
+        }
+    }
+
+    /// the in-flight requests per connection. the requests over the limit wait in the client.
+    pub fn with_concurrency_limit(self, concurrency_limit: usize) -> Self {
+        Self {
+            concurrency_limit: concurrency_limit.max(1),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn batch_channel_options_test() {
+        assert_eq!(Priority::Interactive, Priority::default());
+
+        let options = BatchChannelOptions::new();
+        assert_eq!(1, options.pool_size);
+        assert_eq!(DEFAULT_BATCH_CONCURRENCY_LIMIT, options.concurrency_limit);
+
+        let options = options.with_pool_size(0).with_concurrency_limit(0);
+        assert_eq!(1, options.pool_size);
+        assert_eq!(1, options.concurrency_limit);
+    }
+}
@@ -1,14 +1,18 @@
-use anyhow::{anyhow, Result};
+use super::error::{FirestoreError, Result};
+use std::collections::HashMap;
+use std::str::FromStr;
 
-use super::FValue;
+use super::value::field_path::validate_query_field_path;
+use super::{FMap, FValue, MAX_IN_CLAUS_NUM};
 use google_cloud_grpc_proto::firestore::v1::{
-    batch_get_documents_response, firestore_client,
+    structured_aggregation_query::{self, aggregation},
     structured_query::{
-        self, composite_filter, field_filter, filter, filter::FilterType, unary_filter,
-        CollectionSelector, CompositeFilter, Direction, FieldFilter, FieldReference, Filter, Order,
-        Projection, UnaryFilter,
+        self, composite_filter, field_filter, filter::FilterType, find_nearest, unary_filter,
+        CollectionSelector, CompositeFilter, Direction, FieldFilter, FieldReference, Filter,
+        FindNearest, Order, Projection, UnaryFilter,
     },
-    Cursor, Document, StructuredQuery, Value, WriteResult,
+    value::ValueType,
+    Cursor, Document, StructuredAggregationQuery, StructuredQuery, Value,
 };
 
 fn select_projection<F: Into<String>>(fields: Vec<F>) -> Projection {
@@ -77,47 +81,211 @@ fn order<F: Into<String>>(field: F, direction: Direction) -> Order {
     }
 }
 
-fn str_to_field_op<S: AsRef<str>>(s: S) -> Result<field_filter::Operator> {
-    match s.as_ref() {
-        "<" => Ok(field_filter::Operator::LessThan),
-        "<=" => Ok(field_filter::Operator::LessThanOrEqual),
-        "==" => Ok(field_filter::Operator::Equal),
-        ">" => Ok(field_filter::Operator::GreaterThan),
-        ">=" => Ok(field_filter::Operator::GreaterThanOrEqual),
-        "!=" => Ok(field_filter::Operator::NotEqual),
-        "array-contains" => Ok(field_filter::Operator::ArrayContains),
-        "array-contains-any" => Ok(field_filter::Operator::ArrayContainsAny),
-        "in" => Ok(field_filter::Operator::In),
-        "not-in" => Ok(field_filter::Operator::NotIn),
-        _ => Err(anyhow!("{}", s.as_ref())),
+/// operator of the field filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldOp {
+    Lt,
+    Le,
+    Eq,
+    Gt,
+    Ge,
+    Ne,
+    ArrayContains,
+    ArrayContainsAny,
+    In,
+    NotIn,
+}
+
+impl FieldOp {
+    fn to_grpc_op(self) -> field_filter::Operator {
+        match self {
+            FieldOp::Lt => field_filter::Operator::LessThan,
+            FieldOp::Le => field_filter::Operator::LessThanOrEqual,
+            FieldOp::Eq => field_filter::Operator::Equal,
+            FieldOp::Gt => field_filter::Operator::GreaterThan,
+            FieldOp::Ge => field_filter::Operator::GreaterThanOrEqual,
+            FieldOp::Ne => field_filter::Operator::NotEqual,
+            FieldOp::ArrayContains => field_filter::Operator::ArrayContains,
+            FieldOp::ArrayContainsAny => field_filter::Operator::ArrayContainsAny,
+            FieldOp::In => field_filter::Operator::In,
+            FieldOp::NotIn => field_filter::Operator::NotIn,
+        }
+    }
+}
+
+impl FromStr for FieldOp {
+    type Err = FirestoreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "<" => Ok(FieldOp::Lt),
+            "<=" => Ok(FieldOp::Le),
+            "==" => Ok(FieldOp::Eq),
+            ">" => Ok(FieldOp::Gt),
+            ">=" => Ok(FieldOp::Ge),
+            "!=" => Ok(FieldOp::Ne),
+            "array-contains" => Ok(FieldOp::ArrayContains),
+            "array-contains-any" => Ok(FieldOp::ArrayContainsAny),
+            "in" => Ok(FieldOp::In),
+            "not-in" => Ok(FieldOp::NotIn),
+            _ => Err(FirestoreError::invalid_argument(s)),
+        }
+    }
+}
+
+/// operator of the unary filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    IsNan,
+    IsNull,
+    IsNotNan,
+    IsNotNull,
+}
+
+impl UnaryOp {
+    fn to_grpc_op(self) -> unary_filter::Operator {
+        match self {
+            UnaryOp::IsNan => unary_filter::Operator::IsNan,
+            UnaryOp::IsNull => unary_filter::Operator::IsNull,
+            UnaryOp::IsNotNan => unary_filter::Operator::IsNotNan,
+            UnaryOp::IsNotNull => unary_filter::Operator::IsNotNull,
+        }
+    }
+}
+
+impl FromStr for UnaryOp {
+    type Err = FirestoreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "is-nan" => Ok(UnaryOp::IsNan),
+            "is-null" => Ok(UnaryOp::IsNull),
+            "is-not-nan" => Ok(UnaryOp::IsNotNan),
+            "is-not-null" => Ok(UnaryOp::IsNotNull),
+            _ => Err(FirestoreError::invalid_argument(s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderDirection {
+    Asc,
+    Desc,
+}
+
+impl OrderDirection {
+    fn to_grpc_direction(self) -> Direction {
+        match self {
+            OrderDirection::Asc => Direction::Ascending,
+            OrderDirection::Desc => Direction::Descending,
+        }
+    }
+}
+
+impl FromStr for OrderDirection {
+    type Err = FirestoreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "asc" => Ok(OrderDirection::Asc),
+            "desc" => Ok(OrderDirection::Desc),
+            _ => Err(FirestoreError::invalid_argument(format!(
+                "not a order :{}",
+                s
+            ))),
+        }
     }
 }
 
-fn str_to_unary_op<S: AsRef<str>>(s: S) -> Result<unary_filter::Operator> {
-    match s.as_ref() {
-        "is-nan" => Ok(unary_filter::Operator::IsNan),
-        "is-null" => Ok(unary_filter::Operator::IsNull),
-        "is-not-nan" => Ok(unary_filter::Operator::IsNotNan),
-        "is-not-null" => Ok(unary_filter::Operator::IsNotNull),
-        _ => Err(anyhow!("{}", s.as_ref())),
+/// the distance of the vectors in `QueryBuilder::find_nearest`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceMeasure {
+    Euclidean,
+    /// by the angle of the vectors.
+    Cosine,
+    /// as cosine, but affected by the magnitude of the vectors.
+    DotProduct,
+}
+
+impl DistanceMeasure {
+    fn to_grpc_measure(self) -> find_nearest::DistanceMeasure {
+        match self {
+            DistanceMeasure::Euclidean => find_nearest::DistanceMeasure::Euclidean,
+            DistanceMeasure::Cosine => find_nearest::DistanceMeasure::Cosine,
+            DistanceMeasure::DotProduct => find_nearest::DistanceMeasure::DotProduct,
+        }
     }
 }
 
-fn str_to_direction<S: AsRef<str>>(s: S) -> Result<Direction> {
-    match s.as_ref() {
-        "asc" => Ok(Direction::Ascending),
-        "desc" => Ok(Direction::Descending),
-        _ => Err(anyhow!("not a order :{}", s.as_ref())),
+impl FromStr for DistanceMeasure {
+    type Err = FirestoreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "euclidean" => Ok(DistanceMeasure::Euclidean),
+            "cosine" => Ok(DistanceMeasure::Cosine),
+            "dot-product" => Ok(DistanceMeasure::DotProduct),
+            _ => Err(FirestoreError::invalid_argument(format!(
+                "not a distance measure :{}",
+                s
+            ))),
+        }
     }
 }
 
+#[derive(Clone)]
 pub struct QueryBuilder {
     select: Option<structured_query::Projection>,
     from: Vec<CollectionSelector>,
     filters: Vec<Filter>,
     orders: Vec<Order>,
+    start_at: Option<(CursorValues, bool)>,
+    end_at: Option<(CursorValues, bool)>,
     offset: i32,
     limit: Option<i32>,
+    value_list_filters: Vec<ValueListFilter>,
+    find_nearest: Option<FindNearest>,
+}
+
+/// "in", "not-in" or "array-contains-any" filter kept unmerged to be split by `build_queries`.
+#[derive(Clone)]
+struct ValueListFilter {
+    field: String,
+    op: FieldOp,
+    values: Vec<FValue>,
+}
+
+impl ValueListFilter {
+    fn into_filter(self) -> Filter {
+        field_filter(self.field, self.op.to_grpc_op(), FValue::Array(self.values))
+    }
+
+    /// the filters of the values at most `MAX_IN_CLAUS_NUM` each. the results are the union of them.
+    fn split(&self) -> Result<Vec<ValueListFilter>> {
+        if self.values.is_empty() {
+            return Err(FirestoreError::invalid_argument(format!(
+                "no values to filter {} by {:?}",
+                self.field, self.op
+            )));
+        }
+        if self.op == FieldOp::NotIn && self.values.len() > MAX_IN_CLAUS_NUM {
+            return Err(FirestoreError::invalid_argument(format!(
+                "not-in accepts at most {} values but {} values to filter {}",
+                MAX_IN_CLAUS_NUM,
+                self.values.len(),
+                self.field
+            )));
+        }
+        Ok(self
+            .values
+            .chunks(MAX_IN_CLAUS_NUM)
+            .map(|values| ValueListFilter {
+                field: self.field.clone(),
+                op: self.op,
+                values: values.to_vec(),
+            })
+            .collect())
+    }
 }
 
 impl QueryBuilder {
@@ -129,11 +297,26 @@ impl QueryBuilder {
             from: colls,
             filters: Vec::new(),
             orders: Vec::new(),
+            start_at: None,
+            end_at: None,
             offset: 0,
             limit: None,
+            value_list_filters: Vec::new(),
+            find_nearest: None,
         }
     }
 
+    /// query the collections of the id at any depth. (e.g. "comments" in "/posts/p1/comments")
+    pub fn collection_group(collection_id: String) -> Self {
+        Self::collection(collection_id, true)
+    }
+
+    /// replace the collections to query with the collection group.
+    pub(crate) fn with_collection_group(mut self, collection_id: String) -> Self {
+        self.from = vec![from(collection_id, true)];
+        self
+    }
+
     pub fn select<F: Into<String>>(mut self, fields: Vec<F>) -> Self {
         self.select = Some(select_projection(fields));
         self
@@ -144,6 +327,14 @@ impl QueryBuilder {
         self
     }
 
+    pub fn filter_field<F, V>(self, field: F, op: FieldOp, value: V) -> Self
+    where
+        F: Into<String>,
+        V: Into<FValue>,
+    {
+        self.filter(field_filter(field, op.to_grpc_op(), value))
+    }
+
     /// ### operations
     /// * "<"
     /// * "<="
@@ -155,15 +346,64 @@ impl QueryBuilder {
     /// * "array-contains-any"
     /// * "in"
     /// * "not-in"
+    ///
+    /// panics if the operation is invalid. `filter_field` is checked at compile time.
+    ///
+    /// the field of a map is filtered by the dotted path, or by `FieldPath` to escape the names.
+    /// the elements of the arrays can't be filtered by the index, the queries with such paths
+    /// (e.g. "tags.0") fail with `FirestoreError::InvalidArgument` before the request.
+    ///
+    /// ```ignore
+    /// QueryBuilder::collection("users".to_owned(), false)
+    ///     .filter_bin(FieldPath::new(["profile", "age"]), ">=", 21)
+    /// ```
     pub fn filter_bin<F, OP, V>(self, field: F, op: OP, value: V) -> Self
     where
         F: Into<String>,
         OP: AsRef<str>,
         V: Into<FValue>,
     {
-        //TODO(tacogips) tobe type safe
-        let op = str_to_field_op(op).unwrap_or_else(|e| panic!("invalid field op [{}]", e));
-        self.filter(field_filter(field, op, value))
+        let op =
+            FieldOp::from_str(op.as_ref()).unwrap_or_else(|e| panic!("invalid field op [{}]", e));
+        self.filter_field(field, op, value)
+    }
+
+    /// the field equals to one of the values.
+    /// more than `MAX_IN_CLAUS_NUM` values are split into the queries by `build_queries`.
+    pub fn filter_in<F: Into<String>>(self, field: F, values: Vec<FValue>) -> Self {
+        self.filter_values(field, FieldOp::In, values)
+    }
+
+    /// the field equals to none of the values. at most `MAX_IN_CLAUS_NUM` values.
+    pub fn filter_not_in<F: Into<String>>(self, field: F, values: Vec<FValue>) -> Self {
+        self.filter_values(field, FieldOp::NotIn, values)
+    }
+
+    /// the array field contains any of the values.
+    /// more than `MAX_IN_CLAUS_NUM` values are split into the queries by `build_queries`.
+    pub fn filter_array_contains_any<F: Into<String>>(self, field: F, values: Vec<FValue>) -> Self {
+        self.filter_values(field, FieldOp::ArrayContainsAny, values)
+    }
+
+    fn filter_values<F: Into<String>>(
+        mut self,
+        field: F,
+        op: FieldOp,
+        values: Vec<FValue>,
+    ) -> Self {
+        self.value_list_filters.push(ValueListFilter {
+            field: field.into(),
+            op,
+            values,
+        });
+        self
+    }
+
+    pub fn filter_unary<F>(self, field: F, op: UnaryOp) -> Self
+    where
+        F: Into<String>,
+    {
+        self.filter(unary_filter(field, op.to_grpc_op()))
     }
 
     /// ### operations
@@ -171,29 +411,46 @@ impl QueryBuilder {
     /// * "is-null"
     /// * "is-not-nan"
     /// * "is-not-null"
+    ///
+    /// panics if the operation is invalid. `filter_unary` is checked at compile time.
     pub fn filter_una<F, OP, V>(self, field: F, op: OP) -> Self
     where
         F: Into<String>,
         OP: AsRef<str>,
     {
-        //TODO(tacogips) tobe type safe
-        let op = str_to_unary_op(op).unwrap_or_else(|e| panic!("invalid unary op [{}]", e));
-        self.filter(unary_filter(field, op))
+        let op =
+            UnaryOp::from_str(op.as_ref()).unwrap_or_else(|e| panic!("invalid unary op [{}]", e));
+        self.filter_unary(field, op)
+    }
+
+    pub fn order_by<F>(mut self, field: F, direction: OrderDirection) -> Self
+    where
+        F: Into<String>,
+    {
+        self.orders
+            .push(order(field, direction.to_grpc_direction()));
+        self
     }
 
     ///
     /// directions
     /// * "asc"
     /// * "desc"
-    pub fn order<F, D>(mut self, field: F, direction: D) -> Self
+    ///
+    /// panics if the direction is invalid. `order_by` is checked at compile time.
+    pub fn order<F, D>(self, field: F, direction: D) -> Self
     where
         F: Into<String>,
         D: AsRef<str>,
     {
-        //TODO(tacogips) tobe type safe
-        let op = str_to_direction(direction).unwrap_or_else(|e| panic!("{:?}", e));
+        let direction =
+            OrderDirection::from_str(direction.as_ref()).unwrap_or_else(|e| panic!("{:?}", e));
+        self.order_by(field, direction)
+    }
 
-        self.orders.push(order(field, op));
+    /// drop the order clauses, the results are ordered by `__name__`.
+    pub(crate) fn without_orders(mut self) -> Self {
+        self.orders.clear();
         self
     }
 
@@ -207,25 +464,1073 @@ impl QueryBuilder {
         self
     }
 
+    /// the `limit` documents nearest to `query_vector` by the vector field, nearest first.
+    /// the field needs the vector index of the dimension of `query_vector` (at most 2048).
+    /// `limit` is at most 1000. the orders are superseded by the distance.
+    ///
+    /// ```ignore
+    /// let query = QueryBuilder::collection("articles".to_owned(), false)
+    ///     .filter_field("lang", FieldOp::Eq, "en")
+    ///     .find_nearest("embedding", embedding, 10, DistanceMeasure::Cosine)
+    ///     .build();
+    /// ```
+    pub fn find_nearest<F: Into<String>>(
+        mut self,
+        field: F,
+        query_vector: Vec<f64>,
+        limit: i32,
+        distance_measure: DistanceMeasure,
+    ) -> Self {
+        self.find_nearest = Some(FindNearest {
+            vector_field: Some(field_reference(field)),
+            query_vector: Some(FValue::Vector(query_vector).to_grpc_value()),
+            distance_measure: distance_measure.to_grpc_measure() as i32,
+            limit: Some(limit),
+        });
+        self
+    }
+
+    /// start the results at the position. (inclusive)
+    ///
+    /// the values are of the fields in the order clauses. with a document, the values are
+    /// taken from the document and the order by `__name__` is appended if not specified.
+    pub fn start_at<C: Into<CursorValues>>(mut self, values: C) -> Self {
+        self.start_at = Some((values.into(), true));
+        self
+    }
+
+    /// start the results after the position. (exclusive)
+    pub fn start_after<C: Into<CursorValues>>(mut self, values: C) -> Self {
+        self.start_at = Some((values.into(), false));
+        self
+    }
+
+    /// end the results at the position. (inclusive)
+    pub fn end_at<C: Into<CursorValues>>(mut self, values: C) -> Self {
+        self.end_at = Some((values.into(), false));
+        self
+    }
+
+    /// end the results before the position. (exclusive)
+    pub fn end_before<C: Into<CursorValues>>(mut self, values: C) -> Self {
+        self.end_at = Some((values.into(), true));
+        self
+    }
+
+    /// the cursors passed override the ones set by `start_at`, `end_at` etc.
+    ///
+    /// panics if a document cursor doesn't have the fields of the order clauses.
+    /// see `try_build_with_cursor`.
     pub fn build_with_cursor(
         self,
         start_at: Option<Cursor>,
         end_at: Option<Cursor>,
     ) -> StructuredQuery {
-        let merged_filter = merge_filters(self.filters);
-        StructuredQuery {
+        self.try_build_with_cursor(start_at, end_at)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// `build_with_cursor` returning Err if a document cursor doesn't have the fields of the
+    /// order clauses (e.g. a sparse field).
+    pub fn try_build_with_cursor(
+        mut self,
+        start_at: Option<Cursor>,
+        end_at: Option<Cursor>,
+    ) -> Result<StructuredQuery> {
+        let has_document_cursor = [&self.start_at, &self.end_at]
+            .iter()
+            .any(|cursor| matches!(cursor, Some((CursorValues::Document(_), _))));
+        let ordered_by_name = self.orders.iter().any(|order| {
+            order
+                .field
+                .as_ref()
+                .map(|field| field.field_path == NAME_FIELD)
+                .unwrap_or(false)
+        });
+        if has_document_cursor && !ordered_by_name {
+            let direction = self
+                .orders
+                .last()
+                .map(|order| order.direction)
+                .unwrap_or(Direction::Ascending as i32);
+            self.orders.push(Order {
+                field: Some(field_reference(NAME_FIELD)),
+                direction,
+            });
+        }
+
+        let orders = self.orders;
+        let (pending_start_at, pending_end_at) = (self.start_at, self.end_at);
+        let start_at = match (start_at, pending_start_at) {
+            (None, Some((values, before))) => Some(values.into_cursor(&orders, before)?),
+            (start_at, _) => start_at,
+        };
+        let end_at = match (end_at, pending_end_at) {
+            (None, Some((values, before))) => Some(values.into_cursor(&orders, before)?),
+            (end_at, _) => end_at,
+        };
+
+        let mut filters = self.filters;
+        filters.extend(
+            self.value_list_filters
+                .into_iter()
+                .map(ValueListFilter::into_filter),
+        );
+        let merged_filter = merge_filters(filters);
+        Ok(StructuredQuery {
             select: self.select,
             from: self.from,
             r#where: merged_filter,
-            order_by: self.orders,
+            order_by: orders,
             start_at,
             end_at,
             offset: self.offset,
             limit: self.limit,
-        }
+            find_nearest: self.find_nearest,
+        })
     }
 
+    /// the filters of `filter_in` etc are not validated. see `build_queries`.
+    ///
+    /// panics if a document cursor doesn't have the fields of the order clauses. see `try_build`.
     pub fn build(self) -> StructuredQuery {
         self.build_with_cursor(None, None)
     }
+
+    /// `build` returning Err if a document cursor doesn't have the fields of the order clauses.
+    pub fn try_build(self) -> Result<StructuredQuery> {
+        self.try_build_with_cursor(None, None)
+    }
+
+    /// build the queries whose results are unioned, splitting the values of `filter_in` and
+    /// `filter_array_contains_any` into `MAX_IN_CLAUS_NUM` each.
+    /// a single query if no split is needed.
+    ///
+    /// each query has the limit of the builder. the offset can't be applied to split queries.
+    pub fn build_queries(self) -> Result<Vec<StructuredQuery>> {
+        let mut combinations: Vec<Vec<ValueListFilter>> = vec![vec![]];
+        for each in self.value_list_filters.iter() {
+            let split = each.split()?;
+            combinations = combinations
+                .into_iter()
+                .flat_map(|combination| {
+                    split.iter().map(move |filter| {
+                        let mut combination = combination.clone();
+                        combination.push(filter.clone());
+                        combination
+                    })
+                })
+                .collect();
+        }
+        validate_field_paths(&self.clone().try_build()?)?;
+        if combinations.len() > 1 && self.offset != 0 {
+            return Err(FirestoreError::invalid_argument(
+                "offset can't be applied to the queries split by the number of the values",
+            ));
+        }
+
+        combinations
+            .into_iter()
+            .map(|value_list_filters| {
+                let mut query = self.clone();
+                query.value_list_filters = value_list_filters;
+                query.try_build()
+            })
+            .collect()
+    }
+
+    pub(crate) fn limit_num(&self) -> Option<i32> {
+        self.limit
+    }
+
+    /// the query of the page after `last_document`. the offset only applies to the first page,
+    /// and the limit of the builder caps the total. None if the limit is reached.
+    pub(crate) fn build_page(
+        &self,
+        last_document: Option<&Document>,
+        fetched: i32,
+        page_size: i32,
+    ) -> Result<Option<StructuredQuery>> {
+        let page_size = match self.limit {
+            Some(limit) if limit - fetched <= 0 => return Ok(None),
+            Some(limit) => page_size.min(limit - fetched),
+            None => page_size,
+        };
+        let page = self.clone().limit(page_size);
+        match last_document {
+            Some(document) => page.offset(0).start_after(document).try_build(),
+            None => page.try_build(),
+        }
+        .map(Some)
+    }
+
+    /// build the query which has `param(..)` placeholders as filter values.
+    pub fn build_template(self) -> QueryTemplate {
+        QueryTemplate::new(self.build())
+    }
+
+    /// build the query which aggregates the documents on the server.
+    pub fn build_aggregation(self, aggregations: Vec<Aggregation>) -> StructuredAggregationQuery {
+        StructuredAggregationQuery {
+            query_type: Some(structured_aggregation_query::QueryType::StructuredQuery(
+                self.build(),
+            )),
+            aggregations: aggregations
+                .into_iter()
+                .map(|each| each.into_grpc_aggregation())
+                .collect(),
+        }
+    }
+}
+
+const NAME_FIELD: &str = "__name__";
+
+/// reject the field paths firestore doesn't support (e.g. the array indexes) before the request.
+pub(crate) fn validate_field_paths(query: &StructuredQuery) -> Result<()> {
+    fn validate_filter(filter: &Filter) -> Result<()> {
+        match &filter.filter_type {
+            Some(FilterType::CompositeFilter(composite)) => {
+                composite.filters.iter().try_for_each(validate_filter)
+            }
+            Some(FilterType::FieldFilter(FieldFilter {
+                field: Some(field), ..
+            })) => validate_query_field_path(&field.field_path),
+            Some(FilterType::UnaryFilter(UnaryFilter {
+                operand_type: Some(unary_filter::OperandType::Field(field)),
+                ..
+            })) => validate_query_field_path(&field.field_path),
+            _ => Ok(()),
+        }
+    }
+
+    if let Some(select) = &query.select {
+        for field in select.fields.iter() {
+            validate_query_field_path(&field.field_path)?;
+        }
+    }
+    if let Some(filter) = &query.r#where {
+        validate_filter(filter)?;
+    }
+    for order in query.order_by.iter() {
+        if let Some(field) = &order.field {
+            validate_query_field_path(&field.field_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// the queries of the partitions split by the cursors of `partition_query_all`.
+/// the query is ordered by `__name__` as the cursors are, and the partitions don't overlap.
+/// n cursors make n + 1 queries.
+pub fn partition_queries(query: StructuredQuery, partitions: &[Cursor]) -> Vec<StructuredQuery> {
+    let mut query = query;
+    if query.order_by.is_empty() {
+        query.order_by.push(order(NAME_FIELD, Direction::Ascending));
+    }
+    let bound = |cursor: &Cursor| Cursor {
+        values: cursor.values.clone(),
+        before: true,
+    };
+    (0..=partitions.len())
+        .map(|index| StructuredQuery {
+            start_at: index.checked_sub(1).map(|prev| bound(&partitions[prev])),
+            end_at: partitions.get(index).map(bound),
+            ..query.clone()
+        })
+        .collect()
+}
+
+/// fails if the stream of the query can't be resumed after a document by `resume_query`.
+/// the query needs the order clauses, the order fields in the projection and no `find_nearest`.
+pub(crate) fn validate_resumable_query(query: &StructuredQuery) -> Result<()> {
+    if query.order_by.is_empty() {
+        return Err(FirestoreError::invalid_argument(
+            "the query needs order_by to be resumed",
+        ));
+    }
+    if query.find_nearest.is_some() {
+        return Err(FirestoreError::invalid_argument(
+            "the query of find_nearest can't be resumed",
+        ));
+    }
+    if let Some(select) = query.select.as_ref() {
+        let selected = |field_path: &str| {
+            field_path == NAME_FIELD
+                || select
+                    .fields
+                    .iter()
+                    .any(|field| field.field_path == field_path)
+        };
+        for field in query
+            .order_by
+            .iter()
+            .filter_map(|order| order.field.as_ref())
+        {
+            if !selected(&field.field_path) {
+                return Err(FirestoreError::invalid_argument(format!(
+                    "the order field {} is not selected to resume the query",
+                    field.field_path
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// the rest of the query after `last_document`, the last one of the `delivered` documents.
+/// `__name__` is ordered after the order clauses to start right after the document, as the
+/// server does implicitly. the offset is consumed, and the limit is reduced by the documents
+/// delivered. None if the limit is reached.
+pub(crate) fn resume_query(
+    query: &StructuredQuery,
+    last_document: &Document,
+    delivered: i32,
+) -> Result<Option<StructuredQuery>> {
+    let limit = match query.limit {
+        Some(limit) if limit - delivered <= 0 => return Ok(None),
+        Some(limit) => Some(limit - delivered),
+        None => None,
+    };
+    let mut order_by = query.order_by.clone();
+    let ordered_by_name = order_by
+        .iter()
+        .any(|order| matches!(order.field.as_ref(), Some(field) if field.field_path == NAME_FIELD));
+    if !ordered_by_name {
+        let direction = order_by
+            .last()
+            .map(|order| order.direction)
+            .unwrap_or(Direction::Ascending as i32);
+        order_by.push(Order {
+            field: Some(field_reference(NAME_FIELD)),
+            direction,
+        });
+    }
+    let values = order_by
+        .iter()
+        .filter_map(|order| order.field.as_ref())
+        .map(|field| {
+            if field.field_path == NAME_FIELD {
+                Ok(Value {
+                    value_type: Some(ValueType::ReferenceValue(last_document.name.clone())),
+                })
+            } else {
+                document_field_value(last_document, &field.field_path).ok_or_else(|| {
+                    FirestoreError::invalid_argument(format!(
+                        "document {} doesn't have the order field {} to resume the query",
+                        last_document.name, field.field_path
+                    ))
+                })
+            }
+        })
+        .collect::<Result<Vec<Value>>>()?;
+    Ok(Some(StructuredQuery {
+        order_by,
+        start_at: Some(Cursor {
+            values,
+            before: false,
+        }),
+        offset: 0,
+        limit,
+        ..query.clone()
+    }))
+}
+
+/// position of a query cursor. the values of the order fields, or a document.
+#[derive(Debug, Clone)]
+pub enum CursorValues {
+    Values(Vec<Value>),
+    Document(Document),
+}
+
+impl CursorValues {
+    fn into_cursor(self, orders: &[Order], before: bool) -> Result<Cursor> {
+        let values = match self {
+            CursorValues::Values(values) => values,
+            CursorValues::Document(document) => orders
+                .iter()
+                .filter_map(|order| order.field.as_ref())
+                .map(|field| {
+                    if field.field_path == NAME_FIELD {
+                        Ok(Value {
+                            value_type: Some(ValueType::ReferenceValue(document.name.clone())),
+                        })
+                    } else {
+                        document_field_value(&document, &field.field_path).ok_or_else(|| {
+                            FirestoreError::invalid_argument(format!(
+                                "cursor document {} doesn't have the order field {}",
+                                document.name, field.field_path
+                            ))
+                        })
+                    }
+                })
+                .collect::<Result<Vec<Value>>>()?,
+        };
+        Ok(Cursor { values, before })
+    }
+}
+
+/// "a.b" looks up the field b in the map a.
+fn document_field_value(document: &Document, field_path: &str) -> Option<Value> {
+    let mut keys = field_path.split('.');
+    let mut value = document.fields.get(keys.next()?)?;
+    for key in keys {
+        value = match value.value_type.as_ref()? {
+            ValueType::MapValue(map) => map.fields.get(key)?,
+            _ => return None,
+        };
+    }
+    Some(value.clone())
+}
+
+impl From<Vec<FValue>> for CursorValues {
+    fn from(values: Vec<FValue>) -> Self {
+        CursorValues::Values(values.into_iter().map(|v| v.to_grpc_value()).collect())
+    }
+}
+
+impl From<Document> for CursorValues {
+    fn from(document: Document) -> Self {
+        CursorValues::Document(document)
+    }
+}
+
+impl From<&Document> for CursorValues {
+    fn from(document: &Document) -> Self {
+        CursorValues::Document(document.clone())
+    }
+}
+
+/// an aggregation run by `FirestoreClient::run_aggregation_query`.
+/// the result is returned under the `alias`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Aggregation {
+    /// count the documents. stops counting at `up_to` if specified.
+    Count { alias: String, up_to: Option<i64> },
+    /// sum of the numeric values of the field. non numeric values are skipped.
+    Sum { alias: String, field: String },
+    /// average of the numeric values of the field. null if no value is aggregated.
+    Avg { alias: String, field: String },
+}
+
+impl Aggregation {
+    pub fn count<A: Into<String>>(alias: A) -> Self {
+        Aggregation::Count {
+            alias: alias.into(),
+            up_to: None,
+        }
+    }
+
+    pub fn sum<A: Into<String>, F: Into<String>>(alias: A, field: F) -> Self {
+        Aggregation::Sum {
+            alias: alias.into(),
+            field: field.into(),
+        }
+    }
+
+    pub fn avg<A: Into<String>, F: Into<String>>(alias: A, field: F) -> Self {
+        Aggregation::Avg {
+            alias: alias.into(),
+            field: field.into(),
+        }
+    }
+
+    pub fn alias(&self) -> &str {
+        match self {
+            Aggregation::Count { alias, .. }
+            | Aggregation::Sum { alias, .. }
+            | Aggregation::Avg { alias, .. } => alias,
+        }
+    }
+
+    fn into_grpc_aggregation(self) -> structured_aggregation_query::Aggregation {
+        use aggregation::{Avg, Count, Operator, Sum};
+        let (alias, operator) = match self {
+            Aggregation::Count { alias, up_to } => (alias, Operator::Count(Count { up_to })),
+            Aggregation::Sum { alias, field } => (
+                alias,
+                Operator::Sum(Sum {
+                    field: Some(field_reference(field)),
+                }),
+            ),
+            Aggregation::Avg { alias, field } => (
+                alias,
+                Operator::Avg(Avg {
+                    field: Some(field_reference(field)),
+                }),
+            ),
+        };
+        structured_aggregation_query::Aggregation {
+            operator: Some(operator),
+            alias,
+        }
+    }
+}
+
+const QUERY_PARAM_KEY: &str = "__firestore_query_param__";
+
+/// placeholder of a filter value which is bound at execution by `QueryTemplate::bind`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryParam(String);
+
+/// placeholder of a filter value.
+///
+/// ```ignore
+/// let template = QueryBuilder::collection("orders".to_owned(), false)
+///     .filter_bin("status", "==", param("status"))
+///     .build_template();
+/// let query = template.bind(vec![("status", FValue::from("shipped"))])?;
+/// ```
+pub fn param<S: Into<String>>(name: S) -> QueryParam {
+    QueryParam(name.into())
+}
+
+impl From<QueryParam> for FValue {
+    fn from(p: QueryParam) -> Self {
+        let mut m = FMap::new();
+        m.insert(QUERY_PARAM_KEY.to_owned(), FValue::Str(p.0));
+        FValue::Map(m)
+    }
+}
+
+fn as_param_name(value: &Value) -> Option<&str> {
+    match &value.value_type {
+        Some(ValueType::MapValue(map)) if map.fields.len() == 1 => {
+            match map.fields.get(QUERY_PARAM_KEY)?.value_type.as_ref()? {
+                ValueType::StringValue(name) => Some(name.as_str()),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn visit_filter_values<F>(filter: &mut Filter, f: &mut F) -> Result<()>
+where
+    F: FnMut(&mut Value) -> Result<()>,
+{
+    match filter.filter_type.as_mut() {
+        Some(FilterType::CompositeFilter(composite)) => {
+            for each in composite.filters.iter_mut() {
+                visit_filter_values(each, f)?;
+            }
+            Ok(())
+        }
+        Some(FilterType::FieldFilter(field_filter)) => match field_filter.value.as_mut() {
+            Some(value) => visit_value(value, f),
+            None => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// params can be placed in an array value too. (e.g. for "in" filter)
+fn visit_value<F>(value: &mut Value, f: &mut F) -> Result<()>
+where
+    F: FnMut(&mut Value) -> Result<()>,
+{
+    if as_param_name(value).is_some() {
+        return f(value);
+    }
+    if let Some(ValueType::ArrayValue(array)) = value.value_type.as_mut() {
+        for each in array.values.iter_mut() {
+            visit_value(each, f)?;
+        }
+    }
+    Ok(())
+}
+
+/// a query with named placeholders. the shape of the query is built once and
+/// the values are bound on each execution.
+#[derive(Debug, Clone)]
+pub struct QueryTemplate {
+    query: StructuredQuery,
+    param_names: Vec<String>,
+}
+
+impl QueryTemplate {
+    fn new(mut query: StructuredQuery) -> Self {
+        let mut param_names = Vec::<String>::new();
+        if let Some(filter) = query.r#where.as_mut() {
+            visit_filter_values(filter, &mut |value| {
+                if let Some(name) = as_param_name(value) {
+                    if !param_names.iter().any(|each| each == name) {
+                        param_names.push(name.to_owned());
+                    }
+                }
+                Ok(())
+            })
+            .unwrap();
+        }
+        Self { query, param_names }
+    }
+
+    pub fn param_names(&self) -> &[String] {
+        &self.param_names
+    }
+
+    /// returns the query whose placeholders are replaced with the values.
+    /// fails if a param is not bound or an unknown param is passed.
+    pub fn bind<K, V>(&self, params: Vec<(K, V)>) -> Result<StructuredQuery>
+    where
+        K: Into<String>,
+        V: Into<FValue>,
+    {
+        let params: HashMap<String, Value> = params
+            .into_iter()
+            .map(|(k, v)| (k.into(), v.into().to_grpc_value()))
+            .collect();
+
+        if let Some(unknown) = params
+            .keys()
+            .find(|k| !self.param_names.iter().any(|each| each == *k))
+        {
+            return Err(FirestoreError::invalid_argument(format!(
+                "unknown query param: {}",
+                unknown
+            )));
+        }
+
+        let mut query = self.query.clone();
+        if let Some(filter) = query.r#where.as_mut() {
+            visit_filter_values(filter, &mut |value| {
+                let name = as_param_name(value).unwrap_or_default().to_owned();
+                match params.get(&name) {
+                    Some(bound) => {
+                        *value = bound.clone();
+                        Ok(())
+                    }
+                    None => Err(FirestoreError::invalid_argument(format!(
+                        "query param is not bound: {}",
+                        name
+                    ))),
+                }
+            })?;
+        }
+        Ok(query)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        find_nearest, param, partition_queries, resume_query, validate_field_paths,
+        validate_resumable_query, Aggregation, DistanceMeasure, FValue, FieldOp, OrderDirection,
+        QueryBuilder, UnaryOp, MAX_IN_CLAUS_NUM, NAME_FIELD,
+    };
+    use crate::firestore::error::FirestoreError;
+    use crate::firestore::value::field_path::FieldPath;
+    use google_cloud_grpc_proto::firestore::v1::structured_aggregation_query::{
+        aggregation::Operator, QueryType,
+    };
+    use google_cloud_grpc_proto::firestore::v1::{value::ValueType, Cursor, Document, Value};
+
+    #[test]
+    fn query_template_test() {
+        let template = QueryBuilder::collection("orders".to_owned(), false)
+            .filter_bin("status", "==", param("status"))
+            .filter_bin("amount", ">", param("min_amount"))
+            .filter_bin(
+                "region",
+                "in",
+                FValue::Array(vec![FValue::from(param("region")), FValue::from("jp")]),
+            )
+            .build_template();
+
+        assert_eq!(
+            vec!["status", "min_amount", "region"],
+            template.param_names().to_vec()
+        );
+
+        let expected = QueryBuilder::collection("orders".to_owned(), false)
+            .filter_bin("status", "==", "shipped")
+            .filter_bin("amount", ">", 100i64)
+            .filter_bin(
+                "region",
+                "in",
+                FValue::Array(vec![FValue::from("us"), FValue::from("jp")]),
+            )
+            .build();
+
+        let actual = template
+            .bind(vec![
+                ("status", FValue::from("shipped")),
+                ("min_amount", FValue::from(100i64)),
+                ("region", FValue::from("us")),
+            ])
+            .unwrap();
+        assert_eq!(expected, actual);
+
+        assert!(template
+            .bind(vec![("status", FValue::from("shipped"))])
+            .is_err());
+        assert!(template
+            .bind(vec![
+                ("status", FValue::from("shipped")),
+                ("min_amount", FValue::from(100i64)),
+                ("region", FValue::from("us")),
+                ("unknown", FValue::from(1i64)),
+            ])
+            .is_err());
+    }
+
+    #[test]
+    fn build_aggregation_test() {
+        let aggregation_query = QueryBuilder::collection("orders".to_owned(), false)
+            .filter_bin("status", "==", "shipped")
+            .build_aggregation(vec![
+                Aggregation::count("total"),
+                Aggregation::sum("amount_sum", "amount"),
+                Aggregation::avg("amount_avg", "amount"),
+            ]);
+
+        assert_eq!(
+            Some(QueryType::StructuredQuery(
+                QueryBuilder::collection("orders".to_owned(), false)
+                    .filter_bin("status", "==", "shipped")
+                    .build()
+            )),
+            aggregation_query.query_type
+        );
+
+        let aliases: Vec<&str> = aggregation_query
+            .aggregations
+            .iter()
+            .map(|each| each.alias.as_str())
+            .collect();
+        assert_eq!(vec!["total", "amount_sum", "amount_avg"], aliases);
+
+        match aggregation_query.aggregations[1].operator.as_ref() {
+            Some(Operator::Sum(sum)) => {
+                assert_eq!("amount", sum.field.as_ref().unwrap().field_path)
+            }
+            other => panic!("unexpected operator {:?}", other),
+        }
+    }
+
+    #[test]
+    fn find_nearest_test() {
+        let query = QueryBuilder::collection("articles".to_owned(), false)
+            .filter_field("lang", FieldOp::Eq, "en")
+            .find_nearest("embedding", vec![0.1, 0.2], 5, DistanceMeasure::Cosine)
+            .build();
+        let find_nearest = query.find_nearest.unwrap();
+        assert_eq!("embedding", find_nearest.vector_field.unwrap().field_path);
+        assert_eq!(
+            FValue::Vector(vec![0.1, 0.2]),
+            FValue::from(find_nearest.query_vector.unwrap())
+        );
+        assert_eq!(Some(5), find_nearest.limit);
+        assert_eq!(
+            find_nearest::DistanceMeasure::Cosine as i32,
+            find_nearest.distance_measure
+        );
+        assert_eq!(
+            DistanceMeasure::DotProduct,
+            "dot-product".parse::<DistanceMeasure>().unwrap()
+        );
+    }
+
+    #[test]
+    fn typed_operators_test() {
+        let typed = QueryBuilder::collection("orders".to_owned(), false)
+            .filter_field("status", FieldOp::Eq, "shipped")
+            .filter_field("tags", FieldOp::ArrayContainsAny, vec!["a", "b"])
+            .filter_unary("deleted_at", UnaryOp::IsNull)
+            .order_by("amount", OrderDirection::Desc)
+            .build();
+        let stringly = QueryBuilder::collection("orders".to_owned(), false)
+            .filter_bin("status", "==", "shipped")
+            .filter_bin("tags", "array-contains-any", vec!["a", "b"])
+            .filter_una::<_, _, FValue>("deleted_at", "is-null")
+            .order("amount", "desc")
+            .build();
+        assert_eq!(typed, stringly);
+
+        assert_eq!(FieldOp::NotIn, "not-in".parse().unwrap());
+        assert!("=".parse::<FieldOp>().is_err());
+        assert!("ascending".parse::<OrderDirection>().is_err());
+    }
+
+    #[test]
+    fn cursor_test() {
+        let query = QueryBuilder::collection("orders".to_owned(), false)
+            .order_by("amount", OrderDirection::Desc)
+            .start_after(vec![FValue::from(100i64)])
+            .end_at(vec![FValue::from(10i64)])
+            .build();
+        assert_eq!(
+            Some(Cursor {
+                values: vec![FValue::from(100i64).to_grpc_value()],
+                before: false,
+            }),
+            query.start_at
+        );
+        assert_eq!(
+            Some(Cursor {
+                values: vec![FValue::from(10i64).to_grpc_value()],
+                before: false,
+            }),
+            query.end_at
+        );
+
+        let query = QueryBuilder::collection("orders".to_owned(), false)
+            .start_at(vec![FValue::from(1i64)])
+            .end_before(vec![FValue::from(2i64)])
+            .build();
+        assert!(query.start_at.unwrap().before);
+        assert!(query.end_at.unwrap().before);
+    }
+
+    #[test]
+    fn partition_queries_test() {
+        let query = QueryBuilder::collection("orders".to_owned(), true).build();
+        let cursor = |id: &str| Cursor {
+            values: vec![FValue::Str(id.to_owned()).to_grpc_value()],
+            before: false,
+        };
+        let queries = partition_queries(query.clone(), &[cursor("a"), cursor("b")]);
+        assert_eq!(3, queries.len());
+        assert_eq!(
+            NAME_FIELD,
+            queries[0].order_by[0].field.as_ref().unwrap().field_path
+        );
+
+        assert_eq!(None, queries[0].start_at);
+        assert_eq!(
+            Some(Cursor {
+                before: true,
+                ..cursor("a")
+            }),
+            queries[0].end_at
+        );
+        assert_eq!(queries[0].end_at, queries[1].start_at);
+        assert_eq!(queries[1].end_at, queries[2].start_at);
+        assert_eq!(None, queries[2].end_at);
+
+        assert_eq!(1, partition_queries(query, &[]).len());
+    }
+
+    #[test]
+    fn build_page_test() {
+        let builder = QueryBuilder::collection("orders".to_owned(), false)
+            .order_by("amount", OrderDirection::Asc)
+            .offset(5)
+            .limit(25);
+
+        let first = builder.build_page(None, 0, 10).unwrap().unwrap();
+        assert_eq!(Some(10), first.limit);
+        assert_eq!(5, first.offset);
+        assert_eq!(None, first.start_at);
+
+        let mut last = Document {
+            name: "projects/p/databases/(default)/documents/orders/o10".to_owned(),
+            ..Default::default()
+        };
+        last.fields
+            .insert("amount".to_owned(), FValue::from(10i64).to_grpc_value());
+        let third = builder.build_page(Some(&last), 20, 10).unwrap().unwrap();
+        assert_eq!(Some(5), third.limit);
+        assert_eq!(0, third.offset);
+        assert!(!third.start_at.unwrap().before);
+        assert_eq!(2, third.order_by.len());
+
+        assert!(builder.build_page(Some(&last), 25, 10).unwrap().is_none());
+
+        // the last document without the order field
+        last.fields.clear();
+        match builder.build_page(Some(&last), 20, 10) {
+            Err(FirestoreError::InvalidArgument(message)) => {
+                assert!(message.contains("amount"), "{}", message)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn resume_query_test() {
+        let query = QueryBuilder::collection("orders".to_owned(), false)
+            .order_by("amount", OrderDirection::Desc)
+            .offset(5)
+            .limit(25)
+            .build();
+        assert!(validate_resumable_query(&query).is_ok());
+
+        let mut last = Document {
+            name: "projects/p/databases/(default)/documents/orders/o10".to_owned(),
+            ..Default::default()
+        };
+        last.fields
+            .insert("amount".to_owned(), FValue::from(10i64).to_grpc_value());
+        let resumed = resume_query(&query, &last, 10).unwrap().unwrap();
+        assert_eq!(Some(15), resumed.limit);
+        assert_eq!(0, resumed.offset);
+        assert_eq!(
+            QueryBuilder::collection("orders".to_owned(), false)
+                .order_by("amount", OrderDirection::Desc)
+                .order_by("__name__", OrderDirection::Desc)
+                .build()
+                .order_by,
+            resumed.order_by
+        );
+        assert_eq!(
+            Some(Cursor {
+                values: vec![
+                    FValue::from(10i64).to_grpc_value(),
+                    Value {
+                        value_type: Some(ValueType::ReferenceValue(last.name.clone())),
+                    },
+                ],
+                before: false,
+            }),
+            resumed.start_at
+        );
+        assert!(resume_query(&query, &last, 25).unwrap().is_none());
+
+        last.fields.clear();
+        assert!(resume_query(&query, &last, 10).is_err());
+    }
+
+    #[test]
+    fn validate_resumable_query_test() {
+        let unordered = QueryBuilder::collection("orders".to_owned(), false).build();
+        assert!(validate_resumable_query(&unordered).is_err());
+
+        let not_selected = QueryBuilder::collection("orders".to_owned(), false)
+            .select(vec!["status"])
+            .order_by("amount", OrderDirection::Asc)
+            .build();
+        assert!(validate_resumable_query(&not_selected).is_err());
+
+        let by_name = QueryBuilder::collection("orders".to_owned(), false)
+            .select(vec!["status"])
+            .order_by("__name__", OrderDirection::Asc)
+            .build();
+        assert!(validate_resumable_query(&by_name).is_ok());
+    }
+
+    #[test]
+    fn collection_group_test() {
+        let query = QueryBuilder::collection("ignored".to_owned(), false)
+            .filter_field("status", FieldOp::Eq, "open")
+            .with_collection_group("comments".to_owned())
+            .build();
+        assert_eq!(1, query.from.len());
+        assert_eq!("comments", query.from[0].collection_id);
+        assert!(query.from[0].all_descendants);
+        assert_eq!(
+            QueryBuilder::collection_group("comments".to_owned())
+                .filter_field("status", FieldOp::Eq, "open")
+                .build(),
+            query
+        );
+    }
+
+    #[test]
+    fn document_cursor_test() {
+        let mut document = Document {
+            name: "projects/p/databases/(default)/documents/orders/o1".to_owned(),
+            ..Default::default()
+        };
+        document
+            .fields
+            .insert("amount".to_owned(), FValue::from(100i64).to_grpc_value());
+
+        let query = QueryBuilder::collection("orders".to_owned(), false)
+            .order_by("amount", OrderDirection::Desc)
+            .start_after(&document)
+            .build();
+
+        let expected = QueryBuilder::collection("orders".to_owned(), false)
+            .order_by("amount", OrderDirection::Desc)
+            .order_by("__name__", OrderDirection::Desc)
+            .build();
+        assert_eq!(expected.order_by, query.order_by);
+        assert_eq!(
+            Some(Cursor {
+                values: vec![
+                    FValue::from(100i64).to_grpc_value(),
+                    Value {
+                        value_type: Some(ValueType::ReferenceValue(document.name.clone())),
+                    },
+                ],
+                before: false,
+            }),
+            query.start_at
+        );
+    }
+
+    #[test]
+    fn build_queries_test() {
+        let values = |n: usize| (0..n as i64).map(FValue::from).collect::<Vec<FValue>>();
+        let query = |n: usize| {
+            QueryBuilder::collection("users".to_owned(), false)
+                .filter_field("active", FieldOp::Eq, true)
+                .filter_in("id", values(n))
+                .limit(5)
+        };
+
+        let queries = query(MAX_IN_CLAUS_NUM).build_queries().unwrap();
+        assert_eq!(vec![query(MAX_IN_CLAUS_NUM).build()], queries);
+
+        let queries = query(MAX_IN_CLAUS_NUM * 2 + 1).build_queries().unwrap();
+        assert_eq!(3, queries.len());
+        assert_eq!(
+            QueryBuilder::collection("users".to_owned(), false)
+                .filter_field("active", FieldOp::Eq, true)
+                .filter_field("id", FieldOp::In, FValue::Array(vec![FValue::from(20i64)]))
+                .limit(5)
+                .build(),
+            queries[2]
+        );
+        assert!(queries.iter().all(|q| q.limit == Some(5)));
+
+        let queries = QueryBuilder::collection("users".to_owned(), false)
+            .filter_in("id", values(MAX_IN_CLAUS_NUM + 1))
+            .filter_array_contains_any("tags", values(MAX_IN_CLAUS_NUM + 1))
+            .build_queries()
+            .unwrap();
+        assert_eq!(4, queries.len());
+
+        for invalid in [
+            QueryBuilder::collection("users".to_owned(), false)
+                .filter_not_in("id", values(MAX_IN_CLAUS_NUM + 1)),
+            QueryBuilder::collection("users".to_owned(), false).filter_in("id", vec![]),
+            query(MAX_IN_CLAUS_NUM + 1).offset(3),
+        ] {
+            assert!(matches!(
+                invalid.build_queries(),
+                Err(FirestoreError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn field_path_filter_test() {
+        let query = QueryBuilder::collection("users".to_owned(), false)
+            .filter_bin(FieldPath::new(["profile", "age"]), ">=", 21)
+            .filter_unary(FieldPath::new(["scores", "2021"]), UnaryOp::IsNotNull)
+            .order_by(FieldPath::new(["profile", "age"]), OrderDirection::Asc)
+            .build();
+        assert!(validate_field_paths(&query).is_ok());
+        let filter = format!("{:?}", query.r#where);
+        assert!(filter.contains("\"profile.age\""), "{}", filter);
+        assert!(filter.contains("\"scores.`2021`\""), "{}", filter);
+
+        let array_index = QueryBuilder::collection("users".to_owned(), false)
+            .filter_bin("name", "==", "taco")
+            .filter_bin("tags.0", "==", "a");
+        assert!(matches!(
+            array_index.clone().build_queries(),
+            Err(FirestoreError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            validate_field_paths(&array_index.build()),
+            Err(FirestoreError::InvalidArgument(_))
+        ));
+
+        let order = QueryBuilder::collection("users".to_owned(), false)
+            .order("tags[1]", "asc")
+            .build();
+        assert!(validate_field_paths(&order).is_err());
+    }
 }
@@ -1,7 +1,57 @@
+//! the low-level parts of the client, for the rpcs the client doesn't cover (e.g. with the
+//! labels or the custom masks). the requests are sent by `FirestoreClient::raw_client` on the
+//! channel of the client, with the auth token and the rpc hooks of the client. the raw client
+//! is not available on the read-only and the dry-run clients.
+//!
+//! ```ignore
+//! let mut request = V1RequestFactory.new_batch_write_request(
+//!     client.project_id().to_owned(),
+//!     operations,
+//! );
+//! request.labels.insert("job".to_owned(), "import".to_owned());
+//! let response = client.raw_client()?.batch_write(request).await?;
+//! ```
+
+pub use super::request::{
+    documents_root_path, fmt_document_path, project_and_default_database, DocumentWriteOperation,
+    ListDocumentsOptions, ReadConsistency, RequestFactory, V1RequestFactory,
+};
+pub use crate::grpc::hooks::HookedChannel;
 pub use google_cloud_grpc_proto::{
     firestore::v1::{
-        batch_get_documents_response, firestore_client, Cursor, Document, StructuredQuery, Value,
-        WriteResult,
+        self as proto, batch_get_documents_response, firestore_client, Cursor, Document,
+        DocumentMask, StructuredQuery, Value, WriteResult,
     },
-    tonic::{transport::Channel, Code},
+    tonic::{transport::Channel, Code, Request, Response, Status, Streaming},
 };
+
+/// the generated grpc client of `FirestoreClient::raw_client`.
+pub type RawFirestoreClient = firestore_client::FirestoreClient<HookedChannel>;
+
+#[cfg(test)]
+mod test {
+    use crate::firestore::{DryRunRecorder, FirestoreClient, FirestoreError};
+    use std::time::{Duration, SystemTime};
+
+    #[tokio::test]
+    async fn raw_client_test() {
+        let client = FirestoreClient::offline("p");
+        assert!(client.raw_client().is_ok());
+
+        match client.clone().read_only().raw_client() {
+            Err(FirestoreError::ReadOnlyViolation(rpc)) => assert_eq!("raw_client", rpc),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+        let read_time = SystemTime::now() - Duration::from_secs(60);
+        assert!(client
+            .clone()
+            .read_at(read_time)
+            .unwrap()
+            .raw_client()
+            .is_err());
+        assert!(client
+            .with_dry_run(DryRunRecorder::new())
+            .raw_client()
+            .is_err());
+    }
+}
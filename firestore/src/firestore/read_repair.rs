@@ -0,0 +1,204 @@
+use super::client::FirestoreClient;
+use super::query::QueryBuilder;
+use super::request::DocumentWriteOperation;
+use super::trigger::relative_document_path;
+use super::value::{FDocument, FFields};
+
+use super::error::Result;
+use futures::TryStreamExt;
+use std::collections::{HashMap, HashSet};
+
+/// the source documents read at once. the targets of a page are read and repaired together.
+pub const READ_REPAIR_PAGE_SIZE: i32 = 300;
+
+/// a denormalized copy of a source document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepairTarget {
+    /// e.g. "/users/u1/orders/o1"
+    pub document_path: String,
+    /// the fields expected in the target. the other fields of the target are not compared.
+    pub fields: FFields,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ReadRepairReport {
+    pub source_document_num: usize,
+    pub checked_target_num: usize,
+    /// the targets which were missing or had diverged fields.
+    pub diverged_targets: Vec<String>,
+    pub written_num: usize,
+}
+
+/// detect the denormalized copies diverged from the source of truth, and overwrite
+/// the diverged fields of them in batches.
+///
+/// ```ignore
+/// let report = client
+///     .read_repair(None, QueryBuilder::collection("users".to_owned(), false), |user| {
+///         vec![RepairTarget {
+///             document_path: format!("/profiles/{}", user.doc_path.document_id),
+///             fields: user.fields.clone(),
+///         }]
+///     })
+///     .with_dry_run(true)
+///     .run()
+///     .await?;
+/// ```
+pub struct ReadRepair<M> {
+    client: FirestoreClient,
+    parent_path: Option<String>,
+    source: QueryBuilder,
+    mapping: M,
+    page_size: i32,
+    dry_run: bool,
+}
+
+impl<M> ReadRepair<M>
+where
+    M: FnMut(&FDocument) -> Vec<RepairTarget>,
+{
+    pub(crate) fn new(
+        client: FirestoreClient,
+        parent_path: Option<String>,
+        source: QueryBuilder,
+        mapping: M,
+    ) -> Self {
+        Self {
+            // the mapping reads any field of the sources
+            client: client.without_default_field_masks(),
+            parent_path,
+            source,
+            mapping,
+            page_size: READ_REPAIR_PAGE_SIZE,
+            dry_run: false,
+        }
+    }
+
+    pub fn with_page_size(self, page_size: i32) -> Self {
+        Self {
+            page_size: page_size.max(1),
+            ..self
+        }
+    }
+
+    /// only report the divergence without writing.
+    pub fn with_dry_run(self, dry_run: bool) -> Self {
+        Self { dry_run, ..self }
+    }
+
+    pub async fn run(mut self) -> Result<ReadRepairReport> {
+        let mut report = ReadRepairReport::default();
+        let mut pages = Box::pin(self.client.paginate_query(
+            self.parent_path.clone(),
+            self.source.clone(),
+            self.page_size,
+        ));
+        while let Some(page) = pages.try_next().await? {
+            report.source_document_num += page.len();
+            let mut targets = Vec::new();
+            for document in page {
+                targets.extend((self.mapping)(&FDocument::from_document(document)?));
+            }
+            report.checked_target_num += targets.len();
+
+            let actuals = self.get_target_fields(&targets).await?;
+            let repairs: Vec<RepairTarget> = targets
+                .into_iter()
+                .filter(|target| is_diverged(&target.fields, actuals.get(&target.document_path)))
+                .collect();
+            report
+                .diverged_targets
+                .extend(repairs.iter().map(|target| target.document_path.clone()));
+
+            if !self.dry_run && !repairs.is_empty() {
+                let operations = repairs.into_iter().map(repair_operation).collect();
+                report.written_num += self.client.large_batch_write(operations).await?.len();
+            }
+        }
+        Ok(report)
+    }
+
+    /// the expected fields of the existing targets, keyed by the document path.
+    async fn get_target_fields(
+        &mut self,
+        targets: &[RepairTarget],
+    ) -> Result<HashMap<String, FFields>> {
+        let document_paths: HashSet<String> = targets
+            .iter()
+            .map(|target| target.document_path.clone())
+            .collect();
+        let field_mask: HashSet<String> = targets
+            .iter()
+            .flat_map(|target| target.fields.keys().cloned())
+            .collect();
+        if document_paths.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut actuals = HashMap::new();
+        self.client
+            .batch_get_documents(
+                document_paths.into_iter().collect(),
+                Some(field_mask.into_iter().collect()),
+                None,
+                |document| {
+                    let document_path = relative_document_path(&document.name);
+                    actuals.insert(document_path, FFields::from_grpc_doc(document));
+                    Ok(())
+                },
+            )
+            .await?;
+        Ok(actuals)
+    }
+}
+
+fn is_diverged(expected: &FFields, actual: Option<&FFields>) -> bool {
+    match actual {
+        Some(actual) => expected
+            .keys()
+            .any(|key| actual.get(key) != expected.get(key)),
+        None => true,
+    }
+}
+
+/// overwrite only the expected fields. the missing target is created.
+fn repair_operation(target: RepairTarget) -> DocumentWriteOperation {
+    let update_field_mask = target.fields.keys().cloned().collect();
+    DocumentWriteOperation::new_update(target.document_path, target.fields, Some(update_field_mask))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::firestore::FValue;
+
+    fn fields(values: Vec<(&str, FValue)>) -> FFields {
+        FFields::new(values.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+    }
+
+    #[test]
+    fn is_diverged_test() {
+        let expected = fields(vec![("name", "taco".into()), ("age", 20i64.into())]);
+
+        assert!(is_diverged(&expected, None));
+        assert!(!is_diverged(
+            &expected,
+            Some(&fields(vec![
+                ("name", "taco".into()),
+                ("age", 20i64.into()),
+                ("other", true.into()),
+            ]))
+        ));
+        assert!(is_diverged(
+            &expected,
+            Some(&fields(vec![
+                ("name", "taco".into()),
+                ("age", 21i64.into())
+            ]))
+        ));
+        assert!(is_diverged(
+            &expected,
+            Some(&fields(vec![("name", "taco".into())]))
+        ));
+    }
+}
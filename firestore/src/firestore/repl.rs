@@ -0,0 +1,514 @@
+//! the functions for the command line tools exploring the database: the small query syntax,
+//! the tables and the json of the documents, and the pages of the results.
+//!
+//! ```text
+//! from <collection> [where <condition> [and <condition>]..] [select <field>, ..]
+//!     [order by <field> [asc|desc], ..] [limit <n>] [offset <n>]
+//!
+//! <collection> := users | /users/u1/orders | group comments
+//! <condition>  := <field> <op> <value> | <field> is-null | is-not-null | is-nan | is-not-nan
+//! <op>         := < | <= | == | > | >= | != | array-contains | array-contains-any | in | not-in
+//! <value>      := "text" | 'text' | 1 | 1.5 | true | false | null | [<value>, ..]
+//! ```
+//!
+//! the fields are the paths of `QueryBuilder::filter_bin` (e.g. address.city or `zip code`).
+//!
+//! ```ignore
+//! let query = repl::parse_query(r#"from users where age >= 20 order by age desc limit 10"#)?;
+//! let mut pages = Box::pin(query.pages(&client, 20));
+//! while let Some(page) = pages.try_next().await? {
+//!     println!("{}", repl::format_table(&page));
+//! }
+//! ```
+
+use super::client::FirestoreClient;
+use super::error::{FirestoreError, Result};
+use super::query::{FieldOp, OrderDirection, QueryBuilder, UnaryOp};
+use super::value::{fdoc::JsonMetadataKeys, FDocument, FValue};
+use futures::Stream;
+use google_cloud_grpc_proto::firestore::v1::Document;
+use serde_json::Value as JValue;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+/// the cells of `format_table` longer than this are truncated.
+pub const MAX_CELL_WIDTH: usize = 40;
+
+/// the query parsed by `parse_query`.
+#[derive(Clone)]
+pub struct ReplQuery {
+    /// the document path of the collection (e.g. "/users/u1"), None for the root collections.
+    pub parent_path: Option<String>,
+    pub query: QueryBuilder,
+}
+
+impl ReplQuery {
+    /// the pages of the results. see `FirestoreClient::paginate_query`.
+    pub fn pages(
+        &self,
+        client: &FirestoreClient,
+        page_size: i32,
+    ) -> impl Stream<Item = Result<Vec<Document>>> {
+        client.paginate_query(self.parent_path.clone(), self.query.clone(), page_size)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Comma,
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            ',' | '[' | ']' => {
+                chars.next();
+                tokens.push(match c {
+                    ',' => Token::Comma,
+                    '[' => Token::Open,
+                    _ => Token::Close,
+                });
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => text.extend(chars.next()),
+                        Some(ch) if ch == c => break,
+                        Some(ch) => text.push(ch),
+                        None => {
+                            return Err(FirestoreError::invalid_argument(format!(
+                                "unclosed {} in the query",
+                                c
+                            )))
+                        }
+                    }
+                }
+                tokens.push(Token::Text(text));
+            }
+            _ => {
+                let mut word = String::new();
+                let mut quoted = false;
+                while let Some(&ch) = chars.peek() {
+                    if !quoted && (ch.is_whitespace() || matches!(ch, ',' | '[' | ']')) {
+                        break;
+                    }
+                    if ch == '`' {
+                        quoted = !quoted;
+                    }
+                    word.push(ch);
+                    chars.next();
+                }
+                if quoted {
+                    return Err(FirestoreError::invalid_argument(format!(
+                        "unclosed ` in {}",
+                        word
+                    )));
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.position), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek_keyword(keyword);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.unexpected(keyword))
+        }
+    }
+
+    fn eat_comma(&mut self) -> bool {
+        let found = self.tokens.get(self.position) == Some(&Token::Comma);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn word(&mut self, expected: &str) -> Result<String> {
+        match self.tokens.get(self.position) {
+            Some(Token::Word(word)) => {
+                self.position += 1;
+                Ok(word.clone())
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    fn number(&mut self, expected: &str) -> Result<i32> {
+        let word = self.word(expected)?;
+        word.parse()
+            .map_err(|_| FirestoreError::invalid_argument(format!("{} is not a number", word)))
+    }
+
+    fn value(&mut self) -> Result<FValue> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Text(text)) => Ok(FValue::Str(text)),
+            Some(Token::Open) => {
+                let mut values = Vec::new();
+                if self.tokens.get(self.position) == Some(&Token::Close) {
+                    self.position += 1;
+                    return Ok(FValue::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    match self.tokens.get(self.position) {
+                        Some(Token::Comma) => self.position += 1,
+                        Some(Token::Close) => {
+                            self.position += 1;
+                            return Ok(FValue::Array(values));
+                        }
+                        _ => return Err(self.unexpected("] or ,")),
+                    }
+                }
+            }
+            Some(Token::Word(word)) => match word.as_str() {
+                "true" => Ok(FValue::Bool(true)),
+                "false" => Ok(FValue::Bool(false)),
+                "null" => Ok(FValue::NullValue),
+                _ => word
+                    .parse::<i64>()
+                    .map(FValue::Int)
+                    .or_else(|_| word.parse::<f64>().map(FValue::Double))
+                    .map_err(|_| {
+                        FirestoreError::invalid_argument(format!(
+                            "{} is not a value. quote the strings",
+                            word
+                        ))
+                    }),
+            },
+            _ => {
+                self.position -= 1;
+                Err(self.unexpected("a value"))
+            }
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> FirestoreError {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                FirestoreError::invalid_argument(format!("{} expected but {:?}", expected, token))
+            }
+            None => FirestoreError::invalid_argument(format!(
+                "{} expected but the query ended",
+                expected
+            )),
+        }
+    }
+
+    fn is_clause_end(&self) -> bool {
+        self.position >= self.tokens.len()
+            || ["where", "select", "order", "limit", "offset"]
+                .iter()
+                .any(|keyword| self.peek_keyword(keyword))
+    }
+}
+
+/// parse the query of the syntax of the module doc.
+pub fn parse_query(text: &str) -> Result<ReplQuery> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        position: 0,
+    };
+    parser.expect_keyword("from")?;
+    let (parent_path, mut query) = if parser.eat_keyword("group") {
+        let collection_id = parser.word("collection id")?;
+        (None, QueryBuilder::collection_group(collection_id))
+    } else {
+        let path = parser.word("collection")?;
+        let (parent_path, collection_id) = split_collection_path(&path)?;
+        (
+            parent_path,
+            QueryBuilder::collection(collection_id.to_owned(), false),
+        )
+    };
+
+    while parser.position < parser.tokens.len() {
+        if parser.eat_keyword("where") {
+            loop {
+                query = parse_condition(&mut parser, query)?;
+                if !parser.eat_keyword("and") {
+                    break;
+                }
+            }
+        } else if parser.eat_keyword("select") {
+            let mut fields = vec![parser.word("field")?];
+            while parser.eat_comma() {
+                fields.push(parser.word("field")?);
+            }
+            query = query.select(fields);
+        } else if parser.eat_keyword("order") {
+            parser.expect_keyword("by")?;
+            loop {
+                let field = parser.word("field")?;
+                let direction = if parser.eat_keyword("desc") {
+                    OrderDirection::Desc
+                } else {
+                    parser.eat_keyword("asc");
+                    OrderDirection::Asc
+                };
+                query = query.order_by(field, direction);
+                if !parser.eat_comma() {
+                    break;
+                }
+            }
+        } else if parser.eat_keyword("limit") {
+            query = query.limit(parser.number("limit")?);
+        } else if parser.eat_keyword("offset") {
+            query = query.offset(parser.number("offset")?);
+        } else {
+            return Err(parser.unexpected("where, select, order by, limit or offset"));
+        }
+        if !parser.is_clause_end() {
+            return Err(parser.unexpected("the next clause"));
+        }
+    }
+    Ok(ReplQuery { parent_path, query })
+}
+
+fn parse_condition(parser: &mut Parser, query: QueryBuilder) -> Result<QueryBuilder> {
+    let field = parser.word("field")?;
+    let op = parser.word("operator")?;
+    if let Ok(op) = UnaryOp::from_str(&op) {
+        return Ok(query.filter_unary(field, op));
+    }
+    let op = FieldOp::from_str(&op)
+        .map_err(|_| FirestoreError::invalid_argument(format!("unknown operator {}", op)))?;
+    let value = parser.value()?;
+    Ok(match (op, value) {
+        (FieldOp::In, FValue::Array(values)) => query.filter_in(field, values),
+        (FieldOp::NotIn, FValue::Array(values)) => query.filter_not_in(field, values),
+        (FieldOp::ArrayContainsAny, FValue::Array(values)) => {
+            query.filter_array_contains_any(field, values)
+        }
+        (FieldOp::In, _) | (FieldOp::NotIn, _) | (FieldOp::ArrayContainsAny, _) => {
+            return Err(FirestoreError::invalid_argument(format!(
+                "the value of {} is not a list",
+                field
+            )))
+        }
+        (op, value) => query.filter_field(field, op, value),
+    })
+}
+
+/// e.g. (Some("/users/u1"), "orders") of "/users/u1/orders", (None, "users") of "users"
+fn split_collection_path(path: &str) -> Result<(Option<String>, &str)> {
+    let invalid = || FirestoreError::invalid_argument(format!("invalid collection path {}", path));
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    if segments.iter().any(|segment| segment.is_empty()) {
+        return Err(invalid());
+    }
+    // the pairs of the collection id and the document id, and the collection id
+    let (collection_id, parents) = segments.split_last().ok_or_else(invalid)?;
+    if parents.chunks(2).any(|pair| pair.len() != 2) {
+        return Err(invalid());
+    }
+    let parent_path = if parents.is_empty() {
+        None
+    } else {
+        Some(format!("/{}", parents.join("/")))
+    };
+    Ok((parent_path, collection_id))
+}
+
+/// the documents as a table of the document ids and the top level fields.
+/// the strings are shown without the quotes, and the other values as json.
+///
+/// ```text
+/// id | age | name
+/// ---+-----+-----
+/// u1 | 20  | jeff
+/// u2 |     | bob
+/// ```
+pub fn format_table(documents: &[Document]) -> String {
+    let fields: BTreeSet<&str> = documents
+        .iter()
+        .flat_map(|document| document.fields.keys().map(String::as_str))
+        .collect();
+    let mut rows = vec![std::iter::once("id".to_owned())
+        .chain(fields.iter().map(|field| field.to_string()))
+        .collect::<Vec<String>>()];
+    for document in documents {
+        let id = document.name.rsplit('/').next().unwrap_or_default();
+        rows.push(
+            std::iter::once(id.to_owned())
+                .chain(fields.iter().map(|field| {
+                    document
+                        .fields
+                        .get(*field)
+                        .map(|value| format_cell(FValue::from(value.clone())))
+                        .unwrap_or_default()
+                }))
+                .collect(),
+        );
+    }
+
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let format_row = |row: &[String]| {
+        row.iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<String>>()
+            .join(" | ")
+            .trim_end()
+            .to_owned()
+    };
+    let separator = widths
+        .iter()
+        .map(|width| "-".repeat(*width))
+        .collect::<Vec<String>>()
+        .join("-+-");
+
+    let mut lines = vec![format_row(&rows[0]), separator];
+    lines.extend(rows[1..].iter().map(|row| format_row(row)));
+    lines.join("\n")
+}
+
+fn format_cell(value: FValue) -> String {
+    let cell = match value {
+        FValue::Str(s) => s,
+        FValue::Int(i) => i.to_string(),
+        value => JValue::from(value).to_string(),
+    };
+    let cell = cell.replace('\n', " ");
+    if cell.chars().count() > MAX_CELL_WIDTH {
+        let truncated: String = cell.chars().take(MAX_CELL_WIDTH - 3).collect();
+        format!("{}...", truncated)
+    } else {
+        cell
+    }
+}
+
+/// the documents as a pretty printed json array, with "_id", "_createTime" and "_updateTime".
+pub fn format_json(documents: &[Document]) -> Result<String> {
+    let keys = JsonMetadataKeys::default();
+    let values = documents
+        .iter()
+        .map(|document| {
+            FDocument::from_document(document.clone())
+                .map(|document| document.to_json_with_metadata(&keys))
+        })
+        .collect::<Result<Vec<JValue>>>()?;
+    serde_json::to_string_pretty(&values)
+        .map_err(|e| FirestoreError::Internal(format!("failed to format json: {}", e)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::firestore::OrderDirection;
+    use crate::fixtures::documents;
+
+    #[test]
+    fn parse_query_test() {
+        let parsed = parse_query(
+            r#"from /users/u1/orders where status == "open" and amount >= 1.5
+                and tags array-contains-any ['a', "b"] and memo is-null
+                select status, amount order by amount desc, created_at limit 10 offset 5"#,
+        )
+        .unwrap();
+        assert_eq!(Some("/users/u1".to_owned()), parsed.parent_path);
+        assert_eq!(
+            QueryBuilder::collection("orders".to_owned(), false)
+                .filter_bin("status", "==", "open")
+                .filter_bin("amount", ">=", 1.5)
+                .filter_array_contains_any("tags", vec![FValue::from("a"), FValue::from("b")])
+                .filter_unary("memo", UnaryOp::IsNull)
+                .select(vec!["status", "amount"])
+                .order_by("amount", OrderDirection::Desc)
+                .order_by("created_at", OrderDirection::Asc)
+                .limit(10)
+                .offset(5)
+                .build(),
+            parsed.query.build()
+        );
+
+        let parsed = parse_query("FROM group comments WHERE `likes count` > 3").unwrap();
+        assert_eq!(None, parsed.parent_path);
+        assert_eq!(
+            QueryBuilder::collection_group("comments".to_owned())
+                .filter_bin("`likes count`", ">", 3i64)
+                .build(),
+            parsed.query.build()
+        );
+    }
+
+    #[test]
+    fn parse_query_error_test() {
+        for text in [
+            "users",
+            "from /users/u1",
+            "from users where age",
+            "from users where age ~ 1",
+            "from users where name == jeff",
+            "from users where id in 1",
+            "from users limit ten",
+            "from users where name == 'jeff",
+            "from users order age",
+        ]
+        .iter()
+        {
+            assert!(parse_query(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn format_test() {
+        let docs = documents("/users", "u", 2, |i, doc| {
+            let doc = doc.field("name", if i == 0 { "jeff" } else { "bob" });
+            if i == 0 {
+                doc.field("age", 20i64)
+            } else {
+                doc
+            }
+        });
+        assert_eq!(
+            "id | age | name\n---+-----+-----\nu0 | 20  | jeff\nu1 |     | bob",
+            format_table(&docs)
+        );
+
+        let json: JValue = serde_json::from_str(&format_json(&docs).unwrap()).unwrap();
+        assert_eq!("u0", json[0]["_id"]);
+        assert_eq!(Some(20.0), json[0]["age"].as_f64());
+    }
+}
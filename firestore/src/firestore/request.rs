@@ -1,15 +1,28 @@
+use super::error::{FirestoreError, Result};
+use super::page_size::AdaptivePageSize;
+use super::size_calculator::{
+    document_path_size, grpc_fields_size, string_size, value_size, DOCUMENT_ADDITIONAL_BYTES,
+};
+use super::value::{timestamp, FFields, FTransform, FValue};
+use super::write_validation::MAX_REQUEST_SIZE;
+use google_cloud_grpc_proto::firestore::admin::v1::ListIndexesRequest;
 use google_cloud_grpc_proto::firestore::v1::{
-    batch_get_documents_request, get_document_request, list_documents_request,
-    partition_query_request, run_query_request, transaction_options, write::Operation,
-    BatchGetDocumentsRequest, BatchWriteRequest, BeginTransactionRequest, CommitRequest,
-    CreateDocumentRequest, DeleteDocumentRequest, Document, DocumentMask, GetDocumentRequest,
-    ListCollectionIdsRequest, ListDocumentsRequest, PartitionQueryRequest, RollbackRequest,
-    RunQueryRequest, StructuredQuery, TransactionOptions, UpdateDocumentRequest, Value, Write,
-    WriteRequest,
+    batch_get_documents_request,
+    document_transform::{field_transform, FieldTransform},
+    get_document_request, list_documents_request, listen_request, partition_query_request,
+    precondition, run_aggregation_query_request, run_query_request, transaction_options,
+    write::Operation,
+    ArrayValue, BatchGetDocumentsRequest, BatchWriteRequest, BeginTransactionRequest,
+    CommitRequest, CreateDocumentRequest, DeleteDocumentRequest, Document, DocumentMask,
+    GetDocumentRequest, ListCollectionIdsRequest, ListDocumentsRequest, ListenRequest,
+    PartitionQueryRequest, Precondition, RollbackRequest, RunAggregationQueryRequest,
+    RunQueryRequest, StructuredAggregationQuery, StructuredQuery, Target, TransactionOptions,
+    UpdateDocumentRequest, Value, Write, WriteRequest,
 };
 use google_cloud_grpc_proto::prost_types::Timestamp;
+use ring::rand::{SecureRandom, SystemRandom};
 use std::collections::HashMap;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 fn validate_partial_document_paths(document_paths: &[String]) -> bool {
     document_paths
@@ -32,15 +45,39 @@ fn validate_partial_document_path(document_path: &String) -> bool {
     true
 }
 
-fn project_and_default_database(project_id: String) -> String {
+const AUTO_ID_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+const AUTO_ID_LEN: usize = 20;
+
+/// random 20 alphanumeric characters same as the document ids generated by the official client libraries.
+pub(crate) fn new_auto_id() -> String {
+    let rng = SystemRandom::new();
+    let mut id = String::with_capacity(AUTO_ID_LEN);
+    let mut buf = [0u8; 1];
+    while id.len() < AUTO_ID_LEN {
+        rng.fill(&mut buf).expect("failed to generate random bytes");
+        // reject to keep the distribution uniform (62 * 4 = 248)
+        if (buf[0] as usize) < AUTO_ID_CHARS.len() * 4 {
+            id.push(AUTO_ID_CHARS[buf[0] as usize % AUTO_ID_CHARS.len()] as char);
+        }
+    }
+    id
+}
+
+/// the database of the requests. e.g. "projects/p/databases/(default)"
+pub fn project_and_default_database(project_id: String) -> String {
     format!("projects/{}/databases/{}", project_id, default_database())
 }
 
+/// the client always connects to the default database of the project.
+pub(crate) const DEFAULT_DATABASE_ID: &str = "(default)";
+
 fn default_database() -> String {
-    "(default)".to_string()
+    DEFAULT_DATABASE_ID.to_string()
 }
 
-fn fmt_document_path<P: AsRef<str>, D: AsRef<str>>(project_id: P, document_path: D) -> String {
+/// the name of the document. e.g. "projects/p/databases/(default)/documents/users/jeff" of
+/// "/users/jeff"
+pub fn fmt_document_path<P: AsRef<str>, D: AsRef<str>>(project_id: P, document_path: D) -> String {
     format!(
         "projects/{}/databases/(default)/documents{}",
         project_id.as_ref(),
@@ -48,7 +85,240 @@ fn fmt_document_path<P: AsRef<str>, D: AsRef<str>>(project_id: P, document_path:
     )
 }
 
-pub(super) fn new_collection_ids_request(
+/// builds the requests of the client. an alternative factory can override some of the methods
+/// (e.g. to add labels, or for a later api revision) and be passed to
+/// `FirestoreClient::with_request_factory` without changing the client api.
+/// the default methods build the requests of the firestore v1 api.
+///
+/// ```ignore
+/// struct Labeled;
+/// impl RequestFactory for Labeled {
+///     fn new_batch_write_request(
+///         &self,
+///         project_id: String,
+///         operations: Vec<DocumentWriteOperation>,
+///     ) -> BatchWriteRequest {
+///         let mut request = V1RequestFactory.new_batch_write_request(project_id, operations);
+///         request.labels.insert("job".to_owned(), "import".to_owned());
+///         request
+///     }
+/// }
+/// let client = client.with_request_factory(Arc::new(Labeled));
+/// ```
+pub trait RequestFactory: Send + Sync {
+    fn new_get_document_request(
+        &self,
+        project_id: String,
+        document_path: String,
+        field_mask: Option<Vec<String>>,
+        consistency: ReadConsistency,
+    ) -> GetDocumentRequest {
+        new_get_document_request(project_id, document_path, field_mask, consistency)
+    }
+
+    fn new_batch_get_documents_request(
+        &self,
+        project_id: String,
+        document_paths: Vec<String>,
+        field_mask: Option<Vec<String>>,
+        consistency: ReadConsistency,
+    ) -> BatchGetDocumentsRequest {
+        new_batch_get_documents_request(project_id, document_paths, field_mask, consistency)
+    }
+
+    fn new_list_document_request(
+        &self,
+        project_id: String,
+        document_path: String,
+        collection_id: String,
+        page_token: String,
+        options: ListDocumentsOptions,
+    ) -> ListDocumentsRequest {
+        new_list_document_request(
+            project_id,
+            document_path,
+            collection_id,
+            page_token,
+            options,
+        )
+    }
+
+    fn new_collection_ids_request(
+        &self,
+        project_id: String,
+        document_path: String,
+        chunk_size: Option<i32>,
+        page_token: String,
+    ) -> ListCollectionIdsRequest {
+        new_collection_ids_request(project_id, document_path, chunk_size, page_token)
+    }
+
+    fn new_query_request(
+        &self,
+        project_id: String,
+        parent_path: String,
+        query: StructuredQuery,
+        consistency: ReadConsistency,
+    ) -> RunQueryRequest {
+        new_query_request(project_id, parent_path, query, consistency)
+    }
+
+    fn new_aggregation_query_request(
+        &self,
+        project_id: String,
+        parent_path: String,
+        query: StructuredAggregationQuery,
+        consistency: ReadConsistency,
+    ) -> RunAggregationQueryRequest {
+        new_aggregation_query_request(project_id, parent_path, query, consistency)
+    }
+
+    fn new_partition_query_request(
+        &self,
+        project_id: String,
+        document_path: String,
+        query: StructuredQuery,
+        max_partition_count: i64,
+        chunk_size: i32,
+        token: String,
+    ) -> PartitionQueryRequest {
+        new_partition_query_request(
+            project_id,
+            document_path,
+            query,
+            max_partition_count,
+            chunk_size,
+            token,
+        )
+    }
+
+    fn new_listen_request(&self, project_id: String, target: Target) -> ListenRequest {
+        new_listen_request(project_id, target)
+    }
+
+    fn new_create_document_request(
+        &self,
+        project_id: String,
+        parent_path: String,
+        collection_id: String,
+        document_id: String,
+        values: HashMap<String, Value>,
+        response_field_mask: Option<Vec<String>>,
+    ) -> CreateDocumentRequest {
+        new_create_document_request(
+            project_id,
+            parent_path,
+            collection_id,
+            document_id,
+            values,
+            response_field_mask,
+        )
+    }
+
+    fn new_update_document_request(
+        &self,
+        project_id: String,
+        document_path: String,
+        values: HashMap<String, Value>,
+        update_field_mask: Option<Vec<String>>,
+        response_field_mask: Option<Vec<String>>,
+    ) -> UpdateDocumentRequest {
+        new_update_document_request(
+            project_id,
+            document_path,
+            values,
+            update_field_mask,
+            response_field_mask,
+        )
+    }
+
+    fn new_delete_document_request(
+        &self,
+        project_id: String,
+        document_path: String,
+    ) -> DeleteDocumentRequest {
+        new_delete_document_request(project_id, document_path)
+    }
+
+    fn new_commit_request(
+        &self,
+        project_id: String,
+        operations: Vec<DocumentWriteOperation>,
+        transaction: Option<Vec<u8>>,
+    ) -> CommitRequest {
+        new_commit_request(project_id, operations, transaction)
+    }
+
+    fn new_batch_write_request(
+        &self,
+        project_id: String,
+        operations: Vec<DocumentWriteOperation>,
+    ) -> BatchWriteRequest {
+        new_batch_write_request(project_id, operations)
+    }
+
+    fn new_start_stream_write_request(
+        &self,
+        project_id: String,
+        stream_id: String,
+        stream_token: Vec<u8>,
+    ) -> WriteRequest {
+        new_start_stream_write_request(project_id, stream_id, stream_token)
+    }
+
+    fn new_stream_write_request(
+        &self,
+        project_id: String,
+        operations: Vec<DocumentWriteOperation>,
+        stream_token: Vec<u8>,
+    ) -> WriteRequest {
+        new_stream_write_request(project_id, operations, stream_token)
+    }
+
+    fn new_begin_transaction_request(
+        &self,
+        project_id: String,
+        read_only_time: Option<SystemTime>,
+    ) -> BeginTransactionRequest {
+        new_begin_transaction_request(project_id, read_only_time)
+    }
+
+    fn new_begin_read_write_transaction_request(
+        &self,
+        project_id: String,
+        retry_transaction: Vec<u8>,
+    ) -> BeginTransactionRequest {
+        new_begin_read_write_transaction_request(project_id, retry_transaction)
+    }
+
+    fn new_begin_read_only_transaction_request(
+        &self,
+        project_id: String,
+        read_time: Option<SystemTime>,
+    ) -> BeginTransactionRequest {
+        new_begin_read_only_transaction_request(project_id, read_time)
+    }
+
+    fn new_rollback_request(&self, project_id: String, transaction: Vec<u8>) -> RollbackRequest {
+        new_rollback_request(project_id, transaction)
+    }
+
+    fn new_list_indexes_request(
+        &self,
+        project_id: String,
+        collection_id: String,
+    ) -> ListIndexesRequest {
+        new_list_indexes_request(project_id, collection_id)
+    }
+}
+
+/// the requests of the firestore v1 api.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct V1RequestFactory;
+
+impl RequestFactory for V1RequestFactory {}
+
+fn new_collection_ids_request(
     project_id: String,
     document_path: String,
     chunk_size: Option<i32>,
@@ -61,26 +331,22 @@ pub(super) fn new_collection_ids_request(
     }
 }
 
-///TODO (tacogips) deal with read time consistency
-pub(super) fn new_get_document_request(
+fn new_get_document_request(
     project_id: String,
     document_path: String,
     field_mask: Option<Vec<String>>,
-    transaction: Option<Vec<u8>>,
+    consistency: ReadConsistency,
 ) -> GetDocumentRequest {
     debug_assert!(validate_partial_document_path(&document_path));
-    use get_document_request::ConsistencySelector::Transaction;
+    use get_document_request::ConsistencySelector::{ReadTime, Transaction};
     GetDocumentRequest {
         name: fmt_document_path(project_id, document_path),
         mask: field_mask.map(|ms| DocumentMask { field_paths: ms }),
-        consistency_selector: transaction.map(|id| Transaction(id)),
+        consistency_selector: consistency.into_selector(Transaction, ReadTime),
     }
 }
 
-pub(super) fn new_delete_document_request(
-    project_id: String,
-    document_path: String,
-) -> DeleteDocumentRequest {
+fn new_delete_document_request(project_id: String, document_path: String) -> DeleteDocumentRequest {
     debug_assert!(validate_partial_document_path(&document_path));
     DeleteDocumentRequest {
         name: fmt_document_path(project_id.as_str(), document_path),
@@ -88,39 +354,192 @@ pub(super) fn new_delete_document_request(
     }
 }
 
-///TODO (tacogips) deal with read time consistency
-pub(super) fn new_list_document_request(
+/// the snapshot which the documents are read from.
+///
+/// the read apis accept `Option<Vec<u8>>` of a transaction id as well.
+///
+/// ```ignore
+/// // point-in-time read
+/// let read_time = SystemTime::now() - Duration::from_secs(30 * 60);
+/// client.get_document(path, None, ReadConsistency::ReadTime(read_time)).await?;
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum ReadConsistency {
+    /// the latest data.
+    #[default]
+    Default,
+    /// in the transaction.
+    Transaction(Vec<u8>),
+    /// the data at the time. within the past hour, or the past 7 days with point-in-time recovery.
+    ReadTime(SystemTime),
+}
+
+impl ReadConsistency {
+    /// the consistency selector of each request.
+    fn into_selector<S, T, R>(self, transaction: T, read_time: R) -> Option<S>
+    where
+        T: FnOnce(Vec<u8>) -> S,
+        R: FnOnce(Timestamp) -> S,
+    {
+        match self {
+            ReadConsistency::Default => None,
+            ReadConsistency::Transaction(id) => Some(transaction(id)),
+            ReadConsistency::ReadTime(time) => Some(read_time(Timestamp::from(time))),
+        }
+    }
+}
+
+/// the versions of the past hour are readable without point-in-time recovery.
+pub const VERSION_RETENTION_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// the versions of the past 7 days are readable with point-in-time recovery,
+/// at the whole minutes beyond `VERSION_RETENTION_PERIOD`.
+pub const POINT_IN_TIME_RECOVERY_WINDOW: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// reject the read time which firestore can't read at, before the request fails with
+/// an opaque INVALID_ARGUMENT.
+pub(crate) fn validate_read_time(read_time: SystemTime, now: SystemTime) -> Result<()> {
+    let age = now.duration_since(read_time).map_err(|_| {
+        FirestoreError::invalid_argument(format!("the read time {:?} is in the future", read_time))
+    })?;
+    if age > POINT_IN_TIME_RECOVERY_WINDOW {
+        return Err(FirestoreError::invalid_argument(format!(
+            "the read time {:?} is outside the point-in-time recovery window of the past {} days",
+            read_time,
+            POINT_IN_TIME_RECOVERY_WINDOW.as_secs() / (24 * 60 * 60)
+        )));
+    }
+    if age > VERSION_RETENTION_PERIOD && read_time != timestamp::truncate_to_minute(read_time) {
+        return Err(FirestoreError::invalid_argument(format!(
+            "the read time {:?} older than an hour must be a whole minute \
+             (see `timestamp::truncate_to_minute`)",
+            read_time
+        )));
+    }
+    Ok(())
+}
+
+impl From<Option<Vec<u8>>> for ReadConsistency {
+    fn from(transaction: Option<Vec<u8>>) -> Self {
+        transaction.map_or(ReadConsistency::Default, ReadConsistency::Transaction)
+    }
+}
+
+/// the string field matching the prefix, for `search_prefix_like`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PrefixMatch {
+    pub field: String,
+    pub prefix: String,
+    /// match the value equal to the prefix too.
+    pub contain_exact_match: bool,
+}
+
+impl PrefixMatch {
+    pub fn new<F: Into<String>, P: Into<String>>(field: F, prefix: P) -> Self {
+        Self {
+            field: field.into(),
+            prefix: prefix.into(),
+            contain_exact_match: true,
+        }
+    }
+
+    pub fn with_contain_exact_match(self, contain_exact_match: bool) -> Self {
+        Self {
+            contain_exact_match,
+            ..self
+        }
+    }
+}
+
+/// options of `list_documents_with`, `list_documents_as` and `list_documents_chunk`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ListDocumentsOptions {
+    /// list the documents which don't exist but have sub collections too. they have no fields
+    /// and no create time. can't be combined with `order_by`.
+    pub show_missing: bool,
+    /// e.g. "priority desc, name"
+    pub order_by: Option<String>,
+    /// 100 if None.
+    pub page_size: Option<i32>,
+    /// tune the page size by each page, instead of `page_size`. only for `list_documents_with`
+    /// and `list_documents_as`.
+    pub adaptive_page_size: Option<AdaptivePageSize>,
+    pub field_mask: Option<Vec<String>>,
+    pub consistency: ReadConsistency,
+}
+
+impl ListDocumentsOptions {
+    pub fn with_show_missing(self, show_missing: bool) -> Self {
+        Self {
+            show_missing,
+            ..self
+        }
+    }
+
+    pub fn with_order_by<O: Into<String>>(self, order_by: O) -> Self {
+        Self {
+            order_by: Some(order_by.into()),
+            ..self
+        }
+    }
+
+    pub fn with_page_size(self, page_size: i32) -> Self {
+        Self {
+            page_size: Some(page_size),
+            ..self
+        }
+    }
+
+    pub fn with_adaptive_page_size(self, adaptive_page_size: AdaptivePageSize) -> Self {
+        Self {
+            adaptive_page_size: Some(adaptive_page_size),
+            ..self
+        }
+    }
+
+    pub fn with_field_mask<F: Into<String>>(self, field_mask: Vec<F>) -> Self {
+        Self {
+            field_mask: Some(field_mask.into_iter().map(Into::into).collect()),
+            ..self
+        }
+    }
+
+    pub fn with_consistency(self, consistency: ReadConsistency) -> Self {
+        Self {
+            consistency,
+            ..self
+        }
+    }
+}
+
+fn new_list_document_request(
     project_id: String,
     document_path: String,
     collection_id: String,
     page_token: String,
-    order_by: Option<String>,
-    chunk_size: Option<i32>,
-    field_mask: Option<Vec<String>>,
-    transaction: Option<Vec<u8>>,
+    options: ListDocumentsOptions,
 ) -> ListDocumentsRequest {
-    use list_documents_request::ConsistencySelector::Transaction;
+    use list_documents_request::ConsistencySelector::{ReadTime, Transaction};
 
     ListDocumentsRequest {
         parent: fmt_document_path(project_id, document_path),
         collection_id,
-        page_size: chunk_size.unwrap_or(100),
+        page_size: options.page_size.unwrap_or(100),
         page_token,
-        order_by: order_by.unwrap_or("".to_owned()),
-        mask: to_document_mask(field_mask),
-        show_missing: false,
-        consistency_selector: transaction.map(|id| Transaction(id)),
+        order_by: options.order_by.unwrap_or("".to_owned()),
+        mask: to_document_mask(options.field_mask),
+        show_missing: options.show_missing,
+        consistency_selector: options.consistency.into_selector(Transaction, ReadTime),
     }
 }
 
-///TODO (tacogips) deal with read time consistency
-pub(super) fn new_batch_get_documents_request(
+fn new_batch_get_documents_request(
     project_id: String,
     document_paths: Vec<String>,
     field_mask: Option<Vec<String>>,
-    transaction: Option<Vec<u8>>,
+    consistency: ReadConsistency,
 ) -> BatchGetDocumentsRequest {
-    use batch_get_documents_request::ConsistencySelector::Transaction;
+    use batch_get_documents_request::ConsistencySelector::{ReadTime, Transaction};
 
     debug_assert!(validate_partial_document_paths(&document_paths));
 
@@ -131,11 +550,11 @@ pub(super) fn new_batch_get_documents_request(
             .map(|each_path| fmt_document_path(project_id.as_str(), each_path))
             .collect(),
         mask: to_document_mask(field_mask),
-        consistency_selector: transaction.map(|id| Transaction(id)),
+        consistency_selector: consistency.into_selector(Transaction, ReadTime),
     }
 }
 
-pub(super) fn new_update_document_request<T: Into<HashMap<String, Value>>>(
+fn new_update_document_request<T: Into<HashMap<String, Value>>>(
     project_id: String,
     document_path: String,
     values: T,
@@ -152,7 +571,7 @@ pub(super) fn new_update_document_request<T: Into<HashMap<String, Value>>>(
     }
 }
 
-pub(super) fn new_create_document_request<T: Into<HashMap<String, Value>>>(
+fn new_create_document_request<T: Into<HashMap<String, Value>>>(
     project_id: String,
     parent_path: String,
     collection_id: String,
@@ -181,11 +600,63 @@ fn to_document_mask(mask: Option<Vec<String>>) -> Option<DocumentMask> {
     mask.map(|ms| DocumentMask { field_paths: ms })
 }
 
+fn array_value_from_fvalues(values: Vec<FValue>) -> ArrayValue {
+    ArrayValue {
+        values: values.into_iter().map(|v| v.to_grpc_value()).collect(),
+    }
+}
+
+fn to_field_transform(field_path: String, transform: FTransform) -> FieldTransform {
+    use field_transform::{ServerValue, TransformType};
+    let transform_type = match transform {
+        FTransform::ServerTimestamp => {
+            TransformType::SetToServerValue(ServerValue::RequestTime as i32)
+        }
+        FTransform::Increment(v) => TransformType::Increment(v.to_grpc_value()),
+        FTransform::ArrayUnion(values) => {
+            TransformType::AppendMissingElements(array_value_from_fvalues(values))
+        }
+        FTransform::ArrayRemove(values) => {
+            TransformType::RemoveAllFromArray(array_value_from_fvalues(values))
+        }
+    };
+    FieldTransform {
+        field_path,
+        transform_type: Some(transform_type),
+    }
+}
+
+/// the condition of the document for the write to be applied.
+/// the write fails with FAILED_PRECONDITION (or ALREADY_EXISTS, NOT_FOUND) otherwise.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WritePrecondition {
+    Exists(bool),
+    /// the document was last updated at the time. (e.g. the `update_time` of the document read)
+    UpdateTime(SystemTime),
+}
+
+impl From<WritePrecondition> for Precondition {
+    fn from(precondition: WritePrecondition) -> Self {
+        use precondition::ConditionType;
+        let condition_type = match precondition {
+            WritePrecondition::Exists(exists) => ConditionType::Exists(exists),
+            WritePrecondition::UpdateTime(update_time) => {
+                ConditionType::UpdateTime(Timestamp::from(update_time))
+            }
+        };
+        Precondition {
+            condition_type: Some(condition_type),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct DocumentWriteOperation {
     document_path: String,
     operation: WriteOperation,
     update_field_mask: Option<Vec<String>>,
+    update_transforms: Vec<(String, FTransform)>,
+    precondition: Option<WritePrecondition>,
 }
 
 impl DocumentWriteOperation {
@@ -199,16 +670,38 @@ impl DocumentWriteOperation {
             document_path: format!(
                 "{}/{}/{}",
                 parent_path
-                    .map(|path| format!("/{}", path))
+                    .map(|path| format!("/{}", path.trim_start_matches('/')))
                     .unwrap_or("".to_owned()),
                 collection_id,
                 doc_id
             ),
             operation: WriteOperation::Create(fields.into()),
             update_field_mask: None,
+            update_transforms: Vec::new(),
+            precondition: None,
         }
     }
 
+    /// create with the random document id. the id is available with `document_id()`
+    pub fn new_create_auto_id<T: Into<HashMap<String, Value>>>(
+        parent_path: Option<String>,
+        collection_id: String,
+        fields: T,
+    ) -> Self {
+        Self::new_create(parent_path, collection_id, new_auto_id(), fields)
+    }
+
+    pub fn document_path(&self) -> &str {
+        &self.document_path
+    }
+
+    pub fn document_id(&self) -> &str {
+        self.document_path
+            .rsplit('/')
+            .next()
+            .unwrap_or(&self.document_path)
+    }
+
     pub fn new_upsert<T: Into<HashMap<String, Value>>>(document_path: String, fields: T) -> Self {
         debug_assert!(validate_partial_document_path(&document_path));
 
@@ -216,6 +709,8 @@ impl DocumentWriteOperation {
             document_path,
             operation: WriteOperation::Update(fields.into()),
             update_field_mask: None,
+            update_transforms: Vec::new(),
+            precondition: None,
         }
     }
 
@@ -230,6 +725,8 @@ impl DocumentWriteOperation {
             document_path,
             operation: WriteOperation::Update(fields.into()),
             update_field_mask,
+            update_transforms: Vec::new(),
+            precondition: None,
         }
     }
 
@@ -240,9 +737,160 @@ impl DocumentWriteOperation {
             document_path,
             operation: WriteOperation::Delete,
             update_field_mask: None,
+            update_transforms: Vec::new(),
+            precondition: None,
+        }
+    }
+
+    /// transforms applied on the server after the document is written.
+    pub fn with_update_transforms(mut self, update_transforms: Vec<(String, FTransform)>) -> Self {
+        self.update_transforms = update_transforms;
+        self
+    }
+
+    pub fn update_transforms(&self) -> &[(String, FTransform)] {
+        &self.update_transforms
+    }
+
+    pub fn add_update_transform<F: Into<String>>(&mut self, field_path: F, transform: FTransform) {
+        self.update_transforms.push((field_path.into(), transform))
+    }
+
+    pub fn with_precondition(self, precondition: WritePrecondition) -> Self {
+        Self {
+            precondition: Some(precondition),
+            ..self
+        }
+    }
+
+    pub fn precondition(&self) -> Option<&WritePrecondition> {
+        self.precondition.as_ref()
+    }
+
+    /// the empty document created only if it doesn't exist yet. committed together with
+    /// a non idempotent write (e.g. `Increment`), the retried commit fails with ALREADY_EXISTS
+    /// instead of applying the write twice.
+    pub fn new_dedup_token(token_document_path: String) -> Self {
+        Self::new_upsert(token_document_path, HashMap::new())
+            .with_precondition(WritePrecondition::Exists(false))
+    }
+
+    /// whether applying the write twice has the same result as applying it once.
+    /// `Increment` is not idempotent unless the precondition fails the second write.
+    pub fn is_idempotent(&self) -> bool {
+        match self.precondition {
+            Some(WritePrecondition::UpdateTime(_)) | Some(WritePrecondition::Exists(false)) => true,
+            _ => !self
+                .update_transforms
+                .iter()
+                .any(|(_, transform)| matches!(transform, FTransform::Increment(_))),
+        }
+    }
+
+    /// the fields written. None for the deletes.
+    pub fn fields(&self) -> Option<&HashMap<String, Value>> {
+        match &self.operation {
+            WriteOperation::Create(values) | WriteOperation::Update(values) => Some(values),
+            WriteOperation::Delete => None,
+        }
+    }
+
+    pub fn update_field_mask(&self) -> Option<&[String]> {
+        self.update_field_mask.as_deref()
+    }
+
+    /// "create", "update" or "delete"
+    pub fn kind(&self) -> &'static str {
+        match self.operation {
+            WriteOperation::Create(_) => "create",
+            WriteOperation::Update(_) => "update",
+            WriteOperation::Delete => "delete",
         }
     }
 
+    /// the size of the write estimated by `size_calculator`, with the paths of the update mask.
+    pub fn estimated_size(&self) -> usize {
+        write_size(
+            &self.document_path,
+            self.fields(),
+            self.update_field_mask.as_deref(),
+        )
+    }
+
+    /// split the masked update estimated over `max_bytes` into the masked updates of the same
+    /// document, each of the paths of the mask (and the fields at them) up to `max_bytes`.
+    /// commit them one by one (e.g. by `FirestoreClient::commit_split_update`) when the update is
+    /// over `MAX_REQUEST_SIZE`, as a commit of all of them is as large as `self`.
+    /// the first one checks the precondition (the others require the document to exist) and the
+    /// last one applies the transforms.
+    pub fn split_update_mask(self, max_bytes: usize) -> Result<Vec<DocumentWriteOperation>> {
+        let estimated_size = self.estimated_size();
+        let (values, mask) = match (self.operation, self.update_field_mask) {
+            (WriteOperation::Update(values), Some(mask)) if estimated_size > max_bytes => {
+                (values, mask)
+            }
+            (operation, update_field_mask) => {
+                return Ok(vec![DocumentWriteOperation {
+                    operation,
+                    update_field_mask,
+                    ..self
+                }])
+            }
+        };
+
+        let fields = FFields::from(values);
+        // a path larger than max_bytes by itself is written alone
+        let base_size = document_path_size(&self.document_path) + DOCUMENT_ADDITIONAL_BYTES;
+        let mut chunks: Vec<&[String]> = Vec::new();
+        let (mut start, mut chunk_size) = (0, base_size);
+        for (i, path) in mask.iter().enumerate() {
+            // the path in the mask and (approximately) in the fields
+            let path_size =
+                string_size(path) * 2 + fields.get_path(path).map(value_size).unwrap_or(0);
+            if i > start && chunk_size + path_size > max_bytes {
+                chunks.push(&mask[start..i]);
+                start = i;
+                chunk_size = base_size;
+            }
+            chunk_size += path_size;
+        }
+        chunks.push(&mask[start..]);
+
+        let chunk_num = chunks.len();
+        let document_path = self.document_path;
+        let first_precondition = self.precondition;
+        let mut update_transforms = Some(self.update_transforms);
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, paths)| {
+                let mut chunk_fields = FFields::empty();
+                for path in paths {
+                    if let Some(value) = fields.get_path(path) {
+                        chunk_fields.set_path(path, value.clone())?;
+                    }
+                }
+                let precondition = match (index, &first_precondition) {
+                    (0, precondition) => precondition.clone(),
+                    (_, Some(_)) => Some(WritePrecondition::Exists(true)),
+                    (_, None) => None,
+                };
+                let update_transforms = if index + 1 == chunk_num {
+                    update_transforms.take().unwrap_or_default()
+                } else {
+                    Vec::new()
+                };
+                Ok(DocumentWriteOperation {
+                    document_path: document_path.clone(),
+                    operation: WriteOperation::Update(chunk_fields.to_grpc_fields()),
+                    update_field_mask: Some(paths.to_vec()),
+                    update_transforms,
+                    precondition,
+                })
+            })
+            .collect()
+    }
+
     fn into_operation_and_mask(self, project_id: String) -> (Operation, Option<DocumentMask>) {
         let full_document_path = fmt_document_path(project_id, self.document_path);
         let operation = match self.operation {
@@ -256,14 +904,19 @@ impl DocumentWriteOperation {
         };
         (operation, to_document_mask(self.update_field_mask))
     }
-    fn into_write(self, project_id: String) -> Write {
+    fn into_write(mut self, project_id: String) -> Write {
+        let update_transforms = std::mem::take(&mut self.update_transforms)
+            .into_iter()
+            .map(|(field_path, transform)| to_field_transform(field_path, transform))
+            .collect();
+        let current_document = self.precondition.take().map(Precondition::from);
         let (operation, mask) = self.into_operation_and_mask(project_id);
 
         Write {
             operation: Some(operation),
             update_mask: mask,
-            update_transforms: Vec::new(),
-            current_document: None,
+            update_transforms,
+            current_document,
         }
     }
 
@@ -275,48 +928,38 @@ impl DocumentWriteOperation {
     }
 }
 
-pub(super) fn new_start_stream_write_request(
-    project_id: String,
-    stream_id: Option<String>,
-) -> WriteRequest {
-    WriteRequest {
-        database: project_and_default_database(project_id),
-        writes: Vec::new(),
-        labels: HashMap::new(),
-        stream_id: stream_id.unwrap_or("".to_owned()),
-        stream_token: Vec::new(),
-    }
-}
-
-pub(super) fn new_finish_stream_write_request(
+/// the first request of the write stream. empty `stream_id` and `stream_token` open a new stream,
+/// the ones of the previous stream resume it.
+fn new_start_stream_write_request(
     project_id: String,
+    stream_id: String,
     stream_token: Vec<u8>,
 ) -> WriteRequest {
     WriteRequest {
         database: project_and_default_database(project_id),
         writes: Vec::new(),
         labels: HashMap::new(),
-        stream_id: "".to_owned(),
+        stream_id,
         stream_token,
     }
 }
 
-pub(super) fn new_stream_write_request(
+/// the database and the stream id are only set in the first request.
+fn new_stream_write_request(
     project_id: String,
     operations: Vec<DocumentWriteOperation>,
-    stream_id: String,
     stream_token: Vec<u8>,
 ) -> WriteRequest {
     WriteRequest {
-        database: project_and_default_database(project_id.clone()),
+        database: "".to_owned(),
         writes: DocumentWriteOperation::into_writes(project_id, operations),
         labels: HashMap::new(),
-        stream_id,
+        stream_id: "".to_owned(),
         stream_token,
     }
 }
 
-pub(super) fn new_batch_write_request(
+fn new_batch_write_request(
     project_id: String,
     operations: Vec<DocumentWriteOperation>,
 ) -> BatchWriteRequest {
@@ -327,6 +970,113 @@ pub(super) fn new_batch_write_request(
     }
 }
 
+/// reject the writes estimated over `MAX_REQUEST_SIZE` in total, before the request fails with
+/// an opaque INVALID_ARGUMENT. the error of a masked update over the limit tells to split it.
+pub(crate) fn check_request_size(operations: &[DocumentWriteOperation]) -> Result<()> {
+    check_write_sizes(
+        operations
+            .iter()
+            .map(|ope| {
+                let masked = ope.update_field_mask.is_some();
+                (ope.document_path.as_str(), ope.estimated_size(), masked)
+            })
+            .collect(),
+    )
+}
+
+/// `check_request_size` of UpdateDocument.
+pub(crate) fn check_update_size(
+    document_path: &str,
+    document: &HashMap<String, Value>,
+    update_field_mask: Option<&[String]>,
+) -> Result<()> {
+    let size = write_size(document_path, Some(document), update_field_mask);
+    check_write_sizes(vec![(document_path, size, update_field_mask.is_some())])
+}
+
+fn write_size(
+    document_path: &str,
+    values: Option<&HashMap<String, Value>>,
+    update_field_mask: Option<&[String]>,
+) -> usize {
+    let mask: usize = update_field_mask
+        .into_iter()
+        .flatten()
+        .map(|path| string_size(path))
+        .sum();
+    document_write_size(document_path, values) + mask
+}
+
+/// the size of the document written with the values, or of the document path only without
+/// them. (e.g. a delete)
+pub(crate) fn document_write_size(
+    document_path: &str,
+    values: Option<&HashMap<String, Value>>,
+) -> usize {
+    let fields = values
+        .map(|values| grpc_fields_size(values) + DOCUMENT_ADDITIONAL_BYTES)
+        .unwrap_or(0);
+    document_path_size(document_path) + fields
+}
+
+/// (document path, estimated size, masked) of the writes.
+fn check_write_sizes(writes: Vec<(&str, usize, bool)>) -> Result<()> {
+    let request_size: usize = writes.iter().map(|(_, size, _)| size).sum();
+    if request_size <= MAX_REQUEST_SIZE {
+        return Ok(());
+    }
+    let message = match writes
+        .into_iter()
+        .find(|(_, size, masked)| *masked && *size > MAX_REQUEST_SIZE)
+    {
+        Some((document_path, size, _)) => format!(
+            "the masked update of {} is {} bytes, over the max request size {}. \
+             commit it with `commit_split_update`, which splits it into multiple commits",
+            document_path, size, MAX_REQUEST_SIZE
+        ),
+        None => format!(
+            "the writes are {} bytes, over the max request size {}. write them in multiple requests",
+            request_size, MAX_REQUEST_SIZE
+        ),
+    };
+    Err(FirestoreError::invalid_argument(message))
+}
+
+/// the max length of the keys and the values of the labels.
+pub const MAX_LABEL_LEN: usize = 63;
+
+/// the labels of the requests must be lowercase letters, digits, '_' and '-' up to
+/// `MAX_LABEL_LEN` characters, and the keys must start with a letter.
+pub(crate) fn validate_labels(labels: &HashMap<String, String>) -> Result<()> {
+    let is_label_char =
+        |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-';
+    for (key, value) in labels {
+        let valid_key = key.len() <= MAX_LABEL_LEN
+            && key.starts_with(|c: char| c.is_ascii_lowercase())
+            && key.chars().all(is_label_char);
+        let valid_value = value.len() <= MAX_LABEL_LEN && value.chars().all(is_label_char);
+        if !valid_key || !valid_value {
+            return Err(FirestoreError::invalid_argument(format!(
+                "invalid label {}={}",
+                key, value
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// add the labels not set by the request factory.
+pub(crate) fn add_labels(
+    request_labels: &mut HashMap<String, String>,
+    labels: &HashMap<String, String>,
+) {
+    for (key, value) in labels {
+        request_labels
+            .entry(key.clone())
+            .or_insert_with(|| value.clone());
+    }
+}
+
 fn new_document<T: Into<HashMap<String, Value>>>(name: String, fields: T) -> Document {
     Document {
         name,
@@ -336,22 +1086,37 @@ fn new_document<T: Into<HashMap<String, Value>>>(name: String, fields: T) -> Doc
     }
 }
 
-pub(super) fn new_query_request(
+fn new_query_request(
     project_id: String,
     parent_path: String,
     query: StructuredQuery,
-    transaction: Option<Vec<u8>>,
+    consistency: ReadConsistency,
 ) -> RunQueryRequest {
-    use run_query_request::ConsistencySelector::Transaction;
+    use run_query_request::ConsistencySelector::{ReadTime, Transaction};
     use run_query_request::QueryType;
     RunQueryRequest {
         parent: fmt_document_path(project_id, parent_path),
         query_type: Some(QueryType::StructuredQuery(query)),
-        consistency_selector: transaction.map(|id| Transaction(id)),
+        consistency_selector: consistency.into_selector(Transaction, ReadTime),
+    }
+}
+
+fn new_aggregation_query_request(
+    project_id: String,
+    parent_path: String,
+    query: StructuredAggregationQuery,
+    consistency: ReadConsistency,
+) -> RunAggregationQueryRequest {
+    use run_aggregation_query_request::ConsistencySelector::{ReadTime, Transaction};
+    use run_aggregation_query_request::QueryType;
+    RunAggregationQueryRequest {
+        parent: fmt_document_path(project_id, parent_path),
+        query_type: Some(QueryType::StructuredAggregationQuery(query)),
+        consistency_selector: consistency.into_selector(Transaction, ReadTime),
     }
 }
 
-pub(super) fn new_partition_query_request(
+fn new_partition_query_request(
     project_id: String,
     document_path: String,
     query: StructuredQuery,
@@ -369,31 +1134,39 @@ pub(super) fn new_partition_query_request(
     }
 }
 
-///TODO(tacogips) need retry_transaction?
-pub(super) fn new_begin_transaction_request(
+fn new_listen_request(project_id: String, target: Target) -> ListenRequest {
+    ListenRequest {
+        database: project_and_default_database(project_id),
+        labels: HashMap::new(),
+        target_change: Some(listen_request::TargetChange::AddTarget(target)),
+    }
+}
+
+/// the path of the documents root. e.g. the parent of collection group queries.
+pub fn documents_root_path(project_id: String) -> String {
+    format!("{}/documents", project_and_default_database(project_id))
+}
+
+fn new_begin_transaction_request(
     project_id: String,
     read_only_time: Option<SystemTime>,
 ) -> BeginTransactionRequest {
-    let option = match read_only_time {
-        Some(read_only_time) => TransactionOptions {
-            mode: Some(transaction_options::Mode::ReadOnly(
-                transaction_options::ReadOnly {
-                    consistency_selector: Some(
-                        transaction_options::read_only::ConsistencySelector::ReadTime(
-                            Timestamp::from(read_only_time),
-                        ),
-                    ),
-                },
-            )),
-        },
+    if read_only_time.is_some() {
+        return new_begin_read_only_transaction_request(project_id, read_only_time);
+    }
 
-        None => TransactionOptions {
-            mode: Some(transaction_options::Mode::ReadWrite(
-                transaction_options::ReadWrite {
-                    retry_transaction: Vec::new(),
-                },
-            )),
-        },
+    new_begin_read_write_transaction_request(project_id, Vec::new())
+}
+
+/// `retry_transaction` is the id of the aborted transaction to retry, or empty.
+fn new_begin_read_write_transaction_request(
+    project_id: String,
+    retry_transaction: Vec<u8>,
+) -> BeginTransactionRequest {
+    let option = TransactionOptions {
+        mode: Some(transaction_options::Mode::ReadWrite(
+            transaction_options::ReadWrite { retry_transaction },
+        )),
     };
 
     BeginTransactionRequest {
@@ -402,7 +1175,30 @@ pub(super) fn new_begin_transaction_request(
     }
 }
 
-pub(super) fn new_commit_request(
+/// reads the latest data if `read_time` is None.
+fn new_begin_read_only_transaction_request(
+    project_id: String,
+    read_time: Option<SystemTime>,
+) -> BeginTransactionRequest {
+    let option = TransactionOptions {
+        mode: Some(transaction_options::Mode::ReadOnly(
+            transaction_options::ReadOnly {
+                consistency_selector: read_time.map(|read_time| {
+                    transaction_options::read_only::ConsistencySelector::ReadTime(Timestamp::from(
+                        read_time,
+                    ))
+                }),
+            },
+        )),
+    };
+
+    BeginTransactionRequest {
+        database: project_and_default_database(project_id),
+        options: Some(option),
+    }
+}
+
+fn new_commit_request(
     project_id: String,
     operations: Vec<DocumentWriteOperation>,
     transaction: Option<Vec<u8>>,
@@ -414,9 +1210,311 @@ pub(super) fn new_commit_request(
     }
 }
 
-pub(super) fn new_rollback_request(project_id: String, transaction: Vec<u8>) -> RollbackRequest {
+/// a page of the indexes of the collection group. used to probe the admin permissions.
+fn new_list_indexes_request(project_id: String, collection_id: String) -> ListIndexesRequest {
+    ListIndexesRequest {
+        parent: format!(
+            "{}/collectionGroups/{}",
+            project_and_default_database(project_id),
+            collection_id
+        ),
+        filter: "".to_owned(),
+        page_size: 1,
+        page_token: "".to_owned(),
+    }
+}
+
+fn new_rollback_request(project_id: String, transaction: Vec<u8>) -> RollbackRequest {
     RollbackRequest {
         database: project_and_default_database(project_id),
         transaction,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        add_labels, check_request_size, list_documents_request, new_auto_id,
+        new_list_document_request, new_query_request, new_start_stream_write_request,
+        new_stream_write_request, precondition, run_query_request, validate_labels,
+        BatchWriteRequest, DocumentWriteOperation, HashMap, ListDocumentsOptions, Operation,
+        ReadConsistency, RequestFactory, StructuredQuery, Timestamp, V1RequestFactory,
+        WritePrecondition, MAX_LABEL_LEN,
+    };
+    use super::{timestamp, validate_read_time};
+    use crate::firestore::value::{FFields, FTransform};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn auto_id_test() {
+        let id = new_auto_id();
+        assert_eq!(20, id.len());
+        assert!(id.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(id, new_auto_id());
+
+        let ope = DocumentWriteOperation::new_create_auto_id(
+            Some("/coll_1/doc_1".to_owned()),
+            "coll_2".to_owned(),
+            FFields::empty(),
+        );
+        assert_eq!(20, ope.document_id().len());
+        assert!(ope.document_path().starts_with("/coll_1/doc_1/coll_2/"));
+    }
+
+    #[test]
+    fn list_document_request_options_test() {
+        let read_time = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let request = new_list_document_request(
+            "p".to_owned(),
+            "/coll_1/doc_1".to_owned(),
+            "coll_2".to_owned(),
+            "".to_owned(),
+            ListDocumentsOptions::default()
+                .with_show_missing(true)
+                .with_page_size(10)
+                .with_consistency(ReadConsistency::ReadTime(read_time)),
+        );
+        assert!(request.show_missing);
+        assert_eq!(10, request.page_size);
+        assert_eq!(
+            Some(list_documents_request::ConsistencySelector::ReadTime(
+                Timestamp::from(read_time)
+            )),
+            request.consistency_selector
+        );
+        assert_eq!(
+            ReadConsistency::Transaction(vec![1]),
+            ReadConsistency::from(Some(vec![1]))
+        );
+    }
+
+    #[test]
+    fn query_request_consistency_test() {
+        let read_time = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let request = new_query_request(
+            "p".to_owned(),
+            "".to_owned(),
+            StructuredQuery::default(),
+            ReadConsistency::ReadTime(read_time),
+        );
+        assert_eq!(
+            Some(run_query_request::ConsistencySelector::ReadTime(
+                Timestamp::from(read_time)
+            )),
+            request.consistency_selector
+        );
+
+        let request = new_query_request(
+            "p".to_owned(),
+            "".to_owned(),
+            StructuredQuery::default(),
+            ReadConsistency::Default,
+        );
+        assert_eq!(None, request.consistency_selector);
+    }
+
+    #[test]
+    fn write_precondition_test() {
+        let increment = DocumentWriteOperation::new_update(
+            "/coll_1/doc_1".to_owned(),
+            FFields::empty(),
+            Some(Vec::new()),
+        )
+        .with_update_transforms(vec![("count".to_owned(), FTransform::Increment(1.into()))]);
+        assert!(!increment.is_idempotent());
+        assert!(!increment
+            .clone()
+            .with_precondition(WritePrecondition::Exists(true))
+            .is_idempotent());
+
+        let update_time = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let guarded = increment.with_precondition(WritePrecondition::UpdateTime(update_time));
+        assert!(guarded.is_idempotent());
+        let write = guarded.into_write("p".to_owned());
+        assert_eq!(
+            Some(precondition::ConditionType::UpdateTime(Timestamp::from(
+                update_time
+            ))),
+            write.current_document.unwrap().condition_type
+        );
+
+        let token = DocumentWriteOperation::new_dedup_token("/tokens/t1".to_owned());
+        assert_eq!(
+            Some(&WritePrecondition::Exists(false)),
+            token.precondition()
+        );
+        assert!(DocumentWriteOperation::new_delete("/coll_1/doc_1".to_owned()).is_idempotent());
+    }
+
+    #[test]
+    fn request_factory_test() {
+        struct Labeled;
+        impl RequestFactory for Labeled {
+            fn new_batch_write_request(
+                &self,
+                project_id: String,
+                operations: Vec<DocumentWriteOperation>,
+            ) -> BatchWriteRequest {
+                let mut request = V1RequestFactory.new_batch_write_request(project_id, operations);
+                request.labels.insert("job".to_owned(), "import".to_owned());
+                request
+            }
+        }
+
+        let factory: Box<dyn RequestFactory> = Box::new(Labeled);
+        let request = factory.new_batch_write_request(
+            "p".to_owned(),
+            vec![DocumentWriteOperation::new_delete("/c/d".to_owned())],
+        );
+        assert_eq!(Some(&"import".to_owned()), request.labels.get("job"));
+        assert_eq!("projects/p/databases/(default)", request.database);
+
+        // not overridden
+        let request = factory.new_rollback_request("p".to_owned(), vec![1]);
+        assert_eq!(
+            V1RequestFactory.new_rollback_request("p".to_owned(), vec![1]),
+            request
+        );
+    }
+
+    #[test]
+    fn labels_test() {
+        let mut labels = HashMap::new();
+        labels.insert("team".to_owned(), "billing".to_owned());
+        labels.insert("job".to_owned(), "nightly-export_1".to_owned());
+        assert!(validate_labels(&labels).is_ok());
+
+        let mut request_labels = HashMap::new();
+        request_labels.insert("job".to_owned(), "import".to_owned());
+        add_labels(&mut request_labels, &labels);
+        assert_eq!(Some(&"import".to_owned()), request_labels.get("job"));
+        assert_eq!(Some(&"billing".to_owned()), request_labels.get("team"));
+
+        for (key, value) in [
+            ("Team", "billing"),
+            ("1team", "billing"),
+            ("team", "Billing"),
+            ("team", "a.b"),
+        ] {
+            let mut labels = HashMap::new();
+            labels.insert(key.to_owned(), value.to_owned());
+            assert!(validate_labels(&labels).is_err(), "{}={}", key, value);
+        }
+        let mut labels = HashMap::new();
+        labels.insert("team".to_owned(), "x".repeat(MAX_LABEL_LEN + 1));
+        assert!(validate_labels(&labels).is_err());
+    }
+
+    #[test]
+    fn stream_write_request_test() {
+        let start = new_start_stream_write_request("p".to_owned(), "".to_owned(), Vec::new());
+        assert_eq!("projects/p/databases/(default)", start.database);
+        assert!(start.writes.is_empty());
+        assert!(start.stream_token.is_empty());
+
+        let request = new_stream_write_request(
+            "p".to_owned(),
+            vec![DocumentWriteOperation::new_delete(
+                "/coll_1/doc_1".to_owned(),
+            )],
+            vec![1, 2],
+        );
+        assert_eq!("", request.database);
+        assert_eq!("", request.stream_id);
+        assert_eq!(vec![1, 2], request.stream_token);
+        assert_eq!(1, request.writes.len());
+    }
+    #[test]
+    fn validate_read_time_test() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000) + Duration::from_millis(500);
+        assert!(validate_read_time(now, now).is_ok());
+        assert!(validate_read_time(now - Duration::from_secs(30 * 60), now).is_ok());
+        assert!(validate_read_time(now + Duration::from_secs(1), now).is_err());
+
+        let yesterday = now - Duration::from_secs(24 * 60 * 60);
+        assert!(validate_read_time(yesterday, now).is_err());
+        assert!(validate_read_time(timestamp::truncate_to_minute(yesterday), now).is_ok());
+
+        let last_month = now - Duration::from_secs(30 * 24 * 60 * 60);
+        assert!(validate_read_time(timestamp::truncate_to_minute(last_month), now).is_err());
+    }
+
+    #[test]
+    fn split_update_mask_test() {
+        let mut fields = FFields::empty();
+        fields.add("a", 1i64);
+        fields.add("b", 2i64);
+        fields.set_path("c.d", 3i64).unwrap();
+        let ope = DocumentWriteOperation::new_update(
+            "/coll_1/doc_1".to_owned(),
+            fields.to_grpc_fields(),
+            Some(vec![
+                "a".to_owned(),
+                "b".to_owned(),
+                "c.d".to_owned(),
+                "e".to_owned(),
+            ]),
+        )
+        .with_update_transforms(vec![("n".to_owned(), FTransform::Increment(1i64.into()))])
+        .with_precondition(WritePrecondition::Exists(false));
+        assert!(check_request_size(std::slice::from_ref(&ope)).is_ok());
+
+        // each split update is 61 bytes without the paths. "a" and "b" are 12 bytes, "c.d" 16
+        let split = ope.clone().split_update_mask(90).unwrap();
+        assert_eq!(2, split.len());
+        assert_eq!(
+            Some(&WritePrecondition::Exists(false)),
+            split[0].precondition()
+        );
+        assert_eq!(
+            Some(&WritePrecondition::Exists(true)),
+            split[1].precondition()
+        );
+
+        let writes = DocumentWriteOperation::into_writes("p".to_owned(), split);
+        assert_eq!(
+            vec!["a".to_owned(), "b".to_owned()],
+            writes[0].update_mask.clone().unwrap().field_paths
+        );
+        assert!(writes[0].update_transforms.is_empty());
+        assert_eq!(1, writes[1].update_transforms.len());
+        // "e" is in the mask without the value, to delete it
+        assert_eq!(
+            vec!["c.d".to_owned(), "e".to_owned()],
+            writes[1].update_mask.clone().unwrap().field_paths
+        );
+        match &writes[1].operation {
+            Some(Operation::Update(document)) => {
+                let fields = FFields::from(document.fields.clone());
+                assert_eq!(vec!["c"], fields.keys().collect::<Vec<_>>());
+                assert_eq!(Some(&3i64.into()), fields.get_path("c.d"));
+            }
+            other => panic!("unexpected operation {:?}", other),
+        }
+
+        // the small update is kept as it is
+        assert_eq!(1, ope.split_update_mask(1024).unwrap().len());
+    }
+
+    #[test]
+    fn check_request_size_test() {
+        let mut fields = FFields::empty();
+        fields.add("a", "x".repeat(6 * 1024 * 1024));
+        fields.add("b", "x".repeat(6 * 1024 * 1024));
+        let ope = DocumentWriteOperation::new_update(
+            "/coll_1/doc_1".to_owned(),
+            fields.to_grpc_fields(),
+            Some(vec!["a".to_owned(), "b".to_owned()]),
+        );
+        let message = check_request_size(std::slice::from_ref(&ope))
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("commit_split_update"), "{}", message);
+
+        let split = ope.split_update_mask(super::MAX_REQUEST_SIZE).unwrap();
+        assert_eq!(2, split.len());
+        assert!(split
+            .iter()
+            .all(|ope| check_request_size(std::slice::from_ref(ope)).is_ok()));
+    }
+}
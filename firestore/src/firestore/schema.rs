@@ -0,0 +1,378 @@
+use super::value::{FFields, FMap, FValue};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// the documents sampled by `FirestoreClient::infer_schema` by default.
+pub const SCHEMA_SAMPLE_NUM: i32 = 100;
+
+/// the type of a field observed in the sampled documents.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldType {
+    Bool,
+    Int,
+    /// also the fields of both integers and doubles.
+    Double,
+    Str,
+    Bytes,
+    Timestamp,
+    /// the embedding of the dimension.
+    Vector(usize),
+    /// the element type, None if all the arrays are empty or only have nulls.
+    Array(Option<Box<FieldType>>),
+    Map(DocumentSchema),
+    /// the values of different types.
+    Mixed,
+}
+
+impl FieldType {
+    fn of(value: &FValue) -> Option<FieldType> {
+        let field_type = match value {
+            FValue::NullValue => return None,
+            FValue::Bool(_) => FieldType::Bool,
+            FValue::Int(_) => FieldType::Int,
+            FValue::Double(_) => FieldType::Double,
+            FValue::Str(_) => FieldType::Str,
+            FValue::Bytes(_) => FieldType::Bytes,
+            FValue::Timestamp(_) => FieldType::Timestamp,
+            FValue::Vector(vs) => FieldType::Vector(vs.len()),
+            FValue::Array(values) => FieldType::Array(
+                values
+                    .iter()
+                    .filter_map(FieldType::of)
+                    .reduce(FieldType::merge)
+                    .map(Box::new),
+            ),
+            FValue::Map(m) => {
+                let mut schema = DocumentSchema::new();
+                schema.observe_map(m);
+                FieldType::Map(schema)
+            }
+        };
+        Some(field_type)
+    }
+
+    fn merge(self, other: FieldType) -> FieldType {
+        match (self, other) {
+            (FieldType::Int, FieldType::Double) | (FieldType::Double, FieldType::Int) => {
+                FieldType::Double
+            }
+            (FieldType::Array(l), FieldType::Array(r)) => FieldType::Array(match (l, r) {
+                (Some(l), Some(r)) => Some(Box::new(l.merge(*r))),
+                (l, r) => l.or(r),
+            }),
+            (FieldType::Map(mut l), FieldType::Map(r)) => {
+                l.merge(r);
+                FieldType::Map(l)
+            }
+            (l, r) if l == r => l,
+            _ => FieldType::Mixed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldSchema {
+    /// None if only nulls are observed.
+    pub field_type: Option<FieldType>,
+    /// the number of the documents (or the maps) having the field.
+    pub present_num: usize,
+    pub null_num: usize,
+}
+
+/// the fields observed in the sampled documents (or in the maps of a field).
+///
+/// ```ignore
+/// let schema = client.infer_schema(None, "users".to_owned(), SCHEMA_SAMPLE_NUM).await?;
+/// println!("{}", schema.to_rust_struct("User"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DocumentSchema {
+    /// the number of the documents (or the maps) observed.
+    pub sample_num: usize,
+    pub fields: BTreeMap<String, FieldSchema>,
+}
+
+impl DocumentSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn infer<'a, I>(documents: I) -> Self
+    where
+        I: IntoIterator<Item = &'a FFields>,
+    {
+        let mut schema = Self::new();
+        for document in documents {
+            schema.observe(document);
+        }
+        schema
+    }
+
+    pub fn observe(&mut self, document: &FFields) {
+        self.sample_num += 1;
+        for key in document.keys() {
+            if let Some(value) = document.get(key) {
+                self.observe_field(key, value);
+            }
+        }
+    }
+
+    fn observe_map(&mut self, m: &FMap<FValue>) {
+        self.sample_num += 1;
+        for (key, value) in m.iter() {
+            self.observe_field(key, value);
+        }
+    }
+
+    fn observe_field(&mut self, key: &str, value: &FValue) {
+        let field = self
+            .fields
+            .entry(key.to_owned())
+            .or_insert_with(|| FieldSchema {
+                field_type: None,
+                present_num: 0,
+                null_num: 0,
+            });
+        field.present_num += 1;
+        match FieldType::of(value) {
+            Some(field_type) => {
+                field.field_type = Some(match field.field_type.take() {
+                    Some(observed) => observed.merge(field_type),
+                    None => field_type,
+                })
+            }
+            None => field.null_num += 1,
+        }
+    }
+
+    fn merge(&mut self, other: DocumentSchema) {
+        self.sample_num += other.sample_num;
+        for (key, other_field) in other.fields {
+            match self.fields.remove(&key) {
+                Some(field) => {
+                    let field_type = match (field.field_type, other_field.field_type) {
+                        (Some(l), Some(r)) => Some(l.merge(r)),
+                        (l, r) => l.or(r),
+                    };
+                    self.fields.insert(
+                        key,
+                        FieldSchema {
+                            field_type,
+                            present_num: field.present_num + other_field.present_num,
+                            null_num: field.null_num + other_field.null_num,
+                        },
+                    );
+                }
+                None => {
+                    self.fields.insert(key, other_field);
+                }
+            }
+        }
+    }
+
+    /// whether the field is missing or null in some of the samples.
+    pub fn is_optional(&self, field: &str) -> bool {
+        self.fields
+            .get(field)
+            .map(|f| f.present_num < self.sample_num || f.null_num > 0)
+            .unwrap_or(true)
+    }
+
+    /// the struct definitions of the schema with serde attributes.
+    /// the maps are defined as the structs named `{name}{Field}`.
+    /// the bytes and the fields of mixed types are `FValue`.
+    pub fn to_rust_struct(&self, name: &str) -> String {
+        let mut structs = Vec::new();
+        self.write_structs(name, &mut structs);
+        structs.join("\n")
+    }
+
+    fn write_structs(&self, name: &str, structs: &mut Vec<String>) {
+        let mut nested = Vec::new();
+        let mut s = String::new();
+        writeln!(
+            s,
+            "#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]"
+        )
+        .unwrap();
+        writeln!(s, "pub struct {} {{", name).unwrap();
+        for (key, field) in self.fields.iter() {
+            let ident = rust_field_name(key);
+            if ident.trim_start_matches("r#") != key {
+                writeln!(s, "    #[serde(rename = \"{}\")]", key.escape_default()).unwrap();
+            }
+            let mut ty = match &field.field_type {
+                Some(field_type) => rust_type(
+                    field_type,
+                    &format!("{}{}", name, pascal_case(key)),
+                    &mut nested,
+                ),
+                None => "FValue".to_owned(),
+            };
+            if self.is_optional(key) {
+                writeln!(
+                    s,
+                    "    #[serde(default, skip_serializing_if = \"Option::is_none\")]"
+                )
+                .unwrap();
+                ty = format!("Option<{}>", ty);
+            }
+            writeln!(s, "    pub {}: {},", ident, ty).unwrap();
+        }
+        writeln!(s, "}}").unwrap();
+        structs.push(s);
+
+        for (struct_name, schema) in nested {
+            schema.write_structs(&struct_name, structs);
+        }
+    }
+}
+
+fn rust_type(
+    field_type: &FieldType,
+    struct_name: &str,
+    nested: &mut Vec<(String, DocumentSchema)>,
+) -> String {
+    match field_type {
+        FieldType::Bool => "bool".to_owned(),
+        FieldType::Int => "i64".to_owned(),
+        FieldType::Double => "f64".to_owned(),
+        FieldType::Str => "String".to_owned(),
+        FieldType::Timestamp => "std::time::SystemTime".to_owned(),
+        FieldType::Bytes | FieldType::Vector(_) | FieldType::Mixed => "FValue".to_owned(),
+        FieldType::Array(Some(element)) => {
+            format!("Vec<{}>", rust_type(element, struct_name, nested))
+        }
+        FieldType::Array(None) => "Vec<FValue>".to_owned(),
+        FieldType::Map(schema) if schema.fields.is_empty() => {
+            "std::collections::HashMap<String, FValue>".to_owned()
+        }
+        FieldType::Map(schema) => {
+            nested.push((struct_name.to_owned(), schema.clone()));
+            struct_name.to_owned()
+        }
+    }
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+    "where", "while", "abstract", "become", "box", "do", "final", "macro", "override", "priv",
+    "try", "typeof", "unsized", "virtual", "yield",
+];
+
+/// snake_case identifier of the field name. e.g. "createdAt" into "created_at"
+fn rust_field_name(key: &str) -> String {
+    let mut ident = String::new();
+    let mut prev_lower = false;
+    for c in key.chars() {
+        if c.is_ascii_uppercase() {
+            if prev_lower {
+                ident.push('_');
+            }
+            ident.push(c.to_ascii_lowercase());
+            prev_lower = false;
+        } else if c.is_ascii_alphanumeric() {
+            ident.push(c);
+            prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        } else {
+            if !ident.ends_with('_') {
+                ident.push('_');
+            }
+            prev_lower = false;
+        }
+    }
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    if RUST_KEYWORDS.contains(&ident.as_str()) {
+        ident.insert_str(0, "r#");
+    }
+    ident
+}
+
+/// e.g. "shipping_address" into "ShippingAddress"
+fn pascal_case(key: &str) -> String {
+    key.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn fields(json: serde_json::Value) -> FFields {
+        FFields::from_json(json).unwrap()
+    }
+
+    #[test]
+    fn infer_schema_test() {
+        let documents = vec![
+            fields(json!({
+                "name": "taco",
+                "score": 1,
+                "tags": [],
+                "address": {"city": "tokyo"},
+                "type": "a",
+            })),
+            fields(json!({
+                "name": "burrito",
+                "score": 1.5,
+                "tags": ["a"],
+                "address": {"city": "osaka", "zip": null},
+                "nickName": null,
+                "type": 1,
+            })),
+        ];
+        let schema = DocumentSchema::infer(&documents);
+        assert_eq!(2, schema.sample_num);
+        assert_eq!(Some(FieldType::Double), schema.fields["score"].field_type);
+        assert_eq!(
+            Some(FieldType::Array(Some(Box::new(FieldType::Str)))),
+            schema.fields["tags"].field_type
+        );
+        assert_eq!(Some(FieldType::Mixed), schema.fields["type"].field_type);
+        assert!(!schema.is_optional("name"));
+        assert!(schema.is_optional("nickName"));
+
+        let expected = r#"#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct User {
+    pub address: UserAddress,
+    pub name: String,
+    #[serde(rename = "nickName")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nick_name: Option<FValue>,
+    pub score: f64,
+    pub tags: Vec<String>,
+    pub r#type: FValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserAddress {
+    pub city: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zip: Option<FValue>,
+}
+"#;
+        assert_eq!(expected, schema.to_rust_struct("User"));
+    }
+
+    #[test]
+    fn rust_field_name_test() {
+        assert_eq!("created_at", rust_field_name("createdAt"));
+        assert_eq!("user_id", rust_field_name("user-id"));
+        assert_eq!("_1st", rust_field_name("1st"));
+        assert_eq!("r#type", rust_field_name("type"));
+        assert_eq!("ShippingAddress", pascal_case("shipping_address"));
+    }
+}
@@ -0,0 +1,218 @@
+// the facade is kept for the compatibility, see `SharedFirestoreClient`.
+#![allow(deprecated)]
+
+use super::client::{FirestoreClient, MissingDocPaths, WithTransaction};
+use super::collection::CollectionRef;
+use super::request::{DocumentWriteOperation, ReadConsistency};
+
+use super::error::Result;
+use google_cloud_grpc_proto::firestore::v1::{Document, StructuredQuery, Value, WriteResult};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// `FirestoreClient` which can be shared across tasks by `&self`.
+///
+/// deprecated as the methods of `FirestoreClient` take `&self` (each rpc runs on a clone of the
+/// grpc client, which is cheap). share a clone of `FirestoreClient` or an `Arc` of it instead.
+///
+/// ```ignore
+/// let client = SharedFirestoreClient::new(FirestoreClient::with_service_account_file(..).await?);
+/// let doc = client.get_document(path, None, None).await?;
+/// ```
+#[deprecated(note = "FirestoreClient takes &self. share a clone of FirestoreClient instead")]
+#[derive(Clone)]
+pub struct SharedFirestoreClient {
+    inner: Arc<FirestoreClient>,
+}
+
+// the facade is useless unless it can be moved into the handlers of multi thread runtimes.
+const _: fn() = || {
+    fn assert_shareable<T: Send + Sync + Clone + 'static>() {}
+    assert_shareable::<SharedFirestoreClient>();
+};
+
+impl SharedFirestoreClient {
+    pub fn new(client: FirestoreClient) -> Self {
+        Self {
+            inner: Arc::new(client),
+        }
+    }
+
+    /// a client to call the methods not provided by the facade.
+    pub fn client(&self) -> FirestoreClient {
+        (*self.inner).clone()
+    }
+
+    pub fn project_id(&self) -> &str {
+        self.inner.project_id()
+    }
+
+    pub fn collection<T>(&self, collection_id: impl Into<String>) -> CollectionRef<T>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        self.inner.collection(collection_id)
+    }
+
+    pub async fn get_document<C>(
+        &self,
+        document_path: String,
+        field_mask: Option<Vec<String>>,
+        consistency: C,
+    ) -> Result<Option<Document>>
+    where
+        C: Into<ReadConsistency>,
+    {
+        self.inner
+            .get_document(document_path, field_mask, consistency)
+            .await
+    }
+
+    pub async fn create_document<D>(
+        &self,
+        parent_path: Option<String>,
+        collection_id: String,
+        document_id: String,
+        document: D,
+    ) -> Result<Document>
+    where
+        D: Into<HashMap<String, Value>>,
+    {
+        self.inner
+            .create_document(parent_path, collection_id, document_id, document)
+            .await
+    }
+
+    pub async fn update_document<D>(
+        &self,
+        document_path: String,
+        document: D,
+        update_field_mask: Option<Vec<String>>,
+        response_field_mask: Option<Vec<String>>,
+    ) -> Result<Document>
+    where
+        D: Into<HashMap<String, Value>>,
+    {
+        self.inner
+            .update_document(
+                document_path,
+                document,
+                update_field_mask,
+                response_field_mask,
+            )
+            .await
+    }
+
+    pub async fn delete_document(&self, document_path: String) -> Result<()> {
+        self.inner.delete_document(document_path).await
+    }
+
+    pub async fn commit(
+        &self,
+        operations: Vec<DocumentWriteOperation>,
+        transaction: Option<Vec<u8>>,
+    ) -> Result<Vec<WriteResult>> {
+        self.inner.commit(operations, transaction).await
+    }
+
+    pub async fn batch_write(
+        &self,
+        operations: Vec<DocumentWriteOperation>,
+    ) -> Result<Vec<WriteResult>> {
+        self.inner.batch_write(operations).await
+    }
+
+    pub async fn large_batch_write(
+        &self,
+        operations: Vec<DocumentWriteOperation>,
+    ) -> Result<Vec<WriteResult>> {
+        self.inner.large_batch_write(operations).await
+    }
+
+    pub async fn batch_get_documents<F, C>(
+        &self,
+        document_paths: Vec<String>,
+        field_mask: Option<Vec<String>>,
+        consistency: C,
+        with_each_doc: F,
+    ) -> Result<MissingDocPaths>
+    where
+        F: FnMut(Document) -> anyhow::Result<()>,
+        C: Into<ReadConsistency>,
+    {
+        self.inner
+            .batch_get_documents(document_paths, field_mask, consistency, with_each_doc)
+            .await
+    }
+
+    pub async fn run_query<F, C>(
+        &self,
+        parent_path: Option<String>,
+        query: StructuredQuery,
+        consistency: C,
+        with_each_doc: F,
+    ) -> Result<i64>
+    where
+        F: FnMut(Document) -> anyhow::Result<()>,
+        C: Into<ReadConsistency>,
+    {
+        self.inner
+            .run_query(parent_path, query, consistency, with_each_doc)
+            .await
+    }
+
+    pub async fn in_transaction<F, R, Ctx>(&self, ctx: Ctx, with_tx: F) -> Result<R>
+    where
+        F: for<'a> WithTransaction<'a, R, Ctx>,
+    {
+        self.inner.in_transaction(ctx, with_tx).await
+    }
+}
+
+impl From<FirestoreClient> for SharedFirestoreClient {
+    fn from(client: FirestoreClient) -> Self {
+        Self::new(client)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::FirestoreClient;
+    use super::SharedFirestoreClient;
+    use futures::Future;
+    use std::sync::Arc;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    fn assert_send<T: Send>(_: &T) {}
+
+    /// never called. checks the futures can be spawned on multi thread runtimes.
+    #[allow(dead_code)]
+    fn futures_are_send(client: SharedFirestoreClient) {
+        assert_send(&client.get_document("/c/d".to_owned(), None, None));
+        assert_send(&client.delete_document("/c/d".to_owned()));
+        assert_send(&client.commit(vec![], None));
+        assert_send(&client.batch_write(vec![]));
+
+        fn spawnable<F: Future + Send + 'static>(_: F) {}
+        spawnable(async move { client.delete_document("/c/d".to_owned()).await });
+    }
+
+    /// never called. the client in an Arc is used concurrently without a lock.
+    #[allow(dead_code)]
+    fn arc_client_futures_are_send(client: Arc<FirestoreClient>) {
+        assert_send(&client.commit(vec![], None));
+        assert_send(&client.run_query_stream(None, Default::default(), None));
+
+        fn spawnable<F: Future + Send + 'static>(_: F) {}
+        let cloned = Arc::clone(&client);
+        spawnable(async move { cloned.delete_document("/c/d".to_owned()).await });
+        spawnable(async move { client.batch_write(vec![]).await });
+    }
+
+    #[test]
+    fn shared_client_is_send_sync() {
+        assert_send_sync::<FirestoreClient>();
+        assert_send_sync::<SharedFirestoreClient>();
+    }
+}
@@ -0,0 +1,210 @@
+//! the storage sizes of the documents, by the rules of
+//! https://firebase.google.com/docs/firestore/storage-size
+//!
+//! the sizes are billed as the stored data, and capped by `MAX_DOCUMENT_SIZE` per document.
+
+#[cfg(feature = "grpc")]
+use super::value::grpc_values::{Value, ValueType};
+use super::value::{fdoc::FDocumentPath, ffields::FFields, fvalue::FValue};
+#[cfg(feature = "grpc")]
+use std::collections::HashMap;
+
+/// the max size of a document.
+pub const MAX_DOCUMENT_SIZE: usize = 1_048_576;
+
+/// the additional bytes of each document.
+pub const DOCUMENT_ADDITIONAL_BYTES: usize = 32;
+
+/// the additional bytes of each document name.
+pub const DOCUMENT_NAME_ADDITIONAL_BYTES: usize = 16;
+
+/// the size of the document: the document name, the field names and the values, and 32 bytes.
+///
+/// ```ignore
+/// let path = FDocumentPath::new(
+///     Some("/users/jeff".to_owned()),
+///     "tasks".to_owned(),
+///     "my_task_id".to_owned(),
+/// );
+/// let mut task = FFields::empty();
+/// task.add("type", "Personal");
+/// task.add("done", false);
+/// task.add("priority", 1i64);
+/// task.add("description", "Learn Cloud Firestore");
+/// assert_eq!(147, estimate_document_size(&task, &path));
+/// ```
+pub fn estimate_document_size(fields: &FFields, path: &FDocumentPath) -> usize {
+    document_name_size(path) + fields_size(fields) + DOCUMENT_ADDITIONAL_BYTES
+}
+
+/// the field names and the values of the document.
+pub fn fields_size(fields: &FFields) -> usize {
+    fields
+        .keys()
+        .map(|name| string_size(name) + fields.get(name).map_or(0, value_size))
+        .sum()
+}
+
+/// `fields_size` of the fields of a grpc document, without converting them.
+#[cfg(feature = "grpc")]
+pub fn grpc_fields_size(fields: &HashMap<String, Value>) -> usize {
+    fields
+        .iter()
+        .map(|(name, value)| string_size(name) + grpc_value_size(value))
+        .sum()
+}
+
+/// the ids of the collections and the documents in the path as the strings, and 16 bytes.
+/// e.g. 44 bytes of "users/jeff/tasks/my_task_id"
+pub fn document_name_size(path: &FDocumentPath) -> usize {
+    let parent_size: usize = path
+        .parent_path
+        .iter()
+        .flat_map(|parent_path| parent_path.split('/'))
+        .filter(|segment| !segment.is_empty())
+        .map(string_size)
+        .sum();
+    parent_size
+        + string_size(&path.collection_id)
+        + string_size(&path.document_id)
+        + DOCUMENT_NAME_ADDITIONAL_BYTES
+}
+
+/// `document_name_size` of the path like "/users/jeff/tasks/my_task_id", or the full name
+/// like "projects/p/databases/(default)/documents/users/jeff/tasks/my_task_id".
+pub fn document_path_size(document_path: &str) -> usize {
+    let relative_path = match document_path.strip_prefix("projects/") {
+        Some(name) => name
+            .split_once("/documents/")
+            .map_or(name, |(_, relative_path)| relative_path),
+        None => document_path,
+    };
+    let segments_size: usize = relative_path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(string_size)
+        .sum();
+    segments_size + DOCUMENT_NAME_ADDITIONAL_BYTES
+}
+
+/// the number of the UTF-8 encoded bytes + 1.
+pub fn string_size(s: &str) -> usize {
+    s.len() + 1
+}
+
+/// the size of the value. the arrays and the maps are the sums of the elements, and the
+/// vectors are the size of the map they are stored as.
+pub fn value_size(value: &FValue) -> usize {
+    match value {
+        FValue::NullValue => 1,
+        FValue::Bool(_) => 1,
+        FValue::Int(_) | FValue::Double(_) | FValue::Timestamp(_) => 8,
+        FValue::Str(s) => string_size(s),
+        FValue::Bytes(bytes) => bytes.len(),
+        FValue::Array(values) => values.iter().map(value_size).sum(),
+        FValue::Map(map) => map
+            .iter()
+            .map(|(key, value)| string_size(key) + value_size(value))
+            .sum(),
+        FValue::Vector(values) => {
+            // {"__type__": "__vector__", "value": [..]}
+            string_size("__type__")
+                + string_size("__vector__")
+                + string_size("value")
+                + 8 * values.len()
+        }
+    }
+}
+
+/// `value_size` of a grpc value. the references are the size of the document name, and the
+/// geo points are 16 bytes.
+#[cfg(feature = "grpc")]
+pub fn grpc_value_size(value: &Value) -> usize {
+    match &value.value_type {
+        None | Some(ValueType::NullValue(_)) => 1,
+        Some(ValueType::BooleanValue(_)) => 1,
+        Some(ValueType::IntegerValue(_))
+        | Some(ValueType::DoubleValue(_))
+        | Some(ValueType::TimestampValue(_)) => 8,
+        Some(ValueType::StringValue(s)) => string_size(s),
+        Some(ValueType::BytesValue(bytes)) => bytes.len(),
+        Some(ValueType::ReferenceValue(name)) => document_path_size(name),
+        Some(ValueType::GeoPointValue(_)) => 16,
+        Some(ValueType::ArrayValue(array)) => array.values.iter().map(grpc_value_size).sum(),
+        // the vectors are stored as the maps
+        Some(ValueType::MapValue(map)) => grpc_fields_size(&map.fields),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::firestore::FMap;
+
+    #[test]
+    fn document_name_size_test() {
+        let path = FDocumentPath::new(
+            Some("/users/jeff".to_owned()),
+            "tasks".to_owned(),
+            "my_task_id".to_owned(),
+        );
+        assert_eq!(44, document_name_size(&path));
+        assert_eq!(44, document_path_size("/users/jeff/tasks/my_task_id"));
+        assert_eq!(
+            44,
+            document_path_size(
+                "projects/p/databases/(default)/documents/users/jeff/tasks/my_task_id"
+            )
+        );
+    }
+
+    #[test]
+    fn value_size_test() {
+        // https://firebase.google.com/docs/firestore/storage-size#string-size
+        assert_eq!(6, value_size(&FValue::from("tasks")));
+        assert_eq!(4, value_size(&FValue::from("日")));
+        assert_eq!(1, value_size(&FValue::NullValue));
+        assert_eq!(8, value_size(&FValue::Double(1.5)));
+        assert_eq!(3, value_size(&FValue::Bytes(vec![1, 2, 3])));
+        assert_eq!(
+            13,
+            value_size(&FValue::Array(vec![
+                FValue::from(1i64),
+                FValue::from(true),
+                FValue::NullValue,
+                FValue::from("ab")
+            ]))
+        );
+
+        let mut map = FMap::new();
+        map.insert("city".to_owned(), FValue::from("Tokyo"));
+        assert_eq!(11, value_size(&FValue::Map(map)));
+        assert_eq!(42, value_size(&FValue::Vector(vec![0.1, 0.2])));
+    }
+
+    #[test]
+    fn estimate_document_size_test() {
+        // https://firebase.google.com/docs/firestore/storage-size#document-size
+        let path = FDocumentPath::new(
+            Some("/users/jeff".to_owned()),
+            "tasks".to_owned(),
+            "my_task_id".to_owned(),
+        );
+        let mut task = FFields::empty();
+        task.add("type", "Personal");
+        task.add("done", false);
+        task.add("priority", 1i64);
+        task.add("description", "Learn Cloud Firestore");
+        assert_eq!(147, estimate_document_size(&task, &path));
+        #[cfg(feature = "grpc")]
+        {
+            task.add("embedding", FValue::Vector(vec![0.1, 0.2]));
+            assert_eq!(fields_size(&task), grpc_fields_size(&task.to_grpc_fields()));
+        }
+
+        assert_eq!(
+            44 + DOCUMENT_ADDITIONAL_BYTES,
+            estimate_document_size(&FFields::empty(), &path)
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! deterministic synthetic documents for benchmarks and load tests.
+//!
+//! the same `(depth, width, seed)` always generates the same document.
+
+use super::value::{fvalue::to_fvalue, FFields, FValue};
+use google_cloud_grpc_proto::firestore::v1::Document;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JValue;
+use std::collections::HashMap;
+
+/// xorshift64. good enough for generating test data without external crates.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    fn bool(&mut self) -> bool {
+        self.next() & 1 == 0
+    }
+
+    fn int(&mut self) -> i64 {
+        (self.next() >> 1) as i64 % 1_000_000
+    }
+
+    fn double(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn string(&mut self, len: usize) -> String {
+        const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+        (0..len)
+            .map(|_| CHARS[(self.next() % CHARS.len() as u64) as usize] as char)
+            .collect()
+    }
+}
+
+/// a typed record nested `depth` levels, each level has `width` children.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyntheticRecord {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub score: f64,
+    pub count: i64,
+    pub active: bool,
+    pub tags: Vec<String>,
+    pub attributes: HashMap<String, i64>,
+    pub children: Vec<SyntheticRecord>,
+}
+
+fn gen_record(rng: &mut Rng, depth: usize, width: usize) -> SyntheticRecord {
+    SyntheticRecord {
+        id: rng.string(20),
+        name: rng.string(12),
+        description: if rng.bool() {
+            Some(rng.string(64))
+        } else {
+            None
+        },
+        score: rng.double(),
+        count: rng.int(),
+        active: rng.bool(),
+        tags: (0..width).map(|_| rng.string(8)).collect(),
+        attributes: (0..width)
+            .map(|i| (format!("attr_{}", i), rng.int()))
+            .collect(),
+        children: if depth == 0 {
+            Vec::new()
+        } else {
+            (0..width)
+                .map(|_| gen_record(rng, depth - 1, width))
+                .collect()
+        },
+    }
+}
+
+/// the number of records is `(width^(depth+1) - 1) / (width - 1)`. be careful of large values.
+pub fn synthetic_record(depth: usize, width: usize, seed: u64) -> SyntheticRecord {
+    gen_record(&mut Rng::new(seed), depth, width)
+}
+
+/// `synthetic_record` as FValue.
+pub fn synthetic_fvalue(depth: usize, width: usize, seed: u64) -> FValue {
+    to_fvalue(synthetic_record(depth, width, seed)).unwrap()
+}
+
+/// `synthetic_record` as a grpc document.
+pub fn synthetic_document(name: String, depth: usize, width: usize, seed: u64) -> Document {
+    let fields = match synthetic_fvalue(depth, width, seed) {
+        FValue::Map(m) => FFields::new(m),
+        _ => unreachable!("a struct is serialized into a map"),
+    };
+    Document {
+        name,
+        fields: fields.to_grpc_fields(),
+        create_time: None,
+        update_time: None,
+    }
+}
+
+/// `synthetic_record` as json.
+pub fn synthetic_json(depth: usize, width: usize, seed: u64) -> JValue {
+    serde_json::to_value(synthetic_record(depth, width, seed)).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::value::fvalue::from_document;
+    use super::*;
+
+    #[test]
+    fn synthetic_round_trip_test() {
+        let record = synthetic_record(2, 3, 42);
+        assert_eq!(record, synthetic_record(2, 3, 42));
+        assert_ne!(record, synthetic_record(2, 3, 43));
+        assert_eq!(3, record.children[0].children.len());
+
+        let doc = synthetic_document(
+            "projects/p/databases/(default)/documents/c/d".to_owned(),
+            2,
+            3,
+            42,
+        );
+        let decoded: SyntheticRecord = from_document(doc).unwrap();
+        assert_eq!(record, decoded);
+    }
+}
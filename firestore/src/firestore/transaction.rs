@@ -0,0 +1,274 @@
+use super::client::{FirestoreClient, TransactionOperation};
+use super::error::{FirestoreError, Result};
+use super::helper::new_write_ope_create;
+use super::request::{DocumentWriteOperation, ReadConsistency};
+use super::value::{fdoc::validate_document_path, field_path::escape_field_name, TryIntoFFields};
+use google_cloud_grpc_proto::firestore::v1::{Document, StructuredQuery};
+use serde::de::DeserializeOwned;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+/// the reads and the writes of `FirestoreClient::run_transaction`. the reads are in the
+/// transaction, and the writes are queued to be committed with it.
+///
+/// the clones share the queue. the writes queued after the closure returned fail.
+#[derive(Clone)]
+pub struct TransactionContext {
+    client: FirestoreClient,
+    transaction: Vec<u8>,
+    /// None after the writes are moved into the transaction.
+    operations: Arc<Mutex<Option<Vec<DocumentWriteOperation>>>>,
+}
+
+impl TransactionContext {
+    fn new(client: FirestoreClient, transaction: Vec<u8>) -> Self {
+        Self {
+            client,
+            transaction,
+            operations: Arc::new(Mutex::new(Some(Vec::new()))),
+        }
+    }
+
+    pub fn transaction_id(&self) -> &[u8] {
+        &self.transaction
+    }
+
+    /// the client, to call the methods not provided by the context.
+    /// pass `transaction_id` to read in the transaction.
+    pub fn client(&self) -> &FirestoreClient {
+        &self.client
+    }
+
+    fn consistency(&self) -> ReadConsistency {
+        ReadConsistency::Transaction(self.transaction.clone())
+    }
+
+    /// the document at the path (e.g. "/users/user_1") in the transaction.
+    pub async fn get_document(
+        &self,
+        document_path: &str,
+        field_mask: Option<Vec<String>>,
+    ) -> Result<Option<Document>> {
+        self.client
+            .get_document(document_path.to_owned(), field_mask, self.consistency())
+            .await
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, document_path: &str) -> Result<Option<T>> {
+        self.client
+            .get_document_as(document_path.to_owned(), None, self.consistency())
+            .await
+    }
+
+    /// the documents of the query in the transaction.
+    pub async fn query<T: DeserializeOwned>(
+        &self,
+        parent_path: Option<String>,
+        query: StructuredQuery,
+    ) -> Result<Vec<T>> {
+        let mut documents = Vec::new();
+        self.client
+            .run_query(parent_path, query, self.consistency(), |document| {
+                documents.push(document);
+                Ok(())
+            })
+            .await?;
+        documents
+            .into_iter()
+            .map(|document| self.client.decode(document))
+            .collect()
+    }
+
+    /// create the document on commit. the transaction fails if the document already exists.
+    pub fn create<T: TryIntoFFields>(&self, document_path: &str, doc: T) -> Result<()> {
+        let (parent_path, collection_id, document_id) = split_document_path(document_path)?;
+        let ope = new_write_ope_create(
+            parent_path,
+            collection_id,
+            document_id,
+            self.client.encode(doc)?,
+        )?;
+        self.add_operation(ope)
+    }
+
+    /// create or overwrite the document on commit.
+    pub fn set<T: TryIntoFFields>(&self, document_path: &str, doc: T) -> Result<()> {
+        validate_document_path(document_path)?;
+        let (fields, transforms) = self.client.encode(doc)?.split_transforms();
+        self.add_operation(
+            DocumentWriteOperation::new_upsert(document_path.to_owned(), fields)
+                .with_update_transforms(transforms),
+        )
+    }
+
+    /// update only the top level fields in `patch` on commit. (e.g. FFields or a struct of some fields)
+    pub fn update<P: TryIntoFFields>(&self, document_path: &str, patch: P) -> Result<()> {
+        validate_document_path(document_path)?;
+        let (fields, transforms) = self.client.encode(patch)?.split_transforms();
+        // the top level keys like "a.b" are not the nested fields
+        let update_field_mask = fields.keys().map(|key| escape_field_name(key)).collect();
+        self.add_operation(
+            DocumentWriteOperation::new_update(
+                document_path.to_owned(),
+                fields,
+                Some(update_field_mask),
+            )
+            .with_update_transforms(transforms),
+        )
+    }
+
+    /// delete the document on commit.
+    pub fn delete(&self, document_path: &str) -> Result<()> {
+        validate_document_path(document_path)?;
+        self.add_operation(DocumentWriteOperation::new_delete(document_path.to_owned()))
+    }
+
+    /// queue the operation built by hand (e.g. with a precondition).
+    pub fn add_operation(&self, operation: DocumentWriteOperation) -> Result<()> {
+        match self.operations.lock().unwrap().as_mut() {
+            Some(operations) => {
+                operations.push(operation);
+                Ok(())
+            }
+            None => Err(FirestoreError::invalid_argument(format!(
+                "the transaction is already finished. the write of {} is not committed",
+                operation.document_path()
+            ))),
+        }
+    }
+
+    /// the number of the writes queued.
+    pub fn operation_num(&self) -> usize {
+        self.operations.lock().unwrap().as_ref().map_or(0, Vec::len)
+    }
+
+    fn take_operations(&self) -> Vec<DocumentWriteOperation> {
+        self.operations.lock().unwrap().take().unwrap_or_default()
+    }
+}
+
+/// e.g. (Some("/users/u1"), "orders", "o1") of "/users/u1/orders/o1"
+fn split_document_path(document_path: &str) -> Result<(Option<String>, String, String)> {
+    validate_document_path(document_path)?;
+    let mut segments = document_path.rsplitn(3, '/');
+    let document_id = segments.next().unwrap_or_default().to_owned();
+    let collection_id = segments.next().unwrap_or_default().to_owned();
+    let parent_path = segments.next().filter(|path| !path.is_empty());
+    Ok((parent_path.map(str::to_owned), collection_id, document_id))
+}
+
+/// `run_transaction` on `in_transaction_with_retry`. the writes of the context are moved into the
+/// transaction to be committed.
+pub(crate) async fn call_with_context<F, Fut, R>(
+    client: &mut FirestoreClient,
+    tx: &mut TransactionOperation,
+    with_tx: Arc<F>,
+) -> anyhow::Result<R>
+where
+    F: Fn(TransactionContext) -> Fut,
+    Fut: Future<Output = anyhow::Result<R>>,
+{
+    let context = TransactionContext::new(client.clone(), tx.transaction.clone());
+    let result = with_tx(context.clone()).await;
+    let operations = context.take_operations();
+    let result = result?;
+    for operation in operations {
+        tx.add_operation(operation)?;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::firestore::FFields;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    struct Account {
+        balance: i64,
+    }
+
+    #[test]
+    fn split_document_path_test() {
+        assert_eq!(
+            (
+                Some("/users/u1".to_owned()),
+                "orders".to_owned(),
+                "o1".to_owned()
+            ),
+            split_document_path("/users/u1/orders/o1").unwrap()
+        );
+        assert_eq!(
+            (None, "users".to_owned(), "u1".to_owned()),
+            split_document_path("/users/u1").unwrap()
+        );
+        assert!(split_document_path("/users/").is_err());
+        assert!(split_document_path("/users/u1/orders").is_err());
+    }
+
+    #[tokio::test]
+    async fn context_test() {
+        let context = TransactionContext::new(FirestoreClient::offline("p"), b"tx".to_vec());
+        let account = Account { balance: 1 };
+        context.create("/users/u1/accounts/a1", &account).unwrap();
+        // the collection paths are rejected
+        assert!(context.create("/users/u1/accounts", &account).is_err());
+        assert!(context.delete("/users/u1/accounts").is_err());
+
+        // the clones share the queue
+        context.clone().delete("/users/u1/accounts/a2").unwrap();
+        assert_eq!(2, context.operation_num());
+
+        assert_eq!(2, context.take_operations().len());
+        assert!(context.delete("/users/u1/accounts/a3").is_err());
+    }
+
+    #[tokio::test]
+    async fn update_mask_test() {
+        let context = TransactionContext::new(FirestoreClient::offline("p"), b"tx".to_vec());
+        let mut patch = FFields::empty();
+        patch.add("a.b", 1i64);
+        context.update("/users/u1", patch).unwrap();
+        let operations = context.take_operations();
+        assert_eq!(
+            Some(&["`a.b`".to_owned()][..]),
+            operations[0].update_field_mask()
+        );
+    }
+
+    // the closures are accepted as is, without the lifetime annotations
+    #[allow(dead_code)]
+    async fn transfer(
+        client: &FirestoreClient,
+        from: String,
+        to: String,
+        amount: i64,
+    ) -> Result<()> {
+        client
+            .run_transaction(move |tx| {
+                let (from, to) = (from.clone(), to.clone());
+                async move {
+                    let from_account: Account = tx
+                        .get(&from)
+                        .await?
+                        .ok_or_else(|| anyhow::anyhow!("no account {}", from))?;
+                    let to_account: Account = tx.get(&to).await?.unwrap_or(Account { balance: 0 });
+                    tx.set(
+                        &from,
+                        &Account {
+                            balance: from_account.balance - amount,
+                        },
+                    )?;
+                    tx.set(
+                        &to,
+                        &Account {
+                            balance: to_account.balance + amount,
+                        },
+                    )?;
+                    Ok(())
+                }
+            })
+            .await
+    }
+}
@@ -0,0 +1,438 @@
+//! approximation of Cloud Functions firestore triggers for local development.
+//!
+//! watches the documents with Listen and calls the handlers registered per document path pattern.
+//!
+//! ```ignore
+//! let triggers = DocumentTriggers::new()
+//!     .on_create("users/{user_id}", |event| async move {
+//!         println!("created {}", event.params["user_id"]);
+//!         Ok(())
+//!     })?;
+//! let client = FirestoreClient::from_emulator_env("test-project".to_owned()).await?;
+//! triggers.run(&client).await?;
+//! ```
+
+use super::client::FirestoreClient;
+use super::query::QueryBuilder;
+use super::request::documents_root_path;
+
+use super::error::{FirestoreError, Result};
+use futures::future::BoxFuture;
+use futures::{Future, FutureExt, TryStreamExt};
+use google_cloud_grpc_proto::firestore::v1::{
+    listen_response::ResponseType,
+    target::{self, query_target},
+    target_change::TargetChangeType,
+    Document, ListenResponse, Target,
+};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerKind {
+    Create,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriggerEvent {
+    pub kind: TriggerKind,
+    /// e.g. "/users/user_1"
+    pub document_path: String,
+    /// the values of the wildcards in the pattern. e.g. {"user_id": "user_1"}
+    pub params: HashMap<String, String>,
+    /// None on create
+    pub before: Option<Document>,
+    /// None on delete
+    pub after: Option<Document>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PatternSegment {
+    Literal(String),
+    Wildcard(String),
+}
+
+/// document path pattern like "users/{user_id}/posts/{post_id}".
+/// the collection ids must be literals.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocumentPattern {
+    segments: Vec<PatternSegment>,
+}
+
+impl DocumentPattern {
+    pub fn parse(pattern: &str) -> Result<Self> {
+        let segments: Vec<PatternSegment> = pattern
+            .trim_start_matches('/')
+            .split('/')
+            .map(|segment| {
+                if segment.starts_with('{') && segment.ends_with('}') && segment.len() > 2 {
+                    PatternSegment::Wildcard(segment[1..segment.len() - 1].to_owned())
+                } else {
+                    PatternSegment::Literal(segment.to_owned())
+                }
+            })
+            .collect();
+
+        let pairs = segments.chunks_exact(2);
+        if !pairs.remainder().is_empty() {
+            return Err(FirestoreError::invalid_argument(format!(
+                "not a document path pattern: {}",
+                pattern
+            )));
+        }
+        for pair in pairs {
+            if let PatternSegment::Wildcard(_) = pair[0] {
+                return Err(FirestoreError::invalid_argument(format!(
+                    "collection id must be a literal: {}",
+                    pattern
+                )));
+            }
+            if pair
+                .iter()
+                .any(|segment| *segment == PatternSegment::Literal("".to_owned()))
+            {
+                return Err(FirestoreError::invalid_argument(format!(
+                    "empty segment in pattern: {}",
+                    pattern
+                )));
+            }
+        }
+        Ok(Self { segments })
+    }
+
+    fn collection_id(&self) -> &str {
+        match &self.segments[self.segments.len() - 2] {
+            PatternSegment::Literal(s) => s.as_str(),
+            PatternSegment::Wildcard(_) => unreachable!("validated on parse"),
+        }
+    }
+
+    /// returns the wildcard values if the path (e.g. "/users/user_1") matches.
+    pub fn matches(&self, document_path: &str) -> Option<HashMap<String, String>> {
+        let path_segments: Vec<&str> = document_path.trim_start_matches('/').split('/').collect();
+        if path_segments.len() != self.segments.len() {
+            return None;
+        }
+
+        let mut params = HashMap::new();
+        for (pattern, actual) in self.segments.iter().zip(path_segments) {
+            match pattern {
+                PatternSegment::Literal(s) if s != actual => return None,
+                PatternSegment::Literal(_) => {}
+                PatternSegment::Wildcard(name) => {
+                    params.insert(name.clone(), actual.to_owned());
+                }
+            }
+        }
+        Some(params)
+    }
+}
+
+type TriggerHandler =
+    Box<dyn Fn(TriggerEvent) -> BoxFuture<'static, anyhow::Result<()>> + Send + Sync>;
+
+/// the handlers of document events.
+#[derive(Default)]
+pub struct DocumentTriggers {
+    handlers: Vec<(TriggerKind, DocumentPattern, TriggerHandler)>,
+}
+
+impl DocumentTriggers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_create<F, Fut>(self, pattern: &str, handler: F) -> Result<Self>
+    where
+        F: Fn(TriggerEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.on(TriggerKind::Create, pattern, handler)
+    }
+
+    pub fn on_update<F, Fut>(self, pattern: &str, handler: F) -> Result<Self>
+    where
+        F: Fn(TriggerEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.on(TriggerKind::Update, pattern, handler)
+    }
+
+    pub fn on_delete<F, Fut>(self, pattern: &str, handler: F) -> Result<Self>
+    where
+        F: Fn(TriggerEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.on(TriggerKind::Delete, pattern, handler)
+    }
+
+    fn on<F, Fut>(mut self, kind: TriggerKind, pattern: &str, handler: F) -> Result<Self>
+    where
+        F: Fn(TriggerEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let pattern = DocumentPattern::parse(pattern)?;
+        self.handlers
+            .push((kind, pattern, Box::new(move |event| handler(event).boxed())));
+        Ok(self)
+    }
+
+    /// listen to the documents and call the handlers until the stream is closed.
+    /// the documents existing at the start don't fire the triggers.
+    /// the errors of the handlers are logged and ignored as Cloud Functions does.
+    pub async fn run(&self, client: &FirestoreClient) -> Result<()> {
+        let mut collection_ids: Vec<&str> = self
+            .handlers
+            .iter()
+            .map(|(_, pattern, _)| pattern.collection_id())
+            .collect();
+        collection_ids.sort_unstable();
+        collection_ids.dedup();
+        if collection_ids.is_empty() {
+            return Err(FirestoreError::invalid_argument(
+                "no trigger handlers registered",
+            ));
+        }
+
+        let parent = documents_root_path(client.project_id().to_owned());
+        let targets: Vec<Target> = collection_ids
+            .iter()
+            .enumerate()
+            .map(|(i, collection_id)| Target {
+                target_id: i as i32 + 1,
+                once: false,
+                target_type: Some(target::TargetType::Query(target::QueryTarget {
+                    parent: parent.clone(),
+                    query_type: Some(query_target::QueryType::StructuredQuery(
+                        QueryBuilder::collection(collection_id.to_string(), true).build(),
+                    )),
+                })),
+                resume_type: None,
+            })
+            .collect();
+
+        let mut state = ListenState::new(targets.iter().map(|t| t.target_id).collect());
+        let mut responses = Box::pin(client.listen(targets).await?);
+        while let Some(response) = responses.try_next().await? {
+            for change in state.apply(response) {
+                self.dispatch(change).await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn dispatch(&self, change: DocumentEvent) {
+        for (kind, pattern, handler) in self.handlers.iter() {
+            if *kind != change.kind {
+                continue;
+            }
+            if let Some(params) = pattern.matches(&change.document_path) {
+                let event = TriggerEvent {
+                    kind: change.kind,
+                    document_path: change.document_path.clone(),
+                    params,
+                    before: change.before.clone(),
+                    after: change.after.clone(),
+                };
+                if let Err(e) = handler(event).await {
+                    log::error!("trigger handler failed on {} : {}", change.document_path, e);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct DocumentEvent {
+    kind: TriggerKind,
+    document_path: String,
+    before: Option<Document>,
+    after: Option<Document>,
+}
+
+/// "projects/p/databases/(default)/documents/users/user_1" => "/users/user_1"
+pub(super) fn relative_document_path(name: &str) -> String {
+    match name.find("/documents/") {
+        Some(i) => name[i + "/documents".len()..].to_owned(),
+        None => name.to_owned(),
+    }
+}
+
+/// tracks the documents to tell create from update, and the initial snapshot from the changes.
+struct ListenState {
+    target_ids: Vec<i32>,
+    current_target_ids: HashSet<i32>,
+    documents: HashMap<String, Document>,
+}
+
+impl ListenState {
+    fn new(target_ids: Vec<i32>) -> Self {
+        Self {
+            target_ids,
+            current_target_ids: HashSet::new(),
+            documents: HashMap::new(),
+        }
+    }
+
+    fn is_current(&self) -> bool {
+        self.target_ids
+            .iter()
+            .all(|id| self.current_target_ids.contains(id))
+    }
+
+    /// empty `target_ids` in TargetChange means all the targets.
+    fn change_target_ids(&self, target_ids: Vec<i32>) -> Vec<i32> {
+        if target_ids.is_empty() {
+            self.target_ids.clone()
+        } else {
+            target_ids
+        }
+    }
+
+    fn apply(&mut self, response: ListenResponse) -> Vec<DocumentEvent> {
+        let mut events = Vec::new();
+        match response.response_type {
+            Some(ResponseType::TargetChange(change)) => {
+                match TargetChangeType::from_i32(change.target_change_type) {
+                    Some(TargetChangeType::Current) => {
+                        for id in self.change_target_ids(change.target_ids) {
+                            self.current_target_ids.insert(id);
+                        }
+                    }
+                    // the server resends the documents. suppress the events until current again.
+                    Some(TargetChangeType::Reset) | Some(TargetChangeType::Remove) => {
+                        for id in self.change_target_ids(change.target_ids) {
+                            self.current_target_ids.remove(&id);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Some(ResponseType::DocumentChange(change)) => {
+                if let Some(document) = change.document {
+                    let document_path = relative_document_path(&document.name);
+                    let before = self
+                        .documents
+                        .insert(document.name.clone(), document.clone());
+                    if self.is_current() {
+                        events.push(DocumentEvent {
+                            kind: if before.is_some() {
+                                TriggerKind::Update
+                            } else {
+                                TriggerKind::Create
+                            },
+                            document_path,
+                            before,
+                            after: Some(document),
+                        });
+                    }
+                }
+            }
+            Some(ResponseType::DocumentDelete(delete)) => {
+                events.extend(self.remove(delete.document));
+            }
+            Some(ResponseType::DocumentRemove(remove)) => {
+                events.extend(self.remove(remove.document));
+            }
+            Some(ResponseType::Filter(_)) | None => {}
+        }
+        events
+    }
+
+    fn remove(&mut self, name: String) -> Option<DocumentEvent> {
+        let before = self.documents.remove(&name);
+        if before.is_some() && self.is_current() {
+            Some(DocumentEvent {
+                kind: TriggerKind::Delete,
+                document_path: relative_document_path(&name),
+                before,
+                after: None,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use google_cloud_grpc_proto::firestore::v1::{DocumentChange, DocumentDelete, TargetChange};
+
+    fn doc(name: &str) -> Document {
+        Document {
+            name: format!("projects/p/databases/(default)/documents{}", name),
+            fields: HashMap::new(),
+            create_time: None,
+            update_time: None,
+        }
+    }
+
+    fn change(document: Document) -> ListenResponse {
+        ListenResponse {
+            response_type: Some(ResponseType::DocumentChange(DocumentChange {
+                document: Some(document),
+                target_ids: vec![1],
+                removed_target_ids: vec![],
+            })),
+        }
+    }
+
+    fn target_change(change_type: TargetChangeType) -> ListenResponse {
+        ListenResponse {
+            response_type: Some(ResponseType::TargetChange(TargetChange {
+                target_change_type: change_type as i32,
+                target_ids: vec![],
+                cause: None,
+                resume_token: vec![],
+                read_time: None,
+            })),
+        }
+    }
+
+    #[test]
+    fn document_pattern_test() {
+        let pattern = DocumentPattern::parse("users/{user_id}/posts/{post_id}").unwrap();
+        let params = pattern.matches("/users/u1/posts/p1").unwrap();
+        assert_eq!("u1", params["user_id"]);
+        assert_eq!("p1", params["post_id"]);
+        assert_eq!("posts", pattern.collection_id());
+
+        assert!(pattern.matches("/users/u1").is_none());
+        assert!(pattern.matches("/groups/u1/posts/p1").is_none());
+
+        assert!(DocumentPattern::parse("users").is_err());
+        assert!(DocumentPattern::parse("{coll}/doc").is_err());
+    }
+
+    #[test]
+    fn listen_state_test() {
+        let mut state = ListenState::new(vec![1]);
+
+        // initial snapshot
+        assert!(state.apply(change(doc("/users/u1"))).is_empty());
+        assert!(state
+            .apply(target_change(TargetChangeType::Current))
+            .is_empty());
+
+        let events = state.apply(change(doc("/users/u2")));
+        assert_eq!(1, events.len());
+        assert_eq!(TriggerKind::Create, events[0].kind);
+        assert_eq!("/users/u2", events[0].document_path);
+
+        let events = state.apply(change(doc("/users/u1")));
+        assert_eq!(TriggerKind::Update, events[0].kind);
+        assert_eq!(Some(doc("/users/u1")), events[0].before);
+
+        let events = state.apply(ListenResponse {
+            response_type: Some(ResponseType::DocumentDelete(DocumentDelete {
+                document: doc("/users/u1").name,
+                removed_target_ids: vec![1],
+                read_time: None,
+            })),
+        });
+        assert_eq!(TriggerKind::Delete, events[0].kind);
+        assert_eq!(None, events[0].after);
+    }
+}
@@ -1,8 +1,16 @@
-use super::grpc_values::Document;
 use super::{fvalue::FValue, FFields};
-use anyhow::{anyhow, Result};
+#[cfg(feature = "grpc")]
+use super::{
+    fvalue::{from_document, from_document_with_casing, FieldCasing},
+    grpc_values::Document,
+};
+use crate::firestore::error::{FirestoreError, Result};
 use lazy_static::lazy_static;
 use regex::Regex;
+#[cfg(feature = "grpc")]
+use serde::de::DeserializeOwned;
+use serde_json::Value as JValue;
+use std::time::SystemTime;
 
 lazy_static! {
     //TODO(tacogips) needs more strict matching accoding to https://firebase.google.com/docs/firestore/quotas
@@ -11,13 +19,15 @@ lazy_static! {
 }
 
 fn parse_document_path(path: &str) -> Result<(Option<String>, String, String)> {
-    DOCUMENT_ID_REGEX
-        .captures(path)
-        .map_or(Err(anyhow!("invalid doc path {}", path)), |captured| {
-            let parent_path = captured
-                .get(1)
-                .map(|m| m.as_str())
-                .ok_or_else(|| anyhow!("invalid doc path {}", path))?;
+    DOCUMENT_ID_REGEX.captures(path).map_or(
+        Err(FirestoreError::invalid_argument(format!(
+            "invalid doc path {}",
+            path
+        ))),
+        |captured| {
+            let parent_path = captured.get(1).map(|m| m.as_str()).ok_or_else(|| {
+                FirestoreError::invalid_argument(format!("invalid doc path {}", path))
+            })?;
 
             let parent_path = if parent_path.is_empty() {
                 None
@@ -25,20 +35,41 @@ fn parse_document_path(path: &str) -> Result<(Option<String>, String, String)> {
                 Some(parent_path.to_owned())
             };
 
-            let collection_id = captured
-                .get(2)
-                .map(|m| m.as_str().to_owned())
-                .ok_or_else(|| anyhow!("invalid doc path {}", path))?;
+            let collection_id =
+                captured
+                    .get(2)
+                    .map(|m| m.as_str().to_owned())
+                    .ok_or_else(|| {
+                        FirestoreError::invalid_argument(format!("invalid doc path {}", path))
+                    })?;
 
             let doc_id = captured
                 .get(3)
                 .map(|m| m.as_str().to_owned())
-                .ok_or_else(|| anyhow!("invalid doc path {}", path))?;
+                .ok_or_else(|| {
+                    FirestoreError::invalid_argument(format!("invalid doc path {}", path))
+                })?;
             Ok((parent_path, collection_id, doc_id))
-        })
+        },
+    )
 }
 
-#[derive(Debug, PartialEq)]
+/// the field deserialized from the document id by `from_document`.
+/// it's set only if the struct has the field, and removed from the fields to write.
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize)]
+/// struct User {
+///     #[serde(rename = "__firestore_document_id__", default)]
+///     id: String,
+///     name: String,
+/// }
+/// ```
+pub const DOCUMENT_ID_FIELD: &str = "__firestore_document_id__";
+/// `DOCUMENT_ID_FIELD` for the full document name. (e.g. "projects/p/databases/(default)/documents/users/u1")
+pub const DOCUMENT_NAME_FIELD: &str = "__firestore_document_name__";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FDocumentPath {
     pub parent_path: Option<String>,
     pub collection_id: String,
@@ -72,6 +103,76 @@ impl FDocumentPath {
             document_id,
         })
     }
+
+    /// the path of the document returned by the server, from its name.
+    /// (e.g. "projects/p/databases/(default)/documents/users/u1")
+    #[cfg(feature = "grpc")]
+    pub fn from_document(document: &Document) -> Result<Self> {
+        Self::parse(document.name.as_str())
+    }
+}
+
+/// the path of a collection. e.g. "/users/user_1/orders"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FCollectionPath {
+    /// the document path (e.g. "/users/user_1"), None for the root collections.
+    pub parent_path: Option<String>,
+    pub collection_id: String,
+}
+
+impl FCollectionPath {
+    pub fn new(parent_path: Option<String>, collection_id: String) -> Self {
+        Self {
+            parent_path,
+            collection_id,
+        }
+    }
+
+    /// "users", "/users" or "/users/user_1/orders".
+    pub fn parse(path: &str) -> Result<Self> {
+        let invalid =
+            || FirestoreError::invalid_argument(format!("invalid collection path {}", path));
+        let path = path.trim_start_matches('/');
+        let (parent_path, collection_id) = match path.rsplit_once('/') {
+            Some((parent_path, collection_id)) => {
+                let parent_path = format!("/{}", parent_path);
+                validate_document_path(&parent_path).map_err(|_| invalid())?;
+                (Some(parent_path), collection_id)
+            }
+            None => (None, path),
+        };
+        if collection_id.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self::new(parent_path, collection_id.to_owned()))
+    }
+
+    pub fn into_string(self) -> String {
+        format!(
+            "{}/{}",
+            self.parent_path.unwrap_or_default(),
+            self.collection_id
+        )
+    }
+}
+
+/// the document path must be like "/users/user_1" or "/users/user_1/orders/order_1".
+#[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+pub(crate) fn validate_document_path(path: &str) -> Result<()> {
+    let invalid = || FirestoreError::invalid_argument(format!("invalid document path {}", path));
+    let segments: Vec<&str> = path
+        .strip_prefix('/')
+        .ok_or_else(invalid)?
+        .split('/')
+        .collect();
+    // pairs of the collection id and the document id
+    if segments
+        .chunks(2)
+        .any(|pair| pair.len() != 2 || pair.iter().any(|segment| segment.is_empty()))
+    {
+        return Err(invalid());
+    }
+    Ok(())
 }
 
 pub fn doc_path(parent: Option<String>, collection_id: String, doc_id: String) -> String {
@@ -87,14 +188,60 @@ pub fn doc_path(parent: Option<String>, collection_id: String, doc_id: String) -
 pub struct FDocument {
     pub doc_path: FDocumentPath,
     pub fields: FFields,
+    pub create_time: Option<SystemTime>,
+    pub update_time: Option<SystemTime>,
+}
+
+/// the keys of the metadata in `FDocument::to_json_with_metadata`. None omits the metadata.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonMetadataKeys {
+    pub id: Option<String>,
+    pub create_time: Option<String>,
+    pub update_time: Option<String>,
+}
+
+impl Default for JsonMetadataKeys {
+    /// "_id", "_createTime" and "_updateTime"
+    fn default() -> Self {
+        Self {
+            id: Some("_id".to_owned()),
+            create_time: Some("_createTime".to_owned()),
+            update_time: Some("_updateTime".to_owned()),
+        }
+    }
 }
 
 impl FDocument {
+    #[cfg(feature = "grpc")]
     pub fn from_document(document: Document) -> Result<FDocument> {
         let doc_path = FDocumentPath::parse(document.name.as_str())?;
+        let create_time = document.create_time.clone().map(SystemTime::from);
+        let update_time = document.update_time.clone().map(SystemTime::from);
         let fields = FFields::from_grpc_doc(document);
 
-        Ok(FDocument { doc_path, fields })
+        Ok(FDocument {
+            doc_path,
+            fields,
+            create_time,
+            update_time,
+        })
+    }
+
+    /// json object of the fields with the document id and the timestamps (in RFC 3339).
+    /// the metadata overwrite the fields of the same keys.
+    pub fn to_json_with_metadata(self, keys: &JsonMetadataKeys) -> JValue {
+        let metadata = vec![
+            (&keys.id, Some(FValue::Str(self.doc_path.document_id))),
+            (&keys.create_time, self.create_time.map(FValue::Timestamp)),
+            (&keys.update_time, self.update_time.map(FValue::Timestamp)),
+        ];
+        let mut fields = self.fields;
+        for (key, value) in metadata {
+            if let (Some(key), Some(value)) = (key, value) {
+                fields.add(key.clone(), value);
+            }
+        }
+        JValue::from(fields)
     }
 
     pub fn to_path_and_fvalue(self) -> (FDocumentPath, FValue) {
@@ -103,24 +250,219 @@ impl FDocument {
     }
 }
 
+/// the document deserialized into `T` with its path and timestamps.
+#[derive(Debug)]
+pub struct DocumentSnapshot<T> {
+    pub doc_path: FDocumentPath,
+    pub data: T,
+    pub create_time: Option<SystemTime>,
+    pub update_time: Option<SystemTime>,
+}
+
+/// `from_document` failing with `FirestoreError::Decode` with the path of the document.
+/// the field names stored by `casing` are read if passed.
+#[cfg(feature = "grpc")]
+pub(crate) fn decode_document_with_casing<T>(
+    document: Document,
+    casing: Option<&FieldCasing>,
+) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let name = document.name.clone();
+    let decoded = match casing {
+        Some(casing) => from_document_with_casing(document, casing),
+        None => from_document(document),
+    };
+    decoded.map_err(|error| FirestoreError::Decode {
+        document_path: FDocumentPath::parse(&name).map_or(name, FDocumentPath::into_string),
+        error,
+    })
+}
+
+#[cfg(feature = "grpc")]
+impl<T> DocumentSnapshot<T>
+where
+    T: DeserializeOwned,
+{
+    pub fn from_document(document: Document) -> Result<Self> {
+        Self::from_document_with_casing(document, None)
+    }
+
+    pub(crate) fn from_document_with_casing(
+        document: Document,
+        casing: Option<&FieldCasing>,
+    ) -> Result<Self> {
+        let doc_path = FDocumentPath::parse(document.name.as_str())?;
+        let create_time = document.create_time.clone().map(SystemTime::from);
+        let update_time = document.update_time.clone().map(SystemTime::from);
+        let data = decode_document_with_casing(document, casing)?;
+
+        Ok(DocumentSnapshot {
+            doc_path,
+            data,
+            create_time,
+            update_time,
+        })
+    }
+}
+
+impl<T> DocumentSnapshot<T> {
+    pub fn document_id(&self) -> &str {
+        &self.doc_path.document_id
+    }
+
+    pub fn into_data(self) -> T {
+        self.data
+    }
+}
+
+#[cfg(feature = "grpc")]
 impl From<Document> for FDocument {
     fn from(document: Document) -> FDocument {
-        let doc_path = FDocumentPath::parse(document.name.as_str()).unwrap();
-        let fields = FFields::from_grpc_doc(document);
+        FDocument::from_document(document).unwrap()
+    }
+}
 
-        FDocument { doc_path, fields }
+/// the fields only. see `to_json_with_metadata` to include the document id and the timestamps.
+impl From<FDocument> for JValue {
+    fn from(document: FDocument) -> JValue {
+        JValue::from(document.fields)
     }
 }
 
 impl Into<FValue> for FDocument {
     fn into(self) -> FValue {
-        FValue::Map(self.fields.into())
+        self.fields.as_fvalue()
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "grpc"))]
 mod test {
-    use super::parse_document_path;
+    use super::{
+        decode_document_with_casing, parse_document_path, validate_document_path, DocumentSnapshot,
+        FCollectionPath, FDocument, FDocumentPath, JsonMetadataKeys,
+    };
+    use crate::firestore::value::FValue;
+    use google_cloud_grpc_proto::firestore::v1::Document;
+    use google_cloud_grpc_proto::prost_types::Timestamp;
+    use serde_json::json;
+    use std::time::SystemTime;
+
+    #[test]
+    fn to_json_with_metadata_test() {
+        let mut document = Document {
+            name: "projects/aaa/databases/(default)/documents/coll_1/doc_1".to_owned(),
+            create_time: Some(Timestamp {
+                seconds: 1_600_000_000,
+                nanos: 0,
+            }),
+            update_time: Some(Timestamp {
+                seconds: 1_600_000_060,
+                nanos: 0,
+            }),
+            ..Default::default()
+        };
+        document
+            .fields
+            .insert("name".to_owned(), FValue::from("taco").to_grpc_value());
+
+        let json =
+            FDocument::from(document.clone()).to_json_with_metadata(&JsonMetadataKeys::default());
+        assert_eq!(
+            json!({
+                "name": "taco",
+                "_id": "doc_1",
+                "_createTime": "2020-09-13T12:26:40+00:00",
+                "_updateTime": "2020-09-13T12:27:40+00:00",
+            }),
+            json
+        );
+
+        let keys = JsonMetadataKeys {
+            id: Some("id".to_owned()),
+            create_time: None,
+            update_time: None,
+        };
+        assert_eq!(
+            json!({"name": "taco", "id": "doc_1"}),
+            FDocument::from(document).to_json_with_metadata(&keys)
+        );
+    }
+
+    #[test]
+    fn decode_document_test() {
+        #[derive(serde::Deserialize, Debug)]
+        struct User {
+            #[allow(dead_code)]
+            age: i64,
+        }
+
+        let mut document = Document {
+            name: "projects/aaa/databases/(default)/documents/users/user_1".to_owned(),
+            ..Default::default()
+        };
+        document
+            .fields
+            .insert("age".to_owned(), FValue::from("ten").to_grpc_value());
+        let e = decode_document_with_casing::<User>(document, None).unwrap_err();
+        assert_eq!(Some("/users/user_1"), e.document_path());
+        assert!(e
+            .to_string()
+            .starts_with("failed to deserialize /users/user_1"));
+    }
+
+    #[test]
+    fn document_snapshot_test() {
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct User {
+            name: String,
+        }
+
+        let mut document = Document {
+            name: "projects/aaa/databases/(default)/documents/coll_1/doc_1/users/user_1".to_owned(),
+            create_time: Some(Timestamp {
+                seconds: 1_600_000_000,
+                nanos: 123,
+            }),
+            update_time: Some(Timestamp {
+                seconds: 1_600_000_060,
+                nanos: 456,
+            }),
+            ..Default::default()
+        };
+        document
+            .fields
+            .insert("name".to_owned(), FValue::from("taco").to_grpc_value());
+
+        let snapshot = DocumentSnapshot::<User>::from_document(document).unwrap();
+        assert_eq!("user_1", snapshot.document_id());
+        assert_eq!(
+            Some("/coll_1/doc_1"),
+            snapshot.doc_path.parent_path.as_deref()
+        );
+        assert_eq!(
+            Some(SystemTime::from(Timestamp {
+                seconds: 1_600_000_000,
+                nanos: 123,
+            })),
+            snapshot.create_time
+        );
+        assert_eq!(
+            Some(SystemTime::from(Timestamp {
+                seconds: 1_600_000_060,
+                nanos: 456,
+            })),
+            snapshot.update_time
+        );
+        assert_eq!(
+            User {
+                name: "taco".to_owned()
+            },
+            snapshot.into_data()
+        );
+    }
+
     #[test]
     fn parse_doc_path_test() {
         {
@@ -166,4 +508,66 @@ mod test {
             assert!(result.is_err())
         }
     }
+
+    #[test]
+    fn document_path_from_document_test() {
+        let document = Document {
+            name: "projects/aaa/databases/(default)/documents/coll_1/doc_1/coll_2/AbCdEf0123"
+                .to_owned(),
+            ..Default::default()
+        };
+        let path = FDocumentPath::from_document(&document).unwrap();
+        assert_eq!(
+            FDocumentPath::new(
+                Some("/coll_1/doc_1".to_owned()),
+                "coll_2".to_owned(),
+                "AbCdEf0123".to_owned()
+            ),
+            path
+        );
+        assert_eq!("/coll_1/doc_1/coll_2/AbCdEf0123", path.into_string());
+
+        assert!(FDocumentPath::from_document(&Document::default()).is_err());
+    }
+
+    #[test]
+    fn validate_document_path_test() {
+        assert!(validate_document_path("/users/u1").is_ok());
+        assert!(validate_document_path("/users/u1/orders/o1").is_ok());
+        for invalid in &[
+            "",
+            "/",
+            "users/u1",
+            "/users",
+            "/users/u1/orders",
+            "/users//u1/o",
+        ] {
+            assert!(validate_document_path(invalid).is_err(), "{}", invalid);
+        }
+
+        assert_eq!(
+            "/users/u1/orders",
+            FCollectionPath::new(Some("/users/u1".to_owned()), "orders".to_owned()).into_string()
+        );
+        assert_eq!(
+            "/users",
+            FCollectionPath::new(None, "users".to_owned()).into_string()
+        );
+
+        assert_eq!(
+            FCollectionPath::new(None, "users".to_owned()),
+            FCollectionPath::parse("users").unwrap()
+        );
+        assert_eq!(
+            FCollectionPath::new(None, "users".to_owned()),
+            FCollectionPath::parse("/users").unwrap()
+        );
+        assert_eq!(
+            FCollectionPath::new(Some("/users/u1".to_owned()), "orders".to_owned()),
+            FCollectionPath::parse("users/u1/orders").unwrap()
+        );
+        for invalid in &["", "/", "users/u1", "users/", "users//orders"] {
+            assert!(FCollectionPath::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
 }
@@ -1,9 +1,14 @@
-use super::fvalue::to_fvalue;
+use super::fdoc::{DOCUMENT_ID_FIELD, DOCUMENT_NAME_FIELD};
+use super::field_path::{join_field_path, parse_field_path};
+use super::fmap::{self, FMap};
 use super::fvalue::FValue;
+use super::fvalue::{to_fvalue, to_fvalue_with_casing, FieldCasing};
+#[cfg(feature = "grpc")]
 use super::grpc_values;
+use super::sentinel::{self, FTransform};
 
-use anyhow::{anyhow, Result};
-use std::collections::{hash_map, HashMap};
+use crate::firestore::error::{FirestoreError, Result};
+use std::collections::HashMap;
 use std::iter::FromIterator;
 
 use serde_json::{Map as JMap, Number as JNumber, Value as JValue};
@@ -12,17 +17,17 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Debug, PartialEq, Clone, Deserialize)]
 pub struct FFields {
-    fields: HashMap<String, FValue>,
+    fields: FMap<FValue>,
 }
 
 impl FFields {
-    pub fn new(m: HashMap<String, FValue>) -> Self {
+    pub fn new(m: FMap<FValue>) -> Self {
         Self { fields: m }
     }
 
     pub fn empty() -> Self {
         Self {
-            fields: HashMap::new(),
+            fields: FMap::new(),
         }
     }
 
@@ -34,6 +39,65 @@ impl FFields {
         self.fields.get(key.as_ref())
     }
 
+    /// the value at the field path. e.g. "address.city" or "address.`zip code`"
+    pub fn get_path(&self, path: &str) -> Option<&FValue> {
+        let names = parse_field_path(path).ok()?;
+        let (first, rest) = names.split_first()?;
+        rest.iter()
+            .try_fold(self.fields.get(first)?, |value, name| {
+                value.as_map()?.get(name)
+            })
+    }
+
+    /// set the value at the field path, creating the intermediate maps.
+    /// returns Err if the path is invalid or goes through a value that is not a map.
+    pub fn set_path<T: Into<FValue>>(&mut self, path: &str, v: T) -> Result<()> {
+        let names = parse_field_path(path)?;
+        let (last, parents) = names.split_last().unwrap();
+        let mut fields = &mut self.fields;
+        for name in parents {
+            let value = fields
+                .entry(name.clone())
+                .or_insert_with(|| FValue::Map(FMap::new()));
+            fields = match value {
+                FValue::Map(m) => m,
+                _ => {
+                    return Err(FirestoreError::invalid_argument(format!(
+                        "{} is not a map in the field path {}",
+                        name, path
+                    )))
+                }
+            };
+        }
+        fields.insert(last.clone(), v.into());
+        Ok(())
+    }
+
+    /// overwrite the fields with `other`. the nested maps are merged recursively.
+    pub fn merge(&mut self, other: FFields) {
+        merge_map(&mut self.fields, other.fields);
+    }
+
+    /// the fields changed from `old` to `new` and the update mask of them.
+    /// the mask also has the removed fields, so that they are deleted by the update.
+    ///
+    /// ```ignore
+    /// let (fields, mask) = FFields::diff(&old, &new);
+    /// client
+    ///     .update_document(document_path, fields, Some(mask), None)
+    ///     .await?;
+    /// ```
+    pub fn diff(old: &FFields, new: &FFields) -> (FFields, Vec<String>) {
+        let mut mask = Vec::new();
+        let fields = diff_map(&old.fields, &new.fields, &[], &mut mask);
+        (FFields { fields }, mask)
+    }
+
+    pub fn keys(&self) -> fmap::Keys<'_, FValue> {
+        self.fields.keys()
+    }
+
+    #[cfg(feature = "grpc")]
     pub fn to_grpc_fields(self) -> HashMap<String, grpc_values::Value> {
         self.fields
             .into_iter()
@@ -41,33 +105,86 @@ impl FFields {
             .collect()
     }
 
+    #[cfg(feature = "grpc")]
     pub fn from_grpc_doc(d: grpc_values::Document) -> Self {
-        let fields: HashMap<String, FValue> = d
-            .fields
-            .into_iter()
-            .map(|(k, v)| (k, FValue::from(v)))
-            .collect();
+        let fields = fmap::from_unordered(d.fields.into_iter().map(|(k, v)| (k, FValue::from(v))));
         FFields { fields }
     }
 
     pub fn from_json(jv: JValue) -> Result<Self> {
         match jv {
             JValue::Object(m) => {
-                let fields: HashMap<String, FValue> =
+                let fields: FMap<FValue> =
                     m.into_iter().map(|(k, v)| (k, FValue::from(v))).collect();
                 Ok(Self { fields })
             }
-            _ => Err(anyhow!("giving json value is not a json object")),
+            _ => Err(FirestoreError::invalid_argument(
+                "giving json value is not a json object",
+            )),
         }
     }
 
-    pub fn into_iter(self) -> hash_map::IntoIter<String, FValue> {
+    pub fn into_iter(self) -> fmap::IntoIter<FValue> {
         self.fields.into_iter()
     }
 
     pub fn as_fvalue(self) -> FValue {
         FValue::Map(self.fields)
     }
+
+    /// take the sentinel values (e.g. `ServerTimestamp`, `Increment`) out of the fields.
+    /// returns the remaining fields and the transforms with its field path.
+    pub fn split_transforms(self) -> (FFields, Vec<(String, FTransform)>) {
+        let (fields, transforms) = sentinel::split_transforms(self.fields, None);
+        (FFields { fields }, transforms)
+    }
+}
+
+fn merge_map(dst: &mut FMap<FValue>, src: FMap<FValue>) {
+    for (key, value) in src.into_iter() {
+        match (dst.get_mut(&key), value) {
+            (Some(FValue::Map(dst_nested)), FValue::Map(src_nested)) => {
+                merge_map(dst_nested, src_nested)
+            }
+            (_, value) => {
+                dst.insert(key, value);
+            }
+        }
+    }
+}
+
+fn diff_map(
+    old: &FMap<FValue>,
+    new: &FMap<FValue>,
+    parents: &[String],
+    mask: &mut Vec<String>,
+) -> FMap<FValue> {
+    let path = |key: &String| {
+        let mut names = parents.to_vec();
+        names.push(key.clone());
+        names
+    };
+
+    let mut changed = FMap::new();
+    for (key, new_value) in new.iter() {
+        match (old.get(key), new_value) {
+            (Some(old_value), new_value) if old_value == new_value => {}
+            (Some(FValue::Map(old_nested)), FValue::Map(new_nested)) if !new_nested.is_empty() => {
+                let nested = diff_map(old_nested, new_nested, &path(key), mask);
+                changed.insert(key.clone(), FValue::Map(nested));
+            }
+            _ => {
+                mask.push(join_field_path(&path(key)));
+                changed.insert(key.clone(), new_value.clone());
+            }
+        }
+    }
+    for key in old.keys() {
+        if !new.contains_key(key) {
+            mask.push(join_field_path(&path(key)));
+        }
+    }
+    changed
 }
 
 impl Into<FValue> for FFields {
@@ -78,10 +195,11 @@ impl Into<FValue> for FFields {
 
 impl Into<HashMap<String, FValue>> for FFields {
     fn into(self) -> HashMap<String, FValue> {
-        self.fields
+        self.fields.into_iter().collect()
     }
 }
 
+#[cfg(feature = "grpc")]
 impl Into<HashMap<String, grpc_values::Value>> for FFields {
     fn into(self) -> HashMap<String, grpc_values::Value> {
         self.fields
@@ -91,17 +209,73 @@ impl Into<HashMap<String, grpc_values::Value>> for FFields {
     }
 }
 
-impl<T> From<T> for FFields
+impl From<HashMap<String, FValue>> for FFields {
+    fn from(fields: HashMap<String, FValue>) -> FFields {
+        FFields {
+            fields: fmap::from_unordered(fields),
+        }
+    }
+}
+
+#[cfg(feature = "preserve_order")]
+impl From<indexmap::IndexMap<String, FValue>> for FFields {
+    fn from(fields: indexmap::IndexMap<String, FValue>) -> FFields {
+        FFields { fields }
+    }
+}
+
+#[cfg(feature = "grpc")]
+impl From<HashMap<String, grpc_values::Value>> for FFields {
+    fn from(fields: HashMap<String, grpc_values::Value>) -> FFields {
+        let fields = fmap::from_unordered(fields.into_iter().map(|(k, v)| (k, FValue::from(v))));
+        FFields { fields }
+    }
+}
+
+/// fallible conversion into FFields.
+/// serializable values that are not serialized into a map (e.g. a string or a vec) return Err.
+pub trait TryIntoFFields {
+    fn try_into_ffields(self) -> Result<FFields>;
+
+    /// the field names of the structs stored by `casing`. FFields are as is.
+    fn try_into_ffields_with_casing(self, _casing: &FieldCasing) -> Result<FFields>
+    where
+        Self: Sized,
+    {
+        self.try_into_ffields()
+    }
+}
+
+impl TryIntoFFields for FFields {
+    fn try_into_ffields(self) -> Result<FFields> {
+        Ok(self)
+    }
+}
+
+impl<T> TryIntoFFields for T
 where
     T: Serialize,
 {
-    fn from(from: T) -> FFields {
-        if let Ok(FValue::Map(map_data)) = to_fvalue(from) {
-            FFields { fields: map_data }
-        } else {
-            //TODO(tacogips) TryFrom<T> conflict another impl,so using From<T> instead now.
-            panic!("not ffield compatible value")
+    fn try_into_ffields(self) -> Result<FFields> {
+        fvalue_into_ffields(to_fvalue(self)?)
+    }
+
+    fn try_into_ffields_with_casing(self, casing: &FieldCasing) -> Result<FFields> {
+        fvalue_into_ffields(to_fvalue_with_casing(self, casing)?)
+    }
+}
+
+fn fvalue_into_ffields(value: FValue) -> Result<FFields> {
+    match value {
+        FValue::Map(mut fields) => {
+            fields.remove(DOCUMENT_ID_FIELD);
+            fields.remove(DOCUMENT_NAME_FIELD);
+            Ok(FFields { fields })
         }
+        other => Err(FirestoreError::invalid_argument(format!(
+            "not ffield compatible value: {:?}",
+            other
+        ))),
     }
 }
 
@@ -116,3 +290,75 @@ impl From<FFields> for JValue {
         JValue::Object(JMap::from_iter(m))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fields(json: JValue) -> FFields {
+        FFields::from_json(json).unwrap()
+    }
+
+    #[test]
+    fn merge_test() {
+        let mut base = fields(serde_json::json!({
+            "name": "taco",
+            "address": {"city": "tokyo", "zip": "100"},
+            "tags": ["a"],
+        }));
+        base.merge(fields(serde_json::json!({
+            "address": {"city": "osaka"},
+            "tags": ["b"],
+            "age": 20,
+        })));
+        assert_eq!(
+            fields(serde_json::json!({
+                "name": "taco",
+                "address": {"city": "osaka", "zip": "100"},
+                "tags": ["b"],
+                "age": 20,
+            })),
+            base
+        );
+    }
+
+    #[test]
+    fn diff_test() {
+        let old = fields(serde_json::json!({
+            "name": "taco",
+            "address": {"city": "tokyo", "zip": "100", "a.b": 1},
+            "profile": {"age": 20},
+            "removed": true,
+        }));
+        let new = fields(serde_json::json!({
+            "name": "taco",
+            "address": {"city": "osaka", "zip": "100"},
+            "profile": "private",
+            "added": 1,
+        }));
+        let (changed, mut mask) = FFields::diff(&old, &new);
+        mask.sort();
+        assert_eq!(
+            vec![
+                "added",
+                "address.`a.b`",
+                "address.city",
+                "profile",
+                "removed"
+            ],
+            mask
+        );
+        assert_eq!(
+            fields(serde_json::json!({
+                "address": {"city": "osaka"},
+                "profile": "private",
+                "added": 1,
+            })),
+            changed
+        );
+
+        let (changed, mask) = FFields::diff(&old, &old);
+        assert_eq!(FFields::empty(), changed);
+        assert!(mask.is_empty());
+    }
+}
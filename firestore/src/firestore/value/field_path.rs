@@ -0,0 +1,321 @@
+//! field paths following the firestore rules.
+//! the field names other than `[a-zA-Z_][a-zA-Z_0-9]*` are quoted with backticks,
+//! and the backticks and backslashes in them are escaped with a backslash.
+//!
+//! ```ignore
+//! let mask = FieldMaskBuilder::new()
+//!     .with_path(&["address", "city"])
+//!     .with_field("a.b")
+//!     .build();
+//! assert_eq!(vec!["address.city", "`a.b`"], mask);
+//! ```
+
+use super::ffields::FFields;
+use super::fvalue::FValue;
+use crate::firestore::error::{FirestoreError, Result};
+use std::fmt;
+
+fn is_simple_field_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {}
+        _ => return false,
+    }
+    chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+/// quote the field name with backticks if needed.
+pub fn escape_field_name(name: &str) -> String {
+    if is_simple_field_name(name) {
+        return name.to_owned();
+    }
+    let mut escaped = String::with_capacity(name.len() + 2);
+    escaped.push('`');
+    for c in name.chars() {
+        if c == '`' || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped.push('`');
+    escaped
+}
+
+/// join the unescaped field names into a field path.
+pub fn join_field_path<S: AsRef<str>>(names: &[S]) -> String {
+    names
+        .iter()
+        .map(|name| escape_field_name(name.as_ref()))
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+/// split the field path (e.g. "a.`b.c`.d") into the unescaped field names.
+pub fn parse_field_path(path: &str) -> Result<Vec<String>> {
+    parse_segments(path).map(|segments| segments.into_iter().map(|(name, _)| name).collect())
+}
+
+/// the unescaped field names and whether each of them is quoted.
+fn parse_segments(path: &str) -> Result<Vec<(String, bool)>> {
+    let invalid = || FirestoreError::invalid_argument(format!("invalid field path: {}", path));
+
+    let mut names = Vec::new();
+    let mut chars = path.chars().peekable();
+    loop {
+        let mut name = String::new();
+        let quoted = chars.peek() == Some(&'`');
+        if quoted {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('`') => break,
+                    Some('\\') => name.push(chars.next().ok_or_else(invalid)?),
+                    Some(c) => name.push(c),
+                    None => return Err(invalid()),
+                }
+            }
+        } else {
+            while let Some(c) = chars.peek() {
+                match c {
+                    '.' => break,
+                    '`' => return Err(invalid()),
+                    _ => name.push(chars.next().unwrap()),
+                }
+            }
+        }
+        if name.is_empty() {
+            return Err(invalid());
+        }
+        names.push((name, quoted));
+
+        match chars.next() {
+            Some('.') => continue,
+            None => return Ok(names),
+            Some(_) => return Err(invalid()),
+        }
+    }
+}
+
+/// re-escape the field path. e.g. "`a`.b c" into "a.`b c`"
+#[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+pub(crate) fn normalize_field_path(path: &str) -> Result<String> {
+    parse_field_path(path).map(|names| join_field_path(&names))
+}
+
+/// validate the field path of the filters, the orders and the projections of a query.
+/// firestore can't refer to the elements of the arrays, so the unquoted numbers
+/// (e.g. "tags.0") and the brackets (e.g. "tags[0]") are rejected before the request.
+/// the map keys of numbers are quoted. (e.g. "scores.`2021`" or `FieldPath::new(["scores", "2021"])`)
+#[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+pub(crate) fn validate_query_field_path(path: &str) -> Result<()> {
+    for (name, quoted) in parse_segments(path)? {
+        let index_like =
+            name.chars().all(|c| c.is_ascii_digit()) || name.contains('[') || name.contains(']');
+        if !quoted && index_like {
+            return Err(FirestoreError::invalid_argument(format!(
+                "array index in the field path is not supported by firestore: {}. \
+                 filter the array with array-contains instead, or quote the map key with backticks",
+                path
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// the path of a nested field by the unescaped names. the names are the keys of the maps,
+/// the elements of the arrays can't be referred.
+///
+/// ```ignore
+/// let query = QueryBuilder::collection("users".to_owned(), false)
+///     .filter_bin(FieldPath::new(["profile", "age"]), ">=", 21)
+///     .order_by(FieldPath::new(["profile", "age"]), OrderDirection::Asc);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FieldPath(Vec<String>);
+
+impl FieldPath {
+    pub fn new<I, S>(names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self(names.into_iter().map(Into::into).collect())
+    }
+
+    /// e.g. "a.`b.c`" into `["a", "b.c"]`
+    pub fn parse(path: &str) -> Result<Self> {
+        parse_field_path(path).map(Self)
+    }
+
+    /// the path of the nested field in the map.
+    pub fn child<S: Into<String>>(mut self, name: S) -> Self {
+        self.0.push(name.into());
+        self
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.0
+    }
+}
+
+impl fmt::Display for FieldPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", join_field_path(&self.0))
+    }
+}
+
+impl From<FieldPath> for String {
+    fn from(path: FieldPath) -> String {
+        path.to_string()
+    }
+}
+
+/// build the field paths of the update mask with escaping the field names.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FieldMaskBuilder {
+    paths: Vec<String>,
+}
+
+impl FieldMaskBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the mask of all the leaf fields. the nested maps are expanded into the dotted paths
+    /// so that the other fields in the maps are not overwritten.
+    pub fn from_fields(fields: &FFields) -> Self {
+        let mut builder = Self::new();
+        for key in fields.keys() {
+            if let Some(value) = fields.get(key) {
+                builder.add_leaves(vec![key.clone()], value);
+            }
+        }
+        builder
+    }
+
+    fn add_leaves(&mut self, names: Vec<String>, value: &FValue) {
+        match value {
+            FValue::Map(m) if !m.is_empty() => {
+                for (key, nested) in m.iter() {
+                    let mut nested_names = names.clone();
+                    nested_names.push(key.clone());
+                    self.add_leaves(nested_names, nested);
+                }
+            }
+            _ => self.paths.push(join_field_path(&names)),
+        }
+    }
+
+    /// a top level field. the name is not split at the dots.
+    pub fn with_field<S: AsRef<str>>(self, name: S) -> Self {
+        self.with_path(&[name])
+    }
+
+    /// the nested field of the names. e.g. `&["a", "b", "c"]` for "a.b.c"
+    pub fn with_path<S: AsRef<str>>(mut self, names: &[S]) -> Self {
+        self.paths.push(join_field_path(names));
+        self
+    }
+
+    /// the field paths in the order added, without the duplications.
+    pub fn build(self) -> Vec<String> {
+        let mut paths: Vec<String> = Vec::with_capacity(self.paths.len());
+        for path in self.paths {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        paths
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escape_field_name_test() {
+        assert_eq!("name", escape_field_name("name"));
+        assert_eq!("_a1", escape_field_name("_a1"));
+        assert_eq!("`1a`", escape_field_name("1a"));
+        assert_eq!("`a.b`", escape_field_name("a.b"));
+        assert_eq!("`a\\`b`", escape_field_name("a`b"));
+        assert_eq!("`a\\\\b`", escape_field_name("a\\b"));
+        assert_eq!("`名前`", escape_field_name("名前"));
+    }
+
+    #[test]
+    fn parse_field_path_test() {
+        assert_eq!(vec!["a", "b", "c"], parse_field_path("a.b.c").unwrap());
+        assert_eq!(
+            vec!["a", "b.c", "d"],
+            parse_field_path("a.`b.c`.d").unwrap()
+        );
+        assert_eq!(
+            vec!["a`b", "c\\"],
+            parse_field_path("`a\\`b`.`c\\\\`").unwrap()
+        );
+
+        for invalid in &["", "a..b", "a.", "`a", "a`b`", "`a`b", "``"] {
+            assert!(parse_field_path(invalid).is_err(), "{}", invalid);
+        }
+
+        for names in &[vec!["a", "b.c"], vec!["x`y", "1", "z\\"]] {
+            assert_eq!(*names, parse_field_path(&join_field_path(names)).unwrap());
+        }
+        assert_eq!("a.`b c`", normalize_field_path("`a`.b c").unwrap());
+    }
+
+    #[test]
+    fn field_path_test() {
+        let path = FieldPath::new(["profile", "age"]);
+        assert_eq!("profile.age", String::from(path.clone()));
+        assert_eq!(
+            "profile.age.`first name`",
+            path.child("first name").to_string()
+        );
+        assert_eq!(
+            vec!["a", "b.c"],
+            FieldPath::parse("a.`b.c`").unwrap().names()
+        );
+
+        assert!(validate_query_field_path("profile.age").is_ok());
+        assert!(validate_query_field_path("__name__").is_ok());
+        assert!(validate_query_field_path(&FieldPath::new(["scores", "2021"]).to_string()).is_ok());
+        for invalid in &["tags.0", "tags[0]", "a.tags[1].b", "a..b"] {
+            assert!(
+                matches!(
+                    validate_query_field_path(invalid),
+                    Err(FirestoreError::InvalidArgument(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn field_mask_builder_test() {
+        let mask = FieldMaskBuilder::new()
+            .with_path(&["address", "city"])
+            .with_field("a.b")
+            .with_field("name")
+            .with_path(&["address", "city"])
+            .build();
+        assert_eq!(vec!["address.city", "`a.b`", "name"], mask);
+
+        let mut fields = FFields::empty();
+        fields.set_path("address.`zip code`", "100").unwrap();
+        fields.set_path("address.city", "tokyo").unwrap();
+        fields.add("tags", FValue::Map(Default::default()));
+        assert_eq!(
+            Some(&FValue::from("100")),
+            fields.get_path("address.`zip code`")
+        );
+        assert!(fields.set_path("address.city.name", "x").is_err());
+        assert!(fields.get_path("address.city.name").is_none());
+        let mut mask = FieldMaskBuilder::from_fields(&fields).build();
+        mask.sort();
+        assert_eq!(vec!["address.`zip code`", "address.city", "tags"], mask);
+    }
+}
@@ -0,0 +1,53 @@
+//! the map of `FValue::Map` and `FFields`.
+//!
+//! `HashMap` by default. with the `preserve_order` feature, `IndexMap` keeping the insertion
+//! order, so that the serialized values and the diffs of them are stable.
+
+#[cfg(not(feature = "preserve_order"))]
+pub type FMap<V> = std::collections::HashMap<String, V>;
+#[cfg(feature = "preserve_order")]
+pub type FMap<V> = indexmap::IndexMap<String, V>;
+
+#[cfg(not(feature = "preserve_order"))]
+pub type Keys<'a, V> = std::collections::hash_map::Keys<'a, String, V>;
+#[cfg(feature = "preserve_order")]
+pub type Keys<'a, V> = indexmap::map::Keys<'a, String, V>;
+
+#[cfg(not(feature = "preserve_order"))]
+pub type IntoIter<V> = std::collections::hash_map::IntoIter<String, V>;
+#[cfg(feature = "preserve_order")]
+pub type IntoIter<V> = indexmap::map::IntoIter<String, V>;
+
+/// collect the entries in random order (e.g. the fields of a grpc document).
+/// with `preserve_order`, they are sorted by the key.
+pub(crate) fn from_unordered<V, I>(entries: I) -> FMap<V>
+where
+    I: IntoIterator<Item = (String, V)>,
+{
+    #[cfg(feature = "preserve_order")]
+    {
+        let mut entries: Vec<(String, V)> = entries.into_iter().collect();
+        entries.sort_by(|(k1, _), (k2, _)| k1.cmp(k2));
+        entries.into_iter().collect()
+    }
+    #[cfg(not(feature = "preserve_order"))]
+    {
+        entries.into_iter().collect()
+    }
+}
+
+#[cfg(all(test, feature = "preserve_order"))]
+mod test {
+    use super::from_unordered;
+
+    #[test]
+    fn from_unordered_test() {
+        let m = from_unordered(vec![
+            ("b".to_owned(), 2),
+            ("c".to_owned(), 3),
+            ("a".to_owned(), 1),
+        ]);
+        let keys: Vec<&str> = m.keys().map(|k| k.as_str()).collect();
+        assert_eq!(vec!["a", "b", "c"], keys);
+    }
+}
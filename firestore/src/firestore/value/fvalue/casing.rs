@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// the case of the stored field names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldNameCase {
+    /// the field names of the structs as is (e.g. "created_at")
+    AsIs,
+    /// e.g. "createdAt"
+    Camel,
+    /// e.g. "CreatedAt"
+    Pascal,
+    /// e.g. "created-at"
+    Kebab,
+}
+
+/// the conversion of the field names of the structs (snake_case) into the stored ones on
+/// serialization, and back on deserialization. the keys of the maps (e.g. `HashMap`) are not
+/// converted, nor the names starting with "__" (e.g. `DOCUMENT_ID_FIELD`).
+/// the field paths of the queries and the masks are the stored names (see `to_stored`).
+///
+/// ```ignore
+/// let casing = FieldCasing::camel().with_override("id_token", "IDToken");
+/// let value = to_fvalue_with_casing(&user, &casing)?; // {"displayName": .., "IDToken": ..}
+/// let user: User = from_fvalue_with_casing(value, &casing)?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldCasing {
+    case: FieldNameCase,
+    /// the field name of the struct => the stored name
+    overrides: Arc<HashMap<String, String>>,
+}
+
+impl Default for FieldCasing {
+    fn default() -> Self {
+        Self::new(FieldNameCase::AsIs)
+    }
+}
+
+impl FieldCasing {
+    pub fn new(case: FieldNameCase) -> Self {
+        Self {
+            case,
+            overrides: Arc::new(HashMap::new()),
+        }
+    }
+
+    pub fn camel() -> Self {
+        Self::new(FieldNameCase::Camel)
+    }
+
+    /// store the field `field_name` (of any struct) as `stored_name`.
+    pub fn with_override<F: Into<String>, S: Into<String>>(
+        mut self,
+        field_name: F,
+        stored_name: S,
+    ) -> Self {
+        Arc::make_mut(&mut self.overrides).insert(field_name.into(), stored_name.into());
+        self
+    }
+
+    pub fn case(&self) -> FieldNameCase {
+        self.case
+    }
+
+    /// the stored name of the field of a struct.
+    pub fn to_stored(&self, field_name: &str) -> String {
+        if let Some(stored_name) = self.overrides.get(field_name) {
+            return stored_name.clone();
+        }
+        if field_name.starts_with("__") {
+            return field_name.to_owned();
+        }
+        match self.case {
+            FieldNameCase::AsIs => field_name.to_owned(),
+            FieldNameCase::Camel => join_words(field_name, false),
+            FieldNameCase::Pascal => join_words(field_name, true),
+            FieldNameCase::Kebab => field_name.replace('_', "-"),
+        }
+    }
+}
+
+/// "created_at" => "createdAt" (or "CreatedAt" if `capitalize_first`).
+/// the leading and the trailing '_' are kept.
+fn join_words(field_name: &str, capitalize_first: bool) -> String {
+    let body = field_name.trim_matches('_');
+    let leading = &field_name[..field_name.len() - field_name.trim_start_matches('_').len()];
+    let trailing = &field_name[field_name.trim_end_matches('_').len()..];
+    if body.is_empty() {
+        return field_name.to_owned();
+    }
+
+    let mut stored = String::with_capacity(field_name.len());
+    stored.push_str(leading);
+    let mut capitalize = capitalize_first;
+    for c in body.chars() {
+        if c == '_' {
+            capitalize = true;
+        } else if capitalize {
+            stored.extend(c.to_uppercase());
+            capitalize = false;
+        } else {
+            stored.push(c);
+        }
+    }
+    stored.push_str(trailing);
+    stored
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{from_fvalue, from_fvalue_with_casing, to_fvalue_with_casing, FValue};
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[test]
+    fn to_stored_test() {
+        let camel = FieldCasing::camel().with_override("id_token", "IDToken");
+        assert_eq!("createdAt", camel.to_stored("created_at"));
+        assert_eq!("name", camel.to_stored("name"));
+        assert_eq!("userId2", camel.to_stored("user_id_2"));
+        assert_eq!("_privateField", camel.to_stored("_private_field"));
+        assert_eq!("IDToken", camel.to_stored("id_token"));
+        assert_eq!(
+            "__firestore_document_id__",
+            camel.to_stored("__firestore_document_id__")
+        );
+        // already renamed by serde
+        assert_eq!("displayName", camel.to_stored("displayName"));
+
+        assert_eq!(
+            "CreatedAt",
+            FieldCasing::new(FieldNameCase::Pascal).to_stored("created_at")
+        );
+        assert_eq!(
+            "created-at",
+            FieldCasing::new(FieldNameCase::Kebab).to_stored("created_at")
+        );
+        assert_eq!("created_at", FieldCasing::default().to_stored("created_at"));
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Profile {
+        display_name: String,
+        id_token: Option<String>,
+        home_address: Address,
+        visit_counts: HashMap<String, i64>,
+        tags: Vec<Address>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Address {
+        zip_code: String,
+    }
+
+    #[test]
+    fn serde_with_casing_test() {
+        let mut visit_counts = HashMap::new();
+        visit_counts.insert("home_page".to_owned(), 3i64);
+        let profile = Profile {
+            display_name: "jeff".to_owned(),
+            id_token: Some("t".to_owned()),
+            home_address: Address {
+                zip_code: "100-0001".to_owned(),
+            },
+            visit_counts,
+            tags: vec![Address {
+                zip_code: "0".to_owned(),
+            }],
+        };
+        let casing = FieldCasing::camel().with_override("id_token", "IDToken");
+
+        let value = to_fvalue_with_casing(&profile, &casing).unwrap();
+        let map = value.as_map().unwrap();
+        let mut keys: Vec<&str> = map.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(
+            vec![
+                "IDToken",
+                "displayName",
+                "homeAddress",
+                "tags",
+                "visitCounts"
+            ],
+            keys
+        );
+        assert!(map["homeAddress"].as_map().unwrap().contains_key("zipCode"));
+        assert!(map["tags"].as_array().unwrap()[0]
+            .as_map()
+            .unwrap()
+            .contains_key("zipCode"));
+        // the keys of the maps are as is
+        assert!(map["visitCounts"]
+            .as_map()
+            .unwrap()
+            .contains_key("home_page"));
+
+        assert_eq!(
+            profile,
+            from_fvalue_with_casing::<Profile, FValue>(value.clone(), &casing).unwrap()
+        );
+        assert!(from_fvalue::<Profile, FValue>(value).is_err());
+    }
+}
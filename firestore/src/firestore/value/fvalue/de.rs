@@ -1,33 +1,77 @@
-use super::super::FDocument;
+#[cfg(feature = "grpc")]
+use super::super::fdoc::{DOCUMENT_ID_FIELD, DOCUMENT_NAME_FIELD};
+use super::super::fmap::FMap;
+use super::super::timestamp::{to_seconds_nanos, TIMESTAMP_NEWTYPE};
+#[cfg(feature = "grpc")]
+use super::super::{FDocument, FDocumentPath};
 
 use super::error::SerdeError;
-use super::FValue;
-use std::collections::HashMap;
+use super::{non_finite_from_str, FValue, FieldCasing};
 
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::time::SystemTime;
 use std::vec::IntoIter;
 
+#[cfg(feature = "grpc")]
 use super::grpc_values::Document;
 
 use serde::{
-    de::{DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor},
+    de::{
+        DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+        VariantAccess, Visitor,
+    },
     forward_to_deserialize_any, Deserializer,
 };
 
+/// the document id and name are set to the `DOCUMENT_ID_FIELD` and `DOCUMENT_NAME_FIELD`
+/// fields of `T` if it has.
+#[cfg(feature = "grpc")]
 pub fn from_document<T>(doc: Document) -> Result<T, SerdeError>
 where
     T: DeserializeOwned,
 {
+    let name = doc.name.clone();
     let doc_as_fvalue: FValue = FDocument::from(doc).into();
-    from_fvalue(doc_as_fvalue)
+    T::deserialize(DocumentDeserializer {
+        name,
+        value: doc_as_fvalue,
+        casing: None,
+    })
+}
+
+/// `from_document` reading the field names of the structs stored by `casing`.
+#[cfg(feature = "grpc")]
+pub fn from_document_with_casing<T>(doc: Document, casing: &FieldCasing) -> Result<T, SerdeError>
+where
+    T: DeserializeOwned,
+{
+    let name = doc.name.clone();
+    let doc_as_fvalue: FValue = FDocument::from(doc).into();
+    T::deserialize(DocumentDeserializer {
+        name,
+        value: doc_as_fvalue,
+        casing: Some(casing.clone()),
+    })
 }
 
 pub fn from_fvalue<T, F: Into<FValue>>(fvalue: F) -> Result<T, SerdeError>
 where
     T: DeserializeOwned,
 {
-    deserialize_from_with_seed(fvalue.into(), PhantomData)
+    deserialize_from_with_seed(fvalue.into(), PhantomData, None)
+}
+
+/// `from_fvalue` reading the field names of the structs stored by `casing`. see `FieldCasing`.
+pub fn from_fvalue_with_casing<T, F: Into<FValue>>(
+    fvalue: F,
+    casing: &FieldCasing,
+) -> Result<T, SerdeError>
+where
+    T: DeserializeOwned,
+{
+    deserialize_from_with_seed(fvalue.into(), PhantomData, Some(casing.clone()))
 }
 
 pub fn from_fvalues<T, F: Into<FValue>>(fvalues: Vec<F>) -> Result<Vec<T>, SerdeError>
@@ -36,24 +80,131 @@ where
 {
     fvalues
         .into_iter()
-        .map(|each| deserialize_from_with_seed(each.into(), PhantomData))
+        .map(|each| deserialize_from_with_seed(each.into(), PhantomData, None))
         .collect()
 }
 
-fn deserialize_from_with_seed<T, S>(fvalue: FValue, seed: S) -> Result<T, SerdeError>
+fn deserialize_from_with_seed<T, S>(
+    fvalue: FValue,
+    seed: S,
+    casing: Option<FieldCasing>,
+) -> Result<T, SerdeError>
 where
     S: for<'de> DeserializeSeed<'de, Value = T>,
 {
-    seed.deserialize(FValueDeserializer::from(fvalue))
+    seed.deserialize(FValueDeserializer::with_casing(fvalue, casing))
+}
+
+/// inject the document id and name into the struct which has the fields for them.
+#[cfg(feature = "grpc")]
+struct DocumentDeserializer {
+    name: String,
+    value: FValue,
+    casing: Option<FieldCasing>,
+}
+
+#[cfg(feature = "grpc")]
+impl<'de> Deserializer<'de> for DocumentDeserializer {
+    type Error = SerdeError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        FValueDeserializer::with_casing(self.value, self.casing).deserialize_any(visitor)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let mut value = self.value;
+        if let FValue::Map(m) = &mut value {
+            if fields.contains(&DOCUMENT_ID_FIELD) && !m.contains_key(DOCUMENT_ID_FIELD) {
+                let doc_path = FDocumentPath::parse(&self.name)
+                    .map_err(|e| SerdeError::CustomError(e.to_string()))?;
+                m.insert(
+                    DOCUMENT_ID_FIELD.to_owned(),
+                    FValue::Str(doc_path.document_id),
+                );
+            }
+            if fields.contains(&DOCUMENT_NAME_FIELD) && !m.contains_key(DOCUMENT_NAME_FIELD) {
+                m.insert(DOCUMENT_NAME_FIELD.to_owned(), FValue::Str(self.name));
+            }
+        }
+        FValueDeserializer::with_casing(value, self.casing)
+            .deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        FValueDeserializer::with_casing(self.value, self.casing)
+            .deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_some(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        tuple_struct map ignored_any identifier
+    }
 }
 
 struct FValueDeserializer {
     value: FValue,
+    casing: Option<FieldCasing>,
 }
 impl FValueDeserializer {
     fn from(fvalue: FValue) -> FValueDeserializer {
-        FValueDeserializer { value: fvalue }
+        Self::with_casing(fvalue, None)
+    }
+
+    fn with_casing(fvalue: FValue, casing: Option<FieldCasing>) -> FValueDeserializer {
+        FValueDeserializer {
+            value: fvalue,
+            casing,
+        }
+    }
+}
+
+/// the stored names of the fields renamed into the field names of the struct.
+fn rename_stored_fields(
+    map: FMap<FValue>,
+    fields: &'static [&'static str],
+    casing: &FieldCasing,
+) -> FMap<FValue> {
+    let field_names: HashMap<String, &str> = fields
+        .iter()
+        .map(|field| (casing.to_stored(field), *field))
+        .filter(|(stored, field)| stored != field)
+        .collect();
+    if field_names.is_empty() {
+        return map;
     }
+    map.into_iter()
+        .map(|(key, value)| match field_names.get(&key) {
+            Some(field) => ((*field).to_owned(), value),
+            None => (key, value),
+        })
+        .collect()
 }
 
 impl<'de> Deserializer<'de> for FValueDeserializer {
@@ -70,12 +221,12 @@ impl<'de> Deserializer<'de> for FValueDeserializer {
             FValue::Double(val) => visitor.visit_f64(val),
             FValue::Bool(b) => visitor.visit_bool(b),
             FValue::Bytes(bytes) => visitor.visit_byte_buf(bytes),
-            FValue::Array(_) => self.deserialize_seq(visitor),
+            FValue::Array(_) | FValue::Vector(_) => self.deserialize_seq(visitor),
             FValue::Map(_) => self.deserialize_map(visitor),
-            _ => Err(SerdeError::IncompatibleDeserializeType(format!(
-                "{:?} not deserialze to struct",
-                self.value
-            ))),
+            // e.g. `chrono::DateTime<Utc>` without `timestamp::chrono_utc`
+            FValue::Timestamp(t) => visitor.visit_string(
+                DateTime::<Utc>::from(t).to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            ),
         }
     }
 
@@ -90,14 +241,35 @@ impl<'de> Deserializer<'de> for FValueDeserializer {
     {
         match self.value {
             FValue::NullValue => visitor.visit_none(),
-            _ => visitor.visit_some(FValueDeserializer::from(self.value)),
+            _ => visitor.visit_some(FValueDeserializer::with_casing(self.value, self.casing)),
         }
     }
 
+    /// "NaN", "Infinity" and "-Infinity" stored by `NonFiniteDouble::Str` are read back.
+    fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match &self.value {
+            FValue::Str(s) => match non_finite_from_str(s) {
+                Some(v) => visitor.visit_f64(v),
+                None => self.deserialize_any(visitor),
+            },
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_f64(visitor)
+    }
+
     fn deserialize_struct<V>(
         self,
         name: &'static str,
-        _fields: &'static [&'static str],
+        fields: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
@@ -114,8 +286,12 @@ impl<'de> Deserializer<'de> for FValueDeserializer {
                     self.value
                 )))
             }
-        } else if let FValue::Map(_) = self.value {
-            self.deserialize_map(visitor)
+        } else if let FValue::Map(map) = self.value {
+            let map = match &self.casing {
+                Some(casing) => rename_stored_fields(map, fields, casing),
+                None => map,
+            };
+            FValueDeserializer::with_casing(FValue::Map(map), self.casing).deserialize_map(visitor)
         } else {
             Err(SerdeError::IncompatibleDeserializeType(format!(
                 "{:?} could not deserialze to struct",
@@ -124,12 +300,38 @@ impl<'de> Deserializer<'de> for FValueDeserializer {
         }
     }
 
+    fn deserialize_newtype_struct<V>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if name == TIMESTAMP_NEWTYPE {
+            if let FValue::Timestamp(t) = self.value {
+                let (seconds, nanos) = to_seconds_nanos(t);
+                visitor.visit_newtype_struct(FValueDeserializer::from(FValue::Array(vec![
+                    FValue::Int(seconds),
+                    FValue::Int(nanos as i64),
+                ])))
+            } else {
+                Err(SerdeError::IncompatibleDeserializeType(format!(
+                    "{:?} could not deserialze to timestamp",
+                    self.value
+                )))
+            }
+        } else {
+            self.deserialize_any(visitor)
+        }
+    }
+
     fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
         if let FValue::Map(map_value) = self.value {
-            let map_access = MapFValueAccess::new(map_value);
+            let map_access = MapFValueAccess::new(map_value, self.casing);
             visitor.visit_map(map_access)
         } else {
             Err(SerdeError::IncompatibleDeserializeType(format!(
@@ -143,39 +345,39 @@ impl<'de> Deserializer<'de> for FValueDeserializer {
     where
         V: Visitor<'de>,
     {
-        if let FValue::Array(arr) = self.value {
-            let seq_access = SeqFValueAccess::new(arr);
-            visitor.visit_seq(seq_access)
-        } else {
-            Err(SerdeError::IncompatibleDeserializeType(format!(
+        match self.value {
+            FValue::Array(arr) => visitor.visit_seq(SeqFValueAccess::new(arr, self.casing)),
+            FValue::Vector(vs) => visitor.visit_seq(SeqFValueAccess::new(
+                vs.into_iter().map(FValue::Double).collect(),
+                None,
+            )),
+            _ => Err(SerdeError::IncompatibleDeserializeType(format!(
                 "{:?} could not deserialze to seq",
                 self.value
-            )))
+            ))),
         }
     }
 
     fn deserialize_enum<V>(
         self,
         name: &'static str,
-        variants: &'static [&'static str],
+        _variants: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
         if name == "FValue" {
-            Err(SerdeError::IncompatibleDeserializeType(format!(
-                "deserializing fvalue to fvalue itself is not implemented yet {:?}",
-                variants
-            )))
+            // FValue itself (derived Deserialize) is passed through as is
+            visitor.visit_enum(FValueEnumAccess { fvalue: self.value })
         } else {
             self.deserialize_any(visitor)
         }
     }
 
     forward_to_deserialize_any! {
-        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
-        bytes byte_buf unit unit_struct newtype_struct tuple
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 char str string
+        bytes byte_buf unit unit_struct tuple
         tuple_struct ignored_any identifier
     }
 }
@@ -214,11 +416,12 @@ impl<'de> SeqAccess<'de> for SeqFValueAccessForSystemTime {
 }
 
 /// pass the fValue enum name to the Deserialize visitor created by derive of FValue
-struct FValuePrimitiveDeserializer {
-    value: FValue,
+/// the variant name of the borrowed fvalue
+struct FValuePrimitiveDeserializer<'a> {
+    value: &'a FValue,
 }
 
-impl<'de> Deserializer<'de> for FValuePrimitiveDeserializer {
+impl<'de, 'a> Deserializer<'de> for FValuePrimitiveDeserializer<'a> {
     type Error = SerdeError;
 
     fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
@@ -236,97 +439,95 @@ impl<'de> Deserializer<'de> for FValuePrimitiveDeserializer {
     }
 }
 
-/// TODO(tacogips) deserialize to FValue it self
-//struct FValueEnumAccess {
-//    fvalue: FValue,
-//}
-//
-//impl<'de> EnumAccess<'de> for FValueEnumAccess {
-//    type Error = SerdeError;
-//
-//    type Variant = FValueVariantAccess;
-//
-//    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
-//    where
-//        V: DeserializeSeed<'de>,
-//    {
-//        let field = seed.deserialize(FValuePrimitiveDeserializer { value: self.fvalue })?;
-//
-//        unimplemented!()
-//    }
-//}
-
-/// TODO(tacogips) deserialize to FValue it self
-///// pass the fValue enum name to the Deserialize visitor created by derive of FValue
-//struct FValueVariantAccess {
-//    fvalue: FValue,
-//}
-//impl<'de> VariantAccess<'de> for FValueVariantAccess {
-//    type Error = SerdeError;
-//    fn unit_variant(self) -> Result<(), Self::Error> {
-//        if self.fvalue == FValue::NullValue {
-//            Ok(())
-//        } else {
-//            Err(SerdeError::InvalidFValueVariable(format!(
-//                "could not extract unit variable from {:?}",
-//                self.fvalue
-//            )))
-//        }
-//    }
-//
-//    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
-//    where
-//        T: DeserializeSeed<'de>,
-//    {
-//        //fn deserialize_from_with_seed<T, S>(fvalue: FValue, seed: S) -> Result<T, SerdeError>
-//        //where
-//        //    S: for<'de> DeserializeSeed<'de, Value = T>,
-//
-//        match self.fvalue {
-//            FValue::Str(v) => seed.deserialize(v.into_deserializer()),
-//            FValue::Int(v) => seed.deserialize(v.into_deserializer()),
-//            FValue::Double(v) => seed.deserialize(v.into_deserializer()),
-//            FValue::Bool(v) => seed.deserialize(v.into_deserializer()),
-//            FValue::Bytes(v) => seed.deserialize(v.into_deserializer()),
-//            FValue::Timestamp(v) => seed.deserialize(v.into_deserializer()),
-//            FValue::Array(_) => deserialize_from_with_seed(self.fvalue, seed),
-//            FValue::Map(_) => deserialize_from_with_seed(self.fvalue, seed),
-//            _ => unimplemented!(),
-//        }
-//    }
-//
-//    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
-//    where
-//        V: Visitor<'de>,
-//    {
-//        Err(SerdeError::InvalidFValueVariable(
-//            "could not extract tuple value from any fvalue".to_owned(),
-//        ))
-//    }
-//
-//    fn struct_variant<V>(
-//        self,
-//        fields: &'static [&'static str],
-//        visitor: V,
-//    ) -> Result<V::Value, Self::Error>
-//    where
-//        V: Visitor<'de>,
-//    {
-//        Err(SerdeError::InvalidFValueVariable(format!(
-//            "could not extract tuple value from any fvalue {:?}",
-//            fields
-//        )))
-//    }
-//}
+/// hand the variant and the value of the fvalue to the Deserialize visitor created by derive of FValue
+struct FValueEnumAccess {
+    fvalue: FValue,
+}
+
+impl<'de> EnumAccess<'de> for FValueEnumAccess {
+    type Error = SerdeError;
+
+    type Variant = FValueVariantAccess;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(FValuePrimitiveDeserializer {
+            value: &self.fvalue,
+        })?;
+        Ok((
+            variant,
+            FValueVariantAccess {
+                fvalue: self.fvalue,
+            },
+        ))
+    }
+}
+
+struct FValueVariantAccess {
+    fvalue: FValue,
+}
+
+impl<'de> VariantAccess<'de> for FValueVariantAccess {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        if self.fvalue == FValue::NullValue {
+            Ok(())
+        } else {
+            Err(SerdeError::InvalidFValueVariable(format!(
+                "could not extract unit variable from {:?}",
+                self.fvalue
+            )))
+        }
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.fvalue {
+            // Vec<u8> is deserialized from a seq
+            FValue::Bytes(v) => seed.deserialize(v.into_deserializer()),
+            fvalue => seed.deserialize(FValueDeserializer::from(fvalue)),
+        }
+    }
+
+    fn tuple_variant<V>(self, _len: usize, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(SerdeError::InvalidFValueVariable(
+            "could not extract tuple value from any fvalue".to_owned(),
+        ))
+    }
+
+    fn struct_variant<V>(
+        self,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(SerdeError::InvalidFValueVariable(format!(
+            "could not extract struct value from any fvalue {:?}",
+            fields
+        )))
+    }
+}
 
 struct SeqFValueAccess {
     value_iters: IntoIter<FValue>,
+    casing: Option<FieldCasing>,
 }
 
 impl SeqFValueAccess {
-    fn new(values: Vec<FValue>) -> Self {
+    fn new(values: Vec<FValue>, casing: Option<FieldCasing>) -> Self {
         Self {
             value_iters: values.into_iter(),
+            casing,
         }
     }
 }
@@ -339,7 +540,9 @@ impl<'de> SeqAccess<'de> for SeqFValueAccess {
         T: DeserializeSeed<'de>,
     {
         match self.value_iters.next() {
-            Some(value) => seed.deserialize(FValueDeserializer::from(value)).map(Some),
+            Some(value) => seed
+                .deserialize(FValueDeserializer::with_casing(value, self.casing.clone()))
+                .map(Some),
             None => Ok(None),
         }
     }
@@ -353,15 +556,17 @@ impl<'de> SeqAccess<'de> for SeqFValueAccess {
 }
 
 struct MapFValueAccess {
-    map_iter: <HashMap<String, FValue> as IntoIterator>::IntoIter,
+    map_iter: <FMap<FValue> as IntoIterator>::IntoIter,
     current_value: Option<FValue>,
+    casing: Option<FieldCasing>,
 }
 
 impl MapFValueAccess {
-    fn new(values: HashMap<String, FValue>) -> Self {
+    fn new(values: FMap<FValue>, casing: Option<FieldCasing>) -> Self {
         Self {
             map_iter: values.into_iter(),
             current_value: None,
+            casing,
         }
     }
 }
@@ -388,7 +593,9 @@ impl<'de> MapAccess<'de> for MapFValueAccess {
         T: DeserializeSeed<'de>,
     {
         match self.current_value.take() {
-            Some(value) => seed.deserialize(FValueDeserializer::from(value)),
+            Some(value) => {
+                seed.deserialize(FValueDeserializer::with_casing(value, self.casing.clone()))
+            }
             None => panic!("this panic will be never happend. current value is "),
         }
     }
@@ -401,10 +608,10 @@ impl<'de> MapAccess<'de> for MapFValueAccess {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "grpc"))]
 mod test {
 
-    use super::{from_fvalue, FValue};
+    use super::{from_document, from_fvalue, Document, FValue};
     use serde::Deserialize;
     use std::collections::HashMap;
     use std::time::SystemTime;
@@ -424,8 +631,7 @@ mod test {
         arr: Vec<f64>,
         another: Testing2,
         option_value: Option<f64>,
-        // TODO(tacogips )deserialize to fvalue
-        // fvalue: FValue,
+        fvalue: FValue,
     }
 
     #[test]
@@ -437,8 +643,8 @@ mod test {
         input.insert("arr".to_owned(), FValue::from(vec![123.4f64, 555f64]));
         input.insert("ttt".to_owned(), FValue::from(time));
         input.insert("to_be_some".to_owned(), FValue::from(Some(time)));
-        input.insert("fvalue".to_owned(), FValue::from(9999f64)); // TODO(tacogips )deserialize to fvalue
-        input.insert("option_value".to_owned(), FValue::from(Some(9999f64))); // TODO(tacogips )deserialize to fvalue
+        input.insert("fvalue".to_owned(), FValue::from(9999f64));
+        input.insert("option_value".to_owned(), FValue::from(Some(9999f64)));
 
         let mut another = HashMap::<String, FValue>::new();
         another.insert("the_field".to_owned(), FValue::from(200i64));
@@ -455,5 +661,130 @@ mod test {
         assert_eq!(vec![123.4f64, 555f64], actual.arr);
         assert_eq!(Testing2 { the_field: 200 }, actual.another);
         assert_eq!(Some(9999f64), actual.option_value);
+        assert_eq!(FValue::from(9999f64), actual.fvalue);
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct WithId {
+        #[serde(rename = "__firestore_document_id__")]
+        id: String,
+        #[serde(rename = "__firestore_document_name__")]
+        name: String,
+        value: i64,
+    }
+
+    #[test]
+    fn deserialize_document_with_id() {
+        let mut doc = Document {
+            name: "projects/p/databases/(default)/documents/users/u1/items/i1".to_owned(),
+            ..Default::default()
+        };
+        doc.fields
+            .insert("value".to_owned(), FValue::from(3i64).to_grpc_value());
+
+        let actual: WithId = from_document(doc.clone()).unwrap();
+        assert_eq!(
+            WithId {
+                id: "i1".to_owned(),
+                name: "projects/p/databases/(default)/documents/users/u1/items/i1".to_owned(),
+                value: 3,
+            },
+            actual
+        );
+
+        // the struct without the fields is deserialized as before
+        let actual: Testing2 = from_document(Document {
+            fields: vec![("the_field".to_owned(), FValue::from(1i64).to_grpc_value())]
+                .into_iter()
+                .collect(),
+            ..doc.clone()
+        })
+        .unwrap();
+        assert_eq!(Testing2 { the_field: 1 }, actual);
+
+        let actual: Option<HashMap<String, FValue>> = from_document(doc).unwrap();
+        assert_eq!(Some(&FValue::from(3i64)), actual.unwrap().get("value"));
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct PartiallyTyped {
+        name: String,
+        extra: FValue,
+        others: HashMap<String, FValue>,
+    }
+
+    #[test]
+    fn deserialize_to_fvalue() {
+        let time = SystemTime::now();
+        let mut extra = HashMap::<String, FValue>::new();
+        extra.insert("null".to_owned(), FValue::NullValue);
+        extra.insert("bytes".to_owned(), FValue::Bytes(vec![0, 1, 255]));
+        extra.insert("time".to_owned(), FValue::from(time));
+        extra.insert(
+            "arr".to_owned(),
+            FValue::from(vec![FValue::from(1i64), FValue::from("a".to_owned())]),
+        );
+        let extra = FValue::from(extra);
+
+        let mut others = HashMap::<String, FValue>::new();
+        others.insert("nan".to_owned(), FValue::from("NaN".to_owned()));
+        others.insert("bool".to_owned(), FValue::from(true));
+        others.insert("double".to_owned(), FValue::from(1.5f64));
+
+        let mut input = HashMap::<String, FValue>::new();
+        input.insert("name".to_owned(), FValue::from("doc".to_owned()));
+        input.insert("extra".to_owned(), extra.clone());
+        input.insert("others".to_owned(), FValue::from(others.clone()));
+
+        let actual: PartiallyTyped = from_fvalue(input).unwrap();
+        assert_eq!("doc", actual.name);
+        assert_eq!(extra, actual.extra);
+        assert_eq!(others, actual.others);
+
+        let actual: FValue = from_fvalue(extra.clone()).unwrap();
+        assert_eq!(extra, actual);
+    }
+
+    #[test]
+    fn vector_test() {
+        #[derive(serde::Serialize, Deserialize, Debug, PartialEq)]
+        struct Article {
+            title: String,
+            embedding: FValue,
+            raw_embedding: Vec<f64>,
+        }
+
+        let vector = FValue::vector(vec![0.5, -1.0, 2.0]);
+        let grpc_value = vector.clone().to_grpc_value();
+        assert_eq!(vector, FValue::from(grpc_value));
+
+        let mut fields = HashMap::new();
+        fields.insert("title".to_owned(), FValue::from("a").to_grpc_value());
+        fields.insert("embedding".to_owned(), vector.clone().to_grpc_value());
+        fields.insert("raw_embedding".to_owned(), vector.clone().to_grpc_value());
+        let article: Article = from_document(Document {
+            name: "projects/p/databases/(default)/documents/articles/a".to_owned(),
+            fields,
+            create_time: None,
+            update_time: None,
+        })
+        .unwrap();
+        assert_eq!(vector, article.embedding);
+        assert_eq!(vec![0.5, -1.0, 2.0], article.raw_embedding);
+
+        // the embedding is kept as the vector, not the array
+        let serialized = crate::firestore::to_fvalue(&article).unwrap();
+        assert_eq!(
+            Some(&vector),
+            serialized.as_map().and_then(|m| m.get("embedding"))
+        );
+
+        // the map of the other keys is not a vector
+        let mut not_vector = HashMap::new();
+        not_vector.insert("__type__".to_owned(), FValue::from("__vector__"));
+        not_vector.insert("value".to_owned(), FValue::from(vec![1.0f64]));
+        not_vector.insert("other".to_owned(), FValue::from(true));
+        let not_vector = FValue::from(not_vector);
+        assert_eq!(not_vector, FValue::from(not_vector.clone().to_grpc_value()));
     }
 }
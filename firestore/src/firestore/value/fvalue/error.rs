@@ -10,6 +10,8 @@ pub enum SerdeError {
     IncompatibleDeserializeType(String),
     InvalidMapKey(FValue),
     CustomError(String),
+    /// NaN or ±Infinity with `NonFiniteDouble::Error`
+    NonFiniteDouble(f64),
 }
 impl Display for SerdeError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
@@ -36,6 +38,7 @@ impl std::error::Error for SerdeError {
             SerdeError::IncompatibleDeserializeType(_) => None,
             SerdeError::InvalidMapKey(_) => None,
             SerdeError::CustomError(_) => None,
+            SerdeError::NonFiniteDouble(_) => None,
         }
     }
 }
@@ -0,0 +1,182 @@
+use super::error::SerdeError;
+
+use serde::{
+    de::{DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, Visitor},
+    forward_to_deserialize_any, Deserializer,
+};
+use std::cell::Cell;
+
+/// names of the fields of `T` which must be present to deserialize the document.
+/// the field list comes from `#[derive(Deserialize)]` (renamed names are returned),
+/// and `Option` fields are excluded.
+///
+/// fields with `#[serde(default)]` are treated as required too since it can't be told
+/// from the derived code. fails if `T` is not a struct. (e.g. it has `#[serde(flatten)]`)
+pub fn required_fields<T>() -> Result<Vec<&'static str>, SerdeError>
+where
+    T: DeserializeOwned,
+{
+    let fields = Cell::new(None);
+    let _ = T::deserialize(StructProbe {
+        fields: &fields,
+        probe_field: None,
+        optional: &Cell::new(None),
+    });
+    let fields = fields.get().ok_or_else(|| {
+        SerdeError::IncompatibleDeserializeType(format!(
+            "{} is not a struct",
+            std::any::type_name::<T>()
+        ))
+    })?;
+
+    let mut required = Vec::<&'static str>::new();
+    for each in fields {
+        let optional = Cell::new(None);
+        let _ = T::deserialize(StructProbe {
+            fields: &Cell::new(None),
+            probe_field: Some(each),
+            optional: &optional,
+        });
+        if optional.get() != Some(true) {
+            required.push(each);
+        }
+    }
+    Ok(required)
+}
+
+/// deserializer which records the field list of the struct, and feeds only `probe_field`
+/// to see whether the field is deserialized as an option.
+struct StructProbe<'a> {
+    fields: &'a Cell<Option<&'static [&'static str]>>,
+    probe_field: Option<&'static str>,
+    optional: &'a Cell<Option<bool>>,
+}
+
+impl<'de, 'a> Deserializer<'de> for StructProbe<'a> {
+    type Error = SerdeError;
+
+    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(SerdeError::IncompatibleDeserializeType(
+            "not a struct".to_owned(),
+        ))
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.fields.set(Some(fields));
+        visitor.visit_map(ProbeMapAccess {
+            probe_field: self.probe_field,
+            optional: self.optional,
+        })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+struct ProbeMapAccess<'a> {
+    probe_field: Option<&'static str>,
+    optional: &'a Cell<Option<bool>>,
+}
+
+impl<'de, 'a> MapAccess<'de> for ProbeMapAccess<'a> {
+    type Error = SerdeError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        match self.probe_field.take() {
+            Some(field) => seed.deserialize(field.into_deserializer()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        seed.deserialize(ValueProbe {
+            optional: self.optional,
+        })
+    }
+}
+
+/// records whether the value is requested as an option, then stops the deserialization.
+struct ValueProbe<'a> {
+    optional: &'a Cell<Option<bool>>,
+}
+
+impl<'de, 'a> Deserializer<'de> for ValueProbe<'a> {
+    type Error = SerdeError;
+
+    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.optional.set(Some(false));
+        Err(SerdeError::CustomError("probed".to_owned()))
+    }
+
+    fn deserialize_option<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.optional.set(Some(true));
+        Err(SerdeError::CustomError("probed".to_owned()))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::required_fields;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+    use std::time::SystemTime;
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Nested {
+        the_field: i64,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Testing {
+        something: i64,
+        #[serde(rename = "renamed")]
+        sss: String,
+        ttt: SystemTime,
+        to_be_none: Option<SystemTime>,
+        another: Nested,
+        option_nested: Option<Nested>,
+    }
+
+    #[test]
+    fn required_fields_test() {
+        assert_eq!(
+            vec!["something", "renamed", "ttt", "another"],
+            required_fields::<Testing>().unwrap()
+        );
+        assert!(required_fields::<HashMap<String, i64>>().is_err());
+    }
+}
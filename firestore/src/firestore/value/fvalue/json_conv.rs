@@ -1,39 +1,72 @@
-use super::FValue;
+use super::super::fmap::FMap;
+use super::{non_finite_to_str, FValue, NonFiniteDouble, SerdeError};
 use anyhow::Result;
 use chrono::{offset::Utc, DateTime};
 use serde_json::{Map as JMap, Number as JNumber, Value as JValue};
-use std::collections::HashMap;
 use std::iter::FromIterator;
 use std::time::SystemTime;
 
+/// NaN and ±Infinity are converted into the strings.
 impl From<FValue> for JValue {
     fn from(fvalue: FValue) -> JValue {
-        match fvalue {
-            FValue::NullValue => JValue::Null,
-            FValue::Str(s) => JValue::String(s),
-            FValue::Int(i) => JValue::Number(JNumber::from_f64(i as f64).unwrap()),
-            FValue::Double(v) => JValue::Number(JNumber::from_f64(v).unwrap()),
-            FValue::Bool(b) => JValue::Bool(b),
-            FValue::Bytes(bytes) => JValue::Array(
-                bytes
-                    .into_iter()
-                    .map(|each| JValue::Number(JNumber::from_f64(each as f64).unwrap()))
-                    .collect(),
-            ),
-            FValue::Timestamp(dt) => {
-                let dt: DateTime<Utc> = dt.into();
-                JValue::String(dt.to_rfc3339())
-            }
-            FValue::Array(vs) => JValue::Array(vs.into_iter().map(JValue::from).collect()),
-            FValue::Map(vs) => {
-                let m: Vec<(String, JValue)> =
-                    vs.into_iter().map(|(k, v)| (k, JValue::from(v))).collect();
-                JValue::Object(JMap::from_iter(m))
-            }
-        }
+        fvalue_to_json(fvalue, NonFiniteDouble::Str).unwrap_or(JValue::Null)
     }
 }
 
+impl FValue {
+    /// JSON has no NaN and ±Infinity. `NonFiniteDouble::Keep` converts them into the strings.
+    pub fn to_json_with(self, non_finite: NonFiniteDouble) -> Result<JValue, SerdeError> {
+        fvalue_to_json(self, non_finite)
+    }
+}
+
+fn fvalue_to_json(fvalue: FValue, non_finite: NonFiniteDouble) -> Result<JValue, SerdeError> {
+    let jvalue = match fvalue {
+        FValue::NullValue => JValue::Null,
+        FValue::Str(s) => JValue::String(s),
+        FValue::Int(i) => JValue::Number(JNumber::from_f64(i as f64).unwrap()),
+        FValue::Double(v) => match JNumber::from_f64(v) {
+            Some(n) => JValue::Number(n),
+            None => match non_finite {
+                NonFiniteDouble::Keep | NonFiniteDouble::Str => {
+                    JValue::String(non_finite_to_str(v).to_owned())
+                }
+                NonFiniteDouble::Error => return Err(SerdeError::NonFiniteDouble(v)),
+                NonFiniteDouble::Null => JValue::Null,
+            },
+        },
+        FValue::Bool(b) => JValue::Bool(b),
+        FValue::Bytes(bytes) => JValue::Array(
+            bytes
+                .into_iter()
+                .map(|each| JValue::Number(JNumber::from_f64(each as f64).unwrap()))
+                .collect(),
+        ),
+        FValue::Timestamp(dt) => {
+            let dt: DateTime<Utc> = dt.into();
+            JValue::String(dt.to_rfc3339())
+        }
+        FValue::Array(vs) => JValue::Array(
+            vs.into_iter()
+                .map(|v| fvalue_to_json(v, non_finite))
+                .collect::<Result<_, _>>()?,
+        ),
+        FValue::Vector(vs) => JValue::Array(
+            vs.into_iter()
+                .map(|v| fvalue_to_json(FValue::Double(v), non_finite))
+                .collect::<Result<_, _>>()?,
+        ),
+        FValue::Map(vs) => {
+            let m = vs
+                .into_iter()
+                .map(|(k, v)| Ok((k, fvalue_to_json(v, non_finite)?)))
+                .collect::<Result<Vec<(String, JValue)>, SerdeError>>()?;
+            JValue::Object(JMap::from_iter(m))
+        }
+    };
+    Ok(jvalue)
+}
+
 impl From<JValue> for FValue {
     fn from(jvalue: JValue) -> FValue {
         match jvalue {
@@ -54,7 +87,7 @@ impl From<JValue> for FValue {
                 FValue::Array(values.into_iter().map(|v| FValue::from(v)).collect())
             }
             JValue::Object(object_map) => {
-                let fvalue_map: HashMap<String, FValue> = object_map
+                let fvalue_map: FMap<FValue> = object_map
                     .into_iter()
                     .map(|(key, value)| (key, FValue::from(value)))
                     .collect();
@@ -68,8 +101,8 @@ impl From<JValue> for FValue {
 #[cfg(test)]
 mod test {
 
-    use super::super::FValue;
-    use crate::firestore::value::fvalue::from_fvalue;
+    use super::super::{FValue, NonFiniteDouble, SerdeError};
+    use crate::firestore::value::fvalue::{from_fvalue, to_fvalue, to_fvalue_with};
     use serde::{Deserialize, Serialize};
     use serde_json;
     use serde_json::{Map as JMap, Number as JNumber, Value as JValue};
@@ -97,4 +130,43 @@ mod test {
         assert_eq!("something".to_string(), s.s);
         assert_eq!(12.2f64, s.f);
     }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Measure {
+        v: f64,
+        w: Option<f64>,
+    }
+
+    #[test]
+    fn non_finite_double_test() {
+        let m = Measure {
+            v: f64::INFINITY,
+            w: Some(f64::NAN),
+        };
+
+        let kept = to_fvalue(&m).unwrap();
+        assert_eq!(
+            r#"{"v":"Infinity","w":"NaN"}"#,
+            serde_json::to_string(&JValue::from(kept.clone())).unwrap()
+        );
+        assert!(matches!(
+            kept.to_json_with(NonFiniteDouble::Error),
+            Err(SerdeError::NonFiniteDouble(_))
+        ));
+
+        assert!(matches!(
+            to_fvalue_with(&m, NonFiniteDouble::Error),
+            Err(SerdeError::NonFiniteDouble(_))
+        ));
+
+        assert_eq!(
+            FValue::NullValue,
+            to_fvalue_with(f64::NAN, NonFiniteDouble::Null).unwrap()
+        );
+
+        let strs = to_fvalue_with(&m, NonFiniteDouble::Str).unwrap();
+        let back: Measure = from_fvalue(strs).unwrap();
+        assert_eq!(f64::INFINITY, back.v);
+        assert!(back.w.unwrap().is_nan());
+    }
 }
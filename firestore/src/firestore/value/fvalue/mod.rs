@@ -1,16 +1,74 @@
+use super::fmap::{self, FMap};
+#[cfg(feature = "grpc")]
 use super::grpc_values::{self, ValueType, WriteResult};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::time::SystemTime;
 use strum_macros::AsRefStr;
 
+mod casing;
 mod de;
 mod error;
+mod fields;
 mod json_conv;
 mod ser;
 
-pub use de::{from_document, from_fvalue, from_fvalues};
-pub use ser::{to_fvalue, to_fvalues};
+pub use casing::{FieldCasing, FieldNameCase};
+#[cfg(feature = "grpc")]
+pub use de::{from_document, from_document_with_casing};
+pub use de::{from_fvalue, from_fvalue_with_casing, from_fvalues};
+pub use error::SerdeError;
+pub use fields::required_fields;
+pub use ser::{to_fvalue, to_fvalue_with, to_fvalue_with_casing, to_fvalues};
+
+/// how NaN and ±Infinity doubles are stored.
+/// firestore stores them as doubles, but NaN matches no filter except "is-nan",
+/// and JSON has no representation of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NonFiniteDouble {
+    /// the double as is. as the string in JSON.
+    #[default]
+    Keep,
+    /// fail the conversion.
+    Error,
+    /// null. deserialized into `Option<f64>` as None.
+    Null,
+    /// "NaN", "Infinity" or "-Infinity". deserialized into f64 back.
+    Str,
+}
+
+impl NonFiniteDouble {
+    pub(crate) fn apply(self, v: f64) -> Result<FValue, SerdeError> {
+        if v.is_finite() {
+            return Ok(FValue::Double(v));
+        }
+        match self {
+            NonFiniteDouble::Keep => Ok(FValue::Double(v)),
+            NonFiniteDouble::Error => Err(SerdeError::NonFiniteDouble(v)),
+            NonFiniteDouble::Null => Ok(FValue::NullValue),
+            NonFiniteDouble::Str => Ok(FValue::Str(non_finite_to_str(v).to_owned())),
+        }
+    }
+}
+
+fn non_finite_to_str(v: f64) -> &'static str {
+    if v.is_nan() {
+        "NaN"
+    } else if v.is_sign_positive() {
+        "Infinity"
+    } else {
+        "-Infinity"
+    }
+}
+
+fn non_finite_from_str(s: &str) -> Option<f64> {
+    match s {
+        "NaN" => Some(f64::NAN),
+        "Infinity" => Some(f64::INFINITY),
+        "-Infinity" => Some(f64::NEG_INFINITY),
+        _ => None,
+    }
+}
 
 //TODO(tacogips) deal with Reference And GeoPoint
 #[derive(Debug, PartialEq, Deserialize, Serialize, AsRefStr, Clone)]
@@ -23,7 +81,10 @@ pub enum FValue {
     Bytes(Vec<u8>),
     Timestamp(SystemTime),
     Array(Vec<FValue>),
-    Map(HashMap<String, FValue>),
+    Map(FMap<FValue>),
+    /// the embedding, stored as `{"__type__": "__vector__", "value": [..]}` map.
+    /// see `QueryBuilder::find_nearest`.
+    Vector(Vec<f64>),
 }
 
 /// generate function which turn the enum into Option<{TargetType}>
@@ -60,7 +121,8 @@ impl FValue {
     fvalue_into!(into_bytes, Bytes, Vec<u8>);
     fvalue_into!(into_system, Timestamp, SystemTime);
     fvalue_into!(into_array, Array, Vec<FValue>);
-    fvalue_into!(into_map, Map, HashMap<String, FValue>);
+    fvalue_into!(into_map, Map, FMap<FValue>);
+    fvalue_into!(into_vector, Vector, Vec<f64>);
 
     fvalue_as!(as_string, Str, String);
     fvalue_as!(as_int, Int, i64);
@@ -69,8 +131,16 @@ impl FValue {
     fvalue_as!(as_bytes, Bytes, Vec<u8>);
     fvalue_as!(as_system, Timestamp, SystemTime);
     fvalue_as!(as_array, Array, Vec<FValue>);
-    fvalue_as!(as_map, Map, HashMap<String, FValue>);
+    fvalue_as!(as_map, Map, FMap<FValue>);
+    fvalue_as!(as_vector, Vector, Vec<f64>);
+
+    pub fn vector<V: Into<f64>>(vs: Vec<V>) -> Self {
+        FValue::Vector(vs.into_iter().map(Into::into).collect())
+    }
+}
 
+#[cfg(feature = "grpc")]
+impl FValue {
     pub fn to_grpc_value(self) -> grpc_values::Value {
         self.to_grpc_value_with_depth(0)
     }
@@ -100,6 +170,7 @@ impl FValue {
                     .collect();
                 grpc_values::map_value(vs)
             }
+            FValue::Vector(vs) => grpc_values::vector_value(vs),
         }
     }
 
@@ -122,12 +193,14 @@ impl FValue {
                     .map(|v| Self::from_grpc_value_with_depth(v, depth + 1))
                     .collect(),
             ),
-            Some(ValueType::MapValue(v)) => FValue::Map(
-                v.fields
-                    .into_iter()
-                    .map(|(k, v)| (k, Self::from_grpc_value_with_depth(v, depth + 1)))
-                    .collect(),
-            ),
+            Some(ValueType::MapValue(v)) => match grpc_values::vector_from_map(&v.fields) {
+                Some(vs) => FValue::Vector(vs),
+                None => FValue::Map(fmap::from_unordered(
+                    v.fields
+                        .into_iter()
+                        .map(|(k, v)| (k, Self::from_grpc_value_with_depth(v, depth + 1))),
+                )),
+            },
 
             Some(ValueType::ReferenceValue(_v)) => unimplemented!("reference not supported yet"),
             Some(ValueType::GeoPointValue(_v)) => unimplemented!("geopoint not supported yet"),
@@ -167,12 +240,26 @@ fvalue_from!(f64, Double);
 fvalue_from!(Vec<u8>, Bytes);
 fvalue_from!(SystemTime, Timestamp);
 
+impl From<chrono::DateTime<chrono::Utc>> for FValue {
+    fn from(v: chrono::DateTime<chrono::Utc>) -> Self {
+        FValue::Timestamp(v.into())
+    }
+}
+
+#[cfg(feature = "time")]
+impl From<time::OffsetDateTime> for FValue {
+    fn from(v: time::OffsetDateTime) -> Self {
+        FValue::Timestamp(v.into())
+    }
+}
+
 impl From<&str> for FValue {
     fn from(v: &str) -> Self {
         Self::Str(v.to_string())
     }
 }
 
+#[cfg(feature = "grpc")]
 impl From<grpc_values::Value> for FValue {
     fn from(v: grpc_values::Value) -> Self {
         Self::from_grpc_value(v)
@@ -194,8 +281,19 @@ where
     T: Into<FValue>,
 {
     fn from(v: HashMap<String, T>) -> Self {
-        let v: HashMap<String, FValue> = v.into_iter().map(|(k, v)| (k, v.into())).collect();
-        FValue::Map(v)
+        FValue::Map(fmap::from_unordered(
+            v.into_iter().map(|(k, v)| (k, v.into())),
+        ))
+    }
+}
+
+#[cfg(feature = "preserve_order")]
+impl<T> From<indexmap::IndexMap<String, T>> for FValue
+where
+    T: Into<FValue>,
+{
+    fn from(v: indexmap::IndexMap<String, T>) -> Self {
+        FValue::Map(v.into_iter().map(|(k, v)| (k, v.into())).collect())
     }
 }
 
@@ -217,6 +315,6 @@ pub fn array_value_from_vec<T: Into<FValue>>(m: Vec<T>) -> FValue {
 }
 
 pub fn map_value_from_vec<K: Into<String>, T: Into<FValue>>(m: Vec<(K, T)>) -> FValue {
-    let v: HashMap<String, FValue> = m.into_iter().map(|(k, v)| (k.into(), v.into())).collect();
-    FValue::from(v)
+    let v: FMap<FValue> = m.into_iter().map(|(k, v)| (k.into(), v.into())).collect();
+    FValue::Map(v)
 }
@@ -1,30 +1,95 @@
-use super::FValue;
+use super::super::fmap::FMap;
+use super::super::timestamp::{from_seconds_nanos, TIMESTAMP_NEWTYPE};
+use super::{non_finite_from_str, FValue, FieldCasing, NonFiniteDouble};
 use anyhow::Result;
 
 use serde::ser;
-use std::collections::HashMap;
 
 use super::error::SerdeError;
 use std::time::{Duration, UNIX_EPOCH};
 
+const FVALUE_ENUM_NAME: &str = "FValue";
+
+/// FValue itself (derived Serialize) is passed through as is, instead of `{"FValue":{variant: value}}`
+fn fvalue_passthrough(variant: &'static str, v: FValue) -> Result<FValue, SerdeError> {
+    match (variant, v) {
+        // Vec<u8> is serialized as a seq
+        ("Bytes", FValue::Array(vs)) => vs
+            .into_iter()
+            .map(|each| match each {
+                FValue::Int(v) if (0..=u8::MAX as i64).contains(&v) => Ok(v as u8),
+                each => Err(SerdeError::InvalidFValueVariable(format!(
+                    "{:?} is not a byte",
+                    each
+                ))),
+            })
+            .collect::<Result<Vec<u8>, SerdeError>>()
+            .map(FValue::Bytes),
+        // Vec<f64> is serialized as a seq, the non finite doubles by `NonFiniteDouble`
+        ("Vector", FValue::Array(vs)) => vs
+            .into_iter()
+            .map(|each| {
+                match &each {
+                    FValue::Int(v) => Some(*v as f64),
+                    FValue::Double(v) => Some(*v),
+                    FValue::Str(s) => non_finite_from_str(s),
+                    _ => None,
+                }
+                .ok_or_else(|| {
+                    SerdeError::InvalidFValueVariable(format!("{:?} is not a vector element", each))
+                })
+            })
+            .collect::<Result<Vec<f64>, SerdeError>>()
+            .map(FValue::Vector),
+        (_, v) => Ok(v),
+    }
+}
+
 pub fn to_fvalue<T>(elem: T) -> Result<FValue, SerdeError>
 where
     T: ser::Serialize,
 {
-    elem.serialize(FValueSerializer)
+    to_fvalue_with(elem, NonFiniteDouble::Keep)
+}
+
+/// `to_fvalue` storing NaN and ±Infinity by `non_finite`
+pub fn to_fvalue_with<T>(elem: T, non_finite: NonFiniteDouble) -> Result<FValue, SerdeError>
+where
+    T: ser::Serialize,
+{
+    serialize_with(elem, non_finite, None)
+}
+
+/// `to_fvalue` storing the field names of the structs by `casing`. see `FieldCasing`.
+pub fn to_fvalue_with_casing<T>(elem: T, casing: &FieldCasing) -> Result<FValue, SerdeError>
+where
+    T: ser::Serialize,
+{
+    serialize_with(elem, NonFiniteDouble::Keep, Some(casing.clone()))
+}
+
+fn serialize_with<T>(
+    elem: T,
+    non_finite: NonFiniteDouble,
+    casing: Option<FieldCasing>,
+) -> Result<FValue, SerdeError>
+where
+    T: ser::Serialize,
+{
+    elem.serialize(FValueSerializer { non_finite, casing })
 }
 
 pub fn to_fvalues<T>(elems: Vec<T>) -> Result<Vec<FValue>, SerdeError>
 where
     T: ser::Serialize,
 {
-    elems
-        .into_iter()
-        .map(|each| each.serialize(FValueSerializer))
-        .collect()
+    elems.into_iter().map(to_fvalue).collect()
 }
 
-pub struct FValueSerializer;
+pub struct FValueSerializer {
+    non_finite: NonFiniteDouble,
+    casing: Option<FieldCasing>,
+}
 impl ser::Serializer for FValueSerializer {
     type Ok = FValue;
     type Error = SerdeError;
@@ -74,11 +139,11 @@ impl ser::Serializer for FValueSerializer {
     }
 
     fn serialize_f32(self, v: f32) -> Result<FValue, SerdeError> {
-        Ok(FValue::from(v as f64))
+        self.non_finite.apply(v as f64)
     }
 
     fn serialize_f64(self, v: f64) -> Result<FValue, SerdeError> {
-        Ok(FValue::from(v as f64))
+        self.non_finite.apply(v)
     }
 
     fn serialize_char(self, v: char) -> Result<FValue, SerdeError> {
@@ -104,10 +169,13 @@ impl ser::Serializer for FValueSerializer {
 
     fn serialize_unit_variant(
         self,
-        _name: &'static str,
+        name: &'static str,
         _variant_index: u32,
         variant: &'static str,
     ) -> Result<FValue, SerdeError> {
+        if name == FVALUE_ENUM_NAME {
+            return Ok(FValue::NullValue);
+        }
         Ok(FValue::from(variant.to_string()))
     }
 
@@ -119,8 +187,19 @@ impl ser::Serializer for FValueSerializer {
     where
         T: ser::Serialize,
     {
-        let v = to_fvalue(v)?;
-        let mut m = HashMap::<String, FValue>::new();
+        let v = serialize_with(v, self.non_finite, self.casing.clone())?;
+        if name == TIMESTAMP_NEWTYPE {
+            return match v.as_array().map(|vs| vs.as_slice()) {
+                Some([FValue::Int(seconds), FValue::Int(nanos)]) => Ok(FValue::Timestamp(
+                    from_seconds_nanos(*seconds, *nanos as i32),
+                )),
+                _ => Err(SerdeError::CustomError(format!(
+                    "{:?} is not a timestamp",
+                    v
+                ))),
+            };
+        }
+        let mut m = FMap::<FValue>::new();
         m.insert(name.to_owned(), v);
         Ok(FValue::from(m))
     }
@@ -135,13 +214,16 @@ impl ser::Serializer for FValueSerializer {
     where
         T: ser::Serialize,
     {
-        let v = to_fvalue(value)?;
+        let v = serialize_with(value, self.non_finite, self.casing.clone())?;
+        if name == FVALUE_ENUM_NAME {
+            return fvalue_passthrough(variant, v);
+        }
 
-        let mut val_m = HashMap::<String, FValue>::new();
+        let mut val_m = FMap::<FValue>::new();
         val_m.insert(variant.to_owned(), v);
 
-        let mut m = HashMap::<String, FValue>::new();
-        m.insert(name.to_owned(), FValue::from(val_m));
+        let mut m = FMap::<FValue>::new();
+        m.insert(name.to_owned(), FValue::Map(val_m));
         Ok(FValue::from(m))
     }
 
@@ -153,7 +235,7 @@ impl ser::Serializer for FValueSerializer {
     where
         V: ser::Serialize,
     {
-        to_fvalue(value)
+        serialize_with(value, self.non_finite, self.casing.clone())
     }
 
     fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, SerdeError> {
@@ -162,7 +244,11 @@ impl ser::Serializer for FValueSerializer {
             None => Vec::<FValue>::new(),
         };
 
-        Ok(FValueSerializeSeq { data })
+        Ok(FValueSerializeSeq {
+            data,
+            non_finite: self.non_finite,
+            casing: self.casing,
+        })
     }
 
     fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, SerdeError> {
@@ -190,8 +276,10 @@ impl ser::Serializer for FValueSerializer {
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, SerdeError> {
         Ok(FValueSerializeMap {
             struct_name: None,
-            map_value: HashMap::new(),
+            map_value: FMap::new(),
             current_key: None,
+            non_finite: self.non_finite,
+            casing: self.casing,
         })
     }
 
@@ -202,8 +290,10 @@ impl ser::Serializer for FValueSerializer {
     ) -> Result<Self::SerializeStruct, SerdeError> {
         Ok(FValueSerializeMap {
             struct_name: Some(name.to_owned()),
-            map_value: HashMap::new(),
+            map_value: FMap::new(),
             current_key: None,
+            non_finite: self.non_finite,
+            casing: self.casing,
         })
     }
 
@@ -220,6 +310,8 @@ impl ser::Serializer for FValueSerializer {
 
 pub struct FValueSerializeSeq {
     data: Vec<FValue>,
+    non_finite: NonFiniteDouble,
+    casing: Option<FieldCasing>,
 }
 
 impl ser::SerializeSeq for FValueSerializeSeq {
@@ -231,7 +323,8 @@ impl ser::SerializeSeq for FValueSerializeSeq {
     where
         T: ser::Serialize,
     {
-        self.data.push(to_fvalue(value)?);
+        self.data
+            .push(serialize_with(value, self.non_finite, self.casing.clone())?);
         Ok(())
     }
 
@@ -290,8 +383,20 @@ impl ser::SerializeTupleVariant for FValueSerializeSeq {
 
 pub struct FValueSerializeMap {
     struct_name: Option<String>,
-    map_value: HashMap<String, FValue>,
+    map_value: FMap<FValue>,
     current_key: Option<String>,
+    non_finite: NonFiniteDouble,
+    casing: Option<FieldCasing>,
+}
+
+impl FValueSerializeMap {
+    /// the fields of `SystemTime` are read back by `end`
+    fn stored_key(&self, key: &'static str) -> String {
+        match (&self.casing, self.struct_name.as_deref()) {
+            (Some(casing), name) if name != Some("SystemTime") => casing.to_stored(key),
+            _ => key.to_owned(),
+        }
+    }
 }
 
 impl ser::SerializeMap for FValueSerializeMap {
@@ -302,7 +407,7 @@ impl ser::SerializeMap for FValueSerializeMap {
     where
         T: ser::Serialize,
     {
-        let maybe_str_value = to_fvalue(key)?;
+        let maybe_str_value = serialize_with(key, self.non_finite, self.casing.clone())?;
         if let FValue::Str(key) = maybe_str_value {
             self.current_key = Some(key);
             Ok(())
@@ -315,7 +420,7 @@ impl ser::SerializeMap for FValueSerializeMap {
     where
         T: ser::Serialize,
     {
-        let value = to_fvalue(value)?;
+        let value = serialize_with(value, self.non_finite, self.casing.clone())?;
         match self.current_key.take() {
             Some(key) => self.map_value.insert(key, value),
             None => panic!("no map key found before `{:?}`", value),
@@ -332,9 +437,12 @@ impl ser::SerializeMap for FValueSerializeMap {
         K: ser::Serialize,
         V: ser::Serialize,
     {
-        let maybe_str_value = to_fvalue(key)?;
+        let maybe_str_value = serialize_with(key, self.non_finite, self.casing.clone())?;
         if let FValue::Str(key) = maybe_str_value {
-            self.map_value.insert(key, to_fvalue(value)?);
+            self.map_value.insert(
+                key,
+                serialize_with(value, self.non_finite, self.casing.clone())?,
+            );
             Ok(())
         } else {
             Err(SerdeError::InvalidMapKey(maybe_str_value))
@@ -344,13 +452,15 @@ impl ser::SerializeMap for FValueSerializeMap {
     fn end(self) -> Result<FValue, SerdeError> {
         if let Some(struct_name) = &self.struct_name {
             if struct_name == "SystemTime" {
+                let field = |name: &str| {
+                    self.map_value
+                        .get(name)
+                        .and_then(|t| t.as_int())
+                        .map(|t| *t as u64)
+                        .unwrap_or(0u64)
+                };
                 let system_time = UNIX_EPOCH
-                    + Duration::from_secs(
-                        self.map_value
-                            .get("secs_since_epoch")
-                            .map(|t| *(t.as_int().unwrap_or(&0)) as u64)
-                            .unwrap_or(0u64),
-                    );
+                    + Duration::new(field("secs_since_epoch"), field("nanos_since_epoch") as u32);
                 Ok(FValue::Timestamp(system_time))
             } else {
                 Ok(FValue::from(self.map_value))
@@ -369,7 +479,8 @@ impl ser::SerializeStruct for FValueSerializeMap {
     where
         V: ser::Serialize,
     {
-        ser::SerializeMap::serialize_entry(self, key, value)
+        let key = self.stored_key(key);
+        ser::SerializeMap::serialize_entry(self, &key, value)
     }
 
     fn end(self) -> Result<FValue, SerdeError> {
@@ -385,7 +496,8 @@ impl ser::SerializeStructVariant for FValueSerializeMap {
     where
         V: ser::Serialize,
     {
-        ser::SerializeMap::serialize_entry(self, key, value)
+        let key = self.stored_key(key);
+        ser::SerializeMap::serialize_entry(self, &key, value)
     }
 
     fn end(self) -> Result<FValue, SerdeError> {
@@ -397,7 +509,7 @@ impl ser::SerializeStructVariant for FValueSerializeMap {
 mod test {
 
     use super::super::map_value_from_vec;
-    use super::{to_fvalue, FValue};
+    use super::{fvalue_passthrough, to_fvalue, FValue};
     use std::collections::HashMap;
 
     #[test]
@@ -430,5 +542,44 @@ mod test {
                 map_value_from_vec(vec![("aaa".to_owned(), 100i64), ("bbb".to_owned(), 200i64)]);
             assert_eq!(expected, actual);
         }
+
+        {
+            let mut input = HashMap::<String, FValue>::new();
+            input.insert("aaa".to_owned(), FValue::from("ccc"));
+            input.insert("bbb".to_owned(), FValue::NullValue);
+            input.insert("ddd".to_owned(), FValue::Bytes(vec![1, 2]));
+            input.insert("eee".to_owned(), FValue::from(vec![1i64, 2]));
+            let actual = to_fvalue(input.clone()).unwrap();
+
+            assert_eq!(FValue::from(input), actual);
+        }
+    }
+
+    #[test]
+    fn bytes_passthrough_test() {
+        let bytes = |vs: Vec<FValue>| fvalue_passthrough("Bytes", FValue::Array(vs));
+        assert_eq!(
+            FValue::Bytes(vec![0, 255]),
+            bytes(vec![FValue::Int(0), FValue::Int(255)]).unwrap()
+        );
+        assert!(bytes(vec![FValue::Int(256)]).is_err());
+        assert!(bytes(vec![FValue::Int(-1)]).is_err());
+        assert!(bytes(vec![FValue::from("a")]).is_err());
+    }
+
+    #[test]
+    fn vector_passthrough_test() {
+        let vector = |vs: Vec<FValue>| fvalue_passthrough("Vector", FValue::Array(vs));
+        assert_eq!(
+            FValue::Vector(vec![1.0, 0.5, f64::INFINITY]),
+            vector(vec![
+                FValue::Int(1),
+                FValue::Double(0.5),
+                FValue::from("Infinity")
+            ])
+            .unwrap()
+        );
+        assert!(vector(vec![FValue::from("a")]).is_err());
+        assert!(vector(vec![FValue::NullValue]).is_err());
     }
 }
@@ -4,6 +4,10 @@ use std::collections::HashMap;
 
 pub use google_cloud_grpc_proto::firestore::v1::{value::ValueType, Document, Value, WriteResult};
 
+pub(crate) const VECTOR_TYPE_KEY: &str = "__type__";
+pub(crate) const VECTOR_TYPE: &str = "__vector__";
+pub(crate) const VECTOR_VALUE_KEY: &str = "value";
+
 #[inline]
 pub fn null_value() -> Value {
     Value {
@@ -67,6 +71,37 @@ pub fn map_value(m: HashMap<String, Value>) -> Value {
     }
 }
 
+/// the map of `{"__type__": "__vector__", "value": [..]}`, as firestore stores the vectors.
+pub fn vector_value(vs: Vec<f64>) -> Value {
+    let mut m = HashMap::new();
+    m.insert(VECTOR_TYPE_KEY.to_owned(), str_value(VECTOR_TYPE));
+    m.insert(
+        VECTOR_VALUE_KEY.to_owned(),
+        array_value(vs.into_iter().map(double_value).collect()),
+    );
+    map_value(m)
+}
+
+/// the elements of the map of `vector_value`. None if the map is not a vector.
+pub fn vector_from_map(m: &HashMap<String, Value>) -> Option<Vec<f64>> {
+    match m.get(VECTOR_TYPE_KEY)?.value_type.as_ref()? {
+        ValueType::StringValue(ty) if ty == VECTOR_TYPE && m.len() == 2 => {}
+        _ => return None,
+    }
+    match m.get(VECTOR_VALUE_KEY)?.value_type.as_ref()? {
+        ValueType::ArrayValue(array) => array
+            .values
+            .iter()
+            .map(|v| match v.value_type {
+                Some(ValueType::DoubleValue(v)) => Some(v),
+                Some(ValueType::IntegerValue(v)) => Some(v as f64),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
 #[inline]
 pub fn map_value_from_vec(m: Vec<(String, Value)>) -> Value {
     let v: HashMap<String, Value> = m.into_iter().collect();
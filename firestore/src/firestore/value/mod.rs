@@ -1,13 +1,36 @@
+// the re-exports below are for the client. `firestore::types` re-exports the items by path.
+#![cfg_attr(not(feature = "grpc"), allow(unused_imports))]
+
 pub(crate) mod fdoc;
 pub(crate) mod ffields;
+pub mod field_path;
+pub mod fmap;
 pub mod fvalue;
+#[cfg(feature = "grpc")]
 pub(crate) mod grpc_values;
+#[cfg(feature = "grpc")]
+mod proto;
+pub(crate) mod sentinel;
+pub mod timestamp;
 
-pub use fdoc::{doc_path, FDocument, FDocumentPath};
-pub use ffields::FFields;
-pub use fvalue::{array_value_from_vec, map_value_from_vec, FValue};
+#[cfg(feature = "grpc")]
+pub(crate) use fdoc::decode_document_with_casing;
+pub use fdoc::{doc_path, DocumentSnapshot, FDocument, FDocumentPath};
+pub use ffields::{FFields, TryIntoFFields};
+pub use fmap::FMap;
+pub use fvalue::{FValue, SerdeError};
+#[cfg(feature = "grpc")]
+pub use proto::DeterministicMessage;
+pub use sentinel::FTransform;
 
 pub mod serde {
-    pub use super::fvalue::{from_document, from_fvalue, from_fvalues};
-    pub use super::fvalue::{to_fvalue, to_fvalues};
+    #[cfg(feature = "grpc")]
+    pub use super::fvalue::{from_document, from_document_with_casing};
+    pub use super::fvalue::{
+        from_fvalue, from_fvalue_with_casing, from_fvalues, required_fields, FieldCasing,
+        FieldNameCase,
+    };
+    pub use super::fvalue::{
+        to_fvalue, to_fvalue_with, to_fvalue_with_casing, to_fvalues, NonFiniteDouble,
+    };
 }
@@ -0,0 +1,190 @@
+//! the protobuf messages stored as the bytes with the type tag, for the services keeping the
+//! protobuf payloads in the documents. the value is the map of
+//! `{"__type__": "__proto__", "type_url": <type tag>, "value": <bytes>}`.
+
+use super::{fmap::FMap, FFields, FValue, SerdeError};
+use crate::firestore::error::Result;
+use google_cloud_grpc_proto::prost::Message;
+
+pub(crate) const PROTO_TYPE_KEY: &str = "__type__";
+pub(crate) const PROTO_TYPE: &str = "__proto__";
+pub(crate) const PROTO_TYPE_URL_KEY: &str = "type_url";
+pub(crate) const PROTO_VALUE_KEY: &str = "value";
+
+/// the messages always encoded to the same bytes, for `FValue::from_proto_deterministic`.
+/// prost encodes the fields in the order of the tags, but the `HashMap` map fields in random
+/// order. implement it for the messages without map fields, or with the map fields generated
+/// as `BTreeMap` (`prost_build::Config::btree_map`).
+///
+/// ```ignore
+/// impl DeterministicMessage for Order {}
+/// ```
+pub trait DeterministicMessage: Message {}
+
+impl FValue {
+    /// the message encoded with the type tag. (e.g. "type.googleapis.com/orders.v1.Order")
+    pub fn from_proto<M: Message>(type_url: &str, message: &M) -> FValue {
+        proto_value(type_url, encode(message))
+    }
+
+    /// `from_proto` of the message whose encoding is stable, so that the same message is always
+    /// stored as the same bytes (e.g. for the checksums and the diffs). see `DeterministicMessage`.
+    pub fn from_proto_deterministic<M: DeterministicMessage>(
+        type_url: &str,
+        message: &M,
+    ) -> FValue {
+        Self::from_proto(type_url, message)
+    }
+
+    /// the message of `from_proto`. fails if the type tag is not `type_url`.
+    pub fn to_proto<M: Message + Default>(
+        &self,
+        type_url: &str,
+    ) -> std::result::Result<M, SerdeError> {
+        let (stored_type_url, bytes) = self.as_proto_parts().ok_or_else(|| {
+            SerdeError::IncompatibleDeserializeType(format!("{:?} is not a protobuf message", self))
+        })?;
+        if stored_type_url != type_url {
+            return Err(SerdeError::IncompatibleDeserializeType(format!(
+                "the message is {} but {} expected",
+                stored_type_url, type_url
+            )));
+        }
+        M::decode(bytes)
+            .map_err(|e| SerdeError::CustomError(format!("failed to decode {}: {}", type_url, e)))
+    }
+
+    /// the type tag of the message of `from_proto`.
+    pub fn proto_type_url(&self) -> Option<&str> {
+        self.as_proto_parts().map(|(type_url, _)| type_url)
+    }
+
+    fn as_proto_parts(&self) -> Option<(&str, &[u8])> {
+        let map = match self {
+            FValue::Map(map) if map.len() == 3 => map,
+            _ => return None,
+        };
+        match map.get(PROTO_TYPE_KEY)? {
+            FValue::Str(ty) if ty == PROTO_TYPE => {}
+            _ => return None,
+        }
+        match (map.get(PROTO_TYPE_URL_KEY)?, map.get(PROTO_VALUE_KEY)?) {
+            (FValue::Str(type_url), FValue::Bytes(bytes)) => Some((type_url, bytes)),
+            _ => None,
+        }
+    }
+}
+
+fn encode<M: Message>(message: &M) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(message.encoded_len());
+    // a Vec grows as needed
+    message.encode(&mut bytes).unwrap();
+    bytes
+}
+
+fn proto_value(type_url: &str, bytes: Vec<u8>) -> FValue {
+    let mut map = FMap::new();
+    map.insert(PROTO_TYPE_KEY.to_owned(), FValue::from(PROTO_TYPE));
+    map.insert(PROTO_TYPE_URL_KEY.to_owned(), FValue::from(type_url));
+    map.insert(PROTO_VALUE_KEY.to_owned(), FValue::Bytes(bytes));
+    FValue::Map(map)
+}
+
+impl FFields {
+    /// store the message at the field with the type tag. see `FValue::from_proto`.
+    ///
+    /// ```ignore
+    /// fields.put_proto("order", ORDER_TYPE_URL, &order);
+    /// let order: Option<Order> = fields.get_proto("order", ORDER_TYPE_URL)?;
+    /// ```
+    pub fn put_proto<K: Into<String>, M: Message>(&mut self, name: K, type_url: &str, message: &M) {
+        self.add(name, FValue::from_proto(type_url, message));
+    }
+
+    /// `put_proto` with `FValue::from_proto_deterministic`.
+    pub fn put_proto_deterministic<K, M>(&mut self, name: K, type_url: &str, message: &M)
+    where
+        K: Into<String>,
+        M: DeterministicMessage,
+    {
+        self.add(name, FValue::from_proto_deterministic(type_url, message));
+    }
+
+    /// the message at the field stored by `put_proto`. None if the field doesn't exist, and
+    /// Err if the field is not a message of `type_url`.
+    pub fn get_proto<M: Message + Default>(&self, name: &str, type_url: &str) -> Result<Option<M>> {
+        match self.get(name) {
+            Some(value) => Ok(Some(value.to_proto(type_url)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use google_cloud_grpc_proto::firestore::v1::{Cursor, Document};
+    use google_cloud_grpc_proto::prost_types::Timestamp;
+
+    const DOCUMENT_TYPE_URL: &str = "type.googleapis.com/google.firestore.v1.Document";
+
+    #[test]
+    fn proto_test() {
+        let document = Document {
+            name: "projects/p/databases/(default)/documents/users/u1".to_owned(),
+            update_time: Some(Timestamp {
+                seconds: 1,
+                nanos: 2,
+            }),
+            ..Default::default()
+        };
+        let mut fields = FFields::empty();
+        fields.put_proto("payload", DOCUMENT_TYPE_URL, &document);
+
+        assert_eq!(
+            Some(DOCUMENT_TYPE_URL),
+            fields.get("payload").unwrap().proto_type_url()
+        );
+        assert_eq!(
+            Some(document.clone()),
+            fields.get_proto("payload", DOCUMENT_TYPE_URL).unwrap()
+        );
+        assert_eq!(
+            None,
+            fields
+                .get_proto::<Document>("none", DOCUMENT_TYPE_URL)
+                .unwrap()
+        );
+        assert!(fields
+            .get_proto::<Cursor>("payload", "type.googleapis.com/google.firestore.v1.Cursor")
+            .is_err());
+
+        // round trip through the grpc value
+        let value = FValue::from(fields.get("payload").unwrap().clone().to_grpc_value());
+        assert_eq!(
+            document,
+            value.to_proto::<Document>(DOCUMENT_TYPE_URL).unwrap()
+        );
+    }
+
+    impl DeterministicMessage for Cursor {}
+
+    #[test]
+    fn proto_deterministic_test() {
+        const CURSOR_TYPE_URL: &str = "type.googleapis.com/google.firestore.v1.Cursor";
+        let cursor = Cursor {
+            values: vec![FValue::from(1i64).to_grpc_value()],
+            before: true,
+        };
+        let mut fields = FFields::empty();
+        fields.put_proto_deterministic("cursor", CURSOR_TYPE_URL, &cursor);
+        assert_eq!(
+            Some(&FValue::from_proto(CURSOR_TYPE_URL, &cursor)),
+            fields.get("cursor")
+        );
+        assert_eq!(
+            Some(cursor),
+            fields.get_proto("cursor", CURSOR_TYPE_URL).unwrap()
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use super::field_path::escape_field_name;
+use super::fmap::FMap;
+use super::fvalue::FValue;
+use serde::{Serialize, Serializer};
+
+pub(crate) const SERVER_TIMESTAMP_SENTINEL: &str = "__firestore_server_timestamp__";
+pub(crate) const INCREMENT_SENTINEL: &str = "__firestore_increment__";
+pub(crate) const ARRAY_UNION_SENTINEL: &str = "__firestore_array_union__";
+pub(crate) const ARRAY_REMOVE_SENTINEL: &str = "__firestore_array_remove__";
+
+/// transform applied to a field on the server side after the document is written.
+#[derive(Debug, PartialEq, Clone)]
+pub enum FTransform {
+    ServerTimestamp,
+    Increment(FValue),
+    /// append the elements not in the array yet.
+    ArrayUnion(Vec<FValue>),
+    /// remove all the elements equal to the values from the array.
+    ArrayRemove(Vec<FValue>),
+}
+
+/// set the field to the time the server processed the write.
+///
+/// the field is written through `update_transforms`, not as a document field,
+/// when the struct is passed to `new_write_ope_*` helpers.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct ServerTimestamp;
+
+impl Serialize for ServerTimestamp {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(SERVER_TIMESTAMP_SENTINEL, &())
+    }
+}
+
+/// add the value to the current field value on the server side.
+/// `T` should be serialized into an integer or a double.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Increment<T>(pub T);
+
+impl<T> Serialize for Increment<T>
+where
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(INCREMENT_SENTINEL, &self.0)
+    }
+}
+
+/// append the elements which are not in the array field yet on the server side.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ArrayUnion<T>(pub Vec<T>);
+
+impl<T> Serialize for ArrayUnion<T>
+where
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(ARRAY_UNION_SENTINEL, &self.0)
+    }
+}
+
+/// remove all the elements equal to the values from the array field on the server side.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ArrayRemove<T>(pub Vec<T>);
+
+impl<T> Serialize for ArrayRemove<T>
+where
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(ARRAY_REMOVE_SENTINEL, &self.0)
+    }
+}
+
+/// the sentinels are serialized into single entry maps keyed by the sentinel name.
+fn as_transform(value: &FValue) -> Option<FTransform> {
+    let map = value.as_map()?;
+    if map.len() != 1 {
+        return None;
+    }
+    let (key, inner) = map.iter().next()?;
+    match key.as_str() {
+        SERVER_TIMESTAMP_SENTINEL => Some(FTransform::ServerTimestamp),
+        INCREMENT_SENTINEL => Some(FTransform::Increment(inner.clone())),
+        ARRAY_UNION_SENTINEL => Some(FTransform::ArrayUnion(inner.clone().into_array()?)),
+        ARRAY_REMOVE_SENTINEL => Some(FTransform::ArrayRemove(inner.clone().into_array()?)),
+        _ => None,
+    }
+}
+
+fn join_field_path(parent: Option<&str>, field: &str) -> String {
+    match parent {
+        Some(parent) => format!("{}.{}", parent, escape_field_name(field)),
+        None => escape_field_name(field),
+    }
+}
+
+/// remove the sentinel values from the fields (including nested maps)
+/// and returns them as (field_path, transform).
+pub(crate) fn split_transforms(
+    fields: FMap<FValue>,
+    parent: Option<&str>,
+) -> (FMap<FValue>, Vec<(String, FTransform)>) {
+    let mut remains = FMap::<FValue>::new();
+    let mut transforms = Vec::<(String, FTransform)>::new();
+
+    for (key, value) in fields.into_iter() {
+        let field_path = join_field_path(parent, &key);
+        if let Some(transform) = as_transform(&value) {
+            transforms.push((field_path, transform));
+            continue;
+        }
+
+        match value {
+            FValue::Map(nested) => {
+                let (nested, mut nested_transforms) =
+                    split_transforms(nested, Some(field_path.as_str()));
+                transforms.append(&mut nested_transforms);
+                remains.insert(key, FValue::Map(nested));
+            }
+            other => {
+                remains.insert(key, other);
+            }
+        }
+    }
+    (remains, transforms)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{ffields::TryIntoFFields, fvalue::FValue};
+    use super::{ArrayRemove, ArrayUnion, FTransform, Increment, ServerTimestamp};
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct Inner {
+        count: Increment<i64>,
+        name: String,
+    }
+
+    #[derive(Serialize)]
+    struct Sample {
+        title: String,
+        updated_at: ServerTimestamp,
+        inner: Inner,
+    }
+
+    #[derive(Serialize)]
+    struct Tags {
+        added: ArrayUnion<&'static str>,
+        removed: ArrayRemove<i64>,
+    }
+
+    #[test]
+    fn split_array_sentinel_test() {
+        let tags = Tags {
+            added: ArrayUnion(vec!["a", "b"]),
+            removed: ArrayRemove(vec![1]),
+        };
+        let (fields, mut transforms) = tags.try_into_ffields().unwrap().split_transforms();
+        transforms.sort_by(|l, r| l.0.cmp(&r.0));
+
+        assert_eq!(
+            vec![
+                (
+                    "added".to_owned(),
+                    FTransform::ArrayUnion(vec![FValue::from("a"), FValue::from("b")])
+                ),
+                (
+                    "removed".to_owned(),
+                    FTransform::ArrayRemove(vec![FValue::Int(1)])
+                ),
+            ],
+            transforms
+        );
+        assert!(fields.get("added").is_none());
+    }
+
+    #[test]
+    fn split_sentinel_test() {
+        let sample = Sample {
+            title: "aaa".to_owned(),
+            updated_at: ServerTimestamp,
+            inner: Inner {
+                count: Increment(3),
+                name: "bbb".to_owned(),
+            },
+        };
+
+        let (fields, mut transforms) = sample.try_into_ffields().unwrap().split_transforms();
+        transforms.sort_by(|l, r| l.0.cmp(&r.0));
+
+        assert_eq!(
+            vec![
+                (
+                    "inner.count".to_owned(),
+                    FTransform::Increment(FValue::Int(3))
+                ),
+                ("updated_at".to_owned(), FTransform::ServerTimestamp),
+            ],
+            transforms
+        );
+
+        assert_eq!(Some(&FValue::from("aaa")), fields.get("title"));
+        assert!(fields.get("updated_at").is_none());
+
+        let inner = fields.get("inner").unwrap().as_map().unwrap();
+        assert_eq!(Some(&FValue::from("bbb")), inner.get("name"));
+        assert!(inner.get("count").is_none());
+    }
+}
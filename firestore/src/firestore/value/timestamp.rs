@@ -0,0 +1,262 @@
+//! serde helpers storing date times as firestore timestamps with nanosecond precision.
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct Event {
+//!     #[serde(with = "firestore::firestore::timestamp::chrono_utc")]
+//!     created_at: DateTime<Utc>,
+//!     #[serde(with = "firestore::firestore::timestamp::chrono_utc::option")]
+//!     deleted_at: Option<DateTime<Utc>>,
+//!     // with the `time` feature
+//!     #[serde(with = "firestore::firestore::timestamp::offset_date_time")]
+//!     updated_at: time::OffsetDateTime,
+//! }
+//! ```
+//!
+//! other serializers (e.g. serde_json) see the timestamp as `[seconds, nanos]`.
+
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub(crate) const TIMESTAMP_NEWTYPE: &str = "__firestore_timestamp__";
+
+/// seconds and nanos since the unix epoch, as `google.protobuf.Timestamp`.
+/// the nanos are non-negative even before the epoch.
+pub(crate) fn to_seconds_nanos(t: SystemTime) -> (i64, i32) {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_nanos() as i32),
+        Err(e) => {
+            let d = e.duration();
+            match d.subsec_nanos() {
+                0 => (-(d.as_secs() as i64), 0),
+                nanos => (-(d.as_secs() as i64) - 1, (1_000_000_000 - nanos) as i32),
+            }
+        }
+    }
+}
+
+pub(crate) fn from_seconds_nanos(seconds: i64, nanos: i32) -> SystemTime {
+    let t = if seconds >= 0 {
+        UNIX_EPOCH + Duration::from_secs(seconds as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs())
+    };
+    if nanos >= 0 {
+        t + Duration::from_nanos(nanos as u64)
+    } else {
+        t - Duration::from_nanos(nanos.unsigned_abs() as u64)
+    }
+}
+
+/// the start of the minute of `t`. the point-in-time recovery reads are at the whole minutes.
+pub fn truncate_to_minute(t: SystemTime) -> SystemTime {
+    let (seconds, _) = to_seconds_nanos(t);
+    from_seconds_nanos(seconds - seconds.rem_euclid(60), 0)
+}
+
+/// `SystemTime` serialized as a firestore timestamp.
+struct FTimestamp(SystemTime);
+
+impl Serialize for FTimestamp {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(TIMESTAMP_NEWTYPE, &to_seconds_nanos(self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for FTimestamp {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_newtype_struct(TIMESTAMP_NEWTYPE, FTimestampVisitor)
+    }
+}
+
+struct FTimestampVisitor;
+
+impl<'de> Visitor<'de> for FTimestampVisitor {
+    type Value = FTimestamp;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a timestamp")
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let (seconds, nanos) = <(i64, i32)>::deserialize(deserializer)?;
+        if !(0..1_000_000_000).contains(&nanos) {
+            return Err(de::Error::custom(format!(
+                "invalid nanos of timestamp {}",
+                nanos
+            )));
+        }
+        Ok(FTimestamp(from_seconds_nanos(seconds, nanos)))
+    }
+}
+
+macro_rules! timestamp_serde_module {
+    ($(#[$attr:meta])* $module:ident, $ty:ty) => {
+        $(#[$attr])*
+        pub mod $module {
+            use super::FTimestamp;
+            use serde::{Deserialize, Deserializer, Serialize, Serializer};
+            use std::time::SystemTime;
+
+            pub fn serialize<S>(v: &$ty, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                FTimestamp(SystemTime::from(*v)).serialize(serializer)
+            }
+
+            pub fn deserialize<'de, D>(deserializer: D) -> Result<$ty, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                FTimestamp::deserialize(deserializer).map(|FTimestamp(t)| <$ty>::from(t))
+            }
+
+            pub mod option {
+                use super::super::FTimestamp;
+                use serde::{Deserialize, Deserializer, Serialize, Serializer};
+                use std::time::SystemTime;
+
+                pub fn serialize<S>(v: &Option<$ty>, serializer: S) -> Result<S::Ok, S::Error>
+                where
+                    S: Serializer,
+                {
+                    v.map(|v| FTimestamp(SystemTime::from(v)))
+                        .serialize(serializer)
+                }
+
+                pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<$ty>, D::Error>
+                where
+                    D: Deserializer<'de>,
+                {
+                    Option::<FTimestamp>::deserialize(deserializer)
+                        .map(|v| v.map(|FTimestamp(t)| <$ty>::from(t)))
+                }
+            }
+        }
+    };
+}
+
+timestamp_serde_module!(
+    /// `#[serde(with)]` for `chrono::DateTime<Utc>`.
+    chrono_utc,
+    chrono::DateTime<chrono::Utc>
+);
+
+timestamp_serde_module!(
+    /// `#[serde(with)]` for `time::OffsetDateTime`. the offset is not stored and read as UTC.
+    #[cfg(feature = "time")]
+    offset_date_time,
+    time::OffsetDateTime
+);
+
+#[cfg(test)]
+mod test {
+    use super::super::fvalue::{from_fvalue, to_fvalue, FValue};
+    use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+    use serde::{Deserialize, Serialize};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Event {
+        #[serde(with = "super::chrono_utc")]
+        created_at: DateTime<Utc>,
+        #[serde(with = "super::chrono_utc::option")]
+        deleted_at: Option<DateTime<Utc>>,
+        #[serde(with = "super::chrono_utc::option")]
+        restored_at: Option<DateTime<Utc>>,
+        updated_at: SystemTime,
+    }
+
+    #[test]
+    fn chrono_timestamp_test() {
+        let created_at = Utc.from_utc_datetime(
+            &NaiveDate::from_ymd_opt(2021, 4, 1)
+                .and_then(|date| date.and_hms_nano_opt(12, 30, 15, 123_456_789))
+                .unwrap(),
+        );
+        let before_epoch = Utc.from_utc_datetime(
+            &NaiveDate::from_ymd_opt(1960, 1, 1)
+                .and_then(|date| date.and_hms_nano_opt(0, 0, 0, 5))
+                .unwrap(),
+        );
+        let updated_at = UNIX_EPOCH + Duration::new(1_617_280_215, 987_654_321);
+        let event = Event {
+            created_at,
+            deleted_at: Some(before_epoch),
+            restored_at: None,
+            updated_at,
+        };
+
+        let fvalue = to_fvalue(&event).unwrap();
+        let fields = fvalue.as_map().unwrap();
+        assert_eq!(
+            Some(&FValue::Timestamp(SystemTime::from(created_at))),
+            fields.get("created_at")
+        );
+        assert_eq!(
+            Some(&FValue::Timestamp(SystemTime::from(before_epoch))),
+            fields.get("deleted_at")
+        );
+        assert_eq!(Some(&FValue::NullValue), fields.get("restored_at"));
+        assert_eq!(
+            Some(&FValue::Timestamp(updated_at)),
+            fields.get("updated_at")
+        );
+
+        let actual: Event = from_fvalue(fvalue).unwrap();
+        assert_eq!(event, actual);
+
+        // without `chrono_utc`, read through RFC 3339
+        let plain: DateTime<Utc> = from_fvalue(FValue::Timestamp(created_at.into())).unwrap();
+        assert_eq!(created_at, plain);
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn offset_date_time_test() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Log {
+            #[serde(with = "super::offset_date_time")]
+            at: time::OffsetDateTime,
+        }
+
+        let at =
+            time::OffsetDateTime::from_unix_timestamp_nanos(1_617_280_215_123_456_789).unwrap();
+        let log = Log { at };
+        let fvalue = to_fvalue(&log).unwrap();
+        assert_eq!(
+            Some(&FValue::Timestamp(SystemTime::from(at))),
+            fvalue.as_map().unwrap().get("at")
+        );
+        let actual: Log = from_fvalue(fvalue).unwrap();
+        assert_eq!(log, actual);
+    }
+
+    #[test]
+    fn truncate_to_minute_test() {
+        let t = UNIX_EPOCH + Duration::new(1_617_280_215, 987_654_321);
+        assert_eq!(
+            UNIX_EPOCH + Duration::from_secs(1_617_280_200),
+            super::truncate_to_minute(t)
+        );
+        let before_epoch = UNIX_EPOCH - Duration::from_millis(1500);
+        assert_eq!(
+            UNIX_EPOCH - Duration::from_secs(60),
+            super::truncate_to_minute(before_epoch)
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use super::cancel::{cancellable, CancellationToken};
+use super::request::{add_labels, check_request_size, DocumentWriteOperation, RequestFactory};
+
+use super::error::{FirestoreError, Result};
+use crate::grpc::hooks::HookedChannel;
+use futures::channel::mpsc;
+use google_cloud_grpc_proto::{
+    firestore::v1::{firestore_client, WriteRequest, WriteResponse, WriteResult},
+    tonic::codec::Streaming,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// the position of a write stream. the writes up to the token have been applied.
+/// pass it to `FirestoreClient::open_write_stream` to resume the stream.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WriteStreamToken {
+    pub stream_id: String,
+    pub stream_token: Vec<u8>,
+}
+
+/// the Write streaming rpc. the operations are applied in the order written.
+///
+/// the first request opens (or resumes) the stream and the server returns the stream id and token.
+/// each request afterwards carries the token of the last response to acknowledge it.
+///
+/// ```ignore
+/// let mut stream = client.open_write_stream(None).await?;
+/// let write_results = stream.write(operations).await?;
+/// save_checkpoint(stream.token());
+/// stream.close().await?;
+/// ```
+pub struct WriteStream {
+    project_id: String,
+    request_factory: Arc<dyn RequestFactory>,
+    labels: Arc<HashMap<String, String>>,
+    requests: mpsc::UnboundedSender<WriteRequest>,
+    responses: Streaming<WriteResponse>,
+    token: WriteStreamToken,
+    cancellation: Option<CancellationToken>,
+}
+
+impl WriteStream {
+    pub(crate) async fn open(
+        firestore_client: &mut firestore_client::FirestoreClient<HookedChannel>,
+        request_factory: Arc<dyn RequestFactory>,
+        labels: Arc<HashMap<String, String>>,
+        project_id: String,
+        resume_from: Option<WriteStreamToken>,
+        cancellation: Option<CancellationToken>,
+    ) -> Result<Self> {
+        let resume_from = resume_from.unwrap_or_default();
+        let (requests, receiver) = mpsc::unbounded();
+        // queued before the call, the server doesn't respond until the first request.
+        let mut request = request_factory.new_start_stream_write_request(
+            project_id.clone(),
+            resume_from.stream_id,
+            resume_from.stream_token,
+        );
+        add_labels(&mut request.labels, &labels);
+        send(&requests, request)?;
+
+        let mut responses = cancellable(cancellation.as_ref(), async {
+            firestore_client
+                .write(receiver)
+                .await
+                .map_err(FirestoreError::from)
+        })
+        .await?
+        .into_inner();
+        let handshake = next_response(&mut responses, cancellation.as_ref()).await?;
+
+        Ok(Self {
+            project_id,
+            request_factory,
+            labels,
+            requests,
+            responses,
+            token: WriteStreamToken {
+                stream_id: handshake.stream_id,
+                stream_token: handshake.stream_token,
+            },
+            cancellation,
+        })
+    }
+
+    /// the token of the last response.
+    pub fn token(&self) -> &WriteStreamToken {
+        &self.token
+    }
+
+    /// write the operations and wait until they are applied.
+    pub async fn write(
+        &mut self,
+        operations: Vec<DocumentWriteOperation>,
+    ) -> Result<Vec<WriteResult>> {
+        check_request_size(&operations)?;
+        let mut request = self.request_factory.new_stream_write_request(
+            self.project_id.clone(),
+            operations,
+            self.token.stream_token.clone(),
+        );
+        add_labels(&mut request.labels, &self.labels);
+        send(&self.requests, request)?;
+        let response = next_response(&mut self.responses, self.cancellation.as_ref()).await?;
+        self.token.stream_token = response.stream_token;
+        Ok(response.write_results)
+    }
+
+    /// half-close the stream and wait until the server closes it.
+    pub async fn close(mut self) -> Result<()> {
+        self.requests.close_channel();
+        let responses = &mut self.responses;
+        cancellable(self.cancellation.as_ref(), async {
+            while responses.message().await?.is_some() {}
+            Ok(())
+        })
+        .await
+    }
+}
+
+fn send(requests: &mpsc::UnboundedSender<WriteRequest>, request: WriteRequest) -> Result<()> {
+    requests
+        .unbounded_send(request)
+        .map_err(|_| FirestoreError::Internal("the write stream has been closed".to_owned()))
+}
+
+async fn next_response(
+    responses: &mut Streaming<WriteResponse>,
+    cancellation: Option<&CancellationToken>,
+) -> Result<WriteResponse> {
+    cancellable(cancellation, async {
+        responses.message().await?.ok_or_else(|| {
+            FirestoreError::Internal("the write stream was closed by the server".to_owned())
+        })
+    })
+    .await
+}
@@ -0,0 +1,338 @@
+//! the limits of https://firebase.google.com/docs/firestore/quotas checked before the writes
+//! are sent, instead of the opaque INVALID_ARGUMENT (e.g. "datastore transaction or write too big.").
+
+use super::client::MAX_BATCH_WRTIE_SIZE;
+use super::error::FirestoreError;
+use super::request::{document_write_size, DocumentWriteOperation};
+use super::size_calculator::MAX_DOCUMENT_SIZE;
+use super::value::{field_path::join_field_path, FFields, FValue};
+use std::fmt::{self, Display, Formatter};
+
+/// the max depth of the maps and the arrays in a document. the top level fields are at 1.
+pub const MAX_FIELD_DEPTH: usize = 20;
+/// the max bytes of a field path.
+pub const MAX_FIELD_PATH_SIZE: usize = 1500;
+/// the max index entries of a document.
+pub const MAX_INDEX_ENTRIES: usize = 40_000;
+/// the max size of a request (e.g. a commit).
+pub const MAX_REQUEST_SIZE: usize = 10 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteValidationError {
+    /// more writes than a commit or a batch write of the client accepts. the client takes at most
+    /// `MAX_BATCH_WRTIE_SIZE` (450) writes, fewer than the 500 of firestore, as the requests of
+    /// 500 writes may fail with 413 entity too large.
+    TooManyWrites { num: usize },
+    /// the sizes of the documents written are over `MAX_REQUEST_SIZE` in total.
+    RequestTooLarge { size: usize },
+    /// the document written is over `MAX_DOCUMENT_SIZE`.
+    DocumentTooLarge { document_path: String, size: usize },
+    /// the value at the field path is nested deeper than `MAX_FIELD_DEPTH`.
+    TooDeep {
+        document_path: String,
+        field_path: String,
+    },
+    /// the field path is longer than `MAX_FIELD_PATH_SIZE` bytes.
+    FieldPathTooLong {
+        document_path: String,
+        field_path: String,
+    },
+    /// the automatic single-field index entries of the document are over `MAX_INDEX_ENTRIES`.
+    /// (e.g. a large array)
+    TooManyIndexEntries {
+        document_path: String,
+        entries: usize,
+    },
+}
+
+impl Display for WriteValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteValidationError::TooManyWrites { num } => write!(
+                f,
+                "max batch write size = {} but passed {}",
+                MAX_BATCH_WRTIE_SIZE, num
+            ),
+            WriteValidationError::RequestTooLarge { size } => write!(
+                f,
+                "the writes are {} bytes, over the max request size {}",
+                size, MAX_REQUEST_SIZE
+            ),
+            WriteValidationError::DocumentTooLarge {
+                document_path,
+                size,
+            } => write!(
+                f,
+                "{} is {} bytes, over the max document size {}",
+                document_path, size, MAX_DOCUMENT_SIZE
+            ),
+            WriteValidationError::TooDeep {
+                document_path,
+                field_path,
+            } => write!(
+                f,
+                "{} of {} is nested deeper than {}",
+                field_path, document_path, MAX_FIELD_DEPTH
+            ),
+            WriteValidationError::FieldPathTooLong {
+                document_path,
+                field_path,
+            } => write!(
+                f,
+                "the field path {} of {} is over {} bytes",
+                field_path, document_path, MAX_FIELD_PATH_SIZE
+            ),
+            WriteValidationError::TooManyIndexEntries {
+                document_path,
+                entries,
+            } => write!(
+                f,
+                "{} has {} index entries, over {}",
+                document_path, entries, MAX_INDEX_ENTRIES
+            ),
+        }
+    }
+}
+
+impl From<Vec<WriteValidationError>> for FirestoreError {
+    fn from(errors: Vec<WriteValidationError>) -> Self {
+        FirestoreError::invalid_argument(
+            errors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>()
+                .join(", "),
+        )
+    }
+}
+
+/// check the writes of a commit or a batch write against the limits of firestore.
+/// all the violations are returned. the sizes are estimated as the client checks the requests
+/// before sending them (by `size_calculator`), and the index entries as the automatic
+/// single-field indexes (2 per value, 1 per array element), so the exempted fields are not
+/// considered.
+///
+/// ```ignore
+/// validate_write(&operations).map_err(FirestoreError::from)?;
+/// client.commit(operations, None).await?;
+/// ```
+pub fn validate_write(
+    operations: &[DocumentWriteOperation],
+) -> std::result::Result<(), Vec<WriteValidationError>> {
+    let mut errors = Vec::new();
+    if operations.len() > MAX_BATCH_WRTIE_SIZE {
+        errors.push(WriteValidationError::TooManyWrites {
+            num: operations.len(),
+        });
+    }
+
+    let mut request_size = 0;
+    for operation in operations {
+        // the same estimation as the check before the requests
+        request_size += operation.estimated_size();
+        let document_path = operation.document_path();
+        let fields = match operation.fields() {
+            Some(fields) => fields,
+            None => continue,
+        };
+        let size = document_write_size(document_path, Some(fields));
+        if size > MAX_DOCUMENT_SIZE {
+            errors.push(WriteValidationError::DocumentTooLarge {
+                document_path: document_path.to_owned(),
+                size,
+            });
+        }
+
+        let mut checker = FieldChecker {
+            document_path,
+            errors: &mut errors,
+            index_entries: 0,
+        };
+        let fields = FFields::from(fields.clone());
+        for name in fields.keys() {
+            if let Some(value) = fields.get(name) {
+                checker.check_at(&mut vec![name.clone()], value, 1);
+            }
+        }
+        let entries = checker.index_entries;
+        if entries > MAX_INDEX_ENTRIES {
+            errors.push(WriteValidationError::TooManyIndexEntries {
+                document_path: document_path.to_owned(),
+                entries,
+            });
+        }
+    }
+    if request_size > MAX_REQUEST_SIZE {
+        errors.push(WriteValidationError::RequestTooLarge { size: request_size });
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+struct FieldChecker<'a> {
+    document_path: &'a str,
+    errors: &'a mut Vec<WriteValidationError>,
+    index_entries: usize,
+}
+
+impl FieldChecker<'_> {
+    /// `names` is the path of the map fields to the value. the arrays add the depth only.
+    fn check_at(&mut self, names: &mut Vec<String>, value: &FValue, depth: usize) {
+        if depth > MAX_FIELD_DEPTH {
+            self.errors.push(WriteValidationError::TooDeep {
+                document_path: self.document_path.to_owned(),
+                field_path: join_field_path(names),
+            });
+            return;
+        }
+        let field_path = join_field_path(names);
+        if field_path.len() > MAX_FIELD_PATH_SIZE {
+            self.errors.push(WriteValidationError::FieldPathTooLong {
+                document_path: self.document_path.to_owned(),
+                field_path,
+            });
+            return;
+        }
+
+        // ascending and descending
+        self.index_entries += 2;
+        match value {
+            FValue::Map(map) => {
+                for (name, value) in map.iter() {
+                    names.push(name.clone());
+                    self.check_at(names, value, depth + 1);
+                    names.pop();
+                }
+            }
+            FValue::Array(values) => {
+                // array-contains
+                self.index_entries += values.len();
+                for value in values.iter() {
+                    if matches!(value, FValue::Map(_) | FValue::Array(_)) {
+                        self.check_nested_in_array(names, value, depth + 1);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// the values in the arrays are not indexed by the field paths, but count the depth.
+    fn check_nested_in_array(&mut self, names: &mut Vec<String>, value: &FValue, depth: usize) {
+        if depth > MAX_FIELD_DEPTH {
+            self.errors.push(WriteValidationError::TooDeep {
+                document_path: self.document_path.to_owned(),
+                field_path: join_field_path(names),
+            });
+            return;
+        }
+        match value {
+            FValue::Map(map) => {
+                for value in map.values() {
+                    self.check_nested_in_array(names, value, depth + 1);
+                }
+            }
+            FValue::Array(values) => {
+                for value in values.iter() {
+                    self.check_nested_in_array(names, value, depth + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::firestore::FMap;
+
+    fn upsert(document_path: &str, fields: FFields) -> DocumentWriteOperation {
+        DocumentWriteOperation::new_upsert(document_path.to_owned(), fields.to_grpc_fields())
+    }
+
+    fn nested(depth: usize) -> FValue {
+        (1..depth).fold(FValue::from(1i64), |value, _| {
+            let mut map = FMap::new();
+            map.insert("a".to_owned(), value);
+            FValue::Map(map)
+        })
+    }
+
+    #[test]
+    fn validate_write_test() {
+        let mut fields = FFields::empty();
+        fields.add("name", "jeff");
+        fields.add(
+            "tags",
+            FValue::Array(vec![FValue::from("a"), FValue::from("b")]),
+        );
+        assert_eq!(
+            Ok(()),
+            validate_write(&[
+                upsert("/users/jeff", fields),
+                DocumentWriteOperation::new_delete("/users/bob".to_owned()),
+            ])
+        );
+
+        let deletes: Vec<DocumentWriteOperation> = (0..=MAX_BATCH_WRTIE_SIZE)
+            .map(|i| DocumentWriteOperation::new_delete(format!("/users/u{}", i)))
+            .collect();
+        assert_eq!(
+            Err(vec![WriteValidationError::TooManyWrites {
+                num: MAX_BATCH_WRTIE_SIZE + 1
+            }]),
+            validate_write(&deletes)
+        );
+    }
+
+    #[test]
+    fn validate_write_limits_test() {
+        let mut fields = FFields::empty();
+        fields.add("blob", FValue::Bytes(vec![0; MAX_DOCUMENT_SIZE]));
+        let errors = validate_write(&[upsert("/files/f1", fields)]).unwrap_err();
+        assert!(matches!(
+            errors.as_slice(),
+            [WriteValidationError::DocumentTooLarge { .. }]
+        ));
+
+        // the top level field is at 1
+        let mut fields = FFields::empty();
+        fields.add("ok", nested(MAX_FIELD_DEPTH));
+        fields.add("deep", nested(MAX_FIELD_DEPTH + 1));
+        let errors = validate_write(&[upsert("/trees/t1", fields)]).unwrap_err();
+        assert_eq!(1, errors.len());
+        assert!(matches!(
+            &errors[0],
+            WriteValidationError::TooDeep { field_path, .. }
+                if field_path.starts_with("deep.")
+        ));
+
+        let mut fields = FFields::empty();
+        fields.add("x".repeat(MAX_FIELD_PATH_SIZE + 1), 1i64);
+        let errors = validate_write(&[upsert("/users/u1", fields)]).unwrap_err();
+        assert!(matches!(
+            errors.as_slice(),
+            [WriteValidationError::FieldPathTooLong { .. }]
+        ));
+
+        let mut fields = FFields::empty();
+        fields.add(
+            "ids",
+            FValue::Array((0..MAX_INDEX_ENTRIES as i64).map(FValue::from).collect()),
+        );
+        let errors = validate_write(&[upsert("/users/u1", fields)]).unwrap_err();
+        assert!(matches!(
+            errors.as_slice(),
+            [WriteValidationError::TooManyIndexEntries { entries, .. }]
+                if *entries == MAX_INDEX_ENTRIES + 2
+        ));
+        assert!(FirestoreError::from(errors)
+            .to_string()
+            .contains("/users/u1 has"));
+    }
+}
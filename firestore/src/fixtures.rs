@@ -0,0 +1,204 @@
+//! builders of the grpc `Document`s and `FDocument`s for the unit tests, instead of writing the
+//! prost structs by hand. enabled by the `fixtures` feature (e.g. in the dev-dependencies).
+//!
+//! ```ignore
+//! firestore = { path = "../firestore", features = ["fixtures"] }
+//!
+//! use firestore::fixtures::DocumentFixture;
+//! let document = DocumentFixture::new("/users/user_1")
+//!     .field("name", "taco")
+//!     .field("address.city", "tokyo")
+//!     .updated_after(Duration::from_secs(60))
+//!     .build();
+//! assert_eq!("projects/test-project/databases/(default)/documents/users/user_1", document.name);
+//! ```
+//!
+//! the builders panic on invalid paths, as they are for the tests.
+
+use crate::firestore::{FDocument, FFields, FValue};
+use google_cloud_grpc_proto::firestore::v1::Document;
+use google_cloud_grpc_proto::prost_types::Timestamp;
+use serde_json::Value as JValue;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// the project of the document names by default.
+pub const FIXTURE_PROJECT_ID: &str = "test-project";
+
+/// the create time of the documents by default. 2021-01-01T00:00:00Z
+pub fn fixture_time() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1_609_459_200)
+}
+
+/// the document at the path (e.g. "/users/user_1"). created and last updated at `fixture_time`.
+#[derive(Debug, Clone)]
+pub struct DocumentFixture {
+    project_id: String,
+    document_path: String,
+    fields: FFields,
+    create_time: SystemTime,
+    update_time: SystemTime,
+}
+
+impl DocumentFixture {
+    pub fn new<P: Into<String>>(document_path: P) -> Self {
+        let document_path = document_path.into();
+        assert!(
+            document_path.starts_with('/') && document_path.split('/').count() % 2 == 1,
+            "not a document path: {}",
+            document_path
+        );
+        Self {
+            project_id: FIXTURE_PROJECT_ID.to_owned(),
+            document_path,
+            fields: FFields::empty(),
+            create_time: fixture_time(),
+            update_time: fixture_time(),
+        }
+    }
+
+    /// the fields of the json object. `FFields::from_json` converts the values.
+    pub fn from_json<P: Into<String>>(document_path: P, json: JValue) -> Self {
+        let fields = FFields::from_json(json).expect("the fixture must be a json object");
+        Self::new(document_path).fields(fields)
+    }
+
+    pub fn project_id<S: Into<String>>(self, project_id: S) -> Self {
+        Self {
+            project_id: project_id.into(),
+            ..self
+        }
+    }
+
+    /// set the value at the field path (e.g. "address.city"), creating the intermediate maps.
+    pub fn field<V: Into<FValue>>(mut self, field_path: &str, value: V) -> Self {
+        self.fields
+            .set_path(field_path, value)
+            .unwrap_or_else(|e| panic!("invalid fixture field {}: {}", field_path, e));
+        self
+    }
+
+    /// merged into the fields set before.
+    pub fn fields(mut self, fields: FFields) -> Self {
+        self.fields.merge(fields);
+        self
+    }
+
+    pub fn created_at(self, create_time: SystemTime) -> Self {
+        Self {
+            create_time,
+            update_time: create_time.max(self.update_time),
+            ..self
+        }
+    }
+
+    /// the document has been updated `elapsed` after the creation.
+    pub fn updated_after(self, elapsed: Duration) -> Self {
+        Self {
+            update_time: self.create_time + elapsed,
+            ..self
+        }
+    }
+
+    /// the name in "projects/{project_id}/databases/(default)/documents/{path}".
+    pub fn name(&self) -> String {
+        format!(
+            "projects/{}/databases/(default)/documents{}",
+            self.project_id, self.document_path
+        )
+    }
+
+    pub fn build(self) -> Document {
+        Document {
+            name: self.name(),
+            fields: self.fields.to_grpc_fields(),
+            create_time: Some(Timestamp::from(self.create_time)),
+            update_time: Some(Timestamp::from(self.update_time)),
+        }
+    }
+
+    pub fn build_fdocument(self) -> FDocument {
+        FDocument::from(self.build())
+    }
+}
+
+/// the documents "{collection_path}/{id_prefix}{i}" for i in 0..n, built by `fields`.
+///
+/// ```ignore
+/// let users = documents("/users", "user_", 3, |i, fixture| fixture.field("age", i as i64));
+/// ```
+pub fn documents<F>(collection_path: &str, id_prefix: &str, n: usize, fields: F) -> Vec<Document>
+where
+    F: Fn(usize, DocumentFixture) -> DocumentFixture,
+{
+    (0..n)
+        .map(|i| {
+            let fixture = DocumentFixture::new(format!("{}/{}{}", collection_path, id_prefix, i));
+            fields(i, fixture).build()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::firestore::from_document;
+    use serde_json::json;
+
+    #[test]
+    fn document_fixture_test() {
+        let document = DocumentFixture::new("/users/user_1")
+            .field("name", "taco")
+            .field("address.city", "tokyo")
+            .updated_after(Duration::from_secs(60))
+            .build();
+        assert_eq!(
+            "projects/test-project/databases/(default)/documents/users/user_1",
+            document.name
+        );
+        assert_eq!(
+            Some(Timestamp::from(fixture_time() + Duration::from_secs(60))),
+            document.update_time
+        );
+
+        let fdocument = FDocument::from(document);
+        assert_eq!("user_1", fdocument.doc_path.document_id);
+        assert_eq!(
+            Some(&FValue::from("tokyo")),
+            fdocument.fields.get_path("address.city")
+        );
+    }
+
+    #[test]
+    fn document_fixture_from_json_test() {
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct User {
+            name: String,
+            tags: Vec<String>,
+        }
+
+        let document = DocumentFixture::from_json(
+            "/groups/g1/users/user_2",
+            json!({"name": "taco", "tags": ["a", "b"]}),
+        )
+        .project_id("p")
+        .build();
+        assert_eq!(
+            "projects/p/databases/(default)/documents/groups/g1/users/user_2",
+            document.name
+        );
+        let user: User = from_document(document).unwrap();
+        assert_eq!(vec!["a", "b"], user.tags);
+
+        let users = documents("/users", "user_", 3, |i, fixture| {
+            fixture.field("age", i as i64)
+        });
+        assert_eq!(3, users.len());
+        assert!(users[2].name.ends_with("/users/user_2"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn collection_path_is_not_a_fixture_test() {
+        DocumentFixture::new("/users");
+    }
+}
@@ -0,0 +1,130 @@
+use ring::{digest, hmac};
+
+/// signing request with AWS Signature Version 4.
+/// ref. https://docs.aws.amazon.com/general/latest/gr/sigv4_signing.html
+pub(crate) struct AwsSignatureV4<'a> {
+    pub access_key_id: &'a str,
+    pub secret_access_key: &'a str,
+    pub region: &'a str,
+    pub service: &'a str,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    to_hex(digest::digest(&digest::SHA256, data).as_ref())
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    hmac::sign(&key, data).as_ref().to_vec()
+}
+
+impl<'a> AwsSignatureV4<'a> {
+    /// returns the value of `Authorization` header.
+    /// `headers` must contain all headers to be signed including `host` and `x-amz-date`.
+    /// `amz_date` is formatted as `%Y%m%dT%H%M%SZ`
+    pub fn authorization_header(
+        &self,
+        method: &str,
+        canonical_uri: &str,
+        canonical_query: &str,
+        headers: &[(String, String)],
+        payload: &[u8],
+        amz_date: &str,
+    ) -> String {
+        let mut headers: Vec<(String, String)> = headers
+            .iter()
+            .map(|(k, v)| (k.to_lowercase(), v.trim().to_owned()))
+            .collect();
+        headers.sort_by(|l, r| l.0.cmp(&r.0));
+
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(k, v)| format!("{}:{}\n", k, v))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(k, _)| k.as_str())
+            .collect::<Vec<&str>>()
+            .join(";");
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method,
+            canonical_uri,
+            canonical_query,
+            canonical_headers,
+            signed_headers,
+            sha256_hex(payload)
+        );
+
+        let date_stamp = &amz_date[..8];
+        let credential_scope = format!(
+            "{}/{}/{}/aws4_request",
+            date_stamp, self.region, self.service
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            credential_scope,
+            sha256_hex(canonical_request.as_bytes())
+        );
+
+        let signing_key = {
+            let k_date = hmac_sha256(
+                format!("AWS4{}", self.secret_access_key).as_bytes(),
+                date_stamp.as_bytes(),
+            );
+            let k_region = hmac_sha256(&k_date, self.region.as_bytes());
+            let k_service = hmac_sha256(&k_region, self.service.as_bytes());
+            hmac_sha256(&k_service, b"aws4_request")
+        };
+        let signature = to_hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key_id, credential_scope, signed_headers, signature
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AwsSignatureV4;
+
+    #[test]
+    fn sign_test() {
+        // the example in https://docs.aws.amazon.com/general/latest/gr/sigv4-create-canonical-request.html
+        let signer = AwsSignatureV4 {
+            access_key_id: "AKIDEXAMPLE",
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            region: "us-east-1",
+            service: "iam",
+        };
+
+        let headers = vec![
+            (
+                "Content-Type".to_owned(),
+                "application/x-www-form-urlencoded; charset=utf-8".to_owned(),
+            ),
+            ("Host".to_owned(), "iam.amazonaws.com".to_owned()),
+            ("X-Amz-Date".to_owned(), "20150830T123600Z".to_owned()),
+        ];
+        let actual = signer.authorization_header(
+            "GET",
+            "/",
+            "Action=ListUsers&Version=2010-05-08",
+            &headers,
+            b"",
+            "20150830T123600Z",
+        );
+
+        assert_eq!(
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, SignedHeaders=content-type;host;x-amz-date, Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7",
+            actual
+        );
+    }
+}
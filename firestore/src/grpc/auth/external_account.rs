@@ -0,0 +1,397 @@
+use super::aws::AwsSignatureV4;
+use super::scopes::{self, Scope};
+use anyhow::{anyhow, Result};
+use chrono::{offset::Utc, DateTime, Duration};
+use hyper::{self, Body, Method, Request};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use serde_json::{json, Value as JValue};
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use yup_oauth2::AccessToken;
+
+const EXTERNAL_ACCOUNT_TYPE: &str = "external_account";
+const TOKEN_EXCHANGE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+const IMPERSONATED_TOKEN_LIFETIME: &str = "3600s";
+
+/// the external account credential configuration file (workload identity federation).
+/// ref. https://google.aip.dev/auth/4117
+#[derive(Deserialize, Debug, Clone)]
+pub struct ExternalAccountKey {
+    #[serde(rename = "type")]
+    pub key_type: String,
+    pub audience: String,
+    pub subject_token_type: String,
+    pub token_url: String,
+    pub service_account_impersonation_url: Option<String>,
+    pub credential_source: CredentialSource,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CredentialSource {
+    pub file: Option<String>,
+    pub url: Option<String>,
+    pub headers: Option<HashMap<String, String>>,
+    pub format: Option<CredentialSourceFormat>,
+
+    // for AWS
+    pub environment_id: Option<String>,
+    pub region_url: Option<String>,
+    pub regional_cred_verification_url: Option<String>,
+    pub imdsv2_session_token_url: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CredentialSourceFormat {
+    #[serde(rename = "type")]
+    pub format_type: String,
+    pub subject_token_field_name: Option<String>,
+}
+
+pub async fn read_external_account_key(path: PathBuf) -> Result<ExternalAccountKey> {
+    let contents = tokio::fs::read(&path).await?;
+    let key: ExternalAccountKey = serde_json::from_slice(&contents)?;
+    if key.key_type != EXTERNAL_ACCOUNT_TYPE {
+        return Err(anyhow!(
+            "credential type must be {} but {}",
+            EXTERNAL_ACCOUNT_TYPE,
+            key.key_type
+        ));
+    }
+    Ok(key)
+}
+
+fn form_urlencoded(params: &[(&str, &str)]) -> String {
+    params
+        .iter()
+        .map(|(k, v)| {
+            format!(
+                "{}={}",
+                utf8_percent_encode(k, NON_ALPHANUMERIC),
+                utf8_percent_encode(v, NON_ALPHANUMERIC)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+/// `AccessToken` has no public constructor, so it is built through its serde representation.
+pub(crate) fn new_access_token(access_token: String, expires_at: DateTime<Utc>) -> AccessToken {
+    serde_json::from_value(serde_json::json!({
+        "value": access_token,
+        "expires_at": expires_at,
+    }))
+    .expect("access token is always deserializable")
+}
+
+#[derive(Deserialize)]
+struct StsTokenResponse {
+    access_token: String,
+    expires_in: Option<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ImpersonatedTokenResponse {
+    access_token: String,
+    expire_time: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AwsSecurityCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    token: Option<String>,
+}
+
+/// exchange the credential of the external identity provider for a google access token via STS.
+pub struct ExternalAccountAuthenticator<HttpConnector> {
+    key: ExternalAccountKey,
+    client: hyper::Client<HttpConnector>,
+}
+
+impl<HttpConnector> ExternalAccountAuthenticator<HttpConnector>
+where
+    HttpConnector: hyper::client::connect::Connect + Clone + Send + Sync + 'static,
+{
+    pub fn new(key: ExternalAccountKey, client: hyper::Client<HttpConnector>) -> Self {
+        Self { key, client }
+    }
+
+    pub async fn token(&self, scopes: &[Scope]) -> Result<AccessToken> {
+        let subject_token = self.subject_token().await?;
+
+        let sts_scope: Vec<Scope> = if self.key.service_account_impersonation_url.is_some() {
+            vec![&scopes::CLOUD_PLATFORM]
+        } else {
+            scopes.to_vec()
+        };
+        let sts_token = self.exchange_token(subject_token, &sts_scope).await?;
+
+        match &self.key.service_account_impersonation_url {
+            Some(impersonation_url) => {
+                self.impersonate(impersonation_url, sts_token.as_str(), scopes)
+                    .await
+            }
+            None => Ok(sts_token),
+        }
+    }
+
+    async fn send(&self, req: Request<Body>) -> Result<Vec<u8>> {
+        let uri = req.uri().clone();
+        let resp = self.client.request(req).await?;
+        let status = resp.status();
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        if !status.is_success() {
+            return Err(anyhow!(
+                "request to {} failed with {}: {}",
+                uri,
+                status,
+                String::from_utf8_lossy(&body)
+            ));
+        }
+        Ok(body.to_vec())
+    }
+
+    async fn exchange_token(&self, subject_token: String, scopes: &[Scope]) -> Result<AccessToken> {
+        let scope = scopes.join(" ");
+        let body = form_urlencoded(&[
+            ("grant_type", TOKEN_EXCHANGE_GRANT_TYPE),
+            ("audience", self.key.audience.as_str()),
+            ("scope", scope.as_str()),
+            ("requested_token_type", ACCESS_TOKEN_TYPE),
+            ("subject_token", subject_token.as_str()),
+            ("subject_token_type", self.key.subject_token_type.as_str()),
+        ]);
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(self.key.token_url.as_str())
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(Body::from(body))?;
+
+        let resp: StsTokenResponse = serde_json::from_slice(&self.send(req).await?)?;
+        let expires_at = Utc::now() + Duration::seconds(resp.expires_in.unwrap_or(3600));
+        Ok(new_access_token(resp.access_token, expires_at))
+    }
+
+    async fn impersonate(
+        &self,
+        impersonation_url: &str,
+        sts_token: &str,
+        scopes: &[Scope],
+    ) -> Result<AccessToken> {
+        let body = json!({
+            "scope": scopes,
+            "lifetime": IMPERSONATED_TOKEN_LIFETIME,
+        });
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(impersonation_url)
+            .header("content-type", "application/json")
+            .header("authorization", format!("Bearer {}", sts_token))
+            .body(Body::from(body.to_string()))?;
+
+        let resp: ImpersonatedTokenResponse = serde_json::from_slice(&self.send(req).await?)?;
+        let expires_at = DateTime::parse_from_rfc3339(&resp.expire_time)?.with_timezone(&Utc);
+        Ok(new_access_token(resp.access_token, expires_at))
+    }
+
+    async fn subject_token(&self) -> Result<String> {
+        let source = &self.key.credential_source;
+        if let Some(environment_id) = &source.environment_id {
+            if !environment_id.starts_with("aws") {
+                return Err(anyhow!("unsupported environment_id {}", environment_id));
+            }
+            return self.aws_subject_token().await;
+        }
+
+        let raw = if let Some(file) = &source.file {
+            tokio::fs::read(file)
+                .await
+                .map_err(|e| anyhow!("failed to read credential source file {}: {}", file, e))?
+        } else if let Some(url) = &source.url {
+            let mut req = Request::builder().method(Method::GET).uri(url.as_str());
+            for (k, v) in source.headers.iter().flatten() {
+                req = req.header(k.as_str(), v.as_str());
+            }
+            self.send(req.body(Body::empty())?).await?
+        } else {
+            return Err(anyhow!(
+                "credential_source must have one of file, url or environment_id"
+            ));
+        };
+
+        match &source.format {
+            Some(format) if format.format_type == "json" => {
+                let field_name = format.subject_token_field_name.as_ref().ok_or_else(|| {
+                    anyhow!("subject_token_field_name is required for json format")
+                })?;
+                let jv: JValue = serde_json::from_slice(&raw)?;
+                jv.get(field_name)
+                    .and_then(|v| v.as_str())
+                    .map(|v| v.to_owned())
+                    .ok_or_else(|| anyhow!("{} not found in the credential source", field_name))
+            }
+            _ => Ok(String::from_utf8(raw)?.trim().to_owned()),
+        }
+    }
+
+    async fn aws_metadata_get(&self, url: &str, session_token: &Option<String>) -> Result<String> {
+        let mut req = Request::builder().method(Method::GET).uri(url);
+        if let Some(session_token) = session_token {
+            req = req.header("x-aws-ec2-metadata-token", session_token.as_str());
+        }
+        let body = self.send(req.body(Body::empty())?).await?;
+        Ok(String::from_utf8(body)?)
+    }
+
+    /// the subject token of aws is a serialized GetCallerIdentity request signed with AWS Signature Version 4.
+    async fn aws_subject_token(&self) -> Result<String> {
+        let source = &self.key.credential_source;
+
+        let env_region = env::var("AWS_REGION")
+            .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+            .ok();
+        let env_credentials = match (
+            env::var("AWS_ACCESS_KEY_ID"),
+            env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            (Ok(access_key_id), Ok(secret_access_key)) => Some(AwsSecurityCredentials {
+                access_key_id,
+                secret_access_key,
+                token: env::var("AWS_SESSION_TOKEN").ok(),
+            }),
+            _ => None,
+        };
+
+        let session_token = match (
+            &source.imdsv2_session_token_url,
+            &env_region,
+            &env_credentials,
+        ) {
+            (Some(token_url), None, _) | (Some(token_url), _, None) => {
+                let req = Request::builder()
+                    .method(Method::PUT)
+                    .uri(token_url.as_str())
+                    .header("x-aws-ec2-metadata-token-ttl-seconds", "300")
+                    .body(Body::empty())?;
+                Some(String::from_utf8(self.send(req).await?)?)
+            }
+            _ => None,
+        };
+
+        let region = match env_region {
+            Some(region) => region,
+            None => {
+                let region_url = source
+                    .region_url
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("region_url is required for aws credential source"))?;
+                // the metadata server returns the zone such as `us-east-2b`
+                let mut zone = self.aws_metadata_get(region_url, &session_token).await?;
+                zone.pop();
+                zone
+            }
+        };
+
+        let credentials = match env_credentials {
+            Some(credentials) => credentials,
+            None => {
+                let url = source
+                    .url
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("url is required for aws credential source"))?;
+                let role_name = self.aws_metadata_get(url, &session_token).await?;
+                let role_url = format!("{}/{}", url, role_name.trim());
+                serde_json::from_str(&self.aws_metadata_get(&role_url, &session_token).await?)?
+            }
+        };
+
+        let verification_url = source
+            .regional_cred_verification_url
+            .as_ref()
+            .ok_or_else(|| anyhow!("regional_cred_verification_url is required"))?
+            .replace("{region}", &region);
+        let uri: hyper::Uri = verification_url.parse()?;
+        let host = uri
+            .host()
+            .ok_or_else(|| anyhow!("invalid verification url {}", verification_url))?
+            .to_owned();
+
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let mut headers = vec![
+            ("host".to_owned(), host),
+            ("x-amz-date".to_owned(), amz_date.clone()),
+            (
+                "x-goog-cloud-target-resource".to_owned(),
+                self.key.audience.clone(),
+            ),
+        ];
+        if let Some(token) = &credentials.token {
+            headers.push(("x-amz-security-token".to_owned(), token.clone()));
+        }
+
+        let authorization = AwsSignatureV4 {
+            access_key_id: &credentials.access_key_id,
+            secret_access_key: &credentials.secret_access_key,
+            region: &region,
+            service: "sts",
+        }
+        .authorization_header(
+            "POST",
+            uri.path(),
+            uri.query().unwrap_or(""),
+            &headers,
+            b"",
+            &amz_date,
+        );
+        headers.insert(0, ("Authorization".to_owned(), authorization));
+
+        let caller_identity_request = json!({
+            "url": verification_url,
+            "method": "POST",
+            "headers": headers
+                .into_iter()
+                .map(|(key, value)| json!({"key": key, "value": value}))
+                .collect::<Vec<JValue>>(),
+        });
+
+        Ok(utf8_percent_encode(&caller_identity_request.to_string(), NON_ALPHANUMERIC).to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ExternalAccountKey;
+
+    #[test]
+    fn parse_external_account_key() {
+        let raw = r#"{
+          "type": "external_account",
+          "audience": "//iam.googleapis.com/projects/123/locations/global/workloadIdentityPools/pool/providers/github",
+          "subject_token_type": "urn:ietf:params:oauth:token-type:jwt",
+          "token_url": "https://sts.googleapis.com/v1/token",
+          "service_account_impersonation_url": "https://iamcredentials.googleapis.com/v1/projects/-/serviceAccounts/sa@project.iam.gserviceaccount.com:generateAccessToken",
+          "credential_source": {
+            "url": "https://token.actions.githubusercontent.com",
+            "headers": {"Authorization": "bearer xxx"},
+            "format": {"type": "json", "subject_token_field_name": "value"}
+          }
+        }"#;
+
+        let key: ExternalAccountKey = serde_json::from_str(raw).unwrap();
+        assert_eq!("external_account", key.key_type);
+        assert!(key.service_account_impersonation_url.is_some());
+        assert_eq!(
+            Some("value".to_owned()),
+            key.credential_source
+                .format
+                .and_then(|f| f.subject_token_field_name)
+        );
+    }
+}
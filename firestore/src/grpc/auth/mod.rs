@@ -4,20 +4,31 @@ use chrono::{offset::Utc, Duration};
 use hyper;
 use log;
 
-//use async_std::sync::{Condvar, Mutex};
+use futures::FutureExt;
+use std::future::Future;
 use std::path::PathBuf;
-use std::sync::{Arc, Condvar, Mutex};
+use std::pin::Pin;
+use std::sync::{Arc, Weak};
+use tokio::sync::{mpsc, watch};
 use yup_oauth2::{
     self as oauth,
     authenticator::{Authenticator, DefaultHyperClient, HyperClientBuilder},
-    AccessToken,
+    AccessToken, ServiceAccountKey,
 };
 
-use google_cloud_grpc_proto::tonic::{metadata::MetadataValue, Request, Status};
+use super::events::{ClientEvent, ClientEvents};
+use google_cloud_grpc_proto::tonic::{
+    codegen::http::HeaderValue,
+    metadata::{MetadataMap, MetadataValue},
+};
 
-pub(crate) mod scopes;
+mod aws;
+pub(crate) mod external_account;
+pub mod scopes;
+mod self_signed_jwt;
+use external_account::{read_external_account_key, ExternalAccountAuthenticator};
 use scopes::Scope;
-use std::thread;
+use self_signed_jwt::SelfSignedJwt;
 
 #[derive(Clone, Copy, Debug)]
 pub struct TokenRefresh {
@@ -36,16 +47,60 @@ impl Default for TokenRefresh {
     }
 }
 
+/// where the access tokens come from.
+pub enum TokenSource<HttpConnector> {
+    Authenticator(Authenticator<HttpConnector>),
+    ExternalAccount(Box<ExternalAccountAuthenticator<HttpConnector>>),
+    /// the service account with the audience.
+    SelfSignedJwt(Box<SelfSignedJwt>),
+}
+
+impl<HttpConnector> TokenSource<HttpConnector>
+where
+    HttpConnector: hyper::client::connect::Connect + Clone + Send + Sync + 'static,
+{
+    async fn token(&self, scopes: &[Scope]) -> Result<AccessToken> {
+        match self {
+            TokenSource::Authenticator(authenticator) => Ok(authenticator.token(scopes).await?),
+            TokenSource::ExternalAccount(authenticator) => authenticator.token(scopes).await,
+            TokenSource::SelfSignedJwt(jwt) => jwt.token(),
+        }
+    }
+
+    async fn force_refreshed_token(&self, scopes: &[Scope]) -> Result<AccessToken> {
+        match self {
+            TokenSource::Authenticator(authenticator) => {
+                Ok(authenticator.force_refreshed_token(scopes).await?)
+            }
+            TokenSource::ExternalAccount(authenticator) => authenticator.token(scopes).await,
+            TokenSource::SelfSignedJwt(jwt) => jwt.token(),
+        }
+    }
+}
+
 #[allow(dead_code)]
 pub struct TokenManager<HttpConnector> {
-    authenticator: Arc<Authenticator<HttpConnector>>,
+    authenticator: Arc<TokenSource<HttpConnector>>,
     scopes: Vec<Scope>,
     token_refresh: TokenRefresh,
     current_token: Arc<ArcSwap<AccessToken>>,
-    finish_refreshing: Arc<(Mutex<bool>, Condvar)>,
-    pub refresh_token_schedule_jh: std::thread::JoinHandle<()>,
+    /// true to stop the refresh task. the task also stops when the sender is dropped.
+    shutdown: Arc<watch::Sender<bool>>,
     pub refresh_token_loop_jh: tokio::task::JoinHandle<()>,
-    refresh_token_signal_sender: tokio::sync::mpsc::UnboundedSender<std::time::Instant>,
+    refresh_token_signal_sender: mpsc::UnboundedSender<std::time::Instant>,
+    /// the number of the refreshes attempted, to wait for the one requested.
+    refreshed: watch::Receiver<u64>,
+}
+
+/// the time to wait for the token refreshed on demand.
+pub const TOKEN_REFRESH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// refresh the token when a request is rejected as unauthenticated.
+pub(crate) trait TokenRefresher: Send + Sync {
+    /// the authorization header of the refreshed token. None if the refresh failed.
+    fn refreshed_authorization(
+        self: Arc<Self>,
+    ) -> Pin<Box<dyn Future<Output = Option<HeaderValue>> + Send>>;
 }
 
 impl<HttpConnector> TokenManager<HttpConnector>
@@ -53,23 +108,26 @@ where
     HttpConnector: hyper::client::connect::Connect + Clone + Send + Sync + 'static,
 {
     async fn start(
-        authenticator: Authenticator<HttpConnector>,
+        authenticator: TokenSource<HttpConnector>,
         scopes: Vec<Scope>,
         token_refresh: TokenRefresh,
+        events: ClientEvents,
     ) -> Result<Self> {
         let access_token = authenticator.token(scopes.as_ref()).await?;
         let current_token = Arc::new(ArcSwap::from(Arc::new(access_token)));
 
-        let finish_refreshing = Arc::new((Mutex::new(false), Condvar::new()));
+        let (shutdown, shutdown_receiver) = watch::channel(false);
+        let shutdown = Arc::new(shutdown);
         let authenticator = Arc::new(authenticator);
 
-        let (refresh_token_signal_sender, refresh_token_schedule_jh, refresh_token_loop_jh) =
+        let (refresh_token_signal_sender, refreshed, refresh_token_loop_jh) =
             Self::start_refreshing_token(
                 Arc::clone(&authenticator),
                 Arc::clone(&current_token),
-                Arc::clone(&finish_refreshing),
+                shutdown_receiver,
                 scopes.clone(),
-                token_refresh.clone(),
+                token_refresh,
+                events,
             );
 
         let result = Self {
@@ -77,91 +135,101 @@ where
             scopes,
             token_refresh,
             current_token,
-            finish_refreshing,
-            refresh_token_schedule_jh,
+            shutdown,
             refresh_token_loop_jh,
             refresh_token_signal_sender,
+            refreshed,
         };
 
         Ok(result)
     }
 
+    /// spawn the task refreshing the token before it expires, or when `force_refresh_token`
+    /// is called. a failed refresh keeps the current token and is retried at the next check.
     pub fn start_refreshing_token(
-        authenticator: Arc<Authenticator<HttpConnector>>,
+        authenticator: Arc<TokenSource<HttpConnector>>,
         shared_token: Arc<ArcSwap<AccessToken>>,
-        finish_refreshing: Arc<(Mutex<bool>, Condvar)>,
+        mut shutdown: watch::Receiver<bool>,
         scopes: Vec<Scope>,
         token_refresh: TokenRefresh,
+        events: ClientEvents,
     ) -> (
-        tokio::sync::mpsc::UnboundedSender<std::time::Instant>,
-        std::thread::JoinHandle<()>,
+        mpsc::UnboundedSender<std::time::Instant>,
+        watch::Receiver<u64>,
         tokio::task::JoinHandle<()>,
     ) {
-        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<std::time::Instant>();
-        // TODO (tacogips) https://docs.rs/tokio/0.2.25/tokio/task/fn.spawn_blocking.html
-        // loop tokio::task::spawn_blocking a
-        // current implementation based on comment on https://users.rust-lang.org/t/how-to-use-async-fn-in-thread-spawn/46413
-        //
-        //https://users.rust-lang.org/t/is-it-okay-to-use-infinite-loop-in-an-async-function/42385
-        let schedule_tx = tx.clone();
-        let shared_token_current = shared_token.clone();
-        //TODO(tacogips ) could this variable couldn't be tokio::task::JoinHandle?
-        let refresh_token_schedule_jh: std::thread::JoinHandle<()> = thread::spawn(move || {
+        let (tx, mut rx) = mpsc::unbounded_channel::<std::time::Instant>();
+        let (refreshed_sender, refreshed) = watch::channel(0u64);
+        let check_duration = token_refresh
+            .refresh_check_duration
+            .to_std()
+            .unwrap_or(std::time::Duration::from_secs(60));
+
+        let refresh_token_loop_jh = tokio::spawn(async move {
             log::debug!("start gcp auth refresing ...");
+            let mut last_refresh_failed = false;
             loop {
-                let current_token = shared_token_current.load();
-                let need_refresh = (**current_token)
-                    .expiration_time()
-                    .map(|expiration_time| {
-                        expiration_time - token_refresh.refresh_in_minutes_to_expire <= Utc::now()
-                    })
-                    .unwrap_or(false);
-
-                if need_refresh {
-                    log::debug!("refreshing auth token of GCP");
-                    schedule_tx.send(std::time::Instant::now()).unwrap()
-                }
-
-                log::debug!("fetch auth refreshing finish lock");
-                let (finish_lock, cvar) = &*finish_refreshing;
-                let mut finished = finish_lock.lock().unwrap();
-
-                log::debug!("waiting auth refreshing");
-                let waited = cvar
-                    .wait_timeout(
-                        finished,
-                        token_refresh.refresh_check_duration.to_std().unwrap(),
-                    )
-                    .unwrap();
-                finished = waited.0;
-
-                log::debug!("check token manager finished? {}", *finished);
-                if *finished {
-                    log::info!("exit token refreshing loop");
-                    break;
+                let wait = if !token_refresh.do_auto_refresh {
+                    check_duration
+                } else {
+                    match until_refresh(&shared_token, &token_refresh) {
+                        // wait for the next check not to retry the failed refresh immediately
+                        Some(until) if last_refresh_failed => until.max(check_duration),
+                        Some(until) => until.min(check_duration),
+                        None => check_duration,
+                    }
+                };
+
+                let refresh = tokio::select! {
+                    changed = shutdown.changed() => {
+                        if changed.is_err() || *shutdown.borrow() {
+                            break;
+                        }
+                        false
+                    }
+                    signal = rx.recv() => match signal {
+                        Some(time) => {
+                            log::info!("updating token at {:?}", time);
+                            // the requests while waiting are served by the same refresh
+                            while let Some(Some(_)) = rx.recv().now_or_never() {}
+                            true
+                        }
+                        None => break,
+                    },
+                    _ = tokio::time::sleep(wait) => {
+                        token_refresh.do_auto_refresh
+                            && until_refresh(&shared_token, &token_refresh)
+                                == Some(std::time::Duration::from_secs(0))
+                    }
+                };
+                if !refresh {
+                    continue;
                 }
-            }
-        });
 
-        // TODO(tacogips) Is that OK that tokio::spawn contains loop in it.
-        let refresh_token_loop_jh: tokio::task::JoinHandle<()> = tokio::spawn(async move {
-            while let Some(time) = rx.recv().await {
-                log::info!("updating token at {:?}", time);
-                //TODO(tacogips) need backoff
-                let new_token = Self::get_new_token(&authenticator, &scopes).await;
-                match new_token {
-                    Ok(access_token) => shared_token.store(Arc::new(access_token)),
+                log::debug!("refreshing auth token of GCP");
+                match Self::get_new_token(&authenticator, &scopes).await {
+                    Ok(access_token) => {
+                        events.emit(ClientEvent::TokenRefreshed {
+                            expires_at: access_token.expiration_time(),
+                        });
+                        shared_token.store(Arc::new(access_token));
+                        last_refresh_failed = false;
+                    }
                     Err(e) => {
                         log::error!("failed to refresh token :{}", e);
-                        thread::sleep(Duration::seconds(1).to_std().unwrap());
-                        continue;
+                        events.emit(ClientEvent::TokenRefreshFailed {
+                            error: e.to_string(),
+                        });
+                        last_refresh_failed = true;
                     }
                 }
+                let attempts = *refreshed_sender.borrow() + 1;
+                let _ = refreshed_sender.send(attempts);
             }
 
             log::info!("exit from refreshing token loop")
         });
-        (tx, refresh_token_schedule_jh, refresh_token_loop_jh)
+        (tx, refreshed, refresh_token_loop_jh)
     }
 
     pub fn force_refresh_token(&self) -> Result<()> {
@@ -170,46 +238,122 @@ where
         Ok(())
     }
 
+    /// request a refresh and wait for it. Ok(false) if the token is not changed (e.g. failed).
+    pub async fn refresh_token_and_wait(&self) -> Result<bool> {
+        let mut refreshed = self.refreshed.clone();
+        let attempts = *refreshed.borrow();
+        let token = self.current_token.load_full();
+        self.force_refresh_token()?;
+        while *refreshed.borrow() <= attempts {
+            refreshed.changed().await?;
+        }
+        Ok(!Arc::ptr_eq(&token, &self.current_token.load_full()))
+    }
+
     pub async fn get_new_token(
-        authenticator: &Authenticator<HttpConnector>,
+        authenticator: &TokenSource<HttpConnector>,
         scopes: &[Scope],
     ) -> Result<AccessToken> {
         let new_token = authenticator.force_refreshed_token(scopes).await?;
         Ok(new_token)
     }
 
+    /// the function stopping the refresh task, and the future resolved when the task has
+    /// exited. they don't keep the manager alive.
+    pub(crate) fn stop_handle(
+        &self,
+    ) -> (
+        impl FnOnce() + Send + 'static,
+        impl Future<Output = ()> + Send + 'static,
+    ) {
+        let shutdown: Weak<watch::Sender<bool>> = Arc::downgrade(&self.shutdown);
+        let stop = move || {
+            if let Some(shutdown) = shutdown.upgrade() {
+                let _ = shutdown.send(true);
+            }
+        };
+        // the sender is dropped at the exit of the task
+        let mut refreshed = self.refreshed.clone();
+        let exited = async move { while refreshed.changed().await.is_ok() {} };
+        (stop, exited)
+    }
+
     pub fn shared_token(&self) -> Arc<ArcSwap<AccessToken>> {
         Arc::clone(&self.current_token)
     }
 
     pub fn refresh_token(&self) -> Arc<ArcSwap<AccessToken>> {
-        Arc::clone(&self.current_token)
+        self.shared_token()
     }
 
-    pub async fn stop_auth_refreshing(self) -> Result<()> {
-        stop_auth_refreshing(self.finish_refreshing.clone());
+    /// stop the refresh task and wait for it to exit.
+    pub async fn stop_auth_refreshing(mut self) -> Result<()> {
+        let _ = self.shutdown.send(true);
+        (&mut self.refresh_token_loop_jh).await?;
         Ok(())
     }
 }
 
-pub fn stop_auth_refreshing(finish_refreshing: Arc<(Mutex<bool>, Condvar)>) {
-    log::info!("dropping token manager");
-    let (finish_lock, cvar) = &*finish_refreshing;
-    let mut finish = finish_lock.lock().unwrap();
-    *finish = true;
-    cvar.notify_one()
+/// the time until the token should be refreshed. zero if it should be now,
+/// None if the token doesn't expire.
+fn until_refresh(
+    shared_token: &ArcSwap<AccessToken>,
+    token_refresh: &TokenRefresh,
+) -> Option<std::time::Duration> {
+    let expiration_time = shared_token.load().expiration_time()?;
+    let refresh_at = expiration_time - token_refresh.refresh_in_minutes_to_expire;
+    Some(
+        (refresh_at - Utc::now())
+            .to_std()
+            .unwrap_or(std::time::Duration::from_secs(0)),
+    )
+}
+
+impl<HttpConnector> TokenRefresher for TokenManager<HttpConnector>
+where
+    HttpConnector: hyper::client::connect::Connect + Clone + Send + Sync + 'static,
+{
+    fn refreshed_authorization(
+        self: Arc<Self>,
+    ) -> Pin<Box<dyn Future<Output = Option<HeaderValue>> + Send>> {
+        Box::pin(async move {
+            let refreshed =
+                tokio::time::timeout(TOKEN_REFRESH_TIMEOUT, self.refresh_token_and_wait()).await;
+            match refreshed {
+                Ok(Ok(true)) => {
+                    let bearer_token = format!("Bearer {}", self.current_token.load().as_str());
+                    HeaderValue::from_str(&bearer_token).ok()
+                }
+                Ok(Ok(false)) => None,
+                Ok(Err(e)) => {
+                    log::error!("failed to refresh token on demand :{}", e);
+                    None
+                }
+                Err(_) => {
+                    log::error!("timed out to refresh token on demand");
+                    None
+                }
+            }
+        })
+    }
 }
 
 impl<T> Drop for TokenManager<T> {
+    /// doesn't block. the refresh task exits on its next poll.
     fn drop(&mut self) {
-        stop_auth_refreshing(self.finish_refreshing.clone())
+        log::info!("dropping token manager");
+        let _ = self.shutdown.send(true);
     }
 }
 
 pub struct TokenManagerBuilder {
     scopes: Vec<Scope>,
     service_account_file_path: Option<PathBuf>,
+    service_account_key: Option<ServiceAccountKey>,
+    external_account_file_path: Option<PathBuf>,
     token_refresh: Option<TokenRefresh>,
+    events: Option<ClientEvents>,
+    audience: Option<String>,
 }
 
 impl TokenManagerBuilder {
@@ -217,7 +361,11 @@ impl TokenManagerBuilder {
         Self {
             scopes: scopes,
             service_account_file_path: None,
+            service_account_key: None,
+            external_account_file_path: None,
             token_refresh: None,
+            events: None,
+            audience: None,
         }
     }
     pub fn service_account_file(self, path: PathBuf) -> Self {
@@ -227,20 +375,91 @@ impl TokenManagerBuilder {
         }
     }
 
+    /// the service account key in memory. (e.g. from a secret manager)
+    pub fn service_account_key(self, key: ServiceAccountKey) -> Self {
+        TokenManagerBuilder {
+            service_account_key: Some(key),
+            ..self
+        }
+    }
+
+    /// the credential configuration file of workload identity federation (`"type": "external_account"`)
+    pub fn external_account_file(self, path: PathBuf) -> Self {
+        TokenManagerBuilder {
+            external_account_file_path: Some(path),
+            ..self
+        }
+    }
+
+    /// emit the token events to `events` (e.g. of the client using the token).
+    pub fn events(self, events: ClientEvents) -> Self {
+        TokenManagerBuilder {
+            events: Some(events),
+            ..self
+        }
+    }
+
+    /// override the audience of the credential. the audience of the token exchange for
+    /// the external account. the service account sends the self-signed JWTs whose `aud` is the
+    /// audience (e.g. "https://firestore.googleapis.com/") instead of the tokens of the token
+    /// endpoint, and the scopes are not used.
+    pub fn audience(self, audience: String) -> Self {
+        TokenManagerBuilder {
+            audience: Some(audience),
+            ..self
+        }
+    }
+
     pub async fn build(
         self,
     ) -> Result<TokenManager<<DefaultHyperClient as HyperClientBuilder>::Connector>> {
+        if let Some(external_account_path) = self.external_account_file_path.clone() {
+            return self
+                .build_with_external_account_file(external_account_path)
+                .await;
+        }
+        if let Some(sa_key) = self.service_account_key.clone() {
+            return self.build_with_service_account_key(sa_key).await;
+        }
+
         let sa_path = self.service_account_file_path.clone();
         let sa_path = sa_path.ok_or_else(|| {
                 anyhow!(
-                "the service account is required to create authenticator.(in current impmlementation)"
+                "the service account or external account is required to create authenticator.(in current impmlementation)"
             )
             })?;
 
-        self.from_service_account_file(sa_path).await
+        self.build_with_service_account_file(sa_path).await
     }
 
-    async fn from_service_account_file(
+    async fn build_with_external_account_file(
+        self,
+        external_account_cred_file: PathBuf,
+    ) -> Result<TokenManager<<DefaultHyperClient as HyperClientBuilder>::Connector>> {
+        let mut key = read_external_account_key(external_account_cred_file.clone())
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "failed to read external account file at {}: {}",
+                    external_account_cred_file.display(),
+                    e
+                )
+            })?;
+        if let Some(audience) = self.audience.clone() {
+            key.audience = audience;
+        }
+        let auth = ExternalAccountAuthenticator::new(key, DefaultHyperClient.build_hyper_client());
+
+        TokenManager::start(
+            TokenSource::ExternalAccount(Box::new(auth)),
+            self.scopes,
+            self.token_refresh.unwrap_or_default(),
+            self.events.unwrap_or_default(),
+        )
+        .await
+    }
+
+    async fn build_with_service_account_file(
         self,
         service_account_cred_file: PathBuf,
     ) -> Result<TokenManager<<DefaultHyperClient as HyperClientBuilder>::Connector>> {
@@ -250,30 +469,63 @@ impl TokenManagerBuilder {
                 anyhow!(
                     "failed to read service account file at {}: {}",
                     service_account_cred_file.display(),
-                    e.to_string()
+                    e
                 )
             })?;
-        let auth = oauth::ServiceAccountAuthenticator::builder(sa_key)
-            .build()
-            .await?;
+        self.build_with_service_account_key(sa_key).await
+    }
+
+    async fn build_with_service_account_key(
+        self,
+        sa_key: ServiceAccountKey,
+    ) -> Result<TokenManager<<DefaultHyperClient as HyperClientBuilder>::Connector>> {
+        let source = match self.audience.clone() {
+            Some(audience) => {
+                TokenSource::SelfSignedJwt(Box::new(SelfSignedJwt::new(&sa_key, audience)?))
+            }
+            None => TokenSource::Authenticator(
+                oauth::ServiceAccountAuthenticator::builder(sa_key)
+                    .build()
+                    .await?,
+            ),
+        };
 
         TokenManager::start(
-            auth,
+            source,
             self.scopes,
-            self.token_refresh.unwrap_or(Default::default()),
+            self.token_refresh.unwrap_or_default(),
+            self.events.unwrap_or_default(),
         )
         .await
     }
 }
 
-/// the response implments tonic's Into<tonic::Interceptor>
-pub(crate) fn auth_interceptor(
+/// the error doesn't have the contents of the json, not to leak the private key to the logs.
+pub(crate) fn parse_service_account_key_json(json: &str) -> Result<ServiceAccountKey> {
+    serde_json::from_str(json).map_err(|e| {
+        anyhow!(
+            "failed to parse the service account key json at line {} column {}: {:?}",
+            e.line(),
+            e.column(),
+            e.classify()
+        )
+    })
+}
+
+/// add the authorization header of the current token. see `HookedChannel`
+pub(crate) fn auth_headers(
     shared_token: Arc<ArcSwap<AccessToken>>,
-) -> impl Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static {
-    move |mut req: Request<()>| {
+) -> impl Fn(&mut MetadataMap) + Send + Sync + 'static {
+    move |metadata: &mut MetadataMap| {
         let bearer_token = format!("Bearer {}", shared_token.load().as_str());
         let token = MetadataValue::from_str(bearer_token.as_str()).unwrap();
-        req.metadata_mut().insert("authorization", token);
-        Ok(req)
+        metadata.insert("authorization", token);
+    }
+}
+
+/// the emulator accepts the fixed token "owner" which bypasses the security rules.
+pub(crate) fn emulator_auth_headers() -> impl Fn(&mut MetadataMap) + Send + Sync + 'static {
+    move |metadata: &mut MetadataMap| {
+        metadata.insert("authorization", MetadataValue::from_static("Bearer owner"));
     }
 }
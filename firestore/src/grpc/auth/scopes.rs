@@ -1,11 +1,12 @@
 use lazy_static::lazy_static;
 
+/// the oauth2 scope of the access token. e.g. `*scopes::CLOUD_PLATFORM_READ_ONLY`
 pub type Scope = &'static str;
 
 macro_rules! google_cloud_scope{
     ($($variable:ident = $val:expr),*, ) =>{
         lazy_static!{
-            $(pub(crate) static ref $variable : Scope = concat!("https://www.googleapis.com/auth/", $val).as_ref();) *
+            $(pub static ref $variable : Scope = concat!("https://www.googleapis.com/auth/", $val).as_ref();) *
         }
     }
 
@@ -0,0 +1,48 @@
+use anyhow::{anyhow, Result};
+use google_cloud_grpc_proto::tonic::metadata::{Ascii, MetadataValue};
+
+pub(crate) const API_CLIENT_HEADER: &str = "x-goog-api-client";
+
+/// prepended to the user-agent of tonic. (e.g. "firestore-rs/0.1.0 tonic/0.4.3")
+pub(crate) const CRATE_USER_AGENT: &str = concat!("firestore-rs/", env!("CARGO_PKG_VERSION"));
+
+const API_CLIENT: &str = concat!("gl-rust gccl/", env!("CARGO_PKG_VERSION"), " grpc/0.4");
+
+/// "gl-rust gccl/{version} grpc/{version} {app suffix}"
+///
+/// the suffix of the app goes to `x-goog-api-client`
+/// since tonic overwrites the user-agent of the requests by the one of the channel.
+pub(crate) fn api_client_header(user_agent_suffix: Option<&str>) -> Result<MetadataValue<Ascii>> {
+    let value = match user_agent_suffix {
+        Some(suffix) => format!("{} {}", API_CLIENT, suffix.trim()),
+        None => API_CLIENT.to_owned(),
+    };
+    MetadataValue::from_str(&value)
+        .map_err(|_| anyhow!("invalid user agent suffix: {:?}", user_agent_suffix))
+}
+
+/// the header without the suffix of the app.
+pub(crate) fn default_api_client_header() -> MetadataValue<Ascii> {
+    MetadataValue::from_static(API_CLIENT)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn api_client_header_test() {
+        let header = api_client_header(Some("my-app/1.2.3")).unwrap();
+        let header = header.to_str().unwrap();
+        assert!(header.starts_with("gl-rust gccl/"));
+        assert!(header.ends_with(" my-app/1.2.3"));
+
+        assert!(api_client_header(Some("my-app\n")).is_ok());
+        assert!(api_client_header(Some("my\u{7}app")).is_err());
+
+        assert_eq!(
+            api_client_header(None).unwrap(),
+            default_api_client_header()
+        );
+    }
+}
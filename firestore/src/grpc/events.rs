@@ -0,0 +1,108 @@
+use chrono::{DateTime, Utc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// the events kept for the slow subscribers. the older ones are dropped (`RecvError::Lagged`).
+pub const CLIENT_EVENT_CAPACITY: usize = 256;
+
+/// the health events of the client, to alert on without scraping the logs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientEvent {
+    TokenRefreshed {
+        expires_at: Option<DateTime<Utc>>,
+    },
+    /// the current token is kept and the refresh is retried.
+    TokenRefreshFailed {
+        error: String,
+    },
+    /// an rpc got a response again after an rpc failed to connect or was unavailable.
+    ChannelReconnected,
+    /// the writes are delayed by the throttling of `BulkWriter`.
+    ThrottleApplied {
+        delay: Duration,
+        ops: usize,
+    },
+    /// the operation gave up after the retries.
+    RetryExhausted {
+        /// the rpc or the document path of the write.
+        operation: String,
+        attempts: usize,
+        error: String,
+    },
+}
+
+/// the event bus shared by a client and its clones. see `FirestoreClient::subscribe_events`
+#[derive(Debug, Clone)]
+pub struct ClientEvents {
+    sender: broadcast::Sender<ClientEvent>,
+    channel_down: Arc<AtomicBool>,
+}
+
+impl Default for ClientEvents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientEvents {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CLIENT_EVENT_CAPACITY);
+        Self {
+            sender,
+            channel_down: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ClientEvent> {
+        self.sender.subscribe()
+    }
+
+    /// the events without subscribers are dropped.
+    pub(crate) fn emit(&self, event: ClientEvent) {
+        #[cfg(feature = "tracing")]
+        tracing::info!(?event, "firestore client event");
+        let _ = self.sender.send(event);
+    }
+
+    /// record whether the server responded, and emit `ChannelReconnected`
+    /// on the first response after the connection was lost.
+    pub(crate) fn observe_channel(&self, channel_ok: bool) {
+        let was_down = self.channel_down.swap(!channel_ok, Ordering::SeqCst);
+        if was_down && channel_ok {
+            self.emit(ClientEvent::ChannelReconnected);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn client_events_test() {
+        let events = ClientEvents::new();
+        // no subscribers
+        events.emit(ClientEvent::ChannelReconnected);
+
+        let mut receiver = events.clone().subscribe();
+        events.observe_channel(true);
+        events.observe_channel(false);
+        events.observe_channel(false);
+        events.observe_channel(true);
+        events.observe_channel(true);
+        events.emit(ClientEvent::TokenRefreshFailed {
+            error: "failed".to_owned(),
+        });
+
+        assert_eq!(Ok(ClientEvent::ChannelReconnected), receiver.try_recv());
+        assert_eq!(
+            Ok(ClientEvent::TokenRefreshFailed {
+                error: "failed".to_owned()
+            }),
+            receiver.try_recv()
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,502 @@
+use super::auth::TokenRefresher;
+use super::client_info::{default_api_client_header, API_CLIENT_HEADER};
+use super::events::ClientEvents;
+use google_cloud_grpc_proto::tonic::{
+    body::BoxBody,
+    codegen::{http, BoxFuture, Service, StdError},
+    metadata::{Ascii, MetadataMap, MetadataValue},
+    transport::{Body, Channel},
+    Code, Status,
+};
+use hyper::body::{Bytes, HttpBody};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower::ServiceExt;
+
+/// called on every rpc of the client. e.g. to attach request ids, or to record the latency.
+/// `method` is the path of the rpc (e.g. "/google.firestore.v1.Firestore/GetDocument").
+///
+/// ```ignore
+/// struct Metrics;
+/// impl RpcHook for Metrics {
+///     fn on_request(&self, _method: &str, metadata: &mut MetadataMap) {
+///         metadata.insert("x-request-id", new_request_id().parse().unwrap());
+///     }
+///     fn on_response(&self, method: &str, code: Code, latency: Duration) {
+///         histogram(method, code).observe(latency);
+///     }
+/// }
+/// let client = client.with_rpc_hook(Arc::new(Metrics));
+/// ```
+pub trait RpcHook: Send + Sync {
+    /// before the request is sent, after the auth and the client info headers are added.
+    fn on_request(&self, _method: &str, _metadata: &mut MetadataMap) {}
+
+    /// the rpc finished with the grpc status. the latency is until the end of the response,
+    /// (e.g. the last message of a stream). `Code::Cancelled` if the response is dropped before.
+    fn on_response(&self, _method: &str, _code: Code, _latency: Duration) {}
+
+    /// the request failed without a response. (e.g. the connection is lost)
+    fn on_error(
+        &self,
+        _method: &str,
+        _error: &(dyn std::error::Error + Send + Sync),
+        _latency: Duration,
+    ) {
+    }
+}
+
+/// add the headers of every request, e.g. the auth and the client info headers.
+pub(crate) type RequestHeaders = Arc<dyn Fn(&mut MetadataMap) + Send + Sync>;
+
+/// the rpcs streaming the requests. the others are retried after the token is refreshed
+/// if rejected as unauthenticated.
+const CLIENT_STREAMING_METHODS: &[&str] = &[
+    "/google.firestore.v1.Firestore/Write",
+    "/google.firestore.v1.Firestore/Listen",
+];
+
+/// the channel adding the headers to the requests and calling the hooks around them.
+#[derive(Clone)]
+pub struct HookedChannel {
+    inner: Channel,
+    /// `x-goog-api-client`, added before `request_headers`
+    api_client_header: MetadataValue<Ascii>,
+    request_headers: RequestHeaders,
+    hooks: Arc<Vec<Arc<dyn RpcHook>>>,
+    token_refresher: Option<Arc<dyn TokenRefresher>>,
+    /// `ChannelReconnected` is emitted on the first response after the connection was lost
+    events: ClientEvents,
+}
+
+impl HookedChannel {
+    pub(crate) fn new(
+        inner: Channel,
+        request_headers: RequestHeaders,
+        events: ClientEvents,
+    ) -> Self {
+        Self {
+            inner,
+            api_client_header: default_api_client_header(),
+            request_headers,
+            hooks: Arc::new(Vec::new()),
+            token_refresher: None,
+            events,
+        }
+    }
+
+    /// the channel calling `hook` after the hooks of this channel.
+    /// the hooks are copied so the clones of this channel are not affected.
+    pub(crate) fn with_rpc_hook(&self, hook: Arc<dyn RpcHook>) -> Self {
+        let mut hooks = Vec::clone(&self.hooks);
+        hooks.push(hook);
+        Self {
+            hooks: Arc::new(hooks),
+            ..self.clone()
+        }
+    }
+
+    /// the channel sending `header` as `x-goog-api-client`. the clones of this channel are not affected.
+    pub(crate) fn with_api_client_header(&self, header: MetadataValue<Ascii>) -> Self {
+        Self {
+            api_client_header: header,
+            ..self.clone()
+        }
+    }
+
+    /// retry the request once with the refreshed token if it's rejected as unauthenticated,
+    /// e.g. the token expired between the refresh checks.
+    pub(crate) fn with_token_refresher(self, token_refresher: Arc<dyn TokenRefresher>) -> Self {
+        Self {
+            token_refresher: Some(token_refresher),
+            ..self
+        }
+    }
+}
+
+fn is_unauthenticated<B>(response: &http::Response<B>) -> bool {
+    Status::from_header_map(response.headers()).map(|s| s.code()) == Some(Code::Unauthenticated)
+}
+
+async fn call_with_token_refresh(
+    mut inner: Channel,
+    request: http::Request<BoxBody>,
+    token_refresher: Arc<dyn TokenRefresher>,
+) -> Result<http::Response<Body>, StdError> {
+    // the unary request is buffered to be sent again
+    let (parts, body) = request.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+    let new_request = |headers: http::HeaderMap| {
+        let mut request = http::Request::new(BoxBody::map_from(Body::from(body.clone())));
+        *request.method_mut() = parts.method.clone();
+        *request.uri_mut() = parts.uri.clone();
+        *request.version_mut() = parts.version;
+        *request.headers_mut() = headers;
+        request
+    };
+
+    let response = inner.call(new_request(parts.headers.clone())).await?;
+    if !is_unauthenticated(&response) {
+        return Ok(response);
+    }
+    let authorization = match token_refresher.refreshed_authorization().await {
+        Some(authorization) => authorization,
+        None => return Ok(response),
+    };
+    let mut headers = parts.headers.clone();
+    headers.insert(http::header::AUTHORIZATION, authorization);
+    inner.ready().await?;
+    Ok(inner.call(new_request(headers)).await?)
+}
+
+impl Service<http::Request<BoxBody>> for HookedChannel {
+    type Response = http::Response<HookedBody>;
+    type Error = StdError;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, mut request: http::Request<BoxBody>) -> Self::Future {
+        let hooks = self.hooks.clone();
+        let method = request.uri().path().to_owned();
+        let headers = std::mem::take(request.headers_mut());
+        let mut metadata = MetadataMap::from_headers(headers);
+        metadata.insert(API_CLIENT_HEADER, self.api_client_header.clone());
+        (self.request_headers)(&mut metadata);
+        for hook in hooks.iter() {
+            hook.on_request(&method, &mut metadata);
+        }
+        *request.headers_mut() = metadata.into_headers();
+
+        let token_refresher = self
+            .token_refresher
+            .clone()
+            .filter(|_| !CLIENT_STREAMING_METHODS.contains(&method.as_str()));
+        // the channel made ready by `poll_ready` is taken to be called in the future
+        let channel = self.inner.clone();
+        let ready_channel = std::mem::replace(&mut self.inner, channel);
+        let events = self.events.clone();
+        let started_at = Instant::now();
+        // the span of the rpc, recording the grpc status when the response finishes
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "rpc",
+            rpc = %method,
+            status = tracing::field::Empty,
+            error = tracing::field::Empty
+        );
+        #[cfg(feature = "tracing")]
+        let finished_span = span.clone();
+        let future = async move {
+            let response = match token_refresher {
+                Some(token_refresher) => {
+                    call_with_token_refresh(ready_channel, request, token_refresher).await
+                }
+                None => {
+                    let mut ready_channel = ready_channel;
+                    ready_channel.call(request).await.map_err(Into::into)
+                }
+            };
+            match response {
+                Ok(response) => {
+                    // trailers-only responses (e.g. errors) have the status in the headers
+                    let code = Status::from_header_map(response.headers()).map(|s| s.code());
+                    Ok(response.map(|body| HookedBody {
+                        inner: body,
+                        finished: Some(Finished {
+                            hooks,
+                            events,
+                            method,
+                            started_at,
+                            code,
+                            #[cfg(feature = "tracing")]
+                            span: finished_span,
+                        }),
+                    }))
+                }
+                Err(e) => {
+                    events.observe_channel(false);
+                    #[cfg(feature = "tracing")]
+                    {
+                        finished_span.record("error", tracing::field::display(&e));
+                        tracing::warn!(parent: &finished_span, error = %e, "rpc failed");
+                    }
+                    for hook in hooks.iter() {
+                        hook.on_error(&method, e.as_ref(), started_at.elapsed());
+                    }
+                    Err(e)
+                }
+            }
+        };
+        #[cfg(feature = "tracing")]
+        let future = tracing::Instrument::instrument(future, span);
+        Box::pin(future)
+    }
+}
+
+struct Finished {
+    hooks: Arc<Vec<Arc<dyn RpcHook>>>,
+    events: ClientEvents,
+    method: String,
+    started_at: Instant,
+    code: Option<Code>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl Finished {
+    fn call(self, code: Code) {
+        let latency = self.started_at.elapsed();
+        let code = self.code.unwrap_or(code);
+        self.events.observe_channel(code != Code::Unavailable);
+        #[cfg(feature = "tracing")]
+        {
+            self.span.record("status", tracing::field::debug(code));
+            tracing::debug!(parent: &self.span, ?latency, "rpc finished");
+        }
+        for hook in self.hooks.iter() {
+            hook.on_response(&self.method, code, latency);
+        }
+    }
+}
+
+/// the response body calling `RpcHook::on_response` at the trailers.
+pub struct HookedBody {
+    inner: Body,
+    finished: Option<Finished>,
+}
+
+impl HttpBody for HookedBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        let trailers = Pin::new(&mut self.inner).poll_trailers(cx);
+        if let Poll::Ready(result) = &trailers {
+            if let Some(finished) = self.finished.take() {
+                let code = match result {
+                    Ok(Some(trailers)) => Status::from_header_map(trailers)
+                        .map(|s| s.code())
+                        .unwrap_or(Code::Ok),
+                    Ok(None) => Code::Ok,
+                    Err(_) => Code::Unknown,
+                };
+                finished.call(code);
+            }
+        }
+        trailers
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for HookedBody {
+    fn drop(&mut self) {
+        if let Some(finished) = self.finished.take() {
+            finished.call(Code::Cancelled);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder {
+        codes: Mutex<Vec<(String, Code)>>,
+    }
+
+    impl RpcHook for Recorder {
+        fn on_response(&self, method: &str, code: Code, _latency: Duration) {
+            self.codes.lock().unwrap().push((method.to_owned(), code));
+        }
+    }
+
+    fn hooked_body(inner: Body, recorder: &Arc<Recorder>) -> HookedBody {
+        let hook: Arc<dyn RpcHook> = recorder.clone();
+        HookedBody {
+            inner,
+            finished: Some(Finished {
+                hooks: Arc::new(vec![hook]),
+                events: ClientEvents::new(),
+                method: "/google.firestore.v1.Firestore/GetDocument".to_owned(),
+                started_at: Instant::now(),
+                code: None,
+                #[cfg(feature = "tracing")]
+                span: tracing::Span::none(),
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn hooked_body_test() {
+        let recorder = Arc::new(Recorder::default());
+
+        let (mut sender, inner) = Body::channel();
+        let mut body = hooked_body(inner, &recorder);
+        sender
+            .send_data(Bytes::from_static(b"message"))
+            .await
+            .unwrap();
+        let mut trailers = http::HeaderMap::new();
+        trailers.insert("grpc-status", "5".parse().unwrap());
+        sender.send_trailers(trailers).await.unwrap();
+        drop(sender);
+
+        assert_eq!(
+            Bytes::from_static(b"message"),
+            body.data().await.unwrap().unwrap()
+        );
+        assert!(body.data().await.is_none());
+        assert!(body.trailers().await.unwrap().is_some());
+        drop(body);
+
+        // dropped before the end of the response
+        let (_sender, inner) = Body::channel();
+        drop(hooked_body(inner, &recorder));
+
+        let codes = recorder.codes.lock().unwrap();
+        assert_eq!(
+            vec![Code::NotFound, Code::Cancelled],
+            codes.iter().map(|(_, code)| *code).collect::<Vec<_>>()
+        );
+        assert_eq!("/google.firestore.v1.Firestore/GetDocument", codes[0].0);
+    }
+
+    #[test]
+    fn channel_reconnected_test() {
+        use super::super::events::ClientEvent;
+
+        let events = ClientEvents::new();
+        let mut receiver = events.subscribe();
+        let finished = |code| Finished {
+            hooks: Arc::new(Vec::new()),
+            events: events.clone(),
+            method: "/google.firestore.v1.Firestore/GetDocument".to_owned(),
+            started_at: Instant::now(),
+            code,
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::none(),
+        };
+        finished(Some(Code::Unavailable)).call(Code::Ok);
+        finished(None).call(Code::NotFound);
+        finished(None).call(Code::Unavailable);
+        finished(None).call(Code::Ok);
+
+        assert_eq!(Ok(ClientEvent::ChannelReconnected), receiver.try_recv());
+        assert_eq!(Ok(ClientEvent::ChannelReconnected), receiver.try_recv());
+        assert!(receiver.try_recv().is_err());
+    }
+
+    struct FixedToken;
+
+    impl TokenRefresher for FixedToken {
+        fn refreshed_authorization(
+            self: Arc<Self>,
+        ) -> Pin<Box<dyn std::future::Future<Output = Option<http::HeaderValue>> + Send>> {
+            Box::pin(async { Some(http::HeaderValue::from_static("Bearer new")) })
+        }
+    }
+
+    #[tokio::test]
+    async fn token_refresh_retry_test() {
+        use hyper::service::{make_service_fn, service_fn};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // rejects the requests but of the refreshed token
+        let request_num = Arc::new(AtomicUsize::new(0));
+        let server_request_num = request_num.clone();
+        let make_service = make_service_fn(move |_| {
+            let request_num = server_request_num.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |request: http::Request<Body>| {
+                    request_num.fetch_add(1, Ordering::SeqCst);
+                    let status = match request.headers().get("authorization") {
+                        Some(token) if token == "Bearer new" => "0",
+                        _ => "16",
+                    };
+                    let response = http::Response::builder()
+                        .header("content-type", "application/grpc")
+                        .header("grpc-status", status)
+                        .body(Body::empty())
+                        .unwrap();
+                    async move { Ok::<_, hyper::Error>(response) }
+                }))
+            }
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into())
+            .http2_only(true)
+            .serve(make_service);
+        let address = server.local_addr();
+        tokio::spawn(server);
+
+        let channel = Channel::from_shared(format!("http://{}", address))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let recorder = Arc::new(Recorder::default());
+        let request_headers: RequestHeaders = Arc::new(|metadata: &mut MetadataMap| {
+            metadata.insert("authorization", "Bearer old".parse().unwrap());
+        });
+        let mut channel = HookedChannel::new(channel, request_headers, ClientEvents::new())
+            .with_rpc_hook(recorder.clone())
+            .with_token_refresher(Arc::new(FixedToken));
+
+        let request = |method: &str| {
+            http::Request::builder()
+                .uri(format!("http://{}{}", address, method))
+                .body(BoxBody::map_from(Body::from("message")))
+                .unwrap()
+        };
+        channel.ready().await.unwrap();
+        let response = channel
+            .call(request("/google.firestore.v1.Firestore/GetDocument"))
+            .await
+            .unwrap();
+        assert!(!is_unauthenticated(&response));
+        drop(response);
+        assert_eq!(2, request_num.load(Ordering::SeqCst));
+
+        // the streaming requests are not retried
+        channel.ready().await.unwrap();
+        let response = channel
+            .call(request("/google.firestore.v1.Firestore/Write"))
+            .await
+            .unwrap();
+        assert!(is_unauthenticated(&response));
+        drop(response);
+        assert_eq!(3, request_num.load(Ordering::SeqCst));
+
+        let codes: Vec<Code> = recorder
+            .codes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, code)| *code)
+            .collect();
+        assert_eq!(vec![Code::Ok, Code::Unauthenticated], codes);
+    }
+}
@@ -1,8 +1,12 @@
-use anyhow::Result;
-use google_cloud_grpc_proto::tonic::transport::{Channel, ClientTlsConfig};
+use anyhow::{anyhow, Result};
+use google_cloud_grpc_proto::tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+use tower::discover::Change;
 
 pub(crate) mod auth;
+pub(crate) mod client_info;
 pub(crate) mod connection_point;
+pub mod events;
+pub mod hooks;
 use connection_point::GrpcConnectionPoint;
 
 pub struct GrpcChannel {
@@ -18,14 +22,67 @@ impl GrpcChannel {
         })
     }
 
+    /// `pool_size` connections balanced by the number of the pending requests
+    /// (the less loaded of two random connections is chosen).
+    /// the connections are opened lazily on the first requests.
+    pub async fn new_pooled_channel(
+        connection_point: &GrpcConnectionPoint,
+        pool_size: usize,
+    ) -> Result<GrpcChannel> {
+        if pool_size <= 1 {
+            return Self::new_connected_channnel(connection_point).await;
+        }
+        Self::new_limited_pooled_channel(connection_point, pool_size, None).await
+    }
+
+    /// `new_pooled_channel` with at most `concurrency_limit` in-flight requests per connection.
+    /// the requests over the limit wait for the others to complete.
+    pub async fn new_limited_pooled_channel(
+        connection_point: &GrpcConnectionPoint,
+        pool_size: usize,
+        concurrency_limit: Option<usize>,
+    ) -> Result<GrpcChannel> {
+        let mut endpoint = Self::endpoint(connection_point)?;
+        if let Some(limit) = concurrency_limit {
+            endpoint = endpoint.concurrency_limit(limit.max(1));
+        }
+        let pool_size = pool_size.max(1);
+        let (opened_channel, changes) = Channel::balance_channel::<usize>(pool_size);
+        for index in 0..pool_size {
+            changes
+                .send(Change::Insert(index, endpoint.clone()))
+                .await
+                .map_err(|_| anyhow!("failed to add the connection to the pool"))?;
+        }
+        Ok(GrpcChannel {
+            opened_channel: Some(opened_channel),
+        })
+    }
+
+    /// plain http channel without tls. e.g. for the emulator
+    pub async fn new_insecure_channel(endpoint: String) -> Result<GrpcChannel> {
+        let opened_channel = Channel::from_shared(endpoint)?
+            .user_agent(client_info::CRATE_USER_AGENT)?
+            .connect()
+            .await?;
+        Ok(GrpcChannel {
+            opened_channel: Some(opened_channel),
+        })
+    }
+
     async fn connect(connection_point: &GrpcConnectionPoint) -> Result<Channel> {
+        let channel = Self::endpoint(connection_point)?.connect().await?;
+        Ok(channel)
+    }
+
+    fn endpoint(connection_point: &GrpcConnectionPoint) -> Result<Endpoint> {
         let GrpcConnectionPoint(endpoint, domain) = *connection_point;
         let tls_config = ClientTlsConfig::new().domain_name(domain);
-        let endpoint = Channel::from_static(endpoint).tls_config(tls_config)?;
-        let channel = endpoint.connect().await?;
-        Ok(channel)
+        let endpoint = Channel::from_static(endpoint)
+            .tls_config(tls_config)?
+            .user_agent(client_info::CRATE_USER_AGENT)?;
+        Ok(endpoint)
     }
 }
-pub(crate) mod error;
 
 pub(crate) mod macros;
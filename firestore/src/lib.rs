@@ -1,2 +1,10 @@
+#[cfg(feature = "blocking")]
+pub mod blocking;
 pub mod firestore;
+#[cfg(any(all(test, feature = "grpc"), feature = "fixtures"))]
+pub mod fixtures;
+#[cfg(feature = "grpc")]
 pub mod grpc;
+#[cfg(feature = "grpc")]
+pub mod prelude;
+pub mod types;
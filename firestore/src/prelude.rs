@@ -0,0 +1,12 @@
+//! commonly used types.
+//!
+//! ```ignore
+//! use firestore::prelude::*;
+//! ```
+
+pub use crate::firestore::{
+    doc_path, from_document, from_fvalue, new_write_ope_create, new_write_ope_delete,
+    new_write_ope_update, new_write_ope_upsert, param, to_fvalue, BulkWriter, CollectionRef,
+    DocumentWriteOperation, FDocument, FDocumentPath, FFields, FValue, FieldOp, FirestoreClient,
+    FirestoreError, OrderDirection, QueryBuilder, QueryTemplate, Result, TryIntoFFields, UnaryOp,
+};
@@ -0,0 +1,22 @@
+//! the document models and their serde/json conversions, without the grpc stack.
+//!
+//! with `default-features = false` only this module (and `firestore::{FirestoreError, Result}`)
+//! is built, so that the other services can share the models without compiling tonic and prost.
+//!
+//! ```ignore
+//! firestore = { path = "../firestore", default-features = false }
+//!
+//! use firestore::types::{from_fvalue, to_fvalue, FValue};
+//! let value: FValue = to_fvalue(&user)?;
+//! let json = serde_json::Value::from(value);
+//! ```
+
+pub use crate::firestore::{
+    array_value_from_vec, doc_path, escape_field_name, from_fvalue, from_fvalue_with_casing,
+    from_fvalues, join_field_path, map_value_from_vec, parse_field_path, required_fields,
+    timestamp, to_fvalue, to_fvalue_with, to_fvalue_with_casing, to_fvalues, ArrayRemove,
+    ArrayUnion, DocumentSnapshot, ErrorKind, FCollectionPath, FDocument, FDocumentPath, FFields,
+    FMap, FTransform, FValue, FieldCasing, FieldMaskBuilder, FieldNameCase, FieldPath,
+    FirestoreError, Increment, JsonMetadataKeys, NonFiniteDouble, Result, SerdeError,
+    ServerTimestamp, TryIntoFFields, DOCUMENT_ID_FIELD, DOCUMENT_NAME_FIELD,
+};
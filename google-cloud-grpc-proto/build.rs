@@ -8,6 +8,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             "proto/google/firestore/admin/v1beta2/firestore_admin.proto",
             "proto/google/firestore/v1/firestore.proto",
             "proto/google/firestore/v1beta1/firestore.proto",
+            "proto/google/rpc/error_details.proto",
         ],
         &["proto"],
     )?;
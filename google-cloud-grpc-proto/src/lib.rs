@@ -32,5 +32,6 @@ pub mod r#type {
     tonic::include_proto!("google.r#type");
 }
 
+pub use prost;
 pub use prost_types;
 pub use tonic;